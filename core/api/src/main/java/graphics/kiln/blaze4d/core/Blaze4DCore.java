@@ -20,6 +20,9 @@ public class Blaze4DCore implements AutoCloseable {
 
         MemoryAddress surfaceProvider = Natives.b4dCreateGlfwSurfaceProvider(glfwWindow);
         this.handle = Natives.b4dInit(surfaceProvider, enableValidation);
+        if(this.handle.toRawLongValue() == 0L) {
+            throw new RuntimeException("Failed to initialize Blaze4D natives. See log for details");
+        }
     }
 
     public void setDebugMode(DebugMode mode) {
@@ -35,7 +38,12 @@ public class Blaze4DCore implements AutoCloseable {
     }
 
     public GlobalMesh createGlobalMesh(B4DMeshData meshData) {
-        return new GlobalMesh(Natives.b4dCreateGlobalMesh(this.handle, meshData.getAddress()));
+        MemoryAddress mesh = Natives.b4dCreateGlobalMesh(this.handle, meshData.getAddress());
+        if(mesh.toRawLongValue() == 0L) {
+            return null;
+        } else {
+            return new GlobalMesh(mesh);
+        }
     }
 
     public GlobalImage createGlobalImage(int width, int height, B4DFormat format) {
@@ -11,10 +11,14 @@ configure<graphics.kiln.blaze4d.build.assets.AssetsPluginExtension> {
             addModule("debug/color.vert")
             addModule("debug/uv.vert")
             addModule("debug/null.vert")
+            addModule("debug/procedural.vert")
             addModule("debug/debug.frag")
             addModule("debug/textured.frag")
+            addModule("debug/bindless.frag")
             addModule("debug/background.vert")
             addModule("debug/background.frag")
+            addModule("blit/fullscreen.vert")
+            addModule("blit/composite.frag")
         }
 
         addProject("Utils") {
@@ -0,0 +1,28 @@
+#version 450
+
+layout(set=0, binding=0) uniform sampler2D src;
+
+layout(push_constant) uniform Options {
+    uint flags;
+    float gamma;
+} options;
+
+layout(location=0) in vec2 in_uv;
+
+layout(location=0) out vec4 out_color;
+
+const uint FLAG_TONEMAP = 1;
+const uint FLAG_GAMMA = 2;
+
+void main() {
+    vec4 color = texture(src, in_uv);
+
+    if ((options.flags & FLAG_TONEMAP) != 0) {
+        color.rgb = color.rgb / (color.rgb + vec3(1.0));
+    }
+    if ((options.flags & FLAG_GAMMA) != 0) {
+        color.rgb = pow(max(color.rgb, vec3(0.0)), vec3(1.0 / options.gamma));
+    }
+
+    out_color = color;
+}
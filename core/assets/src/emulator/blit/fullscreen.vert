@@ -0,0 +1,13 @@
+#version 450
+/**
+ * Generates a single triangle covering the full framebuffer from 3 vertices without any vertex input.
+ */
+
+layout(location=0) out vec2 out_uv;
+
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+
+    gl_Position = vec4((uv * 2.0) - 1.0, 0.0, 1.0);
+    out_uv = uv;
+}
@@ -2,14 +2,25 @@
 
 layout(input_attachment_index=0, set=0, binding=0) uniform subpassInput rendered;
 
+layout(push_constant) uniform Background {
+    vec4 color0;
+    vec4 color1;
+    uint style;
+} background;
+
 layout(location=0) in vec2 in_pixel_coord;
+layout(location=1) in float in_gradient;
 
 layout(location=0) out vec4 out_color;
 
+const uint STYLE_CHECKERBOARD = 0;
+const uint STYLE_SOLID = 1;
+const uint STYLE_GRADIENT = 2;
+
 const float BASE_VALUE[2] = float[](0.2, 0.4);
 const float OFFSET_VALUE[2] = float[](0.0, -0.1);
 
-vec3 generate_bg() {
+vec3 generate_checkerboard() {
     int x = int(round(in_pixel_coord.x));
     int y = int(round(in_pixel_coord.y));
     float base = BASE_VALUE[((x / 200) + (y / 200)) % 2];
@@ -18,10 +29,20 @@ vec3 generate_bg() {
     return vec3(base + offset);
 }
 
+vec3 generate_bg() {
+    if (background.style == STYLE_SOLID) {
+        return background.color0.rgb;
+    } else if (background.style == STYLE_GRADIENT) {
+        return mix(background.color0.rgb, background.color1.rgb, in_gradient);
+    } else {
+        return generate_checkerboard();
+    }
+}
+
 void main() {
     vec4 in_color = subpassLoad(rendered);
 
     float alpha = in_color.a;
 
     out_color = vec4(((1.0 - alpha) * generate_bg()) + (alpha * in_color.rgb), 1.0);
-}
\ No newline at end of file
+}
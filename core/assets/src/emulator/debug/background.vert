@@ -17,9 +17,11 @@ layout(constant_id=0) const float FRAMEBUFFER_WIDTH = 1.0;
 layout(constant_id=1) const float FRAMEBUFFER_HEIGHT = 1.0;
 
 layout(location=0) out vec2 out_pixel_coord;
+layout(location=1) out float out_gradient;
 
 void main() {
     gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
 
     out_pixel_coord = vec2(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT) * pixel_coords[gl_VertexIndex];
+    out_gradient = pixel_coords[gl_VertexIndex].y;
 }
\ No newline at end of file
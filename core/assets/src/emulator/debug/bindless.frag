@@ -0,0 +1,30 @@
+#version 450
+#extension GL_EXT_nonuniform_qualifier : require
+
+/**
+ * A debug shader sampling a texture of the bindless texture table.
+ */
+
+layout(location=1) in vec2 in_uv;
+
+layout(location=0) out vec4 out_color;
+
+// Additional color attachments with a float format receive the same color
+layout(location=1) out vec4 out_color1;
+layout(location=2) out vec4 out_color2;
+layout(location=3) out vec4 out_color3;
+
+layout(set=1, binding=0) uniform sampler2D _bindless_textures[];
+
+// Follows the _PushConstant block of mc_uniforms.glsl
+layout(push_constant)
+uniform _BindlessPushConstant {
+    layout(offset=80) uint texture_index;
+} _bindless_push_constant;
+
+void main() {
+    out_color = texture(_bindless_textures[_bindless_push_constant.texture_index], in_uv);
+    out_color1 = out_color;
+    out_color2 = out_color;
+    out_color3 = out_color;
+}
\ No newline at end of file
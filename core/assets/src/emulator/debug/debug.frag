@@ -4,6 +4,14 @@ layout(location=0) in vec4 in_color;
 
 layout(location=0) out vec4 out_color;
 
+// Additional color attachments with a float format receive the same color
+layout(location=1) out vec4 out_color1;
+layout(location=2) out vec4 out_color2;
+layout(location=3) out vec4 out_color3;
+
 void main() {
     out_color = in_color;
+    out_color1 = out_color;
+    out_color2 = out_color;
+    out_color3 = out_color;
 }
\ No newline at end of file
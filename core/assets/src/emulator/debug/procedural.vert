@@ -0,0 +1,17 @@
+#version 450
+/**
+ * A debug shader generating a fullscreen triangle from the vertex index without any vertex input.
+ */
+
+layout(location=0) out vec4 out_color;
+
+vec2 positions[3] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(3.0, -1.0),
+    vec2(-1.0, 3.0)
+);
+
+void main() {
+    gl_Position = vec4(positions[gl_VertexIndex % 3], 0.0, 1.0);
+    out_color = vec4(1.0, 1.0, 1.0, 1.0);
+}
@@ -6,8 +6,16 @@ layout(location=1) in vec2 in_uv;
 
 layout(location=0) out vec4 out_color;
 
+// Additional color attachments with a float format receive the same color
+layout(location=1) out vec4 out_color1;
+layout(location=2) out vec4 out_color2;
+layout(location=3) out vec4 out_color3;
+
 layout(constant_id=0) const uint IMAGE_INDEX = 0;
 
 void main() {
     out_color = mc_image(IMAGE_INDEX, in_uv);
+    out_color1 = out_color;
+    out_color2 = out_color;
+    out_color3 = out_color;
 }
\ No newline at end of file
@@ -28,6 +28,7 @@ layout(push_constant)
 uniform _PushConstant {
     mat4 model_view_matrix;
     vec3 chunk_offset;
+    uint mvp_enabled; // If not 0 model_view_matrix contains the full mvp matrix
 } _push_constant;
 
 mat4 mc_model_view_matrix() {
@@ -92,7 +93,12 @@ vec3 mc_chunk_offset() {
 }
 
 vec4 mc_transform_position(vec3 position) {
-    vec4 tmp = mc_projection_matrix() * (mc_model_view_matrix() * vec4(position + mc_chunk_offset(), 1.0));
+    vec4 tmp;
+    if (_push_constant.mvp_enabled != 0) {
+        tmp = _push_constant.model_view_matrix * vec4(position + mc_chunk_offset(), 1.0);
+    } else {
+        tmp = mc_projection_matrix() * (mc_model_view_matrix() * vec4(position + mc_chunk_offset(), 1.0));
+    }
     tmp.z = (tmp.z + tmp.w) / 2.0;
     tmp.y *= -1.0;
     return tmp;
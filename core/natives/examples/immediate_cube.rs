@@ -8,6 +8,7 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use b4d_core::prelude::*;
 use b4d_core::renderer::emulator::debug_pipeline::DebugPipelineMode;
 use b4d_core::renderer::emulator::mc_shaders::{McUniform, McUniformData, VertexFormat, VertexFormatEntry};
+use b4d_core::b4d::FrameStartResult;
 use b4d_core::renderer::emulator::MeshData;
 
 use b4d_core::window::WinitWindow;
@@ -18,7 +19,7 @@ fn main() {
     let event_loop = EventLoop::new();
     let window = Box::new(WinitWindow::new("ImmediateCube", 800.0, 600.0, &event_loop));
 
-    let b4d = b4d_core::b4d::Blaze4D::new(window, true);
+    let b4d = b4d_core::b4d::Blaze4D::new(window, true).unwrap();
     b4d.set_debug_mode(Some(DebugPipelineMode::Textured0));
     let vertex_format = Vertex::make_b4d_vertex_format();
     let mut shader = b4d.create_shader(&vertex_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
@@ -32,7 +33,7 @@ fn main() {
         primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
     };
 
-    let mut mesh = b4d.create_global_mesh(&data);
+    let mut mesh = b4d.create_global_mesh(&data).unwrap();
 
     let mut draw_times = Vec::with_capacity(1000);
     let mut last_update = std::time::Instant::now();
@@ -61,9 +62,9 @@ fn main() {
             Event::MainEventsCleared => {
                 let now = std::time::Instant::now();
 
-                mesh = b4d.create_global_mesh(&data);
+                mesh = b4d.create_global_mesh(&data).unwrap();
 
-                if let Some(mut recorder) = b4d.try_start_frame(current_size) {
+                if let FrameStartResult::Started(mut recorder) = b4d.try_start_frame(current_size) {
 
                     recorder.update_uniform(&McUniformData::ProjectionMatrix(make_projection_matrix(current_size, 90f32)), shader);
 
@@ -183,7 +184,8 @@ impl Vertex {
             color: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32, format: vk::Format::R32G32B32A32_SFLOAT }),
             uv0: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32 + std::mem::size_of::<Vec4f32>() as u32, format: vk::Format::R32G32_SFLOAT }),
             uv1: None,
-            uv2: None
+            uv2: None,
+            instance_divisor: None
         }
     }
 }
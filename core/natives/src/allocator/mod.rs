@@ -2,6 +2,7 @@ use std::ffi::CString;
 use std::fmt;
 use std::ptr::NonNull;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use ash::vk;
 
@@ -12,7 +13,7 @@ mod vma;
 pub struct Allocator {
     vma_allocator: vma::Allocator,
 
-    debug: bool,
+    debug: AtomicBool,
     functions: Arc<DeviceFunctions>,
 }
 
@@ -20,13 +21,29 @@ impl Allocator {
     pub fn new(functions: Arc<DeviceFunctions>) -> Result<Self, vk::Result> {
         let vma_allocator = vma::Allocator::new(&functions, vma::AllocatorCreateFlags::empty())?;
 
+        // Naming allocations requires a string allocation so we only do it if someone can see it
+        let debug = functions.instance.is_validation_enabled();
+
         Ok(Self {
             vma_allocator,
-            debug: true,
+            debug: AtomicBool::new(debug),
             functions
         })
     }
 
+    /// Enables or disables naming of allocations. If enabled the name passed to any allocation
+    /// function is attached to the allocation which costs a string allocation per call.
+    ///
+    /// Defaults to enabled if validation layers are enabled on the instance.
+    pub fn set_debug_naming(&self, enable: bool) {
+        self.debug.store(enable, Ordering::Relaxed);
+    }
+
+    /// Returns true if allocations are named.
+    pub fn is_debug_naming(&self) -> bool {
+        self.debug.load(Ordering::Relaxed)
+    }
+
     /// Allocates vulkan memory for some requirements.
     ///
     /// Returns the allocation and a [`AllocationBindingInfo`] containing information necessary to
@@ -40,7 +57,7 @@ impl Allocator {
         let mut allocation_info = vma::AllocationInfo::default();
         match self.vma_allocator.allocate_memory(requirements, &create_info, Some(&mut allocation_info)) {
             Ok(allocation) => {
-                if self.debug {
+                if self.is_debug_naming() {
                     self.set_allocation_name(allocation, name);
                 }
                 let binding_info = AllocationBindingInfo::new(&allocation_info);
@@ -77,6 +94,50 @@ impl Allocator {
         }
     }
 
+    /// Allocates vulkan memory for some requirements which may be bound to multiple aliasing
+    /// resources.
+    ///
+    /// The memory is always allocated gpu only. Returns the allocation and a
+    /// [`AllocationBindingInfo`] containing information necessary to bind and use the memory. If
+    /// allocation fails [`None`] is returned.
+    ///
+    /// # Safety
+    ///
+    /// `requirements` must be a valid [`vk::MemoryRequirements`] instance.
+    pub unsafe fn allocate_aliasable_memory(&self, requirements: &vk::MemoryRequirements, name: &fmt::Arguments) -> Option<(Allocation, AllocationBindingInfo)> {
+        let create_info = Self::make_default_info(HostAccess::None)
+            .flags(vma::AllocationCreateFlags::CAN_ALIAS);
+        let mut allocation_info = vma::AllocationInfo::default();
+        match self.vma_allocator.allocate_memory(requirements, &create_info, Some(&mut allocation_info)) {
+            Ok(allocation) => {
+                if self.is_debug_naming() {
+                    self.set_allocation_name(allocation, name);
+                }
+                let binding_info = AllocationBindingInfo::new(&allocation_info);
+                Some((Allocation::new(allocation), binding_info))
+            }
+            Err(err) => {
+                log::warn!("Failed to allocate aliasable vulkan memory for {:?}. {:?}", name, err);
+                None
+            }
+        }
+    }
+
+    /// Binds a image to previously allocated memory.
+    ///
+    /// Multiple images may be bound to the same allocation as long as the calling code ensures
+    /// that they are never used at the same time.
+    ///
+    /// # Safety
+    ///
+    /// `image` must be a valid [`vk::Image`] handle created on the same device that this allocator
+    /// uses and must not already be bound to memory.
+    /// `allocation` must have been previously allocated from this allocator, not yet freed and
+    /// must satisfy the memory requirements of `image`.
+    pub unsafe fn bind_image_memory(&self, image: vk::Image, allocation: Allocation) -> Result<(), vk::Result> {
+        self.vma_allocator.bind_image_memory(allocation.vma_allocation, image)
+    }
+
     /// Frees previously allocated memory.
     ///
     /// # Safety
@@ -96,6 +157,18 @@ impl Allocator {
         self.vma_allocator.free_memory_pages(mapped.as_ref())
     }
 
+    /// Returns the memory property flags of the memory type an allocation has been placed in.
+    ///
+    /// This is intended for debugging purposes. For example to check if a buffer which prefers
+    /// host access actually landed in `HOST_VISIBLE` memory.
+    ///
+    /// # Safety
+    ///
+    /// The allocation must have been previously allocated from this allocator and not yet freed.
+    pub unsafe fn get_memory_properties(&self, allocation: Allocation) -> vk::MemoryPropertyFlags {
+        self.vma_allocator.get_allocation_memory_properties(allocation.vma_allocation)
+    }
+
     /// Creates a gpu only buffer and binds memory to it.
     ///
     /// If creation, allocation or binding fails [`None`] is returned.
@@ -107,7 +180,7 @@ impl Allocator {
         let allocation_create_info = Self::make_default_info(HostAccess::None);
         match self.vma_allocator.create_buffer(create_info, &allocation_create_info, None) {
             Ok((buffer, allocation)) => {
-                if self.debug {
+                if self.is_debug_naming() {
                     self.set_allocation_name(allocation, name);
                 }
                 Some((buffer, Allocation::new(allocation)))
@@ -135,7 +208,7 @@ impl Allocator {
         let mut allocation_info = vma::AllocationInfo::default();
         match self.vma_allocator.create_buffer(create_info, &allocation_create_info, Some(&mut allocation_info)) {
             Ok((buffer, allocation)) => {
-                if self.debug {
+                if self.is_debug_naming() {
                     self.set_allocation_name(allocation, name);
                 }
                 Some((buffer, Allocation::new(allocation), NonNull::new(allocation_info.p_mapped_data as *mut u8)))
@@ -158,7 +231,7 @@ impl Allocator {
         let allocation_create_info = Self::make_default_info(HostAccess::None);
         match self.vma_allocator.create_image(create_info, &allocation_create_info, None) {
             Ok((image, allocation)) => {
-                if self.debug {
+                if self.is_debug_naming() {
                     self.set_allocation_name(allocation, name);
                 }
                 Some((image, Allocation::new(allocation)))
@@ -186,7 +259,7 @@ impl Allocator {
         let mut allocation_info = vma::AllocationInfo::default();
         match self.vma_allocator.create_image(create_info, &allocation_create_info, Some(&mut allocation_info)) {
             Ok((image, allocation)) => {
-                if self.debug {
+                if self.is_debug_naming() {
                     self.set_allocation_name(allocation, name);
                 }
                 Some((image, Allocation::new(allocation), NonNull::new(allocation_info.p_mapped_data as *mut u8)))
@@ -220,6 +293,56 @@ impl Allocator {
         self.vma_allocator.destroy_image(image, allocation.vma_allocation)
     }
 
+    /// Moves allocations to reduce fragmentation of device memory.
+    ///
+    /// Vma proposes moves in multiple passes. `move_allocation` is called for every proposed move
+    /// and must return true if the resource has been recreated and bound to `dst`, and all uses of
+    /// the old resource have been replaced. Returning false keeps the allocation in place. Once a
+    /// pass has ended the memory of moved allocations is swapped so that `src` refers to the new
+    /// location and the old memory is freed.
+    ///
+    /// # Safety
+    ///
+    /// Only allocations which are not used by any pending gpu work may be moved. If true is
+    /// returned the content of the resource must have been copied to the new resource and the copy
+    /// must have completed.
+    pub unsafe fn defragment<F: FnMut(Allocation, Allocation) -> bool>(&self, mut move_allocation: F) -> Result<DefragStats, vk::Result> {
+        let info = vma::DefragmentationInfo {
+            flags: vma::DefragmentationFlags::ALGORITHM_BALANCED,
+            ..Default::default()
+        };
+
+        let stats = self.vma_allocator.defragment(&info, |moves| {
+            for m in moves {
+                if move_allocation(Allocation::new(m.src_allocation), Allocation::new(m.dst_tmp_allocation)) {
+                    m.operation = vma::DefragmentationMoveOperation::COPY;
+                } else {
+                    m.operation = vma::DefragmentationMoveOperation::IGNORE;
+                }
+            }
+        })?;
+
+        Ok(DefragStats {
+            bytes_moved: stats.bytes_moved,
+            bytes_freed: stats.bytes_freed,
+            allocations_moved: stats.allocations_moved,
+            device_memory_blocks_freed: stats.device_memory_blocks_freed
+        })
+    }
+
+    /// Returns statistics about the fragmentation of all memory allocated by this allocator. This
+    /// function is slow and intended for debugging or to decide if [`Allocator::defragment`]
+    /// should be called.
+    pub fn get_fragmentation_stats(&self) -> FragmentationStats {
+        let stats = self.vma_allocator.calculate_total_statistics();
+        FragmentationStats {
+            block_bytes: stats.statistics.block_bytes,
+            allocation_bytes: stats.statistics.allocation_bytes,
+            unused_range_count: stats.unused_range_count,
+            largest_unused_range: if stats.unused_range_count == 0 { 0 } else { stats.unused_range_size_max }
+        }
+    }
+
     unsafe fn set_allocation_name(&self, allocation: vma::Allocation, name: &fmt::Arguments) {
         if let Some(str) = name.as_str() {
             self.vma_allocator.set_allocation_name(allocation, CString::new(str).unwrap().as_c_str())
@@ -256,9 +379,36 @@ impl Allocation {
     }
 }
 
+/// The result of [`Allocator::defragment`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct DefragStats {
+    pub bytes_moved: vk::DeviceSize,
+    pub bytes_freed: vk::DeviceSize,
+    pub allocations_moved: u32,
+    pub device_memory_blocks_freed: u32,
+}
+
+/// See [`Allocator::get_fragmentation_stats`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FragmentationStats {
+    /// The total size of all allocated device memory blocks.
+    pub block_bytes: vk::DeviceSize,
+
+    /// The total size of all allocations inside the memory blocks.
+    pub allocation_bytes: vk::DeviceSize,
+
+    /// The number of free ranges between allocations.
+    pub unused_range_count: u32,
+
+    /// The size of the largest free range. Allocations larger than this require a new memory
+    /// block.
+    pub largest_unused_range: vk::DeviceSize,
+}
+
 /// Information needed to bind and access vulkan memory.
 #[derive(Copy, Clone)]
 pub struct AllocationBindingInfo {
+    memory_type: u32,
     device_memory: vk::DeviceMemory,
     offset: vk::DeviceSize,
     size: vk::DeviceSize,
@@ -268,12 +418,21 @@ pub struct AllocationBindingInfo {
 impl AllocationBindingInfo {
     fn new(info: &vma::AllocationInfo) -> Self {
         Self {
+            memory_type: info.memory_type,
             device_memory: info.device_memory,
             offset: info.offset,
             size: info.size,
             mapped_data: NonNull::new(info.p_mapped_data as *mut u8)
         }
     }
+
+    pub fn get_memory_type_index(&self) -> u32 {
+        self.memory_type
+    }
+
+    pub fn get_size(&self) -> vk::DeviceSize {
+        self.size
+    }
 }
 
 /// Describes how the host will access some vulkan memory.
@@ -307,4 +466,97 @@ impl HostAccess {
             HostAccess::SequentialWriteOptional => vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE | vma::AllocationCreateFlags::HOST_ACCESS_ALLOW_TRANSFER_INSTEAD | vma::AllocationCreateFlags::CREATE_MAPPED,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_memory_properties() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let allocator = device.get_allocator();
+        let info = vk::BufferCreateInfo::builder()
+            .size(1024)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        unsafe {
+            let (buffer, allocation) = allocator.create_gpu_buffer(&info, &format_args!("GpuBuffer")).unwrap();
+            assert!(allocator.get_memory_properties(allocation).contains(vk::MemoryPropertyFlags::DEVICE_LOCAL));
+            allocator.destroy_buffer(buffer, allocation);
+
+            let (buffer, allocation, mapped) = allocator.create_buffer(&info, HostAccess::SequentialWrite, &format_args!("MappedBuffer")).unwrap();
+            assert!(mapped.is_some());
+            assert!(allocator.get_memory_properties(allocation).contains(vk::MemoryPropertyFlags::HOST_VISIBLE));
+            allocator.destroy_buffer(buffer, allocation);
+        }
+    }
+
+    #[test]
+    fn allocation_names_only_set_with_debug_naming() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let allocator = device.get_allocator();
+        let info = vk::BufferCreateInfo::builder()
+            .size(256)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let get_name = |allocation: Allocation| unsafe {
+            let mut allocation_info = vma::AllocationInfo::default();
+            allocator.vma_allocator.get_allocation_info(allocation.vma_allocation, &mut allocation_info);
+            if allocation_info.p_name.is_null() {
+                None
+            } else {
+                Some(std::ffi::CStr::from_ptr(allocation_info.p_name).to_owned())
+            }
+        };
+
+        unsafe {
+            allocator.set_debug_naming(false);
+            let (buffer, allocation) = allocator.create_gpu_buffer(&info, &format_args!("Unnamed")).unwrap();
+            assert_eq!(get_name(allocation), None);
+            allocator.destroy_buffer(buffer, allocation);
+
+            allocator.set_debug_naming(true);
+            let (buffer, allocation) = allocator.create_gpu_buffer(&info, &format_args!("Named")).unwrap();
+            assert_eq!(get_name(allocation), Some(CString::new("Named").unwrap()));
+            allocator.destroy_buffer(buffer, allocation);
+        }
+    }
+
+    #[test]
+    fn defragment_grows_largest_free_range() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let allocator = device.get_allocator();
+        let requirements = vk::MemoryRequirements {
+            size: 64 * 1024,
+            alignment: 256,
+            memory_type_bits: u32::MAX
+        };
+
+        unsafe {
+            let allocations: Vec<_> = (0..64).map(|_| {
+                allocator.allocate_memory(&requirements, HostAccess::None, &format_args!("Fragment")).unwrap().0
+            }).collect();
+
+            // Freeing every other allocation leaves holes which are too small to be merged
+            let (freed, kept): (Vec<_>, Vec<_>) = allocations.into_iter().enumerate().partition(|(index, _)| index % 2 == 0);
+            for (_, allocation) in freed {
+                allocator.free_memory(allocation);
+            }
+            let before = allocator.get_fragmentation_stats();
+
+            // The allocations are not bound to any resource so nothing needs to be recreated
+            let stats = allocator.defragment(|_, _| true).unwrap();
+            let after = allocator.get_fragmentation_stats();
+            assert!(stats.allocations_moved > 0);
+            assert!(after.unused_range_count < before.unused_range_count);
+            assert!(after.largest_unused_range > before.largest_unused_range);
+
+            for (_, allocation) in kept {
+                allocator.free_memory(allocation);
+            }
+        }
+    }
+}
@@ -163,6 +163,101 @@ impl<'a> AllocationCreateInfoBuilder<'a> {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct DefragmentationFlags(u32);
+
+impl DefragmentationFlags {
+    pub const ALGORITHM_FAST: DefragmentationFlags = DefragmentationFlags(0x00000001);
+    pub const ALGORITHM_BALANCED: DefragmentationFlags = DefragmentationFlags(0x00000002);
+    pub const ALGORITHM_FULL: DefragmentationFlags = DefragmentationFlags(0x00000004);
+    pub const ALGORITHM_EXTENSIVE: DefragmentationFlags = DefragmentationFlags(0x00000008);
+}
+ash::vk_bitflags_wrapped!(DefragmentationFlags, u32);
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[repr(transparent)]
+pub struct DefragmentationMoveOperation(u32);
+
+impl DefragmentationMoveOperation {
+    /// The resource has been recreated at the destination and its content copied.
+    pub const COPY: DefragmentationMoveOperation = DefragmentationMoveOperation(0);
+    /// The allocation must not be moved.
+    pub const IGNORE: DefragmentationMoveOperation = DefragmentationMoveOperation(1);
+    /// The resource has been destroyed and the allocation should be freed.
+    pub const DESTROY: DefragmentationMoveOperation = DefragmentationMoveOperation(2);
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct DefragmentationInfo {
+    pub flags: DefragmentationFlags,
+    pub pool: *const u8,
+    pub max_bytes_per_pass: vk::DeviceSize,
+    pub max_allocations_per_pass: u32,
+}
+impl Default for DefragmentationInfo {
+    fn default() -> Self {
+        Self {
+            flags: DefragmentationFlags::empty(),
+            pool: std::ptr::null(),
+            max_bytes_per_pass: 0,
+            max_allocations_per_pass: 0
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct DefragmentationMove {
+    pub operation: DefragmentationMoveOperation,
+    pub src_allocation: Allocation,
+    pub dst_tmp_allocation: Allocation,
+}
+
+#[repr(C)]
+struct DefragmentationPassMoveInfo {
+    move_count: u32,
+    p_moves: *mut DefragmentationMove,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DefragmentationStats {
+    pub bytes_moved: vk::DeviceSize,
+    pub bytes_freed: vk::DeviceSize,
+    pub allocations_moved: u32,
+    pub device_memory_blocks_freed: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Statistics {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub block_bytes: vk::DeviceSize,
+    pub allocation_bytes: vk::DeviceSize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DetailedStatistics {
+    pub statistics: Statistics,
+    pub unused_range_count: u32,
+    pub allocation_size_min: vk::DeviceSize,
+    pub allocation_size_max: vk::DeviceSize,
+    pub unused_range_size_min: vk::DeviceSize,
+    pub unused_range_size_max: vk::DeviceSize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TotalStatistics {
+    memory_type: [DetailedStatistics; vk::MAX_MEMORY_TYPES],
+    memory_heap: [DetailedStatistics; vk::MAX_MEMORY_HEAPS],
+    total: DetailedStatistics,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct AllocationInfo {
@@ -283,7 +378,7 @@ impl Allocator {
             physical_device: device.physical_device,
             device: device.vk.handle(),
             preferred_large_heap_block_size: 0,
-            p_allocation_callbacks: std::ptr::null(),
+            p_allocation_callbacks: device.get_allocation_callbacks().map_or(std::ptr::null(), |c| c as *const vk::AllocationCallbacks),
             p_device_memory_callbacks: std::ptr::null(),
             p_heap_size_limit: std::ptr::null(),
             p_vulkan_functions: &functions,
@@ -344,6 +439,12 @@ impl Allocator {
         sys::vmaGetAllocationInfo(self.handle, allocation, info)
     }
 
+    pub unsafe fn get_allocation_memory_properties(&self, allocation: Allocation) -> vk::MemoryPropertyFlags {
+        let mut flags = vk::MemoryPropertyFlags::empty();
+        sys::vmaGetAllocationMemoryProperties(self.handle, allocation, &mut flags);
+        flags
+    }
+
     pub unsafe fn set_allocation_name(&self, allocation: Allocation, name: &CStr) {
         sys::vmaSetAllocationName(self.handle, allocation, name.as_ptr())
     }
@@ -379,6 +480,87 @@ impl Allocator {
     pub unsafe fn destroy_image(&self, image: vk::Image, allocation: Allocation) {
         sys::vmaDestroyImage(self.handle, image, allocation)
     }
+
+    pub unsafe fn bind_image_memory(&self, allocation: Allocation, image: vk::Image) -> Result<(), vk::Result> {
+        let result = sys::vmaBindImageMemory(self.handle, allocation, image);
+        if result == vk::Result::SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Runs a full defragmentation. `handle_pass` is called once for every pass with the moves
+    /// proposed by vma and must set the operation of every move before returning.
+    pub unsafe fn defragment<F: FnMut(&mut [DefragmentationMove])>(&self, info: &DefragmentationInfo, handle_pass: F) -> Result<DefragmentationStats, vk::Result> {
+        let mut context = DefragmentationContext(std::ptr::null());
+        let result = sys::vmaBeginDefragmentation(self.handle, info, &mut context);
+        if result != vk::Result::SUCCESS {
+            return Err(result);
+        }
+
+        let result = run_defragmentation_passes(
+            |pass| sys::vmaBeginDefragmentationPass(self.handle, context, pass),
+            |pass| sys::vmaEndDefragmentationPass(self.handle, context, pass),
+            handle_pass
+        );
+
+        // The context must always be ended even if a pass failed
+        let mut stats = DefragmentationStats::default();
+        sys::vmaEndDefragmentation(self.handle, context, &mut stats);
+
+        result.map(|_| stats)
+    }
+
+    /// Returns the statistics of all memory allocated by this allocator. This function is slow and
+    /// intended for debugging.
+    pub fn calculate_total_statistics(&self) -> DetailedStatistics {
+        let mut stats = TotalStatistics {
+            memory_type: [DetailedStatistics::default(); vk::MAX_MEMORY_TYPES],
+            memory_heap: [DetailedStatistics::default(); vk::MAX_MEMORY_HEAPS],
+            total: DetailedStatistics::default()
+        };
+        unsafe {
+            sys::vmaCalculateStatistics(self.handle, &mut stats)
+        };
+        stats.total
+    }
+}
+
+/// Runs defragmentation passes until vma reports that no more moves are possible.
+///
+/// A pass begun with `VK_INCOMPLETE` contains moves which are passed to `handle_pass`. Ending a
+/// pass with `VK_INCOMPLETE` requests another pass.
+fn run_defragmentation_passes<B, E, H>(mut begin_pass: B, mut end_pass: E, mut handle_pass: H) -> Result<(), vk::Result>
+    where B: FnMut(&mut DefragmentationPassMoveInfo) -> vk::Result,
+          E: FnMut(&mut DefragmentationPassMoveInfo) -> vk::Result,
+          H: FnMut(&mut [DefragmentationMove]) {
+
+    loop {
+        let mut pass = DefragmentationPassMoveInfo {
+            move_count: 0,
+            p_moves: std::ptr::null_mut()
+        };
+
+        match begin_pass(&mut pass) {
+            vk::Result::SUCCESS => return Ok(()),
+            vk::Result::INCOMPLETE => {}
+            err => return Err(err),
+        }
+
+        if pass.move_count != 0 {
+            let moves = unsafe {
+                std::slice::from_raw_parts_mut(pass.p_moves, pass.move_count as usize)
+            };
+            handle_pass(moves);
+        }
+
+        match end_pass(&mut pass) {
+            vk::Result::SUCCESS => return Ok(()),
+            vk::Result::INCOMPLETE => {}
+            err => return Err(err),
+        }
+    }
 }
 
 unsafe impl Send for Allocator {}
@@ -392,6 +574,10 @@ impl Drop for Allocator {
     }
 }
 
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct DefragmentationContext(*const u8);
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 #[repr(transparent)]
 pub struct Allocation(*const u8);
@@ -463,6 +649,12 @@ mod sys {
             p_allocation_info: *mut AllocationInfo,
         );
 
+        pub(super) fn vmaGetAllocationMemoryProperties(
+            allocator: AllocatorHandle,
+            allocation: Allocation,
+            p_flags: *mut vk::MemoryPropertyFlags,
+        );
+
         pub(super) fn vmaSetAllocationName(
             allocator: AllocatorHandle,
             allocation: Allocation,
@@ -498,5 +690,87 @@ mod sys {
             image: vk::Image,
             allocation: Allocation,
         );
+
+        pub(super) fn vmaBindImageMemory(
+            allocator: AllocatorHandle,
+            allocation: Allocation,
+            image: vk::Image,
+        ) -> vk::Result;
+
+        pub(super) fn vmaBeginDefragmentation(
+            allocator: AllocatorHandle,
+            p_info: *const DefragmentationInfo,
+            p_context: *mut DefragmentationContext,
+        ) -> vk::Result;
+
+        pub(super) fn vmaEndDefragmentation(
+            allocator: AllocatorHandle,
+            context: DefragmentationContext,
+            p_stats: *mut DefragmentationStats,
+        );
+
+        pub(super) fn vmaBeginDefragmentationPass(
+            allocator: AllocatorHandle,
+            context: DefragmentationContext,
+            p_pass_info: *mut DefragmentationPassMoveInfo,
+        ) -> vk::Result;
+
+        pub(super) fn vmaEndDefragmentationPass(
+            allocator: AllocatorHandle,
+            context: DefragmentationContext,
+            p_pass_info: *mut DefragmentationPassMoveInfo,
+        ) -> vk::Result;
+
+        pub(super) fn vmaCalculateStatistics(
+            allocator: AllocatorHandle,
+            p_stats: *mut TotalStatistics,
+        );
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defragmentation_passes_until_success() {
+        // Emulates vma proposing 2 moves in the first pass and 1 in the second
+        let mut proposed = vec![
+            vec![DefragmentationMove { operation: DefragmentationMoveOperation::COPY, src_allocation: Allocation::null(), dst_tmp_allocation: Allocation::null() }; 2],
+            vec![DefragmentationMove { operation: DefragmentationMoveOperation::COPY, src_allocation: Allocation::null(), dst_tmp_allocation: Allocation::null() }; 1],
+        ];
+        let current = std::cell::Cell::new(0usize);
+        let mut ended = Vec::new();
+        let mut handled = Vec::new();
+
+        let result = run_defragmentation_passes(
+            |pass| {
+                let index = current.get();
+                if index == proposed.len() {
+                    return vk::Result::SUCCESS;
+                }
+                pass.move_count = proposed[index].len() as u32;
+                pass.p_moves = proposed[index].as_mut_ptr();
+                vk::Result::INCOMPLETE
+            },
+            |pass| {
+                let moves = unsafe { std::slice::from_raw_parts(pass.p_moves, pass.move_count as usize) };
+                ended.push(moves.iter().map(|m| m.operation).collect::<Vec<_>>());
+                current.set(current.get() + 1);
+                vk::Result::INCOMPLETE
+            },
+            |moves| {
+                handled.push(moves.len());
+                moves[0].operation = DefragmentationMoveOperation::IGNORE;
+            }
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(handled, vec![2, 1]);
+        assert_eq!(ended[0], vec![DefragmentationMoveOperation::IGNORE, DefragmentationMoveOperation::COPY]);
+        assert_eq!(ended[1], vec![DefragmentationMoveOperation::IGNORE]);
+
+        let result = run_defragmentation_passes(|_| vk::Result::ERROR_OUT_OF_DEVICE_MEMORY, |_| vk::Result::SUCCESS, |_| {});
+        assert_eq!(result, Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+    }
+}
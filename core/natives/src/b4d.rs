@@ -6,14 +6,14 @@ use ash::vk;
 use crate::BUILD_INFO;
 
 use crate::instance::debug_messenger::RustLogDebugMessenger;
-use crate::device::init::{create_device, DeviceCreateConfig};
-use crate::device::surface::{DeviceSurface, SurfaceSwapchain, SwapchainConfig};
-use crate::instance::init::{create_instance, InstanceCreateConfig};
-use crate::vk::objects::surface::SurfaceProvider;
+use crate::device::init::{create_device, DeviceCreateConfig, DeviceCreateError};
+use crate::device::surface::{DeviceSurface, is_vsync_present_mode, PreTransformMode, SurfaceSwapchain, SwapchainConfig, SwapchainCreateError};
+use crate::instance::init::{create_instance, InstanceCreateConfig, InstanceCreateError};
+use crate::vk::objects::surface::{SurfaceInitError, SurfaceProvider};
 
 use crate::prelude::*;
-use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, GlobalMesh, MeshData};
-use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, GlobalMesh, GlobalObjectCreateError, MeshData};
+use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineConfig, DebugPipelineMode, DepthClipRange, DepthConvention, ObjectCreateError};
 use crate::renderer::emulator::mc_shaders::{McUniform, ShaderId, VertexFormat};
 use crate::renderer::emulator::PassRecorder;
 use crate::renderer::emulator::pipeline::{EmulatorPipeline, SwapchainOutput};
@@ -31,8 +31,12 @@ impl Blaze4D {
     /// Creates a new Blaze4D instance and starts all engine modules.
     ///
     /// The supported vertex formats for the [`EmulatorRenderer`] must be provided here.
-    pub fn new(mut main_window: Box<dyn SurfaceProvider>, enable_validation: bool) -> Self {
+    ///
+    /// If the instance, window surface or device cannot be created a [`Blaze4DCreateError`]
+    /// describing the failure is returned.
+    pub fn new(mut main_window: Box<dyn SurfaceProvider>, enable_validation: bool) -> Result<Self, Blaze4DCreateError> {
         log::info!("Creating Blaze4D instance {:?}", BUILD_INFO);
+        log::info!("Detected window system integration platform {:?}", main_window.get_wsi_platform());
 
         let mut instance_config = InstanceCreateConfig::new(
             CString::new("Minecraft").unwrap(),
@@ -46,32 +50,42 @@ impl Blaze4D {
             instance_config.add_required_extension(&ext);
         }
 
-        let instance = create_instance(instance_config).unwrap();
+        let instance = create_instance(instance_config).map_err(|err| {
+            log::error!("Failed to create instance in Blaze4D::new(): {:?}", err);
+            Blaze4DCreateError::Instance(err)
+        })?;
 
-        let window_surface = main_window.init(instance.get_entry(), instance.vk()).unwrap();
+        let window_surface = main_window.init(instance.get_entry(), instance.vk()).map_err(|err| {
+            log::error!("Failed to create window surface in Blaze4D::new(): {:?}", err);
+            Blaze4DCreateError::Surface(err)
+        })?;
 
         let mut device_config = DeviceCreateConfig::new();
         device_config.require_swapchain();
         device_config.add_surface(window_surface);
         device_config.disable_robustness();
 
-        let device = create_device(device_config, instance.clone()).unwrap_or_else(|err| {
+        let device = create_device(device_config, instance.clone()).map_err(|err| {
             log::error!("Failed to create device in Blaze4D::new(): {:?}", err);
-            panic!()
-        });
+            Blaze4DCreateError::Device(err)
+        })?;
         let main_surface = DeviceSurface::new(device.get_functions().clone(), main_window);
 
         let emulator = Arc::new(EmulatorRenderer::new(device.clone()));
 
         let render_config = Mutex::new(RenderConfig::new(device.clone(), emulator.clone(), main_surface));
 
-        Self {
+        Ok(Self {
             instance,
             device,
             emulator,
 
             render_config,
-        }
+        })
+    }
+
+    pub fn get_device(&self) -> &Arc<DeviceContext> {
+        &self.device
     }
 
     /// Configures the current debug mode. Any frame started after calling this function will use
@@ -82,7 +96,57 @@ impl Blaze4D {
         self.render_config.lock().unwrap().set_debug_mode(mode);
     }
 
-    pub fn create_global_mesh(&self, data: &MeshData) -> Arc<GlobalMesh> {
+    /// Enables or disables reverse-Z depth. If enabled near geometry is stored with larger depth
+    /// values than far geometry which improves depth precision for large render distances. All
+    /// projection matrices are adjusted to reverse the depth and the depth clear value and compare
+    /// op are changed together. Any frame started after calling this function uses the new
+    /// setting.
+    pub fn set_reverse_z(&self, reverse_z: bool) {
+        self.render_config.lock().unwrap().set_depth_convention(DepthConvention::from_reverse_z(reverse_z));
+    }
+
+    /// Sets the range of normalized device depth produced by shaders. Minecraft shaders use
+    /// [`DepthClipRange::NegativeOneToOne`].
+    ///
+    /// If the device supports VK_EXT_depth_clip_control the range is configured in the pipelines.
+    /// Otherwise projection matrices are adjusted. [`Blaze4D::uses_depth_clip_control`] reports
+    /// which path is used.
+    pub fn set_depth_clip_range(&self, range: DepthClipRange) {
+        self.render_config.lock().unwrap().set_depth_clip_range(range);
+    }
+
+    pub fn get_depth_clip_range(&self) -> DepthClipRange {
+        self.render_config.lock().unwrap().depth_clip_range
+    }
+
+    /// Returns true if a [`DepthClipRange::NegativeOneToOne`] range is configured using
+    /// VK_EXT_depth_clip_control instead of adjusting projection matrices. Always false while
+    /// the [`DepthClipRange::ZeroToOne`] range is set.
+    pub fn uses_depth_clip_control(&self) -> bool {
+        uses_depth_clip_control(self.device.supports_depth_clip_control(), self.get_depth_clip_range())
+    }
+
+    /// Configures the present mode used for the main window. If the surface does not support the
+    /// mode a fallback is selected. The swapchain is recreated when the next frame is started.
+    ///
+    /// If [`None`] is passed the best supported mode is selected automatically.
+    pub fn set_present_mode(&self, present_mode: Option<vk::PresentModeKHR>) {
+        self.render_config.lock().unwrap().set_preferred_present_mode(present_mode);
+    }
+
+    /// Returns the present mode of the current swapchain after any fallback. Returns [`None`] if
+    /// no swapchain exists, for example before the first frame or while the window is minimized.
+    pub fn current_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.render_config.lock().unwrap().get_current_present_mode()
+    }
+
+    /// Returns true if the current swapchain limits the frame rate to the refresh rate of the
+    /// display. Returns false if no swapchain exists.
+    pub fn is_vsync_enabled(&self) -> bool {
+        self.current_present_mode().map_or(false, is_vsync_present_mode)
+    }
+
+    pub fn create_global_mesh(&self, data: &MeshData) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
         self.emulator.create_global_mesh(data)
     }
 
@@ -98,13 +162,77 @@ impl Blaze4D {
         self.emulator.drop_shader(id);
     }
 
-    pub fn try_start_frame(&self, window_size: Vec2u32) -> Option<PassRecorder> {
-        if let Some(recorder) = self.render_config.lock().unwrap().try_start_frame(&self.emulator, window_size) {
-            Some(recorder)
-        } else {
-            None
+    /// Attempts to start a new frame without blocking.
+    ///
+    /// If no frame could be started the returned [`FrameStartResult`] reports why. In all cases
+    /// the caller may simply call this function again for the next frame.
+    pub fn try_start_frame(&self, window_size: Vec2u32) -> FrameStartResult {
+        self.render_config.lock().unwrap().try_start_frame(&self.emulator, window_size)
+    }
+}
+
+/// Returns true if VK_EXT_depth_clip_control is used to configure the depth clip range.
+fn uses_depth_clip_control(supported: bool, range: DepthClipRange) -> bool {
+    supported && range == DepthClipRange::NegativeOneToOne
+}
+
+/// The result of [`Blaze4D::try_start_frame`].
+pub enum FrameStartResult {
+    /// A frame was started. The id of the frame can be queried using [`PassRecorder::get_id`].
+    Started(PassRecorder),
+
+    /// The swapchain is out of date or could not be recreated. A new swapchain will be created
+    /// for the next frame.
+    SwapchainOutOfDate,
+
+    /// The surface has been lost.
+    SurfaceLost,
+
+    /// No swapchain image is available yet, the window is minimized or swapchain recreation is
+    /// backing off after previous failures.
+    WouldBlock,
+
+    /// The pipeline used to render the frame could not be created. Creation is attempted again
+    /// for the next frame.
+    PipelineCreateFailed(ObjectCreateError),
+
+    /// Creating the swapchain failed too many consecutive times. The error of the last attempt is
+    /// provided. Creation is still attempted again for later frames after backing off.
+    SwapchainCreateFailed(SwapchainCreateError),
+}
+
+impl FrameStartResult {
+    /// Returns the result reported for a failed image acquire or swapchain creation.
+    fn from_vk_error(err: vk::Result) -> Self {
+        match err {
+            vk::Result::ERROR_SURFACE_LOST_KHR => Self::SurfaceLost,
+            vk::Result::TIMEOUT | vk::Result::NOT_READY => Self::WouldBlock,
+            _ => Self::SwapchainOutOfDate,
         }
     }
+
+    /// Returns the result reported for a failed swapchain creation after `failed_rebuilds`
+    /// consecutive failures.
+    fn from_swapchain_error(err: SwapchainCreateError, failed_rebuilds: u32) -> Self {
+        if failed_rebuilds >= RenderConfig::MAX_SWAPCHAIN_REBUILD_ATTEMPTS {
+            return Self::SwapchainCreateFailed(err);
+        }
+        match err {
+            SwapchainCreateError::Vulkan(err) => Self::from_vk_error(err),
+            _ => Self::SwapchainOutOfDate,
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        matches!(self, Self::Started(_))
+    }
+}
+
+#[derive(Debug)]
+pub enum Blaze4DCreateError {
+    Instance(InstanceCreateError),
+    Surface(SurfaceInitError),
+    Device(DeviceCreateError),
 }
 
 struct RenderConfig {
@@ -113,11 +241,17 @@ struct RenderConfig {
     main_surface: Arc<DeviceSurface>,
 
     last_rebuild: Instant,
+    failed_rebuilds: u32,
+    recreate_scheduled: bool,
+    requested_size: Vec2u32,
     current_swapchain: Option<Arc<SurfaceSwapchain>>,
     current_pipeline: Option<(Arc<dyn EmulatorPipeline>, Arc<SwapchainOutput>)>,
 
     debug_mode: Option<DebugPipelineMode>,
     debug_pipeline: Option<(Arc<dyn EmulatorPipeline>, Arc<SwapchainOutput>)>,
+    depth_convention: DepthConvention,
+    depth_clip_range: DepthClipRange,
+    preferred_present_mode: Option<vk::PresentModeKHR>,
 }
 
 impl RenderConfig {
@@ -128,11 +262,43 @@ impl RenderConfig {
             main_surface,
 
             last_rebuild: Instant::now() - Duration::from_secs(100),
+            failed_rebuilds: 0,
+            recreate_scheduled: false,
+            requested_size: Vec2u32::new(0, 0),
             current_swapchain: None,
             current_pipeline: None,
 
             debug_mode: Some(DebugPipelineMode::Color),
-            debug_pipeline: None
+            debug_pipeline: None,
+            depth_convention: DepthConvention::Standard,
+            depth_clip_range: DepthClipRange::ZeroToOne,
+            preferred_present_mode: None,
+        }
+    }
+
+    fn set_preferred_present_mode(&mut self, present_mode: Option<vk::PresentModeKHR>) {
+        if self.preferred_present_mode != present_mode {
+            self.preferred_present_mode = present_mode;
+            self.recreate_scheduled = true;
+        }
+    }
+
+    fn get_current_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.current_swapchain.as_ref().map(|swapchain| swapchain.get_present_mode())
+    }
+
+    fn set_depth_convention(&mut self, depth_convention: DepthConvention) {
+        if self.depth_convention != depth_convention {
+            // The compare op is baked into the pipelines so they must be rebuilt
+            self.depth_convention = depth_convention;
+            self.debug_pipeline = None;
+        }
+    }
+
+    fn set_depth_clip_range(&mut self, depth_clip_range: DepthClipRange) {
+        if self.depth_clip_range != depth_clip_range {
+            self.depth_clip_range = depth_clip_range;
+            self.debug_pipeline = None;
         }
     }
 
@@ -143,67 +309,116 @@ impl RenderConfig {
         }
     }
 
-    fn try_start_frame(&mut self, renderer: &EmulatorRenderer, size: Vec2u32) -> Option<PassRecorder> {
-        let mut force_rebuild = false;
+    fn try_start_frame(&mut self, renderer: &EmulatorRenderer, size: Vec2u32) -> FrameStartResult {
+        let recreate_scheduled = std::mem::replace(&mut self.recreate_scheduled, false);
 
-        // This if block only exists because of wayland
-        if let Some(current) = self.current_swapchain.as_ref() {
-            if current.get_image_size() != size {
-                force_rebuild = true;
-            }
-        }
+        // This check only exists because of wayland. The swapchain extent may differ from the
+        // requested size so we compare against the size used to create it.
+        let size_changed = self.current_swapchain.is_some() && self.requested_size != size;
+
+        let outputs = self.current_pipeline.iter().chain(self.debug_pipeline.iter())
+            .map(|(_, output)| output.is_recreate_required());
+        let force_rebuild = Self::is_rebuild_required(recreate_scheduled, size_changed, outputs);
 
         if self.current_swapchain.is_none() || force_rebuild {
-            if !self.try_create_swapchain(size) {
-                return None;
+            if size[0] == 0 || size[1] == 0 {
+                // The window is most likely minimized. Skip rendering until it has a size again
+                self.current_pipeline = None;
+                self.debug_pipeline = None;
+                self.current_swapchain = None;
+                return FrameStartResult::WouldBlock;
+            }
+            if let Err(result) = self.try_create_swapchain(size) {
+                return result;
             }
             self.current_pipeline = None;
             self.debug_pipeline = None;
         }
 
-        let (pipeline, output) = self.prepare_pipeline(size);
+        renderer.reset_transient_images();
 
-        let (output, suboptimal) = match output.next_image() {
-            None => {
-                self.current_pipeline = None;
-                self.debug_pipeline = None;
-                self.current_swapchain = None;
-                return None;
+        // The surface may have clamped the requested size
+        let extent = self.current_swapchain.as_ref().unwrap().get_image_size();
+        let (pipeline, output) = match self.prepare_pipeline(extent) {
+            Ok(result) => result,
+            Err(err) => {
+                log::error!("Failed to create pipeline for size {:?}: {:?}", extent, err);
+                return FrameStartResult::PipelineCreateFailed(err);
+            }
+        };
+
+        let (output, suboptimal) = match output.next_image(0) {
+            Ok(result) => result,
+            Err(err) => {
+                let result = FrameStartResult::from_vk_error(err);
+                if !matches!(result, FrameStartResult::WouldBlock) {
+                    self.current_pipeline = None;
+                    self.debug_pipeline = None;
+                    self.current_swapchain = None;
+                }
+                return result;
             }
-            Some(result) => result,
         };
 
+        renderer.advance_frame();
         let mut recorder = renderer.start_pass(pipeline.clone());
         recorder.use_output(output);
 
-        if suboptimal {
-            self.current_pipeline = None;
-            self.debug_pipeline = None;
-            self.current_swapchain = None;
+        if suboptimal && !self.current_swapchain.as_ref().unwrap().is_suboptimal_expected() {
+            // The frame can still be rendered normally. The swapchain is recreated for the next one
+            self.recreate_scheduled = true;
         }
 
-        Some(recorder)
+        FrameStartResult::Started(recorder)
     }
 
-    fn prepare_pipeline(&mut self, output_size: Vec2u32) -> (Arc<dyn EmulatorPipeline>, &Arc<SwapchainOutput>) {
+    fn prepare_pipeline(&mut self, output_size: Vec2u32) -> Result<(Arc<dyn EmulatorPipeline>, &Arc<SwapchainOutput>), ObjectCreateError> {
         if let Some(debug_mode) = &self.debug_mode {
             if self.debug_pipeline.is_none() {
                 log::info!("No debug pipeline present. Rebuilding for size {:?}", output_size);
 
-                let pipeline = DebugPipeline::new(self.emulator.clone(), *debug_mode, output_size).unwrap();
+                let config = DebugPipelineConfig {
+                    depth_convention: self.depth_convention,
+                    depth_clip_range: self.depth_clip_range,
+                    ..Default::default()
+                };
+                let pipeline = DebugPipeline::new_with_config(self.emulator.clone(), *debug_mode, output_size, &config)?;
                 let swapchain_output = SwapchainOutput::new(&self.device, pipeline.clone(), self.current_swapchain.as_ref().cloned().unwrap());
 
                 self.debug_pipeline = Some((pipeline, swapchain_output));
             }
 
             let (pipeline, output) = self.debug_pipeline.as_ref().unwrap();
-            (pipeline.clone(), output)
+            Ok((pipeline.clone(), output))
         } else {
             todo!()
         }
     }
 
-    fn try_create_swapchain(&mut self, size: Vec2u32) -> bool {
+    /// Returns true if the swapchain must be rebuilt before starting a frame. `outputs` contains
+    /// for every swapchain output if it reported the swapchain as suboptimal or out of date.
+    fn is_rebuild_required<I: IntoIterator<Item=bool>>(recreate_scheduled: bool, size_changed: bool, outputs: I) -> bool {
+        recreate_scheduled || size_changed || outputs.into_iter().any(|required| required)
+    }
+
+    /// The number of consecutive failed swapchain rebuilds after which
+    /// [`FrameStartResult::SwapchainCreateFailed`] is returned.
+    const MAX_SWAPCHAIN_REBUILD_ATTEMPTS: u32 = 8;
+
+    /// Returns the time to wait before attempting another swapchain rebuild after some number of
+    /// consecutive failures. The delay doubles with every failure starting at 50ms up to 1.6s.
+    fn get_rebuild_backoff(failed_rebuilds: u32) -> Duration {
+        Duration::from_millis(50) * (1u32 << std::cmp::min(failed_rebuilds, 5))
+    }
+
+    fn try_create_swapchain(&mut self, size: Vec2u32) -> Result<(), FrameStartResult> {
+        if self.failed_rebuilds != 0 {
+            // Skip frames instead of blocking while backing off after failures
+            if self.last_rebuild.elapsed() < Self::get_rebuild_backoff(self.failed_rebuilds) {
+                return Err(FrameStartResult::WouldBlock);
+            }
+        }
+
         log::info!("Attempting to rebuild swapchain with size {:?}", size);
 
         let diff = (self.last_rebuild + Duration::from_millis(50)).saturating_duration_since(Instant::now());
@@ -221,18 +436,31 @@ impl RenderConfig {
             ]),
             required_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
             optional_usage: vk::ImageUsageFlags::empty(),
-            clipped: true
+            clipped: true,
+            preferred_present_mode: self.preferred_present_mode,
+            // The emulator output is not rotated so the presentation engine must do it
+            pre_transform: PreTransformMode::Identity,
         };
 
         match self.main_surface.create_swapchain(&config, size) {
             Ok(swapchain) => {
+                if swapchain.get_image_size() != size {
+                    log::info!("Swapchain created with extent {:?} instead of requested size {:?}", swapchain.get_image_size(), size);
+                }
+                self.failed_rebuilds = 0;
+                self.requested_size = size;
                 self.current_swapchain = Some(swapchain);
-                true
+                Ok(())
             }
             Err(err) => {
-                log::info!("Failed to create swapchain of size {:?}: {:?}", size, err);
+                self.failed_rebuilds += 1;
+                if self.failed_rebuilds == Self::MAX_SWAPCHAIN_REBUILD_ATTEMPTS {
+                    log::error!("Failed to create swapchain of size {:?} after {:?} attempts: {:?}", size, self.failed_rebuilds, err);
+                } else {
+                    log::info!("Failed to create swapchain of size {:?}: {:?}", size, err);
+                }
                 self.current_swapchain = None;
-                false
+                Err(FrameStartResult::from_swapchain_error(err, self.failed_rebuilds))
             }
         }
     }
@@ -244,4 +472,91 @@ pub struct B4DVertexFormat {
     pub position: (u32, vk::Format),
     pub color: Option<(u32, vk::Format)>,
     pub uv: Option<(u32, vk::Format)>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A surface provider which fails to initialize with the error returned by its function.
+    struct FailingSurfaceProvider(fn() -> SurfaceInitError);
+
+    impl SurfaceProvider for FailingSurfaceProvider {
+        fn get_required_instance_extensions(&self) -> Vec<CString> {
+            Vec::new()
+        }
+
+        fn init(&mut self, _: &ash::Entry, _: &ash::Instance) -> Result<vk::SurfaceKHR, SurfaceInitError> {
+            Err((self.0)())
+        }
+
+        fn get_handle(&self) -> Option<vk::SurfaceKHR> {
+            None
+        }
+    }
+
+    #[test]
+    fn rebuild_required_by_any_output() {
+        assert!(!RenderConfig::is_rebuild_required(false, false, []));
+        assert!(!RenderConfig::is_rebuild_required(false, false, [false, false]));
+
+        // The main pipeline output is checked as well as the debug pipeline output
+        assert!(RenderConfig::is_rebuild_required(false, false, [true, false]));
+        assert!(RenderConfig::is_rebuild_required(false, false, [false, true]));
+
+        assert!(RenderConfig::is_rebuild_required(true, false, [false]));
+        assert!(RenderConfig::is_rebuild_required(false, true, []));
+    }
+
+    #[test]
+    fn rebuild_backoff() {
+        assert_eq!(RenderConfig::get_rebuild_backoff(1), Duration::from_millis(100));
+        assert!(RenderConfig::get_rebuild_backoff(2) > RenderConfig::get_rebuild_backoff(1));
+        assert_eq!(RenderConfig::get_rebuild_backoff(RenderConfig::MAX_SWAPCHAIN_REBUILD_ATTEMPTS), Duration::from_millis(1600));
+        assert_eq!(RenderConfig::get_rebuild_backoff(100), Duration::from_millis(1600));
+    }
+
+    #[test]
+    fn swapchain_error_after_max_attempts() {
+        let out_of_date = || SwapchainCreateError::Vulkan(vk::Result::ERROR_OUT_OF_DATE_KHR);
+        let max = RenderConfig::MAX_SWAPCHAIN_REBUILD_ATTEMPTS;
+
+        assert!(matches!(FrameStartResult::from_swapchain_error(out_of_date(), 1), FrameStartResult::SwapchainOutOfDate));
+        assert!(matches!(FrameStartResult::from_swapchain_error(SwapchainCreateError::NoExtent, max - 1), FrameStartResult::SwapchainOutOfDate));
+        assert!(matches!(FrameStartResult::from_swapchain_error(out_of_date(), max), FrameStartResult::SwapchainCreateFailed(SwapchainCreateError::Vulkan(vk::Result::ERROR_OUT_OF_DATE_KHR))));
+        assert!(matches!(FrameStartResult::from_swapchain_error(SwapchainCreateError::Unsupported, max + 1), FrameStartResult::SwapchainCreateFailed(SwapchainCreateError::Unsupported)));
+    }
+
+    #[test]
+    fn depth_clip_control_only_for_negative_range() {
+        assert!(uses_depth_clip_control(true, DepthClipRange::NegativeOneToOne));
+        assert!(!uses_depth_clip_control(true, DepthClipRange::ZeroToOne));
+        assert!(!uses_depth_clip_control(false, DepthClipRange::NegativeOneToOne));
+    }
+
+    #[test]
+    fn acquire_errors_map_to_frame_results() {
+        // A swapchain reporting out of date on acquire
+        assert!(matches!(FrameStartResult::from_vk_error(vk::Result::ERROR_OUT_OF_DATE_KHR), FrameStartResult::SwapchainOutOfDate));
+        assert!(matches!(FrameStartResult::from_vk_error(vk::Result::ERROR_SURFACE_LOST_KHR), FrameStartResult::SurfaceLost));
+        assert!(matches!(FrameStartResult::from_vk_error(vk::Result::NOT_READY), FrameStartResult::WouldBlock));
+        assert!(matches!(FrameStartResult::from_vk_error(vk::Result::TIMEOUT), FrameStartResult::WouldBlock));
+        assert!(!FrameStartResult::SwapchainOutOfDate.is_started());
+    }
+
+    #[test]
+    fn surface_init_errors_propagate() {
+        let create = |error: fn() -> SurfaceInitError| {
+            Blaze4D::new(Box::new(FailingSurfaceProvider(error)), false).err().unwrap()
+        };
+
+        let missing = create(|| SurfaceInitError::MissingExtension(CString::new("VK_KHR_wayland_surface").unwrap()));
+        assert!(matches!(missing, Blaze4DCreateError::Surface(SurfaceInitError::MissingExtension(ext)) if ext.as_bytes() == b"VK_KHR_wayland_surface"));
+
+        let unsupported = create(|| SurfaceInitError::UnsupportedPlatform(String::from("headless")));
+        assert!(matches!(unsupported, Blaze4DCreateError::Surface(SurfaceInitError::UnsupportedPlatform(_))));
+
+        let driver = create(|| SurfaceInitError::Vulkan(vk::Result::ERROR_INITIALIZATION_FAILED));
+        assert!(matches!(driver, Blaze4DCreateError::Surface(SurfaceInitError::Vulkan(vk::Result::ERROR_INITIALIZATION_FAILED))));
+    }
+}
@@ -1,8 +1,11 @@
+use std::os::raw::c_char;
 use std::panic::catch_unwind;
 use std::process::exit;
 use std::sync::Arc;
 use ash::vk;
-use crate::b4d::Blaze4D;
+use crate::BUILD_INFO;
+use crate::b4d::{Blaze4D, FrameStartResult};
+use crate::device::device::EnabledFeatures;
 use crate::glfw_surface::GLFWSurfaceProvider;
 use crate::prelude::{Mat4f32, UUID, Vec2f32, Vec2u32, Vec3f32, Vec4f32};
 
@@ -22,6 +25,39 @@ const NATIVE_METADATA: NativeMetadata = NativeMetadata {
     size_bytes: std::mem::size_of::<usize>() as u32,
 };
 
+#[repr(C)]
+struct CEnabledFeatures {
+    swapchain: u32,
+    synchronization_2: u32,
+    timeline_semaphore: u32,
+    push_descriptor: u32,
+    maintenance_4: u32,
+    vertex_attribute_divisor: u32,
+    wide_lines: u32,
+    descriptor_indexing: u32,
+    diagnostic_checkpoints: u32,
+    sampler_ycbcr_conversion: u32,
+    depth_clip_control: u32,
+}
+
+impl CEnabledFeatures {
+    fn from_enabled_features(features: &EnabledFeatures) -> Self {
+        Self {
+            swapchain: features.swapchain as u32,
+            synchronization_2: features.synchronization_2 as u32,
+            timeline_semaphore: features.timeline_semaphore as u32,
+            push_descriptor: features.push_descriptor as u32,
+            maintenance_4: features.maintenance_4 as u32,
+            vertex_attribute_divisor: features.vertex_attribute_divisor as u32,
+            wide_lines: features.wide_lines as u32,
+            descriptor_indexing: features.descriptor_indexing as u32,
+            diagnostic_checkpoints: features.diagnostic_checkpoints as u32,
+            sampler_ycbcr_conversion: features.sampler_ycbcr_conversion as u32,
+            depth_clip_control: features.depth_clip_control as u32,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 struct CDebugMode(u32);
@@ -187,7 +223,8 @@ impl CVertexFormat {
             color,
             uv0,
             uv1,
-            uv2
+            uv2,
+            instance_divisor: None
         }
     }
 }
@@ -212,7 +249,8 @@ impl CImageData {
             data: std::slice::from_raw_parts(self.data_ptr, self.data_ptr_len),
             row_stride: 0,
             offset: Vec2u32::new(self.offset[0], self.offset[1]),
-            extent: Vec2u32::new(self.extent[0], self.extent[1])
+            extent: Vec2u32::new(self.extent[0], self.extent[1]),
+            layer: 0
         }
     }
 }
@@ -318,10 +356,34 @@ unsafe extern "C" fn b4d_get_native_metadata() -> *const NativeMetadata {
     &NATIVE_METADATA
 }
 
+/// Writes the version of the natives into the provided pointers. Returns true if the natives are a
+/// development build.
+#[no_mangle]
+unsafe extern "C" fn b4d_get_version(out_major: *mut u32, out_minor: *mut u32, out_patch: *mut u32) -> bool {
+    catch_unwind(|| {
+        if out_major.is_null() || out_minor.is_null() || out_patch.is_null() {
+            log::error!("Passed null output pointer to b4d_get_version");
+            exit(1);
+        }
+
+        *out_major = BUILD_INFO.version_major;
+        *out_minor = BUILD_INFO.version_minor;
+        *out_patch = BUILD_INFO.version_patch;
+
+        BUILD_INFO.dev_build
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_get_version");
+        exit(1);
+    })
+}
+
 /// Creates a new [`Blaze4D`] instance.
 ///
 /// This function will take ownership of the provided surface and vertex format set builder. The
 /// pointers must not be used again afterwards.
+///
+/// If the instance could not be created (for example because no window system integration is
+/// available) null is returned.
 #[no_mangle]
 unsafe extern "C" fn b4d_init(surface: *mut GLFWSurfaceProvider, enable_validation: u32) -> *mut Blaze4D {
     catch_unwind(|| {
@@ -334,7 +396,13 @@ unsafe extern "C" fn b4d_init(surface: *mut GLFWSurfaceProvider, enable_validati
 
         let enable_validation = enable_validation != 0;
 
-        Box::leak(Box::new(Blaze4D::new(surface_provider, enable_validation)))
+        match Blaze4D::new(surface_provider, enable_validation) {
+            Ok(b4d) => Box::leak(Box::new(b4d)),
+            Err(err) => {
+                log::error!("Failed to create Blaze4D instance in b4d_init: {:?}", err);
+                std::ptr::null_mut()
+            }
+        }
     }).unwrap_or_else(|_| {
         log::error!("panic in b4d_init");
         exit(1);
@@ -356,6 +424,59 @@ unsafe extern "C" fn b4d_destroy(b4d: *mut Blaze4D) {
     })
 }
 
+/// Returns the number of device extensions enabled by the [`Blaze4D`] instance.
+#[no_mangle]
+unsafe extern "C" fn b4d_get_enabled_extension_count(b4d: *const Blaze4D) -> u32 {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_get_enabled_extension_count");
+            exit(1);
+        });
+
+        b4d.get_device().enabled_extensions().len() as u32
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_get_enabled_extension_count");
+        exit(1);
+    })
+}
+
+/// Returns the name of a enabled device extension as a null terminated string. The string is valid
+/// until the [`Blaze4D`] instance is destroyed. If the index is out of bounds null is returned.
+#[no_mangle]
+unsafe extern "C" fn b4d_get_enabled_extension(b4d: *const Blaze4D, index: u32) -> *const c_char {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_get_enabled_extension");
+            exit(1);
+        });
+
+        b4d.get_device().enabled_extensions().get(index as usize).map(|ext| ext.as_ptr()).unwrap_or(std::ptr::null())
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_get_enabled_extension");
+        exit(1);
+    })
+}
+
+/// Writes the enabled device features of the [`Blaze4D`] instance into `features`.
+#[no_mangle]
+unsafe extern "C" fn b4d_get_enabled_features(b4d: *const Blaze4D, features: *mut CEnabledFeatures) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_get_enabled_features");
+            exit(1);
+        });
+        let features = features.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null features to b4d_get_enabled_features");
+            exit(1);
+        });
+
+        *features = CEnabledFeatures::from_enabled_features(&b4d.get_device().enabled_features());
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_get_enabled_features");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_set_debug_mode(b4d: *const Blaze4D, mode: CDebugMode) {
     catch_unwind(|| {
@@ -385,7 +506,13 @@ unsafe extern "C" fn b4d_create_global_mesh(b4d: *const Blaze4D, data: *const CM
 
         let mesh_data = data.to_mesh_data();
 
-        Box::leak(Box::new(b4d.create_global_mesh(&mesh_data)))
+        match b4d.create_global_mesh(&mesh_data) {
+            Ok(mesh) => Box::leak(Box::new(mesh)),
+            Err(err) => {
+                log::error!("Failed to create global mesh {:?}", err);
+                std::ptr::null_mut()
+            }
+        }
     }).unwrap_or_else(|_| {
         log::error!("panic in b4d_create_global_mesh");
         exit(1);
@@ -499,7 +626,8 @@ unsafe extern "C" fn b4d_destroy_shader(b4d: *const Blaze4D, shader_id: u64) {
 
 /// Calls [`Blaze4D::try_start_frame`].
 ///
-/// If [`Blaze4D::try_start_frame`] returns [`None`] this function returns null.
+/// If [`Blaze4D::try_start_frame`] does not return [`FrameStartResult::Started`] this function
+/// returns null.
 #[no_mangle]
 unsafe extern "C" fn b4d_start_frame(b4d: *mut Blaze4D, window_width: u32, window_height: u32) -> *mut PassRecorder {
     catch_unwind(|| {
@@ -508,10 +636,10 @@ unsafe extern "C" fn b4d_start_frame(b4d: *mut Blaze4D, window_width: u32, windo
             exit(1);
         });
 
-        let frame = b4d.try_start_frame(Vec2u32::new(window_width, window_height));
-        frame.map_or(std::ptr::null_mut(), |recorder| {
-            Box::leak(Box::new(recorder))
-        })
+        match b4d.try_start_frame(Vec2u32::new(window_width, window_height)) {
+            FrameStartResult::Started(recorder) => Box::leak(Box::new(recorder)),
+            _ => std::ptr::null_mut(),
+        }
     }).unwrap_or_else(|_| {
         log::error!("panic in b4d_start_frame");
         exit(1);
@@ -627,6 +755,21 @@ unsafe extern "C" fn b4d_pass_draw_immediate(pass: *mut PassRecorder, id: u32, s
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_set_stencil_reference(pass: *mut PassRecorder, reference: u32) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_set_stencil_reference");
+            exit(1);
+        });
+
+        pass.set_stencil_reference(reference);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_set_stencil_reference");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_end_frame(recorder: *mut PassRecorder) {
     catch_unwind(|| {
@@ -639,4 +782,22 @@ unsafe extern "C" fn b4d_end_frame(recorder: *mut PassRecorder) {
         log::error!("panic in b4d_end_frame");
         exit(1);
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_version() {
+        let mut major = u32::MAX;
+        let mut minor = u32::MAX;
+        let mut patch = u32::MAX;
+        let dev_build = unsafe { b4d_get_version(&mut major, &mut minor, &mut patch) };
+
+        assert_eq!(major, BUILD_INFO.version_major);
+        assert_eq!(minor, BUILD_INFO.version_minor);
+        assert_eq!(patch, BUILD_INFO.version_patch);
+        assert_eq!(dev_build, BUILD_INFO.dev_build);
+    }
+}
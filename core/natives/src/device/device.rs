@@ -1,6 +1,7 @@
 use core::panic::{UnwindSafe, RefUnwindSafe};
 
 use std::cmp::Ordering;
+use std::ffi::CString;
 use std::sync::{Arc, Mutex, MutexGuard};
 use ash::prelude::VkResult;
 
@@ -8,7 +9,7 @@ use ash::vk;
 
 use crate::allocator::Allocator;
 use crate::device::device_utils::DeviceUtils;
-use crate::instance::instance::InstanceContext;
+use crate::instance::instance::{HostAllocationCallbacks, InstanceContext};
 
 use crate::prelude::*;
 
@@ -21,12 +22,21 @@ pub struct DeviceFunctions {
     pub push_descriptor_khr: ash::extensions::khr::PushDescriptor,
     pub swapchain_khr: Option<ash::extensions::khr::Swapchain>,
     pub maintenance_4_khr: Option<ash::extensions::khr::Maintenance4>,
+    pub diagnostic_checkpoints_nv: Option<ash::extensions::nv::DeviceDiagnosticCheckpoints>,
+    pub allocation_callbacks: Option<HostAllocationCallbacks>,
+}
+
+impl DeviceFunctions {
+    /// Returns the allocation callbacks which must be used for all device level vulkan objects.
+    pub fn get_allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.allocation_callbacks.as_ref().map(HostAllocationCallbacks::get)
+    }
 }
 
 impl Drop for DeviceFunctions {
     fn drop(&mut self) {
         unsafe {
-            self.vk.destroy_device(None);
+            self.vk.destroy_device(self.get_allocation_callbacks());
         }
     }
 }
@@ -39,6 +49,9 @@ pub struct DeviceContext {
     async_transfer_queue: Option<Arc<Queue>>,
     allocator: Arc<Allocator>,
     utils: Arc<DeviceUtils>,
+    enabled_extensions: Box<[CString]>,
+    enabled_features: EnabledFeatures,
+    limits: DeviceLimits,
 }
 
 impl DeviceContext {
@@ -47,6 +60,9 @@ impl DeviceContext {
         main_queue: Arc<Queue>,
         async_compute_queue: Option<Arc<Queue>>,
         async_transfer_queue: Option<Arc<Queue>>,
+        enabled_extensions: Box<[CString]>,
+        enabled_features: EnabledFeatures,
+        limits: DeviceLimits,
     ) -> Arc<Self> {
         let allocator = Arc::new(Allocator::new(functions.clone()).unwrap());
         let utils = DeviceUtils::new(functions.clone(), allocator.clone());
@@ -58,7 +74,10 @@ impl DeviceContext {
             async_compute_queue,
             async_transfer_queue,
             allocator,
-            utils
+            utils,
+            enabled_extensions,
+            enabled_features,
+            limits
         })
     }
 
@@ -94,6 +113,11 @@ impl DeviceContext {
         &self.functions.push_descriptor_khr
     }
 
+    /// Returns the allocation callbacks which must be used for all device level vulkan objects.
+    pub fn get_allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.functions.get_allocation_callbacks()
+    }
+
     pub fn swapchain_khr(&self) -> Option<&ash::extensions::khr::Swapchain> {
         self.functions.swapchain_khr.as_ref()
     }
@@ -102,6 +126,10 @@ impl DeviceContext {
         self.functions.maintenance_4_khr.as_ref()
     }
 
+    pub fn diagnostic_checkpoints_nv(&self) -> Option<&ash::extensions::nv::DeviceDiagnosticCheckpoints> {
+        self.functions.diagnostic_checkpoints_nv.as_ref()
+    }
+
     pub fn get_main_queue(&self) -> &Arc<Queue> {
         &self.main_queue
     }
@@ -121,6 +149,100 @@ impl DeviceContext {
     pub fn get_utils(&self) -> &Arc<DeviceUtils> {
         &self.utils
     }
+
+    /// Returns the list of all device extensions enabled on this device.
+    pub fn enabled_extensions(&self) -> &[CString] {
+        &self.enabled_extensions
+    }
+
+    /// Returns which optional and required features have been enabled on this device.
+    pub fn enabled_features(&self) -> EnabledFeatures {
+        self.enabled_features
+    }
+
+    /// Returns true if the VK_EXT_vertex_attribute_divisor extension is enabled and instance rate
+    /// vertex bindings may use a divisor other than 1.
+    pub fn supports_vertex_attribute_divisor(&self) -> bool {
+        self.enabled_features.vertex_attribute_divisor
+    }
+
+    /// Returns true if the vertexAttributeInstanceRateZeroDivisor feature is enabled and instance
+    /// rate vertex bindings may use a divisor of 0.
+    pub fn supports_vertex_attribute_zero_divisor(&self) -> bool {
+        self.enabled_features.vertex_attribute_zero_divisor
+    }
+
+    /// Returns true if the wideLines feature is enabled and line widths other than 1.0 may be used.
+    pub fn supports_wide_lines(&self) -> bool {
+        self.enabled_features.wide_lines
+    }
+
+    /// Returns true if the samplerAnisotropy feature is enabled and samplers may use anisotropic
+    /// filtering.
+    pub fn supports_sampler_anisotropy(&self) -> bool {
+        self.enabled_features.sampler_anisotropy
+    }
+
+    /// Returns true if the VK_EXT_descriptor_indexing extension is enabled and partially bound
+    /// update after bind sampler arrays may be used.
+    pub fn supports_descriptor_indexing(&self) -> bool {
+        self.enabled_features.descriptor_indexing
+    }
+
+    /// Returns true if the VK_KHR_sampler_ycbcr_conversion extension is enabled and images with
+    /// multi-planar formats may be created.
+    pub fn supports_sampler_ycbcr_conversion(&self) -> bool {
+        self.enabled_features.sampler_ycbcr_conversion
+    }
+
+    /// Returns true if the VK_EXT_depth_clip_control extension is enabled and pipelines may use a
+    /// -1..1 normalized device depth range.
+    pub fn supports_depth_clip_control(&self) -> bool {
+        self.enabled_features.depth_clip_control
+    }
+
+    /// Returns true if the VK_NV_device_diagnostic_checkpoints extension is enabled and checkpoints
+    /// may be written into command buffers.
+    pub fn supports_diagnostic_checkpoints(&self) -> bool {
+        self.enabled_features.diagnostic_checkpoints
+    }
+
+    /// Returns true if the tessellationShader feature is enabled and pipelines may use tessellation
+    /// stages. The feature is only enabled if it was required using
+    /// [`DeviceCreateConfig::require_feature`](crate::device::init::DeviceCreateConfig::require_feature).
+    pub fn supports_tessellation_shader(&self) -> bool {
+        self.enabled_features.tessellation_shader
+    }
+
+    /// Returns true if the geometryShader feature is enabled and pipelines may use a geometry stage.
+    /// The feature is only enabled if it was required using
+    /// [`DeviceCreateConfig::require_feature`](crate::device::init::DeviceCreateConfig::require_feature).
+    pub fn supports_geometry_shader(&self) -> bool {
+        self.enabled_features.geometry_shader
+    }
+
+    /// Returns the limits of the physical device used by this device.
+    pub fn get_limits(&self) -> DeviceLimits {
+        self.limits
+    }
+
+    /// Returns the maximum size in bytes of the push constant ranges of a pipeline layout.
+    pub fn get_max_push_constants_size(&self) -> u32 {
+        self.limits.max_push_constants_size
+    }
+
+    /// Returns the features supported by a format for images created with optimal tiling.
+    pub fn get_optimal_format_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        self.get_format_features(format).optimal_tiling
+    }
+
+    /// Returns the features supported by a format for optimal and linear tiling images as well as
+    /// for buffers.
+    pub fn get_format_features(&self, format: vk::Format) -> FormatFeatures {
+        unsafe {
+            self.get_instance().vk().get_physical_device_format_properties(self.functions.physical_device, format)
+        }.into()
+    }
 }
 
 impl PartialEq for DeviceContext {
@@ -146,6 +268,84 @@ impl Ord for DeviceContext {
 
 assert_impl_all!(DeviceContext: Send, Sync, UnwindSafe, RefUnwindSafe);
 
+/// The features supported by a format. See `VkFormatProperties`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FormatFeatures {
+    pub optimal_tiling: vk::FormatFeatureFlags,
+    pub linear_tiling: vk::FormatFeatureFlags,
+    pub buffer: vk::FormatFeatureFlags,
+}
+
+impl FormatFeatures {
+    /// Returns the features supported by images created with the specified tiling.
+    ///
+    /// Panics if the tiling is neither optimal nor linear.
+    pub fn get_tiling_features(&self, tiling: vk::ImageTiling) -> vk::FormatFeatureFlags {
+        match tiling {
+            vk::ImageTiling::OPTIMAL => self.optimal_tiling,
+            vk::ImageTiling::LINEAR => self.linear_tiling,
+            _ => {
+                log::error!("Called FormatFeatures::get_tiling_features with unsupported tiling {:?}", tiling);
+                panic!()
+            }
+        }
+    }
+}
+
+impl From<vk::FormatProperties> for FormatFeatures {
+    fn from(properties: vk::FormatProperties) -> Self {
+        Self {
+            optimal_tiling: properties.optimal_tiling_features,
+            linear_tiling: properties.linear_tiling_features,
+            buffer: properties.buffer_features,
+        }
+    }
+}
+
+/// The set of device features relevant to b4d and whether they have been enabled.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct EnabledFeatures {
+    pub swapchain: bool,
+    pub synchronization_2: bool,
+    pub timeline_semaphore: bool,
+    pub push_descriptor: bool,
+    pub maintenance_4: bool,
+    pub vertex_attribute_divisor: bool,
+    pub vertex_attribute_zero_divisor: bool,
+    pub wide_lines: bool,
+    pub sampler_anisotropy: bool,
+    pub descriptor_indexing: bool,
+    pub diagnostic_checkpoints: bool,
+    pub sampler_ycbcr_conversion: bool,
+    pub depth_clip_control: bool,
+    pub tessellation_shader: bool,
+    pub geometry_shader: bool,
+}
+
+/// The limits of a physical device relevant to b4d.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DeviceLimits {
+    pub max_push_constants_size: u32,
+    pub max_image_array_layers: u32,
+    pub max_tessellation_patch_size: u32,
+    /// The minimum and maximum supported line width.
+    pub line_width_range: [f32; 2],
+    /// The maximum anisotropy supported by samplers.
+    pub max_sampler_anisotropy: f32,
+}
+
+impl DeviceLimits {
+    pub fn from_properties(properties: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            max_push_constants_size: properties.limits.max_push_constants_size,
+            max_image_array_layers: properties.limits.max_image_array_layers,
+            max_tessellation_patch_size: properties.limits.max_tessellation_patch_size,
+            line_width_range: properties.limits.line_width_range,
+            max_sampler_anisotropy: properties.limits.max_sampler_anisotropy,
+        }
+    }
+}
+
 pub struct Queue {
     functions: Arc<DeviceFunctions>,
     queue: Mutex<vk::Queue>,
@@ -179,6 +379,19 @@ impl Queue {
         self.functions.synchronization_2_khr.queue_submit2(*queue, submits, fence)
     }
 
+    /// Returns the stage and marker of the last diagnostic checkpoints executed on this queue.
+    /// Intended to be called after a device lost error. Returns an empty list if
+    /// VK_NV_device_diagnostic_checkpoints is not enabled.
+    pub fn get_checkpoint_data(&self) -> Vec<(vk::PipelineStageFlags, u64)> {
+        if let Some(checkpoints) = &self.functions.diagnostic_checkpoints_nv {
+            let queue = self.queue.lock().unwrap();
+            let data = unsafe { checkpoints.get_queue_checkpoint_data(*queue) };
+            data.iter().map(|data| (data.stage, data.p_checkpoint_marker as usize as u64)).collect()
+        } else {
+            Vec::new()
+        }
+    }
+
     pub unsafe fn wait_idle(&self) -> VkResult<()> {
         let queue = self.queue.lock().unwrap();
         self.functions.vk.queue_wait_idle(*queue)
@@ -206,4 +419,24 @@ impl Queue {
     }
 }
 
-assert_impl_all!(Queue: Send, Sync, UnwindSafe, RefUnwindSafe);
\ No newline at end of file
+assert_impl_all!(Queue: Send, Sync, UnwindSafe, RefUnwindSafe);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_features_from_properties() {
+        // Typical properties reported for R8G8B8A8_SRGB
+        let properties = vk::FormatProperties {
+            optimal_tiling_features: vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+            linear_tiling_features: vk::FormatFeatureFlags::TRANSFER_DST,
+            buffer_features: vk::FormatFeatureFlags::empty(),
+        };
+
+        let features = FormatFeatures::from(properties);
+        assert!(features.get_tiling_features(vk::ImageTiling::OPTIMAL).contains(vk::FormatFeatureFlags::SAMPLED_IMAGE));
+        assert!(!features.get_tiling_features(vk::ImageTiling::LINEAR).contains(vk::FormatFeatureFlags::SAMPLED_IMAGE));
+        assert!(features.buffer.is_empty());
+    }
+}
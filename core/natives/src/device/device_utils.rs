@@ -7,16 +7,226 @@ use ash::vk;
 use bytemuck::cast_slice;
 use include_bytes_aligned::include_bytes_aligned;
 use crate::allocator::Allocator;
+use crate::device::init::FeatureFlag;
+use crate::instance::instance::VulkanVersion;
 
 use crate::prelude::*;
 
 pub fn create_shader_from_bytes(device: &DeviceFunctions, code: &[u8]) -> VkResult<vk::ShaderModule> {
+    let properties = unsafe {
+        device.instance.vk().get_physical_device_properties(device.physical_device)
+    };
+    let vulkan_version = std::cmp::min(device.instance.get_version().get_raw(), properties.api_version);
+    let max_version = get_max_spirv_version(VulkanVersion::from_raw(vulkan_version));
+
+    if let Err(err) = validate_spirv_header(code, max_version) {
+        log::error!("Invalid SPIR-V shader code: {:?}", err);
+        return Err(vk::Result::ERROR_INITIALIZATION_FAILED);
+    }
+
     let info = vk::ShaderModuleCreateInfo::builder()
         .code(cast_slice(code));
 
     unsafe {
-        device.vk.create_shader_module(&info, None)
+        device.vk.create_shader_module(&info, device.get_allocation_callbacks())
+    }
+}
+
+/// A SPIR-V version as a (major, minor) pair.
+pub type SpirvVersion = (u32, u32);
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SpirvHeaderError {
+    /// The code is shorter than the 5 word SPIR-V header.
+    TooShort(usize),
+
+    /// The code size in bytes is not a multiple of 4.
+    MisalignedSize(usize),
+
+    /// The first word is not the SPIR-V magic number.
+    InvalidMagic(u32),
+
+    /// The module uses a SPIR-V version which is newer than the version supported by the device.
+    UnsupportedVersion {
+        found: SpirvVersion,
+        max: SpirvVersion,
+    },
+}
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+/// Returns the newest SPIR-V version every device supporting the vulkan version must accept.
+pub fn get_max_spirv_version(vulkan_version: VulkanVersion) -> SpirvVersion {
+    match (vulkan_version.get_major(), vulkan_version.get_minor()) {
+        (1, 0) => (1, 0),
+        (1, 1) => (1, 3),
+        (1, 2) => (1, 5),
+        _ => (1, 6),
+    }
+}
+
+/// Validates the header of SPIR-V code before it is passed to vulkan. Returns the version of the
+/// module.
+///
+/// Creating a shader module with a unsupported SPIR-V version may succeed on some drivers and only
+/// fail later during pipeline creation without a useful error. This catches it early.
+pub fn validate_spirv_header(code: &[u8], max_version: SpirvVersion) -> Result<SpirvVersion, SpirvHeaderError> {
+    if code.len() % 4 != 0 {
+        return Err(SpirvHeaderError::MisalignedSize(code.len()));
+    }
+    if code.len() < 5 * 4 {
+        return Err(SpirvHeaderError::TooShort(code.len()));
+    }
+
+    let word = |index: usize| u32::from_ne_bytes([code[index * 4], code[index * 4 + 1], code[index * 4 + 2], code[index * 4 + 3]]);
+
+    let magic = word(0);
+    if magic != SPIRV_MAGIC {
+        return Err(SpirvHeaderError::InvalidMagic(magic));
+    }
+
+    let version = word(1);
+    let found = ((version >> 16) & 0xFF, (version >> 8) & 0xFF);
+    if found > max_version {
+        return Err(SpirvHeaderError::UnsupportedVersion { found, max: max_version });
+    }
+
+    Ok(found)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ShaderStageError {
+    /// The stage is present more than once.
+    Duplicate(vk::ShaderStageFlags),
+
+    /// A required stage is missing.
+    Missing(vk::ShaderStageFlags),
+
+    /// The stage cannot be used in a graphics pipeline.
+    Unsupported(vk::ShaderStageFlags),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PipelineCreateError {
+    InvalidStages(ShaderStageError),
+
+    /// The pipeline uses a stage whose feature is not enabled on the device.
+    MissingFeature(FeatureFlag),
+
+    /// The primitive topology cannot be used with the shader stages of the pipeline. Patch lists
+    /// must be used if and only if tessellation stages are present.
+    InvalidTopology(vk::PrimitiveTopology),
+
+    /// The number of patch control points is 0 or exceeds the maxTessellationPatchSize limit.
+    InvalidPatchControlPoints {
+        count: u32,
+        max: u32,
+    },
+
+    /// Vulkan failed to create the pipeline.
+    Vulkan(vk::Result),
+}
+
+/// Validates the shader stages of a graphics pipeline before they are passed to vulkan.
+///
+/// Exactly one vertex and fragment stage must be present. Geometry and tessellation stages are
+/// optional but may only be present once. Tessellation control and evaluation stages must be used
+/// together.
+pub fn validate_graphics_shader_stages(stages: &[vk::PipelineShaderStageCreateInfo]) -> Result<(), PipelineCreateError> {
+    let supported = vk::ShaderStageFlags::VERTEX |
+        vk::ShaderStageFlags::TESSELLATION_CONTROL |
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION |
+        vk::ShaderStageFlags::GEOMETRY |
+        vk::ShaderStageFlags::FRAGMENT;
+
+    let mut present = vk::ShaderStageFlags::empty();
+    for stage in stages {
+        if stage.stage.is_empty() || !supported.contains(stage.stage) || stage.stage.as_raw().count_ones() != 1 {
+            return Err(PipelineCreateError::InvalidStages(ShaderStageError::Unsupported(stage.stage)));
+        }
+        if present.contains(stage.stage) {
+            return Err(PipelineCreateError::InvalidStages(ShaderStageError::Duplicate(stage.stage)));
+        }
+        present |= stage.stage;
+    }
+
+    for required in [vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT] {
+        if !present.contains(required) {
+            return Err(PipelineCreateError::InvalidStages(ShaderStageError::Missing(required)));
+        }
+    }
+
+    if present.contains(vk::ShaderStageFlags::TESSELLATION_CONTROL) && !present.contains(vk::ShaderStageFlags::TESSELLATION_EVALUATION) {
+        return Err(PipelineCreateError::InvalidStages(ShaderStageError::Missing(vk::ShaderStageFlags::TESSELLATION_EVALUATION)));
+    }
+    if present.contains(vk::ShaderStageFlags::TESSELLATION_EVALUATION) && !present.contains(vk::ShaderStageFlags::TESSELLATION_CONTROL) {
+        return Err(PipelineCreateError::InvalidStages(ShaderStageError::Missing(vk::ShaderStageFlags::TESSELLATION_CONTROL)));
+    }
+
+    Ok(())
+}
+
+/// Validates that the geometryShader feature is enabled if the stages contain a geometry stage.
+pub fn validate_geometry_stage(stages: &[vk::PipelineShaderStageCreateInfo], geometry_enabled: bool) -> Result<(), PipelineCreateError> {
+    if !geometry_enabled && stages.iter().any(|stage| stage.stage == vk::ShaderStageFlags::GEOMETRY) {
+        return Err(PipelineCreateError::MissingFeature(FeatureFlag::GeometryShader));
+    }
+    Ok(())
+}
+
+/// Returns the tessellation state of a graphics pipeline or [`None`] if the stages do not contain
+/// tessellation stages. The stages must have been validated with
+/// [`validate_graphics_shader_stages`].
+///
+/// `tessellation_enabled` is true if the tessellationShader feature is enabled and `max_patch_size`
+/// is the maxTessellationPatchSize limit of the device.
+pub fn make_tessellation_state(stages: &[vk::PipelineShaderStageCreateInfo], topology: vk::PrimitiveTopology, patch_control_points: u32, tessellation_enabled: bool, max_patch_size: u32) -> Result<Option<vk::PipelineTessellationStateCreateInfo>, PipelineCreateError> {
+    let tessellated = stages.iter().any(|stage| stage.stage == vk::ShaderStageFlags::TESSELLATION_CONTROL);
+    if !tessellated {
+        if topology == vk::PrimitiveTopology::PATCH_LIST {
+            return Err(PipelineCreateError::InvalidTopology(topology));
+        }
+        return Ok(None);
+    }
+
+    if !tessellation_enabled {
+        return Err(PipelineCreateError::MissingFeature(FeatureFlag::TessellationShader));
+    }
+    if topology != vk::PrimitiveTopology::PATCH_LIST {
+        return Err(PipelineCreateError::InvalidTopology(topology));
+    }
+    if patch_control_points == 0 || patch_control_points > max_patch_size {
+        return Err(PipelineCreateError::InvalidPatchControlPoints { count: patch_control_points, max: max_patch_size });
     }
+
+    Ok(Some(vk::PipelineTessellationStateCreateInfo::builder()
+        .patch_control_points(patch_control_points)
+        .build()
+    ))
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum PipelineLayoutCreateError {
+    /// A push constant range ends at `required` bytes which exceeds the `max` push constant size
+    /// supported by the device.
+    PushConstantsTooLarge {
+        required: u32,
+        max: u32,
+    },
+}
+
+/// Validates the push constant ranges of a pipeline layout against the maxPushConstantsSize limit
+/// of the device before they are passed to vulkan.
+pub fn validate_push_constant_ranges(ranges: &[vk::PushConstantRange], max_size: u32) -> Result<(), PipelineLayoutCreateError> {
+    let required = ranges.iter().map(|range| range.offset as u64 + range.size as u64).max().unwrap_or(0);
+    if required > max_size as u64 {
+        return Err(PipelineLayoutCreateError::PushConstantsTooLarge {
+            required: u32::try_from(required).unwrap_or(u32::MAX),
+            max: max_size
+        });
+    }
+
+    Ok(())
 }
 
 pub struct DeviceUtils {
@@ -100,7 +310,7 @@ impl BlitUtils {
             .subpasses(std::slice::from_ref(&subpass));
 
         unsafe {
-            self.device.vk.create_render_pass(&info, None)
+            self.device.vk.create_render_pass(&info, self.device.get_allocation_callbacks())
         }.unwrap()
     }
 
@@ -174,7 +384,7 @@ impl BlitUtils {
             .render_pass(render_pass);
 
         let pipeline = * unsafe {
-            self.device.vk.create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+            self.device.vk.create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), self.device.get_allocation_callbacks())
         }.unwrap().get(0).unwrap();
 
         pipeline
@@ -193,7 +403,7 @@ impl BlitUtils {
             .unnormalized_coordinates(false);
 
         unsafe {
-            device.vk.create_sampler(&info, None)
+            device.vk.create_sampler(&info, device.get_allocation_callbacks())
         }.unwrap()
     }
 
@@ -208,7 +418,7 @@ impl BlitUtils {
             .bindings(std::slice::from_ref(&binding));
 
         unsafe {
-            device.vk.create_descriptor_set_layout(&info, None)
+            device.vk.create_descriptor_set_layout(&info, device.get_allocation_callbacks())
         }.unwrap()
     }
 
@@ -217,7 +427,7 @@ impl BlitUtils {
             .set_layouts(std::slice::from_ref(&set_layout));
 
         unsafe {
-            device.vk.create_pipeline_layout(&info, None)
+            device.vk.create_pipeline_layout(&info, device.get_allocation_callbacks())
         }.unwrap()
     }
 }
@@ -225,11 +435,11 @@ impl BlitUtils {
 impl Drop for BlitUtils {
     fn drop(&mut self) {
         unsafe {
-            self.device.vk.destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.vk.destroy_descriptor_set_layout(self.set_layout, None);
-            self.device.vk.destroy_sampler(self.sampler, None);
-            self.device.vk.destroy_shader_module(self.fragment_shader, None);
-            self.device.vk.destroy_shader_module(self.vertex_shader, None);
+            self.device.vk.destroy_pipeline_layout(self.pipeline_layout, self.device.get_allocation_callbacks());
+            self.device.vk.destroy_descriptor_set_layout(self.set_layout, self.device.get_allocation_callbacks());
+            self.device.vk.destroy_sampler(self.sampler, self.device.get_allocation_callbacks());
+            self.device.vk.destroy_shader_module(self.fragment_shader, self.device.get_allocation_callbacks());
+            self.device.vk.destroy_shader_module(self.vertex_shader, self.device.get_allocation_callbacks());
         }
     }
 }
@@ -294,7 +504,7 @@ impl BlitPass {
             .layers(1);
 
         unsafe {
-            self.utils.blit_utils.device.vk.create_framebuffer(&info, None)
+            self.utils.blit_utils.device.vk.create_framebuffer(&info, self.utils.blit_utils.device.get_allocation_callbacks())
         }
     }
 
@@ -362,11 +572,123 @@ impl BlitPass {
 impl Drop for BlitPass {
     fn drop(&mut self) {
         unsafe {
-            self.utils.blit_utils.device.vk.destroy_pipeline(self.pipeline, None);
-            self.utils.blit_utils.device.vk.destroy_render_pass(self.render_pass, None);
+            self.utils.blit_utils.device.vk.destroy_pipeline(self.pipeline, self.utils.blit_utils.device.get_allocation_callbacks());
+            self.utils.blit_utils.device.vk.destroy_render_pass(self.render_pass, self.utils.blit_utils.device.get_allocation_callbacks());
         }
     }
 }
 
 static FULL_SCREEN_QUAD_VERTEX_SHADER: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "utils/full_screen_quad_vert.spv"));
-static BLIT_FRAGMENT_SHADER: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "utils/blit_frag.spv"));
\ No newline at end of file
+static BLIT_FRAGMENT_SHADER: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "utils/blit_frag.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_stage(stage: vk::ShaderStageFlags) -> vk::PipelineShaderStageCreateInfo {
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(stage)
+            .build()
+    }
+
+    fn make_spirv_header(version: u32) -> Vec<u8> {
+        [SPIRV_MAGIC, version, 0, 16, 0].iter().flat_map(|word| word.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn spirv_version_too_new() {
+        let max = get_max_spirv_version(VulkanVersion::VK_1_2);
+        assert_eq!(max, (1, 5));
+
+        assert_eq!(validate_spirv_header(&make_spirv_header(0x00010500), max), Ok((1, 5)));
+        assert_eq!(validate_spirv_header(&make_spirv_header(0x00016300), max), Err(SpirvHeaderError::UnsupportedVersion {
+            found: (1, 99),
+            max: (1, 5)
+        }));
+
+        let mut bad_magic = make_spirv_header(0x00010000);
+        bad_magic[0] ^= 0xFF;
+        assert!(matches!(validate_spirv_header(&bad_magic, max), Err(SpirvHeaderError::InvalidMagic(_))));
+        assert_eq!(validate_spirv_header(&bad_magic[..8], max), Err(SpirvHeaderError::TooShort(8)));
+        assert_eq!(validate_spirv_header(&bad_magic[..7], max), Err(SpirvHeaderError::MisalignedSize(7)));
+    }
+
+    #[test]
+    fn valid_stages() {
+        let stages = [make_stage(vk::ShaderStageFlags::VERTEX), make_stage(vk::ShaderStageFlags::FRAGMENT)];
+        assert_eq!(validate_graphics_shader_stages(&stages), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_vertex_stage() {
+        let stages = [
+            make_stage(vk::ShaderStageFlags::VERTEX),
+            make_stage(vk::ShaderStageFlags::VERTEX),
+            make_stage(vk::ShaderStageFlags::FRAGMENT)
+        ];
+        assert_eq!(validate_graphics_shader_stages(&stages), Err(PipelineCreateError::InvalidStages(ShaderStageError::Duplicate(vk::ShaderStageFlags::VERTEX))));
+    }
+
+    #[test]
+    fn oversized_push_constants() {
+        let range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
+            offset: 0,
+            size: 512,
+        };
+        assert_eq!(validate_push_constant_ranges(std::slice::from_ref(&range), 128), Err(PipelineLayoutCreateError::PushConstantsTooLarge { required: 512, max: 128 }));
+        assert_eq!(validate_push_constant_ranges(std::slice::from_ref(&range), 512), Ok(()));
+        assert_eq!(validate_push_constant_ranges(&[], 128), Ok(()));
+    }
+
+    #[test]
+    fn tessellation_state() {
+        let stages = [
+            make_stage(vk::ShaderStageFlags::VERTEX),
+            make_stage(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+            make_stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+            make_stage(vk::ShaderStageFlags::FRAGMENT)
+        ];
+        assert_eq!(validate_graphics_shader_stages(&stages), Ok(()));
+
+        let state = make_tessellation_state(&stages, vk::PrimitiveTopology::PATCH_LIST, 3, true, 32).unwrap().unwrap();
+        assert_eq!(state.patch_control_points, 3);
+
+        assert_eq!(make_tessellation_state(&stages, vk::PrimitiveTopology::TRIANGLE_LIST, 3, true, 32).unwrap_err(), PipelineCreateError::InvalidTopology(vk::PrimitiveTopology::TRIANGLE_LIST));
+        assert_eq!(make_tessellation_state(&stages, vk::PrimitiveTopology::PATCH_LIST, 64, true, 32).unwrap_err(), PipelineCreateError::InvalidPatchControlPoints { count: 64, max: 32 });
+
+        let untessellated = [make_stage(vk::ShaderStageFlags::VERTEX), make_stage(vk::ShaderStageFlags::FRAGMENT)];
+        assert!(make_tessellation_state(&untessellated, vk::PrimitiveTopology::TRIANGLE_LIST, 0, false, 0).unwrap().is_none());
+        assert_eq!(make_tessellation_state(&untessellated, vk::PrimitiveTopology::PATCH_LIST, 3, true, 32).unwrap_err(), PipelineCreateError::InvalidTopology(vk::PrimitiveTopology::PATCH_LIST));
+    }
+
+    #[test]
+    fn geometry_stage_feature() {
+        let stages = [
+            make_stage(vk::ShaderStageFlags::VERTEX),
+            make_stage(vk::ShaderStageFlags::GEOMETRY),
+            make_stage(vk::ShaderStageFlags::FRAGMENT)
+        ];
+        assert_eq!(validate_graphics_shader_stages(&stages), Ok(()));
+        assert_eq!(validate_geometry_stage(&stages, true), Ok(()));
+        assert_eq!(validate_geometry_stage(&stages, false), Err(PipelineCreateError::MissingFeature(FeatureFlag::GeometryShader)));
+        assert_eq!(validate_geometry_stage(&stages[..1], false), Ok(()));
+    }
+
+    #[test]
+    fn tessellation_without_feature() {
+        let stages = [
+            make_stage(vk::ShaderStageFlags::VERTEX),
+            make_stage(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+            make_stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+            make_stage(vk::ShaderStageFlags::FRAGMENT)
+        ];
+        assert_eq!(make_tessellation_state(&stages, vk::PrimitiveTopology::PATCH_LIST, 3, false, 32).unwrap_err(), PipelineCreateError::MissingFeature(FeatureFlag::TessellationShader));
+    }
+
+    #[test]
+    fn missing_fragment_stage() {
+        let stages = [make_stage(vk::ShaderStageFlags::VERTEX)];
+        assert_eq!(validate_graphics_shader_stages(&stages), Err(PipelineCreateError::InvalidStages(ShaderStageError::Missing(vk::ShaderStageFlags::FRAGMENT))));
+    }
+}
@@ -7,7 +7,7 @@ use ash::vk;
 use bumpalo::Bump;
 use vk_profiles_rs::{vp, VulkanProfiles};
 
-use crate::device::device::{DeviceFunctions, Queue};
+use crate::device::device::{DeviceFunctions, DeviceLimits, EnabledFeatures, Queue};
 use crate::instance::instance::{InstanceContext, VulkanVersion};
 
 use crate::prelude::*;
@@ -17,6 +17,8 @@ pub struct DeviceCreateConfig {
     used_surfaces: Vec<vk::SurfaceKHR>,
     disable_robustness: bool,
     required_extensions: HashSet<CString>,
+    required_features: HashSet<FeatureFlag>,
+    allocation_callbacks: Option<HostAllocationCallbacks>,
 }
 
 impl DeviceCreateConfig {
@@ -24,7 +26,9 @@ impl DeviceCreateConfig {
         Self {
             used_surfaces: Vec::new(),
             required_extensions: HashSet::new(),
+            required_features: HashSet::new(),
             disable_robustness: false,
+            allocation_callbacks: None,
         }
     }
 
@@ -43,6 +47,94 @@ impl DeviceCreateConfig {
     pub fn require_swapchain(&mut self) {
         self.required_extensions.insert(CString::new("VK_KHR_swapchain").unwrap());
     }
+
+    /// Adds a core vulkan feature which must be supported by the selected device. All required
+    /// features are enabled on the created device. Devices which do not support all required
+    /// features are rejected.
+    pub fn require_feature(&mut self, feature: FeatureFlag) {
+        self.required_features.insert(feature);
+    }
+
+    /// Sets the host allocation callbacks used for the device and all objects created from it. If
+    /// not set the callbacks of the instance are used.
+    pub fn set_allocation_callbacks(&mut self, callbacks: HostAllocationCallbacks) {
+        self.allocation_callbacks = Some(callbacks);
+    }
+}
+
+/// A core vulkan feature which can be required using [`DeviceCreateConfig::require_feature`].
+/// Each variant corresponds to the member of [`vk::PhysicalDeviceFeatures`] of the same name.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FeatureFlag {
+    GeometryShader,
+    TessellationShader,
+    DualSrcBlend,
+    LogicOp,
+    MultiDrawIndirect,
+    DepthClamp,
+    DepthBiasClamp,
+    FillModeNonSolid,
+    LargePoints,
+    SamplerAnisotropy,
+    TextureCompressionBc,
+    ShaderClipDistance,
+    ShaderFloat64,
+    ShaderInt64,
+    ShaderInt16,
+}
+
+impl FeatureFlag {
+    /// Returns the name of the feature as used in the vulkan specification.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            FeatureFlag::GeometryShader => "geometryShader",
+            FeatureFlag::TessellationShader => "tessellationShader",
+            FeatureFlag::DualSrcBlend => "dualSrcBlend",
+            FeatureFlag::LogicOp => "logicOp",
+            FeatureFlag::MultiDrawIndirect => "multiDrawIndirect",
+            FeatureFlag::DepthClamp => "depthClamp",
+            FeatureFlag::DepthBiasClamp => "depthBiasClamp",
+            FeatureFlag::FillModeNonSolid => "fillModeNonSolid",
+            FeatureFlag::LargePoints => "largePoints",
+            FeatureFlag::SamplerAnisotropy => "samplerAnisotropy",
+            FeatureFlag::TextureCompressionBc => "textureCompressionBC",
+            FeatureFlag::ShaderClipDistance => "shaderClipDistance",
+            FeatureFlag::ShaderFloat64 => "shaderFloat64",
+            FeatureFlag::ShaderInt64 => "shaderInt64",
+            FeatureFlag::ShaderInt16 => "shaderInt16",
+        }
+    }
+
+    fn get_mut<'a>(&self, features: &'a mut vk::PhysicalDeviceFeatures) -> &'a mut vk::Bool32 {
+        match self {
+            FeatureFlag::GeometryShader => &mut features.geometry_shader,
+            FeatureFlag::TessellationShader => &mut features.tessellation_shader,
+            FeatureFlag::DualSrcBlend => &mut features.dual_src_blend,
+            FeatureFlag::LogicOp => &mut features.logic_op,
+            FeatureFlag::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            FeatureFlag::DepthClamp => &mut features.depth_clamp,
+            FeatureFlag::DepthBiasClamp => &mut features.depth_bias_clamp,
+            FeatureFlag::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            FeatureFlag::LargePoints => &mut features.large_points,
+            FeatureFlag::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            FeatureFlag::TextureCompressionBc => &mut features.texture_compression_bc,
+            FeatureFlag::ShaderClipDistance => &mut features.shader_clip_distance,
+            FeatureFlag::ShaderFloat64 => &mut features.shader_float64,
+            FeatureFlag::ShaderInt64 => &mut features.shader_int64,
+            FeatureFlag::ShaderInt16 => &mut features.shader_int16,
+        }
+    }
+
+    /// Returns true if the feature is set in `features`.
+    pub fn is_supported(&self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let mut features = *features;
+        *self.get_mut(&mut features) == vk::TRUE
+    }
+
+    /// Sets the feature in `features`.
+    pub fn enable(&self, features: &mut vk::PhysicalDeviceFeatures) {
+        *self.get_mut(features) = vk::TRUE;
+    }
 }
 
 #[derive(Debug)]
@@ -50,6 +142,10 @@ pub enum DeviceCreateError {
     Vulkan(vk::Result),
     NoSupportedDevice,
     SurfaceNotFound,
+
+    /// No device supports all features required by [`DeviceCreateConfig::require_feature`]. Contains
+    /// the required features which were missing on at least one otherwise suitable device.
+    MissingRequiredFeatures(Vec<FeatureFlag>),
 }
 
 impl From<vk::Result> for DeviceCreateError {
@@ -67,7 +163,7 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
 
     let allocator = Bump::new();
 
-    let (device_config, device_create_info, physical_device) = filter_devices(
+    let (device_config, (device_create_info, enabled_extensions), physical_device) = filter_devices(
         unsafe { instance.vk().enumerate_physical_devices()? },
         &instance,
         &vk_vp,
@@ -112,7 +208,8 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
     let selected_properties = unsafe { instance.vk().get_physical_device_properties(physical_device) };
     let selected_device_name = unsafe { CStr::from_ptr(selected_properties.device_name.as_ptr()) };
     log::info!("Selected device {:?} with config {:?}", selected_device_name, device_config);
-    let device = unsafe { vk_vp.create_device(instance.vk(), physical_device, &vp_device_create_info, None)? };
+    let allocation_callbacks = config.allocation_callbacks.or_else(|| instance.get_host_allocation_callbacks());
+    let device = unsafe { vk_vp.create_device(instance.vk(), physical_device, &vp_device_create_info, allocation_callbacks.as_ref().map(HostAllocationCallbacks::get))? };
 
     let synchronization_2_khr = ash::extensions::khr::Synchronization2::new(instance.vk(), &device);
     let timeline_semaphore_khr = ash::extensions::khr::TimelineSemaphore::new(instance.vk(), &device);
@@ -130,6 +227,12 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
         None
     };
 
+    let diagnostic_checkpoints_nv = if device_config.has_diagnostic_checkpoints {
+        Some(ash::extensions::nv::DeviceDiagnosticCheckpoints::new(instance.vk(), &device))
+    } else {
+        None
+    };
+
     let functions = Arc::new(DeviceFunctions {
         instance,
         physical_device,
@@ -138,7 +241,9 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
         timeline_semaphore_khr,
         push_descriptor_khr,
         swapchain_khr,
-        maintenance_4_khr
+        maintenance_4_khr,
+        diagnostic_checkpoints_nv,
+        allocation_callbacks,
     });
 
     let main_queue = Arc::new(Queue::new(functions.clone(), device_config.main_queue_family, 0));
@@ -153,7 +258,26 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
         functions,
         main_queue,
         async_compute_queue,
-        async_transfer_queue
+        async_transfer_queue,
+        enabled_extensions,
+        EnabledFeatures {
+            swapchain: has_swapchain,
+            synchronization_2: device_config.has_synchronization_2,
+            timeline_semaphore: device_config.has_timeline_semaphore,
+            push_descriptor: device_config.has_push_descriptor,
+            maintenance_4: device_config.has_maintenance4,
+            vertex_attribute_divisor: device_config.has_vertex_attribute_divisor,
+            vertex_attribute_zero_divisor: device_config.has_vertex_attribute_zero_divisor,
+            wide_lines: device_config.has_wide_lines,
+            sampler_anisotropy: device_config.has_sampler_anisotropy,
+            descriptor_indexing: device_config.has_descriptor_indexing,
+            diagnostic_checkpoints: device_config.has_diagnostic_checkpoints,
+            sampler_ycbcr_conversion: device_config.has_sampler_ycbcr_conversion,
+            depth_clip_control: device_config.has_depth_clip_control,
+            tessellation_shader: device_config.has_tessellation_shader,
+            geometry_shader: device_config.has_geometry_shader,
+        },
+        DeviceLimits::from_properties(&selected_properties)
     ))
 }
 
@@ -163,10 +287,11 @@ fn filter_devices<'a>(
     vk_vp: &VulkanProfiles,
     config: &DeviceCreateConfig,
     allocator: &'a Bump
-) -> Result<(DeviceConfigInfo, vk::DeviceCreateInfoBuilder<'a>, vk::PhysicalDevice), DeviceCreateError> {
+) -> Result<(DeviceConfigInfo, (vk::DeviceCreateInfoBuilder<'a>, Box<[CString]>), vk::PhysicalDevice), DeviceCreateError> {
     let profile = instance.get_profile();
 
-    let mut best_device: Option<(DeviceConfigInfo, vk::DeviceCreateInfoBuilder, vk::PhysicalDevice)> = None;
+    let mut best_device: Option<(DeviceConfigInfo, (vk::DeviceCreateInfoBuilder, Box<[CString]>), vk::PhysicalDevice)> = None;
+    let mut missing_features = Vec::new();
     for device in devices {
         if let Some(mut configurator) = DeviceConfigurator::new(
             instance,
@@ -176,6 +301,17 @@ fn filter_devices<'a>(
             device,
             allocator
         )? {
+            let supported_features = configurator.get_features(vk::PhysicalDeviceFeatures2::builder());
+            if let Err(missing) = check_required_features(&config.required_features, &supported_features) {
+                log::info!("Physical device {:?} is missing required features {:?}", configurator.get_name(), missing);
+                for feature in missing {
+                    if !missing_features.contains(&feature) {
+                        missing_features.push(feature);
+                    }
+                }
+                continue;
+            }
+
             if let Some(device_config) = configure_device(&mut configurator)? {
                 best_device = if let Some(old) = best_device {
                     if device_config.rating > old.0.rating {
@@ -190,12 +326,65 @@ fn filter_devices<'a>(
         }
     }
 
-    best_device.ok_or(DeviceCreateError::NoSupportedDevice)
+    best_device.ok_or_else(|| no_device_error(missing_features))
+}
+
+/// Validates that all required features are supported. Returns the list of missing features
+/// otherwise.
+fn check_required_features(required: &HashSet<FeatureFlag>, supported: &vk::PhysicalDeviceFeatures) -> Result<(), Vec<FeatureFlag>> {
+    let mut missing: Vec<_> = required.iter().filter(|feature| !feature.is_supported(supported)).copied().collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        missing.sort_by_key(|feature| feature.get_name());
+        Err(missing)
+    }
+}
+
+/// Returns the core features enabled by a profile.
+fn get_profile_core_features(vk_vp: &VulkanProfiles, profile: &vp::ProfileProperties) -> vk::PhysicalDeviceFeatures {
+    let mut features = vk::PhysicalDeviceFeatures2::default();
+    unsafe {
+        vk_vp.get_profile_features(profile, &mut *(&mut features as *mut vk::PhysicalDeviceFeatures2 as *mut vk::BaseOutStructure))
+    };
+    features.features
+}
+
+/// Returns the core features to enable on a device. The optional wideLines and samplerAnisotropy
+/// features are enabled if they are set in `supported_features`.
+///
+/// Since the device is created with [`vp::DeviceCreateFlagBits::OVERRIDE_FEATURES`] the core
+/// features passed to the device replace the features of the profile. The profile features must
+/// therefore be included together with the required and optional features.
+fn make_enabled_core_features(profile_features: &vk::PhysicalDeviceFeatures, required_features: &HashSet<FeatureFlag>, supported_features: &vk::PhysicalDeviceFeatures) -> vk::PhysicalDeviceFeatures {
+    let mut features = *profile_features;
+    for feature in required_features {
+        feature.enable(&mut features);
+    }
+    if supported_features.wide_lines == vk::TRUE {
+        features.wide_lines = vk::TRUE;
+    }
+    if supported_features.sampler_anisotropy == vk::TRUE {
+        features.sampler_anisotropy = vk::TRUE;
+    }
+    features
+}
+
+/// Creates the error returned if no suitable device has been found. If any device has been
+/// rejected because of missing required features those features are reported.
+fn no_device_error(missing_features: Vec<FeatureFlag>) -> DeviceCreateError {
+    if missing_features.is_empty() {
+        DeviceCreateError::NoSupportedDevice
+    } else {
+        DeviceCreateError::MissingRequiredFeatures(missing_features)
+    }
 }
 
 struct DeviceConfigurator<'a, 'b> {
     instance: &'a InstanceContext,
     config: &'a DeviceCreateConfig,
+    /// The core features enabled by the profile.
+    profile_features: vk::PhysicalDeviceFeatures,
     physical_device: vk::PhysicalDevice,
     device_name: CString,
     available_extensions: HashSet<CString>,
@@ -253,9 +442,12 @@ impl<'a, 'b> DeviceConfigurator<'a, 'b> {
             return Ok(None);
         }
 
+        let profile_features = get_profile_core_features(vk_vp, profile);
+
         Ok(Some(DeviceConfigurator {
             instance,
             config,
+            profile_features,
             physical_device,
             device_name,
             available_extensions,
@@ -325,7 +517,8 @@ impl<'a, 'b> DeviceConfigurator<'a, 'b> {
         self.create_info = info.push_next(data);
     }
 
-    fn build(self) -> vk::DeviceCreateInfoBuilder<'b> {
+    /// Builds the device create info. Also returns the list of used extensions.
+    fn build(self) -> (vk::DeviceCreateInfoBuilder<'b>, Box<[CString]>) {
         let c_extensions = self.alloc.alloc_slice_fill_copy(self.used_extensions.len(), std::ptr::null());
 
         for (index, extension) in self.used_extensions.iter().enumerate() {
@@ -333,14 +526,30 @@ impl<'a, 'b> DeviceConfigurator<'a, 'b> {
             c_extensions[index] = c_str;
         }
 
-        self.create_info.enabled_extension_names(c_extensions)
+        let mut extensions: Box<[CString]> = self.used_extensions.into_iter().collect();
+        extensions.sort();
+
+        (self.create_info.enabled_extension_names(c_extensions), extensions)
     }
 }
 
 #[derive(Debug)]
 struct DeviceConfigInfo {
     rating: f32,
+    has_synchronization_2: bool,
+    has_timeline_semaphore: bool,
+    has_push_descriptor: bool,
     has_maintenance4: bool,
+    has_vertex_attribute_divisor: bool,
+    has_vertex_attribute_zero_divisor: bool,
+    has_wide_lines: bool,
+    has_sampler_anisotropy: bool,
+    has_descriptor_indexing: bool,
+    has_diagnostic_checkpoints: bool,
+    has_sampler_ycbcr_conversion: bool,
+    has_depth_clip_control: bool,
+    has_tessellation_shader: bool,
+    has_geometry_shader: bool,
 
     /// The main queue family. It is guaranteed to support presentation to all surfaces as well as
     /// graphics, compute and transfer operations.
@@ -355,6 +564,10 @@ struct DeviceConfigInfo {
     async_transfer_family: Option<u32>,
 }
 
+/// The minimum number of update after bind sampler descriptors a device must support to enable
+/// descriptor indexing. Matches the capacity of the emulator bindless texture table.
+const MIN_BINDLESS_DESCRIPTORS: u32 = 1024;
+
 fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConfigInfo>, DeviceCreateError> {
     // Any device features/properties we need to validate get pushed into this p_next chain
     let mut features = vk::PhysicalDeviceFeatures2::builder();
@@ -368,7 +581,8 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
     device.add_extension(&synchronization_2_name);
 
     let push_descriptor_name = CString::new("VK_KHR_push_descriptor").unwrap();
-    if !device.is_extension_supported(&push_descriptor_name) {
+    let has_push_descriptor = device.is_extension_supported(&push_descriptor_name);
+    if !has_push_descriptor {
         log::info!("Physical device {:?} does not support VK_KHR_push_descriptor", device.get_name());
         return Ok(None);
     }
@@ -388,6 +602,47 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         maintenance4 = None;
     }
 
+    let vertex_attribute_divisor_name = CString::new("VK_EXT_vertex_attribute_divisor").unwrap();
+    let mut vertex_attribute_divisor;
+    if device.is_extension_supported(&vertex_attribute_divisor_name) {
+        vertex_attribute_divisor = Some(vk::PhysicalDeviceVertexAttributeDivisorFeaturesEXT::builder());
+        features = features.push_next(vertex_attribute_divisor.as_mut().unwrap());
+    } else {
+        vertex_attribute_divisor = None;
+    }
+
+    let descriptor_indexing_name = CString::new("VK_EXT_descriptor_indexing").unwrap();
+    let mut descriptor_indexing;
+    if device.is_extension_supported(&descriptor_indexing_name) {
+        descriptor_indexing = Some((
+            vk::PhysicalDeviceDescriptorIndexingFeatures::builder(),
+            vk::PhysicalDeviceDescriptorIndexingProperties::builder()
+        ));
+        let (f, p) = descriptor_indexing.as_mut().unwrap();
+        features = features.push_next(f);
+        properties = properties.push_next(p);
+    } else {
+        descriptor_indexing = None;
+    }
+
+    let sampler_ycbcr_conversion_name = CString::new("VK_KHR_sampler_ycbcr_conversion").unwrap();
+    let mut sampler_ycbcr_conversion;
+    if device.is_extension_supported(&sampler_ycbcr_conversion_name) {
+        sampler_ycbcr_conversion = Some(vk::PhysicalDeviceSamplerYcbcrConversionFeatures::builder());
+        features = features.push_next(sampler_ycbcr_conversion.as_mut().unwrap());
+    } else {
+        sampler_ycbcr_conversion = None;
+    }
+
+    let depth_clip_control_name = CString::new("VK_EXT_depth_clip_control").unwrap();
+    let mut depth_clip_control;
+    if device.is_extension_supported(&depth_clip_control_name) {
+        depth_clip_control = Some(vk::PhysicalDeviceDepthClipControlFeaturesEXT::builder());
+        features = features.push_next(depth_clip_control.as_mut().unwrap());
+    } else {
+        depth_clip_control = None;
+    }
+
     let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder();
     features = features.push_next(&mut timeline_features);
 
@@ -401,16 +656,21 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
     properties = properties.push_next(&mut push_descriptor_properties);
 
     // Read supported features and properties
-    device.get_features(features);
+    let core_features = device.get_features(features);
     device.get_properties(properties);
     let timeline_features = timeline_features.build();
     let timeline_properties = timeline_properties.build();
     let synchronization2_features = synchronization2_features.build();
     let push_descriptor_properties = push_descriptor_properties.build();
     let maintenance4 = maintenance4.map(|(f, p)| (f.build(), p.build()));
+    let vertex_attribute_divisor = vertex_attribute_divisor.map(|f| f.build());
+    let descriptor_indexing = descriptor_indexing.map(|(f, p)| (f.build(), p.build()));
+    let sampler_ycbcr_conversion = sampler_ycbcr_conversion.map(|f| f.build());
+    let depth_clip_control = depth_clip_control.map(|f| f.build());
 
     // Process the supported features and properties
-    if timeline_features.timeline_semaphore != vk::TRUE {
+    let has_timeline_semaphore = timeline_features.timeline_semaphore == vk::TRUE;
+    if !has_timeline_semaphore {
         log::info!("Physical device {:?} does not support the timeline semaphore feature", device.get_name());
         return Ok(None);
     } else {
@@ -424,7 +684,8 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         return Ok(None);
     }
 
-    if synchronization2_features.synchronization2 != vk::TRUE {
+    let has_synchronization_2 = synchronization2_features.synchronization2 == vk::TRUE;
+    if !has_synchronization_2 {
         log::info!("Physical device {:?} does not support the synchronization2 feature", device.get_name());
         return Ok(None);
     } else {
@@ -453,6 +714,106 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         has_maintenance4 = false;
     }
 
+    let has_vertex_attribute_divisor;
+    let has_vertex_attribute_zero_divisor;
+    if let Some(f) = vertex_attribute_divisor.as_ref() {
+        if f.vertex_attribute_instance_rate_divisor == vk::TRUE {
+            has_vertex_attribute_divisor = true;
+            has_vertex_attribute_zero_divisor = f.vertex_attribute_instance_rate_zero_divisor == vk::TRUE;
+            device.add_extension(&vertex_attribute_divisor_name);
+            device.push_next(vk::PhysicalDeviceVertexAttributeDivisorFeaturesEXT::builder()
+                .vertex_attribute_instance_rate_divisor(true)
+                .vertex_attribute_instance_rate_zero_divisor(has_vertex_attribute_zero_divisor)
+            );
+        } else {
+            has_vertex_attribute_divisor = false;
+            has_vertex_attribute_zero_divisor = false;
+        }
+    } else {
+        has_vertex_attribute_divisor = false;
+        has_vertex_attribute_zero_divisor = false;
+    }
+
+    // Core features must be enabled with a single PhysicalDeviceFeatures2 struct
+    let enabled_core_features = make_enabled_core_features(&device.profile_features, &device.config.required_features, &core_features);
+    let has_wide_lines = enabled_core_features.wide_lines == vk::TRUE;
+    let has_sampler_anisotropy = enabled_core_features.sampler_anisotropy == vk::TRUE;
+
+    // Only enabled if required since most devices without it are otherwise suitable
+    let has_tessellation_shader = device.config.required_features.contains(&FeatureFlag::TessellationShader);
+    let has_geometry_shader = device.config.required_features.contains(&FeatureFlag::GeometryShader);
+
+    device.push_next(vk::PhysicalDeviceFeatures2::builder()
+        .features(enabled_core_features)
+    );
+
+    let has_descriptor_indexing;
+    if let Some((f, p)) = descriptor_indexing.as_ref() {
+        let max_descriptors = std::cmp::min(
+            std::cmp::min(p.max_per_stage_descriptor_update_after_bind_samplers, p.max_per_stage_descriptor_update_after_bind_sampled_images),
+            std::cmp::min(p.max_descriptor_set_update_after_bind_samplers, p.max_descriptor_set_update_after_bind_sampled_images)
+        );
+
+        if f.shader_sampled_image_array_non_uniform_indexing == vk::TRUE &&
+            f.descriptor_binding_sampled_image_update_after_bind == vk::TRUE &&
+            f.descriptor_binding_update_unused_while_pending == vk::TRUE &&
+            f.descriptor_binding_partially_bound == vk::TRUE &&
+            f.runtime_descriptor_array == vk::TRUE &&
+            max_descriptors >= MIN_BINDLESS_DESCRIPTORS {
+
+            has_descriptor_indexing = true;
+            device.add_extension(&descriptor_indexing_name);
+            device.push_next(vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+                .shader_sampled_image_array_non_uniform_indexing(true)
+                .descriptor_binding_sampled_image_update_after_bind(true)
+                .descriptor_binding_update_unused_while_pending(true)
+                .descriptor_binding_partially_bound(true)
+                .runtime_descriptor_array(true)
+            );
+        } else {
+            has_descriptor_indexing = false;
+        }
+    } else {
+        has_descriptor_indexing = false;
+    }
+
+    let has_sampler_ycbcr_conversion;
+    if let Some(f) = sampler_ycbcr_conversion.as_ref() {
+        if f.sampler_ycbcr_conversion == vk::TRUE {
+            has_sampler_ycbcr_conversion = true;
+            device.add_extension(&sampler_ycbcr_conversion_name);
+            device.push_next(vk::PhysicalDeviceSamplerYcbcrConversionFeatures::builder()
+                .sampler_ycbcr_conversion(true)
+            );
+        } else {
+            has_sampler_ycbcr_conversion = false;
+        }
+    } else {
+        has_sampler_ycbcr_conversion = false;
+    }
+
+    let has_depth_clip_control;
+    if let Some(f) = depth_clip_control.as_ref() {
+        if f.depth_clip_control == vk::TRUE {
+            has_depth_clip_control = true;
+            device.add_extension(&depth_clip_control_name);
+            device.push_next(vk::PhysicalDeviceDepthClipControlFeaturesEXT::builder()
+                .depth_clip_control(true)
+            );
+        } else {
+            has_depth_clip_control = false;
+        }
+    } else {
+        has_depth_clip_control = false;
+    }
+
+    // Only used to write breadcrumbs for debugging gpu crashes so it is never required
+    let diagnostic_checkpoints_name = CString::new("VK_NV_device_diagnostic_checkpoints").unwrap();
+    let has_diagnostic_checkpoints = device.is_extension_supported(&diagnostic_checkpoints_name);
+    if has_diagnostic_checkpoints {
+        device.add_extension(&diagnostic_checkpoints_name);
+    }
+
     // Calculate queue family assignments
     let main_families = device.filter_sort_queues(|family, properties, surface_support| {
         Some(family)
@@ -467,9 +828,104 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
 
     Ok(Some(DeviceConfigInfo {
         rating: 0.0,
+        has_synchronization_2,
+        has_timeline_semaphore,
+        has_push_descriptor,
         has_maintenance4,
+        has_vertex_attribute_divisor,
+        has_vertex_attribute_zero_divisor,
+        has_wide_lines,
+        has_sampler_anisotropy,
+        has_descriptor_indexing,
+        has_diagnostic_checkpoints,
+        has_sampler_ycbcr_conversion,
+        has_depth_clip_control,
+        has_tessellation_shader,
+        has_geometry_shader,
         main_queue_family,
         async_compute_family: None,
         async_transfer_family: None
     }))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_feature_rejects_device() {
+        let mut config = DeviceCreateConfig::new();
+        config.require_feature(FeatureFlag::ShaderInt16);
+        config.require_feature(FeatureFlag::GeometryShader);
+
+        // A mock device which only supports shaderInt16
+        let supported = vk::PhysicalDeviceFeatures {
+            shader_int16: vk::TRUE,
+            ..Default::default()
+        };
+
+        let missing = check_required_features(&config.required_features, &supported).unwrap_err();
+        assert_eq!(missing, vec![FeatureFlag::GeometryShader]);
+        assert_eq!(missing[0].get_name(), "geometryShader");
+
+        match no_device_error(missing) {
+            DeviceCreateError::MissingRequiredFeatures(features) => assert_eq!(features, vec![FeatureFlag::GeometryShader]),
+            err => panic!("Unexpected error {:?}", err),
+        }
+        assert!(matches!(no_device_error(Vec::new()), DeviceCreateError::NoSupportedDevice));
+
+        let mut enabled = vk::PhysicalDeviceFeatures::default();
+        FeatureFlag::GeometryShader.enable(&mut enabled);
+        assert!(check_required_features(&config.required_features, &vk::PhysicalDeviceFeatures { shader_int16: vk::TRUE, ..enabled }).is_ok());
+    }
+
+    #[test]
+    fn enabled_core_features_include_profile_features() {
+        let profile_features = vk::PhysicalDeviceFeatures {
+            independent_blend: vk::TRUE,
+            ..Default::default()
+        };
+        let supported_features = vk::PhysicalDeviceFeatures {
+            independent_blend: vk::TRUE,
+            geometry_shader: vk::TRUE,
+            wide_lines: vk::TRUE,
+            sampler_anisotropy: vk::TRUE,
+            ..Default::default()
+        };
+        let mut required = HashSet::new();
+        required.insert(FeatureFlag::GeometryShader);
+
+        let enabled = make_enabled_core_features(&profile_features, &required, &supported_features);
+        assert_eq!(enabled.independent_blend, vk::TRUE);
+        assert_eq!(enabled.geometry_shader, vk::TRUE);
+        assert_eq!(enabled.wide_lines, vk::TRUE);
+        assert_eq!(enabled.sampler_anisotropy, vk::TRUE);
+
+        let enabled = make_enabled_core_features(&profile_features, &HashSet::new(), &profile_features);
+        assert_eq!(enabled.independent_blend, vk::TRUE);
+        assert_eq!(enabled.geometry_shader, vk::FALSE);
+        assert_eq!(enabled.wide_lines, vk::FALSE);
+        assert_eq!(enabled.sampler_anisotropy, vk::FALSE);
+    }
+
+    #[test]
+    fn required_features_reported_enabled() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let features = device.enabled_features();
+        assert!(features.synchronization_2);
+        assert!(features.timeline_semaphore);
+        assert!(features.push_descriptor);
+
+        // Extension backed features are only reported if their extension was enabled
+        let has_extension = |name: &str| device.enabled_extensions().iter().any(|ext| ext.to_str() == Ok(name));
+        assert!(has_extension("VK_KHR_synchronization2"));
+        assert!(has_extension("VK_KHR_push_descriptor"));
+        assert_eq!(features.diagnostic_checkpoints, has_extension("VK_NV_device_diagnostic_checkpoints"));
+        assert_eq!(features.depth_clip_control, has_extension("VK_EXT_depth_clip_control"));
+
+        // Headless devices must not enable the swapchain extension
+        assert!(!features.swapchain);
+        assert!(!has_extension("VK_KHR_swapchain"));
+        assert!(device.swapchain_khr().is_none());
+    }
+}
@@ -65,6 +65,10 @@ impl DeviceSurface {
     /// If the current surface capabilities report a max extent of 0 [`SwapchainCreateError::NoExtent`]
     /// is returned.
     ///
+    /// If the surface reports a current extent the swapchain is created with that extent, otherwise
+    /// the requested extent is clamped to the supported range. The actual extent can be queried with
+    /// [`SurfaceSwapchain::get_image_size`].
+    ///
     /// If some part of the config is not supported by the surface [`SwapchainCreateError::Unsupported`]
     /// is returned.
     pub fn create_swapchain(&self, config: &SwapchainConfig, extent: Vec2u32) -> Result<Arc<SurfaceSwapchain>, SwapchainCreateError> {
@@ -76,11 +80,11 @@ impl DeviceSurface {
             .min_image_count(self.find_best_image_count(&capabilities, &config)?)
             .image_format(format.format)
             .image_color_space(format.color_space)
-            .image_extent(self.validate_extent(&capabilities, extent)?)
+            .image_extent(select_extent(&capabilities, extent)?)
             .image_array_layers(1)
             .image_usage(self.find_best_usage_flags(&capabilities, &config)?)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(self.find_best_transform(&capabilities, &config)?)
+            .pre_transform(select_transform(&capabilities, config.pre_transform))
             .composite_alpha(self.find_best_composite_alpha(&capabilities, &config)?)
             .present_mode(self.find_best_present_mode(&config)?)
             .clipped(config.clipped);
@@ -109,7 +113,7 @@ impl DeviceSurface {
         };
 
         let new_swapchain = unsafe {
-            swapchain_khr.create_swapchain(info, None)
+            swapchain_khr.create_swapchain(info, self.device.get_allocation_callbacks())
         }?;
         drop(swapchain_guard);
 
@@ -117,7 +121,7 @@ impl DeviceSurface {
             swapchain_khr.get_swapchain_images(new_swapchain)
         }.map_err(|err| {
             unsafe {
-                swapchain_khr.destroy_swapchain(new_swapchain, None);
+                swapchain_khr.destroy_swapchain(new_swapchain, self.device.get_allocation_callbacks());
             }
             guard.clear_current();
             err
@@ -130,7 +134,7 @@ impl DeviceSurface {
 
         let size = Vec2u32::new(info.image_extent.width, info.image_extent.height);
 
-        let new_swapchain = Arc::new(SurfaceSwapchain::new(self.weak.upgrade().unwrap(), new_swapchain, images.as_slice(), size, format, info.image_usage));
+        let new_swapchain = Arc::new(SurfaceSwapchain::new(self.weak.upgrade().unwrap(), new_swapchain, images.as_slice(), size, format, info.image_usage, info.present_mode, info.pre_transform));
         guard.set_current(&new_swapchain);
         drop(guard);
 
@@ -157,21 +161,6 @@ impl DeviceSurface {
         Err(SwapchainCreateError::Unsupported)
     }
 
-    fn validate_extent(&self, capabilities: &vk::SurfaceCapabilitiesKHR, extent: Vec2u32) -> Result<vk::Extent2D, SwapchainCreateError> {
-        if capabilities.max_image_extent.width == 0 || capabilities.max_image_extent.height == 0 {
-            return Err(SwapchainCreateError::NoExtent)
-        }
-
-        if capabilities.max_image_extent.width < extent[0] ||
-            capabilities.min_image_extent.width > extent[0] ||
-            capabilities.max_image_extent.height < extent[1] ||
-            capabilities.min_image_extent.height > extent[1] {
-            return Err(SwapchainCreateError::Unsupported)
-        }
-
-        Ok(vk::Extent2D{ width: extent[0], height: extent[1] })
-    }
-
     fn find_best_usage_flags(&self, capabilities: &vk::SurfaceCapabilitiesKHR, config: &SwapchainConfig) -> Result<vk::ImageUsageFlags, SwapchainCreateError> {
         if !capabilities.supported_usage_flags.contains(config.required_usage) {
             return Err(SwapchainCreateError::Unsupported);
@@ -183,39 +172,7 @@ impl DeviceSurface {
 
     fn find_best_present_mode(&self, config: &SwapchainConfig) -> Result<vk::PresentModeKHR, SwapchainCreateError> {
         let supported = self.get_surface_present_modes()?;
-
-        if supported.contains(&vk::PresentModeKHR::MAILBOX) {
-            return Ok(vk::PresentModeKHR::MAILBOX);
-        }
-
-        if config.allow_tearing && supported.contains(&vk::PresentModeKHR::IMMEDIATE) {
-            return Ok(vk::PresentModeKHR::IMMEDIATE);
-        }
-
-        Ok(vk::PresentModeKHR::FIFO)
-    }
-
-    fn find_best_transform(&self, capabilities: &vk::SurfaceCapabilitiesKHR, _: &SwapchainConfig) -> Result<vk::SurfaceTransformFlagsKHR, SwapchainCreateError> {
-        if capabilities.supported_transforms.contains(capabilities.current_transform) {
-            Ok(capabilities.current_transform)
-
-        } else if capabilities.supported_transforms.contains(vk::SurfaceTransformFlagsKHR::IDENTITY) {
-            Ok(vk::SurfaceTransformFlagsKHR::IDENTITY)
-
-        } else if capabilities.supported_transforms.contains(vk::SurfaceTransformFlagsKHR::INHERIT) {
-            Ok(vk::SurfaceTransformFlagsKHR::INHERIT)
-
-        } else {
-            let mut flag = 1u32;
-            loop {
-                let transform = vk::SurfaceTransformFlagsKHR::from_raw(Flags::from(flag));
-                if capabilities.supported_transforms.contains(transform) {
-                    return Ok(transform);
-                }
-                // The vulkan spec requires at least one bit to be set so we should panic if that's not the case
-                flag = flag.checked_shr(1).unwrap();
-            }
-        }
+        Ok(select_present_mode(&supported, config))
     }
 
     fn find_best_composite_alpha(&self, capabilities: &vk::SurfaceCapabilitiesKHR, _: &SwapchainConfig) -> Result<vk::CompositeAlphaFlagsKHR, SwapchainCreateError> {
@@ -301,6 +258,25 @@ pub struct SwapchainConfig {
     pub required_usage: vk::ImageUsageFlags,
     pub optional_usage: vk::ImageUsageFlags,
     pub clipped: bool,
+    /// The present mode to use if the surface supports it. If [`None`] or unsupported the best
+    /// supported mode is selected.
+    pub preferred_present_mode: Option<vk::PresentModeKHR>,
+    /// How the current transform of the surface is handled.
+    pub pre_transform: PreTransformMode,
+}
+
+/// Selects which transform is applied to the swapchain images relative to the natural orientation
+/// of the display before presentation.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PreTransformMode {
+    /// Uses the current transform of the surface. This avoids an extra rotation by the
+    /// presentation engine on rotated displays but the application must rotate its output by
+    /// [`SurfaceSwapchain::get_pre_transform_matrix`].
+    Current,
+
+    /// Uses the identity transform if supported and lets the presentation engine rotate the
+    /// images. No adjustment is needed by the application.
+    Identity,
 }
 
 #[derive(Debug)]
@@ -316,6 +292,100 @@ impl From<vk::Result> for SwapchainCreateError {
     }
 }
 
+/// Selects the extent of a new swapchain. If the surface reports a current extent it must be used,
+/// otherwise the requested extent is clamped to the supported range.
+fn select_extent(capabilities: &vk::SurfaceCapabilitiesKHR, extent: Vec2u32) -> Result<vk::Extent2D, SwapchainCreateError> {
+    if capabilities.max_image_extent.width == 0 || capabilities.max_image_extent.height == 0 {
+        return Err(SwapchainCreateError::NoExtent)
+    }
+
+    if capabilities.current_extent.width != u32::MAX && capabilities.current_extent.height != u32::MAX {
+        return Ok(capabilities.current_extent);
+    }
+
+    Ok(vk::Extent2D {
+        width: extent[0].clamp(capabilities.min_image_extent.width, capabilities.max_image_extent.width),
+        height: extent[1].clamp(capabilities.min_image_extent.height, capabilities.max_image_extent.height)
+    })
+}
+
+/// Selects the present mode of a new swapchain. The preferred mode of the config is used if it is
+/// supported. Otherwise mailbox is preferred followed by immediate if tearing is allowed. FIFO is
+/// always supported and used as the final fallback.
+fn select_present_mode(supported: &[vk::PresentModeKHR], config: &SwapchainConfig) -> vk::PresentModeKHR {
+    if let Some(preferred) = config.preferred_present_mode {
+        if supported.contains(&preferred) {
+            return preferred;
+        }
+    }
+
+    if supported.contains(&vk::PresentModeKHR::MAILBOX) {
+        return vk::PresentModeKHR::MAILBOX;
+    }
+
+    if config.allow_tearing && supported.contains(&vk::PresentModeKHR::IMMEDIATE) {
+        return vk::PresentModeKHR::IMMEDIATE;
+    }
+
+    vk::PresentModeKHR::FIFO
+}
+
+/// Selects the pre transform of a new swapchain. Falls back to the current transform if the
+/// identity transform is not supported, and to any supported transform if neither is.
+fn select_transform(capabilities: &vk::SurfaceCapabilitiesKHR, mode: PreTransformMode) -> vk::SurfaceTransformFlagsKHR {
+    let supported = capabilities.supported_transforms;
+    if mode == PreTransformMode::Identity && supported.contains(vk::SurfaceTransformFlagsKHR::IDENTITY) {
+        return vk::SurfaceTransformFlagsKHR::IDENTITY;
+    }
+
+    if supported.contains(capabilities.current_transform) {
+        capabilities.current_transform
+    } else if supported.contains(vk::SurfaceTransformFlagsKHR::IDENTITY) {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else if supported.contains(vk::SurfaceTransformFlagsKHR::INHERIT) {
+        vk::SurfaceTransformFlagsKHR::INHERIT
+    } else {
+        // The vulkan spec requires at least one bit to be set
+        let lowest = supported.as_raw() & supported.as_raw().wrapping_neg();
+        vk::SurfaceTransformFlagsKHR::from_raw(lowest)
+    }
+}
+
+/// Returns true if a swapchain with the given pre transform and size only differs from the
+/// surface in its transform. Presentation then reports the swapchain as suboptimal on every frame
+/// even though a new swapchain would be identical.
+fn is_transform_only_mismatch(capabilities: &vk::SurfaceCapabilitiesKHR, pre_transform: vk::SurfaceTransformFlagsKHR, size: Vec2u32) -> bool {
+    if capabilities.current_transform == pre_transform {
+        return false;
+    }
+
+    let extent = capabilities.current_extent;
+    (extent.width == u32::MAX && extent.height == u32::MAX) || (extent.width == size[0] && extent.height == size[1])
+}
+
+/// Returns the matrix which must be applied to clip space positions to compensate for a swapchain
+/// pre transform. Mirrored transforms are not supported and return the identity.
+pub fn make_pre_transform_matrix(transform: vk::SurfaceTransformFlagsKHR) -> Mat4f32 {
+    let (sin, cos) = match transform {
+        vk::SurfaceTransformFlagsKHR::ROTATE_90 => (1f32, 0f32),
+        vk::SurfaceTransformFlagsKHR::ROTATE_180 => (0f32, -1f32),
+        vk::SurfaceTransformFlagsKHR::ROTATE_270 => (-1f32, 0f32),
+        _ => (0f32, 1f32),
+    };
+
+    Mat4f32::new(
+        cos, -sin, 0f32, 0f32,
+        sin, cos, 0f32, 0f32,
+        0f32, 0f32, 1f32, 0f32,
+        0f32, 0f32, 0f32, 1f32
+    )
+}
+
+/// Returns true if presenting with the mode limits the frame rate to the refresh rate of the display.
+pub fn is_vsync_present_mode(present_mode: vk::PresentModeKHR) -> bool {
+    present_mode == vk::PresentModeKHR::FIFO || present_mode == vk::PresentModeKHR::FIFO_RELAXED
+}
+
 /// Wraps a swapchain of a [`DeviceSurface`]
 ///
 /// The swpachain will be destroyed when this struct is dropped.
@@ -335,10 +405,12 @@ pub struct SurfaceSwapchain {
     size: Vec2u32,
     format: vk::SurfaceFormatKHR,
     usage: vk::ImageUsageFlags,
+    present_mode: vk::PresentModeKHR,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
 }
 
 impl SurfaceSwapchain {
-    fn new(surface: Arc<DeviceSurface>, swapchain: vk::SwapchainKHR, images: &[vk::Image], size: Vec2u32, format: vk::SurfaceFormatKHR, usage: vk::ImageUsageFlags) -> Self {
+    fn new(surface: Arc<DeviceSurface>, swapchain: vk::SwapchainKHR, images: &[vk::Image], size: Vec2u32, format: vk::SurfaceFormatKHR, usage: vk::ImageUsageFlags, present_mode: vk::PresentModeKHR, pre_transform: vk::SurfaceTransformFlagsKHR) -> Self {
         let device = &surface.device;
 
         let acquire_objects = images.iter().map(|_| AcquireObjects::new(device)).collect();
@@ -357,7 +429,9 @@ impl SurfaceSwapchain {
 
             size,
             format,
-            usage
+            usage,
+            present_mode,
+            pre_transform
         }
     }
 
@@ -378,7 +452,8 @@ impl SurfaceSwapchain {
         self.image_objects.as_ref()
     }
 
-    /// Returns the size of the images.
+    /// Returns the size of the images. This is the extent the swapchain was actually created with
+    /// which may differ from the size requested in [`DeviceSurface::create_swapchain`].
     pub fn get_image_size(&self) -> Vec2u32 {
         self.size
     }
@@ -393,6 +468,33 @@ impl SurfaceSwapchain {
         self.usage
     }
 
+    /// Returns the present mode the swapchain was actually created with. This may differ from
+    /// the preferred present mode of the [`SwapchainConfig`] if the surface does not support it.
+    pub fn get_present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    /// Returns the pre transform the swapchain was created with.
+    pub fn get_pre_transform(&self) -> vk::SurfaceTransformFlagsKHR {
+        self.pre_transform
+    }
+
+    /// Returns the matrix which must be applied to clip space positions rendered into this
+    /// swapchain to compensate for its pre transform. See [`make_pre_transform_matrix`].
+    pub fn get_pre_transform_matrix(&self) -> Mat4f32 {
+        make_pre_transform_matrix(self.pre_transform)
+    }
+
+    /// Returns true if a suboptimal result for this swapchain is expected and recreating it would
+    /// not help. This is the case if the only difference to the surface is the pre transform, for
+    /// example if [`PreTransformMode::Identity`] is used on a rotated display.
+    pub fn is_suboptimal_expected(&self) -> bool {
+        match self.surface.get_surface_capabilities() {
+            Ok(capabilities) => is_transform_only_mismatch(&capabilities, self.pre_transform, self.size),
+            Err(_) => false,
+        }
+    }
+
     pub fn acquire_next_image(&self, timeout: u64, fence: Option<vk::Fence>) -> VkResult<(AcquiredImageInfo, bool)> {
         let acquire = self.acquire_objects.get(self.get_next_acquire()).unwrap();
         let (ready_op, acquire_semaphore) = match acquire.wait_and_get(&self.surface.device, timeout) {
@@ -405,11 +507,20 @@ impl SurfaceSwapchain {
         let swapchain_khr = self.surface.device.swapchain_khr.as_ref().unwrap();
 
         let guard = self.swapchain.lock().unwrap();
-        let (image_index, suboptimal) = unsafe {
+        let result = unsafe {
             swapchain_khr.acquire_next_image(*guard, timeout, acquire_semaphore.get_handle(), fence.unwrap_or(vk::Fence::null()))
-        }?;
+        };
         drop(guard);
 
+        let (image_index, suboptimal) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                // Nothing will be submitted for this acquire so we have to make the objects available again
+                acquire.release(&self.surface.device, &ready_op);
+                return Err(err);
+            }
+        };
+
         Ok((AcquiredImageInfo {
             acquire_semaphore: SemaphoreOp::new_binary(acquire_semaphore),
             acquire_ready_semaphore: ready_op,
@@ -455,7 +566,7 @@ impl Drop for SurfaceSwapchain {
         let swapchain = self.swapchain.get_mut().unwrap();
 
         unsafe {
-            swapchain_khr.destroy_swapchain(*swapchain, None)
+            swapchain_khr.destroy_swapchain(*swapchain, self.surface.device.get_allocation_callbacks())
         };
 
         if guard.is_current(self.set_id) {
@@ -480,13 +591,13 @@ impl AcquireObjects {
             .push_next(&mut timeline);
 
         let ready_semaphore = Semaphore::new(unsafe {
-            device.vk.create_semaphore(&info, None)
+            device.vk.create_semaphore(&info, device.get_allocation_callbacks())
         }.unwrap());
 
         let info = vk::SemaphoreCreateInfo::builder();
 
         let acquire_semaphore = Semaphore::new(unsafe {
-            device.vk.create_semaphore(&info, None)
+            device.vk.create_semaphore(&info, device.get_allocation_callbacks())
         }.unwrap());
 
         Self {
@@ -523,10 +634,25 @@ impl AcquireObjects {
         }
     }
 
+    /// Signals the ready semaphore from the host. Must be called if the objects returned by
+    /// [`AcquireObjects::wait_and_get`] end up not being used by a submission.
+    fn release(&self, device: &DeviceFunctions, ready_op: &SemaphoreOp) {
+        let info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(ready_op.semaphore.get_handle())
+            .value(ready_op.value.unwrap());
+
+        unsafe {
+            device.timeline_semaphore_khr.signal_semaphore(&info)
+        }.unwrap_or_else(|err| {
+            log::error!("vkSignalSemaphore returned {:?} in AcquireObjects::release", err);
+            panic!()
+        });
+    }
+
     fn destroy(&mut self, device: &DeviceFunctions) {
         unsafe {
-            device.vk.destroy_semaphore(self.acquire_semaphore.get_handle(), None);
-            device.vk.destroy_semaphore(self.ready_semaphore.get_handle(), None);
+            device.vk.destroy_semaphore(self.acquire_semaphore.get_handle(), device.get_allocation_callbacks());
+            device.vk.destroy_semaphore(self.ready_semaphore.get_handle(), device.get_allocation_callbacks());
         }
     }
 }
@@ -558,13 +684,13 @@ impl ImageObjects {
             });
 
         let framebuffer_view = unsafe {
-            device.vk.create_image_view(&info, None)
+            device.vk.create_image_view(&info, device.get_allocation_callbacks())
         }.unwrap();
 
         let info = vk::SemaphoreCreateInfo::builder();
 
         let present_semaphore = Semaphore::new(unsafe {
-            device.vk.create_semaphore(&info, None)
+            device.vk.create_semaphore(&info, device.get_allocation_callbacks())
         }.unwrap());
 
         Self {
@@ -588,8 +714,8 @@ impl ImageObjects {
 
     fn destroy(&mut self, device: &DeviceFunctions) {
         unsafe {
-            device.vk.destroy_semaphore(self.present_semaphore.get_handle(), None);
-            device.vk.destroy_image_view(self.framebuffer_view, None);
+            device.vk.destroy_semaphore(self.present_semaphore.get_handle(), device.get_allocation_callbacks());
+            device.vk.destroy_image_view(self.framebuffer_view, device.get_allocation_callbacks());
         }
     }
 }
@@ -601,4 +727,88 @@ pub struct AcquiredImageInfo {
     pub acquire_ready_semaphore: SemaphoreOp,
     /// The index of the swapchain image acquired.
     pub image_index: u32,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_capabilities(current: vk::Extent2D) -> vk::SurfaceCapabilitiesKHR {
+        vk::SurfaceCapabilitiesKHR {
+            current_extent: current,
+            min_image_extent: vk::Extent2D { width: 1, height: 1 },
+            max_image_extent: vk::Extent2D { width: 1920, height: 1080 },
+            ..Default::default()
+        }
+    }
+
+    fn make_config(preferred_present_mode: Option<vk::PresentModeKHR>) -> SwapchainConfig {
+        SwapchainConfig {
+            allow_tearing: false,
+            formats: Box::new([]),
+            required_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            optional_usage: vk::ImageUsageFlags::empty(),
+            clipped: true,
+            preferred_present_mode,
+            pre_transform: PreTransformMode::Current,
+        }
+    }
+
+    #[test]
+    fn rotated_surface_transform() {
+        let capabilities = vk::SurfaceCapabilitiesKHR {
+            current_transform: vk::SurfaceTransformFlagsKHR::ROTATE_90,
+            supported_transforms: vk::SurfaceTransformFlagsKHR::IDENTITY | vk::SurfaceTransformFlagsKHR::ROTATE_90,
+            ..make_capabilities(vk::Extent2D { width: 1080, height: 1920 })
+        };
+
+        assert_eq!(select_transform(&capabilities, make_config(None).pre_transform), vk::SurfaceTransformFlagsKHR::ROTATE_90);
+        assert_eq!(select_transform(&capabilities, PreTransformMode::Identity), vk::SurfaceTransformFlagsKHR::IDENTITY);
+
+        // Only the lowest supported transform remains
+        let rotated_only = vk::SurfaceCapabilitiesKHR {
+            current_transform: vk::SurfaceTransformFlagsKHR::IDENTITY,
+            supported_transforms: vk::SurfaceTransformFlagsKHR::ROTATE_180 | vk::SurfaceTransformFlagsKHR::ROTATE_270,
+            ..capabilities
+        };
+        assert_eq!(select_transform(&rotated_only, PreTransformMode::Identity), vk::SurfaceTransformFlagsKHR::ROTATE_180);
+
+        // A identity swapchain on the rotated display is suboptimal on every present
+        assert!(is_transform_only_mismatch(&capabilities, vk::SurfaceTransformFlagsKHR::IDENTITY, Vec2u32::new(1080, 1920)));
+        assert!(!is_transform_only_mismatch(&capabilities, vk::SurfaceTransformFlagsKHR::ROTATE_90, Vec2u32::new(1080, 1920)));
+        // A resize must still recreate the swapchain
+        assert!(!is_transform_only_mismatch(&capabilities, vk::SurfaceTransformFlagsKHR::IDENTITY, Vec2u32::new(1920, 1080)));
+
+        let rotated = make_pre_transform_matrix(vk::SurfaceTransformFlagsKHR::ROTATE_90) * Vec4f32::new(1f32, 0f32, 0f32, 1f32);
+        assert_eq!(rotated, Vec4f32::new(0f32, 1f32, 0f32, 1f32));
+        assert_eq!(make_pre_transform_matrix(vk::SurfaceTransformFlagsKHR::IDENTITY), Mat4f32::identity());
+    }
+
+    #[test]
+    fn mailbox_falls_back_to_fifo() {
+        let fifo_only = [vk::PresentModeKHR::FIFO];
+        let present_mode = select_present_mode(&fifo_only, &make_config(Some(vk::PresentModeKHR::MAILBOX)));
+        assert_eq!(present_mode, vk::PresentModeKHR::FIFO);
+        assert!(is_vsync_present_mode(present_mode));
+
+        let supported = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE];
+        assert_eq!(select_present_mode(&supported, &make_config(Some(vk::PresentModeKHR::FIFO))), vk::PresentModeKHR::FIFO);
+        assert_eq!(select_present_mode(&supported, &make_config(None)), vk::PresentModeKHR::MAILBOX);
+        assert!(!is_vsync_present_mode(vk::PresentModeKHR::MAILBOX));
+    }
+
+    #[test]
+    fn clamped_extent() {
+        let capabilities = make_capabilities(vk::Extent2D { width: 800, height: 600 });
+        let extent = select_extent(&capabilities, Vec2u32::new(1000, 700)).unwrap();
+        assert_eq!(extent, vk::Extent2D { width: 800, height: 600 });
+
+        let capabilities = make_capabilities(vk::Extent2D { width: u32::MAX, height: u32::MAX });
+        let extent = select_extent(&capabilities, Vec2u32::new(4000, 700)).unwrap();
+        assert_eq!(extent, vk::Extent2D { width: 1920, height: 700 });
+
+        let mut capabilities = make_capabilities(vk::Extent2D { width: 0, height: 0 });
+        capabilities.max_image_extent = vk::Extent2D { width: 0, height: 0 };
+        assert!(matches!(select_extent(&capabilities, Vec2u32::new(1, 1)), Err(SwapchainCreateError::NoExtent)));
+    }
+}
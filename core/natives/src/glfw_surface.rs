@@ -29,15 +29,17 @@ impl GLFWSurfaceProvider {
     ) -> Self {
         let mut count = 0u32;
         let extensions = unsafe { glfw_get_required_instance_extensions(&mut count) };
-        if extensions.is_null() {
+        let extensions: Vec<_> = if extensions.is_null() {
+            // This means glfw could not find any vulkan window system integration on this platform.
+            // We report this once the surface is initialized.
             log::error!("Extensions returned by glfwGetRequiredInstanceExtensions is null");
-            panic!();
-        }
-
-        let extensions = unsafe { std::slice::from_raw_parts(extensions, count as usize) };
-        let extensions: Vec<_> = extensions.into_iter().map(|str| {
-            unsafe { CString::from(CStr::from_ptr(*str)) }
-        }).collect();
+            Vec::new()
+        } else {
+            let extensions = unsafe { std::slice::from_raw_parts(extensions, count as usize) };
+            extensions.into_iter().map(|str| {
+                unsafe { CString::from(CStr::from_ptr(*str)) }
+            }).collect()
+        };
 
         Self {
             required_extension: extensions,
@@ -56,8 +58,21 @@ impl SurfaceProvider for GLFWSurfaceProvider {
     fn init(&mut self, entry: &ash::Entry, instance: &ash::Instance) -> Result<vk::SurfaceKHR, SurfaceInitError> {
         let surface_khr = ash::extensions::khr::Surface::new(entry, instance);
 
+        if self.required_extension.is_empty() {
+            return Err(SurfaceInitError::UnsupportedPlatform(String::from("glfwGetRequiredInstanceExtensions returned no extensions")));
+        }
+
         let mut surface = vk::SurfaceKHR::null();
-        unsafe { (self.create_surface_fn)(instance.handle(), self.glfw_window, std::ptr::null(), &mut surface) }.result()?;
+        match unsafe { (self.create_surface_fn)(instance.handle(), self.glfw_window, std::ptr::null(), &mut surface) } {
+            vk::Result::SUCCESS => {},
+            vk::Result::ERROR_EXTENSION_NOT_PRESENT => {
+                return Err(SurfaceInitError::MissingExtension(self.required_extension.iter().find(|ext| ext.as_bytes() != b"VK_KHR_surface").cloned().unwrap_or_else(|| CString::new("VK_KHR_surface").unwrap())));
+            }
+            vk::Result::ERROR_INITIALIZATION_FAILED => {
+                return Err(SurfaceInitError::UnsupportedPlatform(String::from("glfwCreateWindowSurface failed to initialize the surface")));
+            }
+            err => return Err(SurfaceInitError::Vulkan(err)),
+        }
         self.surface = Some((surface, surface_khr));
 
         Ok(surface)
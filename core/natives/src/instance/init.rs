@@ -10,7 +10,7 @@ use vk_profiles_rs::vp;
 use crate::{BUILD_INFO, CRATE_NAME};
 
 use crate::instance::debug_messenger::DebugMessengerCallback;
-use crate::instance::instance::VulkanVersion;
+use crate::instance::instance::{HostAllocationCallbacks, VulkanVersion};
 
 use crate::prelude::*;
 
@@ -20,8 +20,12 @@ pub struct InstanceCreateConfig {
     application_version: u32,
     debug_messengers: Vec<DebugUtilsMessengerWrapper>,
     enable_validation: bool,
+    enable_sync_validation: bool,
+    enable_gpu_assisted_validation: bool,
     required_extensions: HashSet<CString>,
     require_surface_khr: bool,
+    allocation_callbacks: Option<HostAllocationCallbacks>,
+    api_version: Option<VulkanVersion>,
 }
 
 impl InstanceCreateConfig {
@@ -31,8 +35,12 @@ impl InstanceCreateConfig {
             application_version,
             debug_messengers: Vec::new(),
             enable_validation: false,
+            enable_sync_validation: false,
+            enable_gpu_assisted_validation: false,
             required_extensions: HashSet::new(),
             require_surface_khr: false,
+            allocation_callbacks: None,
+            api_version: None,
         }
     }
 
@@ -44,6 +52,27 @@ impl InstanceCreateConfig {
         self.enable_validation = true;
     }
 
+    /// Enables synchronization validation of the validation layer. This reports missing or
+    /// incorrect barriers and semaphores. Implies [`InstanceCreateConfig::enable_validation`].
+    ///
+    /// Requires VK_EXT_validation_features which is provided by the validation layer. If it is not
+    /// available a error is logged and only the default validation is performed.
+    pub fn enable_sync_validation(&mut self) {
+        self.enable_validation = true;
+        self.enable_sync_validation = true;
+    }
+
+    /// Enables gpu assisted validation of the validation layer. This instruments shaders to detect
+    /// out of bounds accesses and invalid descriptors at runtime. Implies
+    /// [`InstanceCreateConfig::enable_validation`].
+    ///
+    /// Requires VK_EXT_validation_features which is provided by the validation layer. If it is not
+    /// available a error is logged and only the default validation is performed.
+    pub fn enable_gpu_assisted_validation(&mut self) {
+        self.enable_validation = true;
+        self.enable_gpu_assisted_validation = true;
+    }
+
     pub fn add_required_extension(&mut self, extension: &CStr) {
         self.required_extensions.insert(CString::from(extension));
     }
@@ -51,6 +80,22 @@ impl InstanceCreateConfig {
     pub fn require_surface_khr(&mut self) {
         self.require_surface_khr = true;
     }
+
+    /// Requests a specific vulkan api version for the instance. If the loader does not support the
+    /// version instance creation fails with [`InstanceCreateError::UnsupportedApiVersion`].
+    ///
+    /// If no version is set vulkan 1.1 or the highest version supported by the loader if lower is
+    /// used.
+    pub fn set_api_version(&mut self, version: VulkanVersion) {
+        self.api_version = Some(version);
+    }
+
+    /// Sets the host allocation callbacks used for the instance and all objects created from it.
+    /// Devices created from the instance use the same callbacks unless overwritten in their
+    /// [`DeviceCreateConfig`](crate::device::init::DeviceCreateConfig).
+    pub fn set_allocation_callbacks(&mut self, callbacks: HostAllocationCallbacks) {
+        self.allocation_callbacks = Some(callbacks);
+    }
 }
 
 #[derive(Debug)]
@@ -59,6 +104,9 @@ pub enum InstanceCreateError {
     ProfileNotSupported,
     MissingExtension(CString),
     Utf8Error(Utf8Error),
+    /// The requested api version is higher than the version supported by the loader. Contains the
+    /// requested and supported version.
+    UnsupportedApiVersion(VulkanVersion, VulkanVersion),
 }
 
 impl From<vk::Result> for InstanceCreateError {
@@ -89,6 +137,8 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
     }
     log::info!("Vulkan instance version: {:?}", vulkan_version);
 
+    let (api_version, vulkan_version) = select_api_version(config.api_version, vulkan_version)?;
+
     log::info!("Using profile {:?} for instance creation", unsafe { CStr::from_ptr(profile.profile_name.as_ptr()) });
     if !unsafe { vp_fn.get_instance_profile_support(None, &profile)? } {
         return Err(InstanceCreateError::ProfileNotSupported)
@@ -117,22 +167,45 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
         }
     }
 
+    let validation_layer = CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap();
+    let validation_features_ext = CStr::from_bytes_with_nul(b"VK_EXT_validation_features\0").unwrap();
+
+    let mut validation_feature_enables = Vec::new();
     let required_layers = if config.enable_validation {
         log::info!("Validation layers enabled");
-        vec![CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap().as_ptr()]
+
+        let layer_available = entry.enumerate_instance_layer_properties()?.iter().any(|layer| {
+            unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) } == validation_layer
+        });
+        if !layer_available {
+            log::error!("Validation was requested but the layer {:?} is not available", validation_layer);
+        }
+
+        let enables = get_validation_feature_enables(config.enable_sync_validation, config.enable_gpu_assisted_validation);
+        if !enables.is_empty() && layer_available {
+            let layer_extensions = entry.enumerate_instance_extension_properties(Some(validation_layer))?;
+            if layer_extensions.iter().any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == validation_features_ext) {
+                log::info!("Enabled validation features: {:?}", enables);
+                required_extensions_str.push(validation_features_ext.as_ptr());
+                validation_feature_enables = enables;
+            } else {
+                log::error!("Validation features {:?} were requested but {:?} is not available", enables, validation_features_ext);
+            }
+        }
+
+        vec![validation_layer.as_ptr()]
     } else {
         log::info!("Validation layers disabled");
         Vec::new()
     };
 
-    let max_api_version = VulkanVersion::VK_1_1;
     let name = CString::new(CRATE_NAME).unwrap();
     let application_info = vk::ApplicationInfo::builder()
         .application_name(config.application_name.as_c_str())
         .application_version(config.application_version)
         .engine_name(&name)
         .engine_version(vk::make_api_version(0, BUILD_INFO.version_major, BUILD_INFO.version_minor, BUILD_INFO.version_patch))
-        .api_version(max_api_version.into());
+        .api_version(api_version.into());
 
     let mut instance_create_info = vk::InstanceCreateInfo::builder()
         .application_info(&application_info)
@@ -152,12 +225,18 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
         instance_create_info = instance_create_info.push_next(debug_messenger);
     }
 
+    let mut validation_features = vk::ValidationFeaturesEXT::builder()
+        .enabled_validation_features(validation_feature_enables.as_slice());
+    if !validation_feature_enables.is_empty() {
+        instance_create_info = instance_create_info.push_next(&mut validation_features);
+    }
+
     let vp_instance_create_info = vp::InstanceCreateInfo::builder()
         .profile(&profile)
         .create_info(&instance_create_info)
         .flags(vp::InstanceCreateFlagBits::MERGE_EXTENSIONS);
 
-    let instance = unsafe { vp_fn.create_instance(&entry, &vp_instance_create_info, None) }?;
+    let instance = unsafe { vp_fn.create_instance(&entry, &vp_instance_create_info, config.allocation_callbacks.as_ref().map(HostAllocationCallbacks::get)) }?;
 
     let surface_khr = if required_extensions.contains(CStr::from_bytes_with_nul(b"VK_KHR_surface\0").unwrap()) {
         Some(ash::extensions::khr::Surface::new(&entry, &instance))
@@ -165,17 +244,49 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
         None
     };
 
-    let vulkan_version = std::cmp::min(max_api_version, vulkan_version);
     Ok(InstanceContext::new(
         vulkan_version,
         profile,
         entry,
         instance,
         surface_khr,
+        config.allocation_callbacks,
+        config.enable_validation,
         debug_messengers
     ))
 }
 
+/// Returns the validation features which need to be enabled through VK_EXT_validation_features.
+fn get_validation_feature_enables(sync_validation: bool, gpu_assisted_validation: bool) -> Vec<vk::ValidationFeatureEnableEXT> {
+    let mut enables = Vec::new();
+    if sync_validation {
+        enables.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+    }
+    if gpu_assisted_validation {
+        enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+    }
+    enables
+}
+
+/// Selects the api version passed to the instance and the resulting instance version.
+///
+/// Returns an error if the requested version exceeds the version supported by the loader.
+fn select_api_version(requested: Option<VulkanVersion>, loader_version: VulkanVersion) -> Result<(VulkanVersion, VulkanVersion), InstanceCreateError> {
+    match requested {
+        Some(requested) => {
+            if requested > loader_version {
+                Err(InstanceCreateError::UnsupportedApiVersion(requested, loader_version))
+            } else {
+                Ok((requested, requested))
+            }
+        }
+        None => {
+            let max_api_version = VulkanVersion::VK_1_1;
+            Ok((max_api_version, std::cmp::min(max_api_version, loader_version)))
+        }
+    }
+}
+
 pub struct DebugUtilsMessengerWrapper {
     callback: Box<dyn DebugMessengerCallback>
 }
@@ -226,4 +337,116 @@ mod tests {
 
         let instance = create_instance(config).unwrap();
     }
+
+    #[test]
+    fn unsupported_api_version() {
+        let result = select_api_version(Some(VulkanVersion::VK_1_3), VulkanVersion::VK_1_1);
+        assert!(matches!(result, Err(InstanceCreateError::UnsupportedApiVersion(VulkanVersion::VK_1_3, VulkanVersion::VK_1_1))));
+
+        let (api_version, version) = select_api_version(Some(VulkanVersion::VK_1_2), VulkanVersion::VK_1_3).unwrap();
+        assert!(api_version == VulkanVersion::VK_1_2 && version == VulkanVersion::VK_1_2);
+
+        let (api_version, version) = select_api_version(None, VulkanVersion::VK_1_0).unwrap();
+        assert!(api_version == VulkanVersion::VK_1_1 && version == VulkanVersion::VK_1_0);
+    }
+
+    #[test]
+    fn validation_features_chain() {
+        let mut config = InstanceCreateConfig::new(CString::new("B4DCoreTest").unwrap(), 1);
+        config.enable_sync_validation();
+        config.enable_gpu_assisted_validation();
+        assert!(config.enable_validation);
+        assert!(get_validation_feature_enables(false, false).is_empty());
+
+        let enables = get_validation_feature_enables(config.enable_sync_validation, config.enable_gpu_assisted_validation);
+        let mut features = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(enables.as_slice());
+        let info = vk::InstanceCreateInfo::builder()
+            .push_next(&mut features);
+
+        let next = unsafe { (info.p_next as *const vk::ValidationFeaturesEXT).as_ref() }.unwrap();
+        assert_eq!(next.s_type, vk::StructureType::VALIDATION_FEATURES_EXT);
+        let chained = unsafe { std::slice::from_raw_parts(next.p_enabled_validation_features, next.enabled_validation_feature_count as usize) };
+        assert_eq!(chained, &[vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION, vk::ValidationFeatureEnableEXT::GPU_ASSISTED]);
+    }
+
+    /// Size of the header placed in front of every allocation to store its layout.
+    const HEADER_SIZE: usize = 16;
+
+    fn make_layout(size: usize, alignment: usize) -> std::alloc::Layout {
+        let alignment = std::cmp::max(alignment, HEADER_SIZE);
+        std::alloc::Layout::from_size_align(size + alignment, alignment).unwrap()
+    }
+
+    unsafe fn counting_alloc(live: &std::sync::atomic::AtomicIsize, size: usize, alignment: usize) -> *mut c_void {
+        let layout = make_layout(size, alignment);
+        let base = std::alloc::alloc(layout);
+        if base.is_null() {
+            return std::ptr::null_mut();
+        }
+        let ptr = base.add(layout.align());
+        *(ptr.sub(HEADER_SIZE) as *mut (usize, usize)) = (size, alignment);
+        live.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        ptr as *mut c_void
+    }
+
+    unsafe fn counting_free(live: &std::sync::atomic::AtomicIsize, ptr: *mut c_void) {
+        if ptr.is_null() {
+            return;
+        }
+        let ptr = ptr as *mut u8;
+        let (size, alignment) = *(ptr.sub(HEADER_SIZE) as *const (usize, usize));
+        let layout = make_layout(size, alignment);
+        std::alloc::dealloc(ptr.sub(layout.align()), layout);
+        live.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    unsafe extern "system" fn alloc_fn(user_data: *mut c_void, size: usize, alignment: usize, _: vk::SystemAllocationScope) -> *mut c_void {
+        counting_alloc(&*(user_data as *const std::sync::atomic::AtomicIsize), size, alignment)
+    }
+
+    unsafe extern "system" fn realloc_fn(user_data: *mut c_void, original: *mut c_void, size: usize, alignment: usize, _: vk::SystemAllocationScope) -> *mut c_void {
+        let live = &*(user_data as *const std::sync::atomic::AtomicIsize);
+        if size == 0 {
+            counting_free(live, original);
+            return std::ptr::null_mut();
+        }
+        let new = counting_alloc(live, size, alignment);
+        if !new.is_null() && !original.is_null() {
+            let (old_size, _) = *((original as *const u8).sub(HEADER_SIZE) as *const (usize, usize));
+            std::ptr::copy_nonoverlapping(original as *const u8, new as *mut u8, std::cmp::min(old_size, size));
+            counting_free(live, original);
+        }
+        new
+    }
+
+    unsafe extern "system" fn free_fn(user_data: *mut c_void, ptr: *mut c_void) {
+        counting_free(&*(user_data as *const std::sync::atomic::AtomicIsize), ptr)
+    }
+
+    #[test]
+    fn allocation_callbacks_balance() {
+        let live = Box::new(std::sync::atomic::AtomicIsize::new(0));
+
+        let callbacks = vk::AllocationCallbacks {
+            p_user_data: live.as_ref() as *const std::sync::atomic::AtomicIsize as *mut c_void,
+            pfn_allocation: Some(alloc_fn),
+            pfn_reallocation: Some(realloc_fn),
+            pfn_free: Some(free_fn),
+            pfn_internal_allocation: None,
+            pfn_internal_free: None
+        };
+
+        let mut config = InstanceCreateConfig::new(
+            CString::from(CStr::from_bytes_with_nul(b"B4DCoreTest\0").unwrap()),
+            1,
+        );
+        config.set_allocation_callbacks(unsafe { HostAllocationCallbacks::new(callbacks) });
+
+        let instance = create_instance(config).unwrap();
+        assert!(instance.get_allocation_callbacks().is_some());
+        drop(instance);
+
+        assert_eq!(live.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
 }
\ No newline at end of file
@@ -57,6 +57,48 @@ impl Debug for VulkanVersion {
     }
 }
 
+/// User supplied host memory allocation callbacks passed to vulkan when creating or destroying
+/// objects.
+///
+/// The vulkan spec requires the callbacks to be callable from any thread which is why this type
+/// is [`Send`] and [`Sync`]. The user data pointer must remain valid for the lifetime of the
+/// instance and all devices created from it.
+#[derive(Copy, Clone)]
+pub struct HostAllocationCallbacks(vk::AllocationCallbacks);
+
+impl HostAllocationCallbacks {
+    /// Wraps some allocation callbacks.
+    ///
+    /// # Safety
+    /// The callbacks must be thread safe and the user data must remain valid for as long as any
+    /// object created with the callbacks exists.
+    pub unsafe fn new(callbacks: vk::AllocationCallbacks) -> Self {
+        Self(callbacks)
+    }
+
+    pub fn get(&self) -> &vk::AllocationCallbacks {
+        &self.0
+    }
+}
+
+unsafe impl Send for HostAllocationCallbacks {
+}
+unsafe impl Sync for HostAllocationCallbacks {
+}
+
+impl UnwindSafe for HostAllocationCallbacks {
+}
+impl RefUnwindSafe for HostAllocationCallbacks {
+}
+
+impl Debug for HostAllocationCallbacks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostAllocationCallbacks")
+            .field("p_user_data", &self.0.p_user_data)
+            .finish()
+    }
+}
+
 /// Implementation of the instance context.
 ///
 /// Since we need to control drop order most of the fields are ManuallyDrop
@@ -67,6 +109,8 @@ pub struct InstanceContext {
     entry: ash::Entry,
     instance: ash::Instance,
     surface_khr: Option<ash::extensions::khr::Surface>,
+    allocation_callbacks: Option<HostAllocationCallbacks>,
+    validation_enabled: bool,
     _debug_messengers: Box<[DebugUtilsMessengerWrapper]>,
 }
 
@@ -77,6 +121,8 @@ impl InstanceContext {
         entry: ash::Entry,
         instance: ash::Instance,
         surface_khr: Option<ash::extensions::khr::Surface>,
+        allocation_callbacks: Option<HostAllocationCallbacks>,
+        validation_enabled: bool,
         debug_messengers: Box<[DebugUtilsMessengerWrapper]>
     ) -> Arc<Self> {
         Arc::new(Self {
@@ -86,6 +132,8 @@ impl InstanceContext {
             entry,
             instance,
             surface_khr,
+            allocation_callbacks,
+            validation_enabled,
             _debug_messengers: debug_messengers,
         })
     }
@@ -106,6 +154,21 @@ impl InstanceContext {
         self.surface_khr.as_ref()
     }
 
+    /// Returns the allocation callbacks which must be used for all instance level vulkan objects.
+    pub fn get_allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.allocation_callbacks.as_ref().map(HostAllocationCallbacks::get)
+    }
+
+    /// Returns the wrapped allocation callbacks the instance was created with.
+    pub fn get_host_allocation_callbacks(&self) -> Option<HostAllocationCallbacks> {
+        self.allocation_callbacks
+    }
+
+    /// Returns true if the instance was created with validation layers enabled.
+    pub fn is_validation_enabled(&self) -> bool {
+        self.validation_enabled
+    }
+
     pub fn get_version(&self) -> VulkanVersion {
         self.version
     }
@@ -118,7 +181,7 @@ impl InstanceContext {
 impl Drop for InstanceContext {
     fn drop(&mut self) {
         unsafe {
-            self.instance.destroy_instance(None);
+            self.instance.destroy_instance(self.allocation_callbacks.as_ref().map(HostAllocationCallbacks::get));
         }
     }
 }
@@ -0,0 +1,251 @@
+//! A global table of textures accessible from shaders by index using descriptor indexing.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+
+use crate::renderer::emulator::global_objects::{GlobalImage, SamplerInfo};
+use crate::renderer::emulator::share::Share;
+
+use crate::prelude::*;
+
+/// A single update after bind descriptor set containing a partially bound array of combined image
+/// samplers. Textures registered in the table can be accessed in shaders by their index.
+///
+/// The [`DebugPipeline`](super::debug_pipeline::DebugPipeline) binds the table as set 1. Draws
+/// select a texture with [`PassRecorder::set_bindless_texture`](super::PassRecorder::set_bindless_texture).
+///
+/// Only available if the device supports descriptor indexing.
+pub struct BindlessTextureTable {
+    share: Arc<Share>,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    slots: Mutex<SlotState>,
+}
+
+impl BindlessTextureTable {
+    /// The number of textures which can be registered in the table at the same time.
+    pub const CAPACITY: u32 = 1024;
+
+    pub(super) fn new(share: Arc<Share>) -> Result<Self, vk::Result> {
+        let device = share.get_device();
+
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(Self::CAPACITY)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+        let binding_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND |
+            vk::DescriptorBindingFlags::UPDATE_AFTER_BIND |
+            vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+
+        let mut flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(std::slice::from_ref(&binding_flags));
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(std::slice::from_ref(&binding))
+            .push_next(&mut flags_info);
+
+        let set_layout = unsafe {
+            device.vk().create_descriptor_set_layout(&info, device.get_allocation_callbacks())
+        }?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: Self::CAPACITY
+        };
+
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(std::slice::from_ref(&pool_size));
+
+        let descriptor_pool = match unsafe {
+            device.vk().create_descriptor_pool(&info, device.get_allocation_callbacks())
+        } {
+            Ok(pool) => pool,
+            Err(err) => {
+                unsafe { device.vk().destroy_descriptor_set_layout(set_layout, device.get_allocation_callbacks()) };
+                return Err(err);
+            }
+        };
+
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(std::slice::from_ref(&set_layout));
+
+        let descriptor_set = match unsafe {
+            device.vk().allocate_descriptor_sets(&info)
+        } {
+            Ok(sets) => sets[0],
+            Err(err) => {
+                unsafe {
+                    device.vk().destroy_descriptor_pool(descriptor_pool, device.get_allocation_callbacks());
+                    device.vk().destroy_descriptor_set_layout(set_layout, device.get_allocation_callbacks());
+                }
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            share,
+            set_layout,
+            descriptor_pool,
+            descriptor_set,
+            slots: Mutex::new(SlotState {
+                allocator: SlotAllocator::new(Self::CAPACITY),
+                images: vec![None; Self::CAPACITY as usize],
+            })
+        })
+    }
+
+    /// Registers a texture in the table and returns its index. The image is kept alive until it
+    /// is unregistered and all passes which may have accessed it have completed.
+    ///
+    /// Returns [`None`] if the table is full.
+    pub fn register(&self, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo) -> Option<u32> {
+        let mut guard = self.slots.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned slot mutex in BindlessTextureTable::register");
+            panic!()
+        });
+
+        let index = guard.allocator.allocate(self.share.get_last_completed_pass())?;
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler: image.get_sampler(sampler_info),
+            image_view: image.get_sampler_view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        };
+
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(std::slice::from_ref(&image_info));
+
+        // Descriptor updates must be externally synchronized so we keep the guard until here
+        unsafe {
+            self.share.get_device().vk().update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+
+        // Any previous image is only dropped now that the slot has been reused
+        guard.images[index as usize] = Some(image.clone());
+
+        Some(index)
+    }
+
+    /// Removes a texture from the table. The index may be reused once all passes started before
+    /// this call have completed.
+    pub fn unregister(&self, index: u32) {
+        let mut guard = self.slots.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned slot mutex in BindlessTextureTable::unregister");
+            panic!()
+        });
+
+        if guard.images.get(index as usize).map_or(true, Option::is_none) || guard.allocator.is_free(index) {
+            log::error!("Called BindlessTextureTable::unregister with unregistered index {:?}", index);
+            panic!()
+        }
+
+        guard.allocator.free(index, self.share.get_last_started_pass());
+    }
+
+    /// Returns the layout of the table descriptor set. Pipelines using the table must create their
+    /// pipeline layout with a set using this layout.
+    pub fn get_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.set_layout
+    }
+
+    /// Returns the descriptor set containing the table. Binding 0 contains the texture array.
+    pub fn get_descriptor_set(&self) -> vk::DescriptorSet {
+        self.descriptor_set
+    }
+}
+
+impl Drop for BindlessTextureTable {
+    fn drop(&mut self) {
+        let device = self.share.get_device();
+        unsafe {
+            device.vk().destroy_descriptor_pool(self.descriptor_pool, device.get_allocation_callbacks());
+            device.vk().destroy_descriptor_set_layout(self.set_layout, device.get_allocation_callbacks());
+        }
+    }
+}
+
+struct SlotState {
+    allocator: SlotAllocator,
+    images: Vec<Option<Arc<GlobalImage>>>,
+}
+
+/// Manages the free indices of the table. Freed indices are only reused once the pass which was
+/// active when they were freed has completed.
+struct SlotAllocator {
+    capacity: u32,
+    next_unused: u32,
+    free: Vec<u32>,
+    pending: VecDeque<(u32, u64)>,
+}
+
+impl SlotAllocator {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            next_unused: 0,
+            free: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn allocate(&mut self, completed_pass: u64) -> Option<u32> {
+        while let Some((index, pass)) = self.pending.front().cloned() {
+            if pass > completed_pass {
+                break;
+            }
+            self.pending.pop_front();
+            self.free.push(index);
+        }
+
+        if let Some(index) = self.free.pop() {
+            Some(index)
+        } else if self.next_unused < self.capacity {
+            let index = self.next_unused;
+            self.next_unused += 1;
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn is_free(&self, index: u32) -> bool {
+        index >= self.next_unused || self.free.contains(&index) || self.pending.iter().any(|(i, _)| *i == index)
+    }
+
+    fn free(&mut self, index: u32, last_pass: u64) {
+        self.pending.push_back((index, last_pass));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_reuse_after_pass() {
+        let mut allocator = SlotAllocator::new(2);
+        let a = allocator.allocate(0).unwrap();
+        let b = allocator.allocate(0).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(allocator.allocate(0), None);
+
+        allocator.free(a, 3);
+        assert!(allocator.is_free(a));
+        assert!(!allocator.is_free(b));
+        assert_eq!(allocator.allocate(2), None);
+        assert_eq!(allocator.allocate(3), Some(a));
+    }
+}
@@ -0,0 +1,433 @@
+//! A fullscreen blit used to composite global images.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+use bytemuck::{bytes_of, Pod, Zeroable};
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::device::device_utils::create_shader_from_bytes;
+use crate::util::format::{ClearColorType, Format};
+use crate::util::vk::{make_full_rect, make_full_viewport};
+
+use crate::prelude::*;
+
+/// Optional color transformations applied while blitting.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct BlitOptions {
+    /// Applies reinhard tonemapping to map hdr colors into the `[0, 1]` range.
+    pub tonemap: bool,
+
+    /// Applies gamma correction with the specified gamma after tonemapping.
+    pub gamma: Option<f32>,
+}
+
+impl BlitOptions {
+    fn to_push_constants(&self) -> BlitPushConstants {
+        let mut flags = 0;
+        if self.tonemap {
+            flags |= BLIT_FLAG_TONEMAP;
+        }
+        if self.gamma.is_some() {
+            flags |= BLIT_FLAG_GAMMA;
+        }
+
+        BlitPushConstants {
+            flags,
+            gamma: self.gamma.unwrap_or(1f32),
+        }
+    }
+}
+
+/// Returns true if the blit can write to images of `format`. The fragment shader outputs float
+/// colors so integer and depth formats cannot be used as the destination.
+pub(super) fn is_blit_dst_format(format: &Format) -> bool {
+    format.get_clear_color_type() == Some(ClearColorType::Float)
+}
+
+/// Objects created for a single blit which must be kept alive until the blit has completed.
+pub(super) struct BlitObjects {
+    attachment_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Draws a fullscreen triangle sampling a source image into a destination image.
+///
+/// Render passes and pipelines are created lazily for each destination format.
+pub(super) struct FullscreenBlit {
+    device: Arc<DeviceContext>,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: Mutex<HashMap<vk::Format, (vk::RenderPass, vk::Pipeline)>>,
+}
+
+impl FullscreenBlit {
+    pub(super) fn new(device: Arc<DeviceContext>) -> Self {
+        let vertex_module = unsafe {
+            create_shader_from_bytes(device.get_functions(), FULLSCREEN_VERTEX_BIN)
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateShaderModule returned {:?} when creating the fullscreen blit vertex module", err);
+            panic!()
+        });
+        let fragment_module = unsafe {
+            create_shader_from_bytes(device.get_functions(), COMPOSITE_FRAGMENT_BIN)
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateShaderModule returned {:?} when creating the fullscreen blit fragment module", err);
+            panic!()
+        });
+
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+            .bindings(std::slice::from_ref(&binding));
+
+        let set_layout = unsafe {
+            device.vk().create_descriptor_set_layout(&info, device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in FullscreenBlit::new", err);
+            panic!()
+        });
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<BlitPushConstants>() as u32,
+        };
+
+        let info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&set_layout))
+            .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+
+        let pipeline_layout = unsafe {
+            device.vk().create_pipeline_layout(&info, device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreatePipelineLayout returned {:?} in FullscreenBlit::new", err);
+            panic!()
+        });
+
+        Self {
+            device,
+            vertex_module,
+            fragment_module,
+            set_layout,
+            pipeline_layout,
+            pipelines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a blit of `src_view` into mip level 0 of `dst_image`. The source image must be in
+    /// the `SHADER_READ_ONLY_OPTIMAL` layout and the destination image in the
+    /// `COLOR_ATTACHMENT_OPTIMAL` layout. No memory barriers are generated.
+    ///
+    /// The returned objects must be passed to [`FullscreenBlit::destroy_objects`] once the blit
+    /// has completed.
+    pub(super) fn record(&self, cmd: vk::CommandBuffer, src_view: vk::ImageView, sampler: vk::Sampler, dst_image: vk::Image, dst_format: vk::Format, dst_size: Vec2u32, options: &BlitOptions) -> BlitObjects {
+        let device = &self.device;
+        let (render_pass, pipeline) = self.get_pipeline(dst_format);
+        let objects = self.create_objects(render_pass, dst_image, dst_format, dst_size);
+
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(objects.framebuffer)
+            .render_area(make_full_rect(dst_size));
+
+        let viewport = make_full_viewport(dst_size);
+        let scissor = make_full_rect(dst_size);
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: src_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        };
+
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(std::slice::from_ref(&image_info));
+
+        let push_constants = options.to_push_constants();
+
+        unsafe {
+            device.vk().cmd_begin_render_pass(cmd, &info, vk::SubpassContents::INLINE);
+            device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.vk().cmd_set_viewport(cmd, 0, std::slice::from_ref(&viewport));
+            device.vk().cmd_set_scissor(cmd, 0, std::slice::from_ref(&scissor));
+            device.push_descriptor_khr().cmd_push_descriptor_set(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, std::slice::from_ref(&write));
+            device.vk().cmd_push_constants(cmd, self.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, bytes_of(&push_constants));
+            device.vk().cmd_draw(cmd, 3, 1, 0, 0);
+            device.vk().cmd_end_render_pass(cmd);
+        }
+
+        objects
+    }
+
+    pub(super) fn destroy_objects(&self, objects: BlitObjects) {
+        unsafe {
+            self.device.vk().destroy_framebuffer(objects.framebuffer, self.device.get_allocation_callbacks());
+            self.device.vk().destroy_image_view(objects.attachment_view, self.device.get_allocation_callbacks());
+        }
+    }
+
+    fn create_objects(&self, render_pass: vk::RenderPass, dst_image: vk::Image, dst_format: vk::Format, dst_size: Vec2u32) -> BlitObjects {
+        let device = &self.device;
+
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(dst_image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(dst_format)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1
+            });
+
+        let attachment_view = unsafe {
+            device.vk().create_image_view(&info, device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateImageView returned {:?} in FullscreenBlit::create_objects", err);
+            panic!()
+        });
+
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(std::slice::from_ref(&attachment_view))
+            .width(dst_size[0])
+            .height(dst_size[1])
+            .layers(1);
+
+        let framebuffer = unsafe {
+            device.vk().create_framebuffer(&info, device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateFramebuffer returned {:?} in FullscreenBlit::create_objects", err);
+            panic!()
+        });
+
+        BlitObjects {
+            attachment_view,
+            framebuffer
+        }
+    }
+
+    fn get_pipeline(&self, format: vk::Format) -> (vk::RenderPass, vk::Pipeline) {
+        let mut guard = self.pipelines.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned pipeline mutex in FullscreenBlit::get_pipeline");
+            panic!()
+        });
+
+        *guard.entry(format).or_insert_with(|| {
+            let render_pass = self.create_render_pass(format);
+            let pipeline = self.create_pipeline(render_pass);
+            (render_pass, pipeline)
+        })
+    }
+
+    fn create_render_pass(&self, format: vk::Format) -> vk::RenderPass {
+        // The whole image is overwritten so the previous content is not needed
+        let attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let attachment_reference = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        };
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(std::slice::from_ref(&attachment_reference));
+
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(std::slice::from_ref(&attachment))
+            .subpasses(std::slice::from_ref(&subpass));
+
+        unsafe {
+            self.device.vk().create_render_pass(&info, self.device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateRenderPass returned {:?} in FullscreenBlit::create_render_pass", err);
+            panic!()
+        })
+    }
+
+    fn create_pipeline(&self, render_pass: vk::RenderPass) -> vk::Pipeline {
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(self.vertex_module)
+                .name(SHADER_ENTRY)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.fragment_module)
+                .name(SHADER_ENTRY)
+                .build()
+        ];
+
+        let input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder();
+
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(std::slice::from_ref(&attachment));
+
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR
+        ];
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&input_state)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(self.pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        let pipelines = unsafe {
+            self.device.vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), self.device.get_allocation_callbacks())
+        }.unwrap_or_else(|(_, err)| {
+            log::error!("vkCreateGraphicsPipelines returned {:?} in FullscreenBlit::create_pipeline", err);
+            panic!()
+        });
+
+        pipelines[0]
+    }
+}
+
+impl Drop for FullscreenBlit {
+    fn drop(&mut self) {
+        let device = &self.device;
+        unsafe {
+            for (render_pass, pipeline) in self.pipelines.get_mut().unwrap().values() {
+                device.vk().destroy_pipeline(*pipeline, device.get_allocation_callbacks());
+                device.vk().destroy_render_pass(*render_pass, device.get_allocation_callbacks());
+            }
+            device.vk().destroy_pipeline_layout(self.pipeline_layout, device.get_allocation_callbacks());
+            device.vk().destroy_descriptor_set_layout(self.set_layout, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(self.fragment_module, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(self.vertex_module, device.get_allocation_callbacks());
+        }
+    }
+}
+
+const BLIT_FLAG_TONEMAP: u32 = 1;
+const BLIT_FLAG_GAMMA: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct BlitPushConstants {
+    flags: u32,
+    gamma: f32,
+}
+const_assert_eq!(std::mem::size_of::<BlitPushConstants>(), 8);
+
+unsafe impl Zeroable for BlitPushConstants {}
+unsafe impl Pod for BlitPushConstants {}
+
+const SHADER_ENTRY: &'static CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+static FULLSCREEN_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/blit/fullscreen_vert.spv"));
+static COMPOSITE_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/blit/composite_frag.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::emulator::{EmulatorRenderer, ImageReadRegion, SamplerInfo};
+
+    #[test]
+    fn blit_option_flags() {
+        let plain = BlitOptions::default().to_push_constants();
+        assert_eq!(plain, BlitPushConstants { flags: 0, gamma: 1.0 });
+
+        let hdr = BlitOptions { tonemap: true, gamma: Some(2.2) }.to_push_constants();
+        assert_eq!(hdr.flags, BLIT_FLAG_TONEMAP | BLIT_FLAG_GAMMA);
+        assert_eq!(hdr.gamma, 2.2);
+    }
+
+    #[test]
+    fn integer_dst_rejected() {
+        assert!(is_blit_dst_format(&Format::R8G8B8A8_UNORM));
+        assert!(is_blit_dst_format(&Format::R16G16B16A16_SFLOAT));
+        assert!(!is_blit_dst_format(&Format::R32_UINT));
+        assert!(!is_blit_dst_format(&Format::R32_SINT));
+        assert!(!is_blit_dst_format(&Format::D32_SFLOAT));
+    }
+
+    #[test]
+    fn fills_dst_of_different_size() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+
+        // A solid 16x16 source blitted into a 40x10 destination must fill every destination pixel
+        let src = emulator.create_global_image_cleared(Vec2u32::new(16, 16), 1, &Format::R8G8B8A8_UNORM, vk::ClearColorValue { float32: [0.0, 1.0, 0.0, 1.0] });
+        let dst_size = Vec2u32::new(40, 10);
+        let dst = emulator.create_global_image(dst_size, &Format::R8G8B8A8_UNORM);
+
+        let sampler = SamplerInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy_enable: false,
+        };
+        emulator.blit_fullscreen(&src, &dst, &sampler, BlitOptions::default());
+
+        let token = dst.read_sub_image_zeroed(&[ImageReadRegion::new_full(dst_size)], (dst_size.x * dst_size.y * 4) as usize);
+        emulator.flush().unwrap();
+        let texels = token.wait();
+
+        assert_eq!(texels.len(), 40 * 10 * 4);
+        for texel in texels.chunks_exact(4) {
+            assert_eq!(texel, &[0, 255, 0, 255]);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Gpu crash breadcrumbs written with VK_NV_device_diagnostic_checkpoints.
+//!
+//! If enabled a checkpoint is written into the command buffer before each emulator task. After a
+//! device lost error the checkpoints the gpu reached can be queried to determine which task caused
+//! the crash.
+
+use std::collections::VecDeque;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::pass::PassId;
+
+/// The maximum number of breadcrumbs which can be resolved into checkpoints. Older breadcrumbs
+/// are discarded.
+const MAX_BREADCRUMBS: usize = 4096;
+
+/// A breadcrumb reached by the gpu.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Checkpoint {
+    /// The pipeline stage at which the gpu reached the breadcrumb.
+    pub stage: vk::PipelineStageFlags,
+
+    /// The pass the task was recorded in. [`None`] for global object tasks which are recorded
+    /// outside of passes.
+    pub pass_id: Option<PassId>,
+
+    /// The name of the task recorded after the breadcrumb.
+    pub task: &'static str,
+}
+
+pub(super) struct BreadcrumbLog {
+    enabled: AtomicBool,
+    entries: Mutex<BreadcrumbEntries>,
+}
+
+struct BreadcrumbEntries {
+    next_marker: u64,
+    entries: VecDeque<(u64, Option<PassId>, &'static str)>,
+}
+
+impl BreadcrumbLog {
+    pub(super) fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            entries: Mutex::new(BreadcrumbEntries {
+                next_marker: 1,
+                entries: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub(super) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Writes a breadcrumb for a task into the command buffer if breadcrumbs are enabled and the
+    /// device supports diagnostic checkpoints.
+    pub(super) fn record(&self, device: &DeviceContext, cmd: vk::CommandBuffer, pass_id: Option<PassId>, task: &'static str) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        if let Some(checkpoints) = device.diagnostic_checkpoints_nv() {
+            let marker = self.push(pass_id, task);
+            unsafe {
+                // The marker is never dereferenced by the driver so we can use it as a plain id
+                checkpoints.cmd_set_checkpoint(cmd, marker as usize as *const c_void);
+            }
+        }
+    }
+
+    /// Allocates a new marker for a task. Markers are never 0 since null markers may be reported
+    /// for checkpoints without a breadcrumb.
+    fn push(&self, pass_id: Option<PassId>, task: &'static str) -> u64 {
+        let mut guard = self.entries.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned breadcrumb mutex in BreadcrumbLog::push");
+            panic!()
+        });
+
+        let marker = guard.next_marker;
+        guard.next_marker += 1;
+
+        if guard.entries.len() == MAX_BREADCRUMBS {
+            guard.entries.pop_front();
+        }
+        guard.entries.push_back((marker, pass_id, task));
+
+        marker
+    }
+
+    /// Resolves the markers reported by the gpu into checkpoints. Unknown markers are skipped.
+    pub(super) fn resolve(&self, data: &[(vk::PipelineStageFlags, u64)]) -> Vec<Checkpoint> {
+        let guard = self.entries.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned breadcrumb mutex in BreadcrumbLog::resolve");
+            panic!()
+        });
+
+        data.iter().filter_map(|(stage, marker)| {
+            // Markers are allocated in increasing order
+            let index = guard.entries.binary_search_by_key(marker, |(marker, _, _)| *marker).ok()?;
+            let (_, pass_id, task) = guard.entries[index];
+            Some(Checkpoint {
+                stage: *stage,
+                pass_id,
+                task,
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints_grow_with_tasks() {
+        let log = BreadcrumbLog::new();
+
+        let mut reported = Vec::new();
+        for (index, task) in ["WriteGlobalImage", "Draw", "Draw"].iter().enumerate() {
+            let marker = log.push(Some(PassId::from_raw(1)), task);
+            assert_ne!(marker, 0);
+
+            // Emulates the gpu reaching the breadcrumb at the bottom of the pipe
+            reported.push((vk::PipelineStageFlags::BOTTOM_OF_PIPE, marker));
+            let checkpoints = log.resolve(&reported);
+            assert_eq!(checkpoints.len(), index + 1);
+            assert_eq!(checkpoints[index].task, *task);
+        }
+
+        let marker = log.push(None, "ReadGlobalImage");
+        let checkpoints = log.resolve(&[(vk::PipelineStageFlags::TOP_OF_PIPE, marker), (vk::PipelineStageFlags::TOP_OF_PIPE, 0)]);
+        assert_eq!(checkpoints, vec![Checkpoint { stage: vk::PipelineStageFlags::TOP_OF_PIPE, pass_id: None, task: "ReadGlobalImage" }]);
+    }
+
+    #[test]
+    fn old_breadcrumbs_discarded() {
+        let log = BreadcrumbLog::new();
+        let first = log.push(None, "WriteGlobalMesh");
+        for _ in 0..MAX_BREADCRUMBS {
+            log.push(None, "Draw");
+        }
+
+        assert!(log.resolve(&[(vk::PipelineStageFlags::TOP_OF_PIPE, first)]).is_empty());
+        assert_eq!(log.entries.lock().unwrap().entries.len(), MAX_BREADCRUMBS);
+    }
+}
@@ -0,0 +1,73 @@
+//! Tracking of device memory used by global objects against a soft limit.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ash::vk;
+
+/// Tracks the number of bytes of device memory used by global objects. If a limit is set
+/// reservations which would exceed it fail so that callers can throttle instead of exhausting
+/// device memory.
+pub(super) struct MemoryBudget {
+    limit: AtomicU64,
+    used: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub(super) fn new() -> Self {
+        Self {
+            limit: AtomicU64::new(u64::MAX),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the limit in bytes. If [`None`] is passed no limit is enforced. Memory which is already
+    /// in use is not affected by a lower limit.
+    pub(super) fn set_limit(&self, limit: Option<vk::DeviceSize>) {
+        self.limit.store(limit.unwrap_or(u64::MAX), Ordering::Release);
+    }
+
+    /// Returns the number of bytes currently reserved.
+    pub(super) fn get_used(&self) -> vk::DeviceSize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Attempts to reserve `size` bytes. Returns false if the reservation would exceed the limit.
+    pub(super) fn try_reserve(&self, size: vk::DeviceSize) -> bool {
+        let limit = self.limit.load(Ordering::Acquire);
+        self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(size).filter(|new| *new <= limit)
+        }).is_ok()
+    }
+
+    /// Releases a previous reservation of `size` bytes.
+    pub(super) fn release(&self, size: vk::DeviceSize) {
+        self.used.fetch_sub(size, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_rejects_allocation() {
+        let budget = MemoryBudget::new();
+        assert!(budget.try_reserve(1 << 40));
+        budget.release(1 << 40);
+
+        budget.set_limit(Some(1000));
+        let mut count = 0;
+        while budget.try_reserve(256) {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+        assert_eq!(budget.get_used(), 768);
+        assert!(!budget.try_reserve(256));
+
+        budget.release(256);
+        assert!(budget.try_reserve(256));
+
+        budget.set_limit(None);
+        assert!(budget.try_reserve(1 << 40));
+    }
+}
@@ -0,0 +1,326 @@
+//! Capturing and replaying of emulator tasks for debugging.
+//!
+//! While a capture is active all global object writes submitted to the emulator are recorded
+//! together with their data. The resulting [`FrameCapture`] can be serialized to reproduce
+//! rendering bugs and later be replayed against the same resources.
+
+use std::collections::HashSet;
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::global_objects::{GlobalImageId, GlobalMeshId, ImageData};
+
+const CAPTURE_MAGIC: [u8; 4] = *b"B4DC";
+const CAPTURE_VERSION: u32 = 1;
+
+const TAG_WRITE_GLOBAL_MESH: u8 = 0;
+const TAG_WRITE_GLOBAL_IMAGE: u8 = 1;
+
+/// A region of a captured image write. Mirrors [`ImageData`] but owns its data.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CapturedImageRegion {
+    pub data: Box<[u8]>,
+    pub row_stride: u32,
+    pub offset: Vec2u32,
+    pub extent: Vec2u32,
+    pub layer: u32,
+}
+
+impl CapturedImageRegion {
+    pub(super) fn from_image_data(data: &ImageData) -> Self {
+        Self {
+            data: data.data.into(),
+            row_stride: data.row_stride,
+            offset: data.offset,
+            extent: data.extent,
+            layer: data.layer,
+        }
+    }
+
+    fn as_image_data(&self) -> ImageData {
+        ImageData {
+            data: &self.data,
+            row_stride: self.row_stride,
+            offset: self.offset,
+            extent: self.extent,
+            layer: self.layer,
+        }
+    }
+}
+
+/// A emulator task recorded during a capture.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CapturedTask {
+    /// A write to a global mesh. Each update is a byte offset into the mesh buffer and the data
+    /// written at that offset.
+    WriteGlobalMesh(GlobalMeshId, Box<[(vk::DeviceSize, Box<[u8]>)]>),
+    WriteGlobalImage(GlobalImageId, Box<[CapturedImageRegion]>),
+}
+
+/// The tasks recorded between [`EmulatorRenderer::begin_capture`](super::EmulatorRenderer::begin_capture)
+/// and [`EmulatorRenderer::end_capture`](super::EmulatorRenderer::end_capture) in submission order.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FrameCapture {
+    tasks: Vec<CapturedTask>,
+}
+
+impl FrameCapture {
+    pub(super) fn new(tasks: Vec<CapturedTask>) -> Self {
+        Self {
+            tasks
+        }
+    }
+
+    pub fn get_tasks(&self) -> &[CapturedTask] {
+        &self.tasks
+    }
+
+    /// Returns the ids of all global meshes referenced by the capture.
+    pub fn get_used_meshes(&self) -> HashSet<GlobalMeshId> {
+        self.tasks.iter().filter_map(|task| match task {
+            CapturedTask::WriteGlobalMesh(id, _) => Some(*id),
+            _ => None,
+        }).collect()
+    }
+
+    /// Returns the ids of all global images referenced by the capture.
+    pub fn get_used_images(&self) -> HashSet<GlobalImageId> {
+        self.tasks.iter().filter_map(|task| match task {
+            CapturedTask::WriteGlobalImage(id, _) => Some(*id),
+            _ => None,
+        }).collect()
+    }
+
+    /// Resubmits all captured tasks in order to the target.
+    pub(super) fn replay_into<T: ReplayTarget>(&self, target: &mut T) {
+        for task in &self.tasks {
+            match task {
+                CapturedTask::WriteGlobalMesh(id, updates) => {
+                    let updates: Vec<_> = updates.iter().map(|(offset, data)| (*offset, data.as_ref())).collect();
+                    target.write_mesh(*id, &updates);
+                }
+                CapturedTask::WriteGlobalImage(id, regions) => {
+                    let regions: Vec<_> = regions.iter().map(CapturedImageRegion::as_image_data).collect();
+                    target.write_image(*id, &regions);
+                }
+            }
+        }
+    }
+
+    /// Serializes the capture into a self contained little endian byte stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&CAPTURE_MAGIC);
+        out.extend_from_slice(&CAPTURE_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.tasks.len() as u32).to_le_bytes());
+
+        for task in &self.tasks {
+            match task {
+                CapturedTask::WriteGlobalMesh(id, updates) => {
+                    out.push(TAG_WRITE_GLOBAL_MESH);
+                    out.extend_from_slice(&id.as_uuid().get_raw().to_le_bytes());
+                    out.extend_from_slice(&(updates.len() as u32).to_le_bytes());
+                    for (offset, data) in updates.iter() {
+                        out.extend_from_slice(&offset.to_le_bytes());
+                        write_bytes(&mut out, data);
+                    }
+                }
+                CapturedTask::WriteGlobalImage(id, regions) => {
+                    out.push(TAG_WRITE_GLOBAL_IMAGE);
+                    out.extend_from_slice(&id.as_uuid().get_raw().to_le_bytes());
+                    out.extend_from_slice(&(regions.len() as u32).to_le_bytes());
+                    for region in regions.iter() {
+                        for value in [region.row_stride, region.offset[0], region.offset[1], region.extent[0], region.extent[1], region.layer] {
+                            out.extend_from_slice(&value.to_le_bytes());
+                        }
+                        write_bytes(&mut out, &region.data);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Deserializes a capture created by [`FrameCapture::to_bytes`]. Returns [`None`] if the data
+    /// is malformed or was created by a incompatible version.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data };
+        if reader.read_slice(4)? != CAPTURE_MAGIC || reader.read_u32()? != CAPTURE_VERSION {
+            return None;
+        }
+
+        let task_count = reader.read_u32()?;
+        let mut tasks = Vec::new();
+        for _ in 0..task_count {
+            let task = match reader.read_u8()? {
+                TAG_WRITE_GLOBAL_MESH => {
+                    let id = GlobalMeshId::from_uuid(reader.read_uuid()?);
+                    let update_count = reader.read_u32()?;
+                    let mut updates = Vec::new();
+                    for _ in 0..update_count {
+                        let offset = reader.read_u64()?;
+                        updates.push((offset, reader.read_bytes()?));
+                    }
+                    CapturedTask::WriteGlobalMesh(id, updates.into_boxed_slice())
+                }
+                TAG_WRITE_GLOBAL_IMAGE => {
+                    let id = GlobalImageId::from_uuid(reader.read_uuid()?);
+                    let region_count = reader.read_u32()?;
+                    let mut regions = Vec::new();
+                    for _ in 0..region_count {
+                        let row_stride = reader.read_u32()?;
+                        let offset = Vec2u32::new(reader.read_u32()?, reader.read_u32()?);
+                        let extent = Vec2u32::new(reader.read_u32()?, reader.read_u32()?);
+                        let layer = reader.read_u32()?;
+                        regions.push(CapturedImageRegion {
+                            data: reader.read_bytes()?,
+                            row_stride,
+                            offset,
+                            extent,
+                            layer,
+                        });
+                    }
+                    CapturedTask::WriteGlobalImage(id, regions.into_boxed_slice())
+                }
+                _ => return None,
+            };
+            tasks.push(task);
+        }
+
+        if !reader.data.is_empty() {
+            return None;
+        }
+
+        Some(Self::new(tasks))
+    }
+}
+
+/// The receiver of the tasks of a replayed [`FrameCapture`].
+pub(super) trait ReplayTarget {
+    fn write_mesh(&mut self, mesh: GlobalMeshId, updates: &[(vk::DeviceSize, &[u8])]);
+
+    fn write_image(&mut self, image: GlobalImageId, regions: &[ImageData]);
+}
+
+/// Describes why a [`FrameCapture`] could not be replayed. No task is submitted if replaying fails.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ReplayError {
+    /// The capture references a global mesh which was not provided.
+    MissingMesh(GlobalMeshId),
+    /// The capture references a global image which was not provided.
+    MissingImage(GlobalImageId),
+}
+
+fn write_bytes(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read_slice(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read_slice(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read_slice(4)?.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.read_slice(8)?.try_into().ok()?))
+    }
+
+    fn read_uuid(&mut self) -> Option<UUID> {
+        let raw = self.read_u64()?;
+        if raw == 0 {
+            return None;
+        }
+        Some(UUID::from_raw(raw))
+    }
+
+    fn read_bytes(&mut self) -> Option<Box<[u8]>> {
+        let len = usize::try_from(self.read_u64()?).ok()?;
+        Some(self.read_slice(len)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Applies mesh writes to host buffers in place of global meshes.
+    struct HostBuffers(HashMap<GlobalMeshId, Vec<u8>>);
+
+    impl ReplayTarget for HostBuffers {
+        fn write_mesh(&mut self, mesh: GlobalMeshId, updates: &[(vk::DeviceSize, &[u8])]) {
+            let buffer = self.0.get_mut(&mesh).unwrap();
+            for (offset, data) in updates {
+                let offset = *offset as usize;
+                buffer[offset..(offset + data.len())].copy_from_slice(data);
+            }
+        }
+
+        fn write_image(&mut self, _: GlobalImageId, _: &[ImageData]) {
+            panic!("Unexpected image write")
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_copies() {
+        let mesh_a = GlobalMeshId::new();
+        let mesh_b = GlobalMeshId::new();
+
+        let capture = FrameCapture::new(vec![
+            CapturedTask::WriteGlobalMesh(mesh_a, Box::new([(0, Box::new([1u8, 2, 3, 4]) as Box<[u8]>), (6, Box::new([7u8, 8]))])),
+            CapturedTask::WriteGlobalMesh(mesh_b, Box::new([(2, Box::new([9u8, 9, 9]) as Box<[u8]>)])),
+            CapturedTask::WriteGlobalMesh(mesh_a, Box::new([(2, Box::new([5u8, 6]) as Box<[u8]>)])),
+        ]);
+
+        let mut expected = HostBuffers(HashMap::from([(mesh_a, vec![0u8; 8]), (mesh_b, vec![0u8; 8])]));
+        capture.replay_into(&mut expected);
+        assert_eq!(expected.0[&mesh_a], vec![1, 2, 5, 6, 0, 0, 7, 8]);
+        assert_eq!(expected.0[&mesh_b], vec![0, 0, 9, 9, 9, 0, 0, 0]);
+
+        let restored = FrameCapture::from_bytes(&capture.to_bytes()).unwrap();
+        assert_eq!(restored, capture);
+        assert_eq!(restored.get_used_meshes(), HashSet::from([mesh_a, mesh_b]));
+
+        let mut replayed = HostBuffers(HashMap::from([(mesh_a, vec![0u8; 8]), (mesh_b, vec![0u8; 8])]));
+        restored.replay_into(&mut replayed);
+        assert_eq!(replayed.0, expected.0);
+    }
+
+    #[test]
+    fn image_regions_survive_serialization() {
+        let image = GlobalImageId::new();
+        let capture = FrameCapture::new(vec![
+            CapturedTask::WriteGlobalImage(image, Box::new([CapturedImageRegion {
+                data: Box::new([255u8; 16]),
+                row_stride: 2,
+                offset: Vec2u32::new(1, 3),
+                extent: Vec2u32::new(2, 2),
+                layer: 4,
+            }])),
+        ]);
+
+        let bytes = capture.to_bytes();
+        assert_eq!(FrameCapture::from_bytes(&bytes), Some(capture));
+        assert_eq!(FrameCapture::from_bytes(&bytes[..bytes.len() - 1]), None);
+    }
+}
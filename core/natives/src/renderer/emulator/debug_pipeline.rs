@@ -11,13 +11,15 @@ use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
 use include_bytes_aligned::include_bytes_aligned;
 use crate::allocator::Allocation;
 use crate::device::device::Queue;
-use crate::device::device_utils::create_shader_from_bytes;
+use crate::device::device_utils::{create_shader_from_bytes, make_tessellation_state, validate_geometry_stage, validate_graphics_shader_stages, validate_push_constant_ranges, PipelineCreateError, PipelineLayoutCreateError};
 
 use crate::prelude::*;
 use crate::renderer::emulator::EmulatorRenderer;
-use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderDropListener, ShaderId, ShaderListener, VertexFormat, VertexFormatEntry};
-use crate::renderer::emulator::pipeline::{DrawTask, EmulatorPipeline, EmulatorPipelinePass, PipelineTask, PooledObjectProvider, SubmitRecorder};
-use crate::util::vk::{make_full_rect, make_full_viewport};
+use crate::renderer::emulator::worker::{EmulatorTaskError, EmulatorTaskKind};
+use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderDropListener, ShaderId, ShaderListener, VertexAttribute, VertexFormat, VertexFormatEntry};
+use crate::renderer::emulator::pipeline::{AttachmentOps, ColorAttachmentInfo, DrawTask, EmulatorPipeline, EmulatorPipelinePass, PassAttachment, PipelineTask, PooledObjectProvider, ProceduralDrawTask, SubmitRecorder};
+use crate::util::format::Format;
+use crate::util::vk::{make_full_rect, make_full_viewport, SpecializationBuilder};
 
 pub struct DepthTypeInfo {
     pub vertex_stride: u32,
@@ -32,6 +34,7 @@ pub struct DepthTypeInfo {
 pub enum ObjectCreateError {
     Vulkan(vk::Result),
     Allocation,
+    PipelineLayout(PipelineLayoutCreateError),
 }
 
 impl From<vk::Result> for ObjectCreateError {
@@ -40,6 +43,12 @@ impl From<vk::Result> for ObjectCreateError {
     }
 }
 
+impl From<PipelineLayoutCreateError> for ObjectCreateError {
+    fn from(err: PipelineLayoutCreateError) -> Self {
+        Self::PipelineLayout(err)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum DebugPipelineMode {
     Depth,
@@ -52,6 +61,280 @@ pub enum DebugPipelineMode {
     Textured0,
     Textured1,
     Textured2,
+    /// Samples the bindless texture selected with
+    /// [`PassRecorder::set_bindless_texture`](super::PassRecorder::set_bindless_texture) using
+    /// uv0. Requires descriptor indexing support.
+    Bindless,
+}
+
+/// The configuration of a [`DebugPipeline`] created with [`DebugPipeline::new_with_config`].
+#[derive(Clone, Debug, Default)]
+pub struct DebugPipelineConfig {
+    /// Additional color attachments rendered next to the main output. Their views can be retrieved
+    /// with [`DebugPipeline::get_color_attachment_views`]. Float attachments receive the same
+    /// color as the main output while integer attachments are only cleared.
+    pub color_attachments: Vec<ColorAttachmentInfo>,
+
+    pub depth_convention: DepthConvention,
+
+    /// The range of normalized device depth produced by the vertex shaders.
+    pub depth_clip_range: DepthClipRange,
+
+    /// The depth attachment. If [`None`] the first format supported by the device according to
+    /// [`choose_depth_format`] is used.
+    pub depth_attachment: Option<DepthAttachmentInfo>,
+}
+
+/// The mapping of depth values into the depth buffer used by a [`DebugPipeline`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DepthConvention {
+    /// Near geometry is stored with small depth values. The depth buffer is cleared to 1.
+    Standard,
+    /// Near geometry is stored with large depth values. The depth buffer is cleared to 0.
+    ///
+    /// Projection matrices are adjusted to produce the reversed depth before the perspective
+    /// divide. Combined with a floating point depth buffer this improves the precision for large
+    /// render distances.
+    ReverseZ,
+}
+
+impl DepthConvention {
+    pub fn from_reverse_z(reverse_z: bool) -> Self {
+        if reverse_z {
+            DepthConvention::ReverseZ
+        } else {
+            DepthConvention::Standard
+        }
+    }
+
+    /// Returns the compare op used for depth testing.
+    pub fn get_compare_op(&self) -> vk::CompareOp {
+        match self {
+            DepthConvention::Standard => vk::CompareOp::LESS,
+            DepthConvention::ReverseZ => vk::CompareOp::GREATER,
+        }
+    }
+
+    /// Returns the value the depth buffer is cleared to. This is the depth of the far plane.
+    pub fn get_clear_depth(&self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::ReverseZ => 0.0,
+        }
+    }
+
+    /// Returns the matrix which reverses clip space depth produced in `clip_range`. The matrix must
+    /// be applied after the projection matrix.
+    pub fn get_projection_adjustment(&self, clip_range: DepthClipRange) -> Mat4f32 {
+        match (self, clip_range) {
+            (DepthConvention::Standard, _) => Mat4f32::identity(),
+            // Maps z to w - z
+            (DepthConvention::ReverseZ, DepthClipRange::ZeroToOne) => Mat4f32::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, -1.0, 1.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+            // Maps z to -z
+            (DepthConvention::ReverseZ, DepthClipRange::NegativeOneToOne) => Mat4f32::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, -1.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+        }
+    }
+
+    /// Maps the depth range of a viewport specified in the standard convention into this
+    /// convention. The full 0 to 1 range is not modified since the depth is already reversed by
+    /// the projection.
+    pub fn apply_to_viewport(&self, viewport: vk::Viewport) -> vk::Viewport {
+        match self {
+            DepthConvention::Standard => viewport,
+            DepthConvention::ReverseZ => vk::Viewport {
+                min_depth: 1.0 - viewport.max_depth,
+                max_depth: 1.0 - viewport.min_depth,
+                ..viewport
+            },
+        }
+    }
+}
+
+impl Default for DepthConvention {
+    fn default() -> Self {
+        DepthConvention::Standard
+    }
+}
+
+/// The range of normalized device depth coordinates produced by the vertex shaders of a
+/// [`DebugPipeline`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DepthClipRange {
+    /// The vulkan convention. Depth values from 0 to 1 are visible.
+    ZeroToOne,
+    /// The OpenGL convention used by minecraft shaders. Depth values from -1 to 1 are visible.
+    ///
+    /// If VK_EXT_depth_clip_control is supported the range is configured in the pipelines.
+    /// Otherwise all projection matrices are adjusted to map the depth into the 0 to 1 range.
+    NegativeOneToOne,
+}
+
+impl DepthClipRange {
+    /// Returns the matrix which maps clip space coordinates produced in this range into the 0 to 1
+    /// range used by vulkan. The matrix must be applied after the projection matrix.
+    pub fn get_projection_adjustment(&self) -> Mat4f32 {
+        match self {
+            DepthClipRange::ZeroToOne => Mat4f32::identity(),
+            DepthClipRange::NegativeOneToOne => Mat4f32::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 0.5, 0.5,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+        }
+    }
+}
+
+impl Default for DepthClipRange {
+    fn default() -> Self {
+        DepthClipRange::ZeroToOne
+    }
+}
+
+/// Returns the matrix applied to all projection and mvp matrices of a [`DebugPipeline`]. If
+/// `depth_clip_control` is false the depth is mapped into the 0 to 1 range before reversing it.
+fn make_projection_adjustment(depth_convention: DepthConvention, depth_clip_range: DepthClipRange, depth_clip_control: bool) -> Mat4f32 {
+    if depth_clip_control {
+        depth_convention.get_projection_adjustment(depth_clip_range)
+    } else {
+        depth_convention.get_projection_adjustment(DepthClipRange::ZeroToOne) * depth_clip_range.get_projection_adjustment()
+    }
+}
+
+/// The depth attachment of a [`DebugPipeline`].
+///
+/// If the format has a stencil aspect the stencil is cleared to `stencil_clear` together with the
+/// depth at the start of every pass. Draws then only cover pixels whose stencil value is smaller
+/// or equal to the stencil reference of the draw and replace the stencil value with the reference.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DepthAttachmentInfo {
+    pub format: vk::Format,
+    pub stencil_clear: u32,
+}
+
+impl DepthAttachmentInfo {
+    pub fn new(format: vk::Format, stencil_clear: u32) -> Self {
+        Self {
+            format,
+            stencil_clear,
+        }
+    }
+
+    /// Returns the aspects of the depth attachment. Panics if the format has no depth aspect.
+    pub fn get_aspect_mask(&self) -> vk::ImageAspectFlags {
+        let aspect_mask = Format::format_for(self.format).get_aspect_mask();
+        if !aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+            log::error!("Depth attachment format {:?} has no depth aspect", self.format);
+            panic!()
+        }
+        aspect_mask
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.get_aspect_mask().contains(vk::ImageAspectFlags::STENCIL)
+    }
+
+    /// Returns the value the attachment is cleared to at the start of a pass.
+    pub fn make_clear_value(&self, depth_convention: DepthConvention) -> vk::ClearDepthStencilValue {
+        vk::ClearDepthStencilValue {
+            depth: depth_convention.get_clear_depth(),
+            stencil: if self.has_stencil() { self.stencil_clear } else { 0 },
+        }
+    }
+}
+
+impl Default for DepthAttachmentInfo {
+    fn default() -> Self {
+        Self::new(vk::Format::D32_SFLOAT, 0)
+    }
+}
+
+/// Returns the usage of the depth images of a [`DebugPipeline`]. If the format supports it the
+/// images can be copied so that passes can be used with a
+/// [`DepthImageOutput`](super::pipeline::DepthImageOutput).
+fn get_depth_image_usage(features: vk::FormatFeatureFlags) -> vk::ImageUsageFlags {
+    let mut usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+    if features.contains(vk::FormatFeatureFlags::TRANSFER_SRC) {
+        usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+    usage
+}
+
+/// The depth formats probed by [`choose_depth_format`] in order of preference.
+const DEPTH_FORMAT_CANDIDATES: [vk::Format; 4] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+
+/// Returns the first depth format which can be used as the depth attachment of a
+/// [`DebugPipeline`] on the device. If `want_stencil` is true only formats with a stencil aspect
+/// are considered. Returns [`None`] if no candidate format is supported.
+pub fn choose_depth_format(device: &DeviceContext, want_stencil: bool) -> Option<vk::Format> {
+    select_depth_format(want_stencil, |format| device.get_optimal_format_features(format))
+}
+
+fn select_depth_format<F: Fn(vk::Format) -> vk::FormatFeatureFlags>(want_stencil: bool, get_features: F) -> Option<vk::Format> {
+    let required = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    DEPTH_FORMAT_CANDIDATES.iter().copied()
+        .filter(|format| !want_stencil || Format::format_for(*format).get_aspect_mask().contains(vk::ImageAspectFlags::STENCIL))
+        .find(|format| get_features(*format).contains(required))
+}
+
+/// The background drawn behind all geometry rendered by a [`DebugPipeline`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BackgroundStyle {
+    /// A grey checkerboard pattern.
+    Checkerboard,
+    /// A single solid color.
+    Solid(Vec3f32),
+    /// A vertical gradient from the top color to the bottom color.
+    Gradient(Vec3f32, Vec3f32),
+}
+
+impl BackgroundStyle {
+    fn to_push_constants(&self) -> BackgroundPushConstants {
+        let (color0, color1, style) = match self {
+            BackgroundStyle::Checkerboard => (Vec3f32::zeros(), Vec3f32::zeros(), 0u32),
+            BackgroundStyle::Solid(color) => (*color, Vec3f32::zeros(), 1u32),
+            BackgroundStyle::Gradient(top, bottom) => (*top, *bottom, 2u32),
+        };
+
+        BackgroundPushConstants {
+            color0: Vec4f32::new(color0[0], color0[1], color0[2], 1.0),
+            color1: Vec4f32::new(color1[0], color1[1], color1[2], 1.0),
+            style,
+            _padding0: [0u8; 12]
+        }
+    }
+}
+
+impl Default for BackgroundStyle {
+    fn default() -> Self {
+        BackgroundStyle::Checkerboard
+    }
+}
+
+/// A command recorded by a [`DebugPipeline`] pass. Only pipeline binds, dynamic state changes and
+/// draws excluding the background are logged. See [`DebugPipeline::set_command_log_enabled`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RecordedCommand {
+    BindPipeline(ShaderId),
+    SetStencilReference(u32),
+    SetLineWidth(f32),
+    SetBlendConstants([f32; 4]),
+    Draw(ShaderId),
 }
 
 /// A [`EmulatorPipeline`] which provides debug information.
@@ -65,35 +348,114 @@ pub enum DebugPipelineMode {
 /// - UV1: The uv1 vertex attribute
 /// - UV2: The uv2 vertex attribute
 /// - Textured0: The textured result from uv0 (Not implemented yet)
+/// - Bindless: The bindless texture selected for the draw sampled with uv0
+///
+/// If the emulator has a [`BindlessTextureTable`](super::BindlessTextureTable) its descriptor set
+/// is bound as set 1 of the draw pipeline layout in all modes.
+///
+/// If the depth mode is used without additional color attachments the draw subpass has no color
+/// attachments and only writes depth. This can be used to render shadow maps.
+///
+/// The debug fragment shaders write their color to the first 3 additional color attachments.
+/// Attachments with an integer format or beyond the first 3 are not written and keep their clear
+/// value of zero.
 pub struct DebugPipeline {
     emulator: Arc<EmulatorRenderer>,
     weak: Weak<Self>,
 
     framebuffer_size: Vec2u32,
+    depth_attachment: DepthAttachmentInfo,
+    depth_only: bool,
+    depth_convention: DepthConvention,
+    depth_clip_range: DepthClipRange,
+    /// True if the depth clip range is configured using VK_EXT_depth_clip_control instead of
+    /// adjusting projection matrices.
+    depth_clip_control: bool,
 
     shader_modules: ShaderModules,
     render_pass: vk::RenderPass,
+    default_attachment_ops: Box<[AttachmentOps]>,
+    render_pass_variants: Mutex<HashMap<Box<[AttachmentOps]>, vk::RenderPass>>,
     draw_pipeline: DrawPipeline,
     background_pipeline: BackgroundPipeline,
-    descriptor_pool: vk::DescriptorPool,
+    background_style: Mutex<BackgroundStyle>,
+    /// The commands logged by passes. [`None`] if logging is disabled.
+    command_log: Mutex<Option<Vec<RecordedCommand>>>,
+    descriptor_pools: Box<[vk::DescriptorPool]>,
 
     pipelines: Mutex<HashMap<ShaderId, ShaderPipelines>>,
     next_index: AtomicUsize,
     pass_objects: Box<[PassObjects]>,
     output_views: Box<[vk::ImageView]>,
+    /// The depth images of all pass objects. Empty if the depth format cannot be copied.
+    depth_output_images: Box<[vk::Image]>,
+    /// The images of all pass objects for each additional color attachment.
+    color_output_images: Box<[Box<[vk::Image]>]>,
+    color_attachments: Box<[ColorAttachmentInfo]>,
 }
 assert_impl_all!(DebugPipeline: Send, Sync);
 
 impl DebugPipeline {
     pub fn new(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32) -> Result<Arc<Self>, ObjectCreateError> {
+        Self::new_with_config(emulator, mode, framebuffer_size, &DebugPipelineConfig::default())
+    }
+
+    /// Creates a new debug pipeline using the attachments and depth settings of `config`.
+    pub fn new_with_config(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32, config: &DebugPipelineConfig) -> Result<Arc<Self>, ObjectCreateError> {
+        let depth_attachment = config.depth_attachment.unwrap_or_else(|| Self::make_default_depth_attachment(&emulator));
+        Self::new_internal(emulator, mode, framebuffer_size, &config.color_attachments, config.depth_convention, config.depth_clip_range, depth_attachment)
+    }
+
+    /// Returns the depth attachment used if none is specified. Uses the first format supported by
+    /// the device according to [`choose_depth_format`].
+    fn make_default_depth_attachment(emulator: &EmulatorRenderer) -> DepthAttachmentInfo {
+        match choose_depth_format(emulator.get_device(), false) {
+            Some(format) => DepthAttachmentInfo::new(format, 0),
+            // Fails with a format error in new_internal
+            None => DepthAttachmentInfo::default(),
+        }
+    }
+
+    fn new_internal(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32, color_attachments: &[ColorAttachmentInfo], depth_convention: DepthConvention, depth_clip_range: DepthClipRange, depth_attachment: DepthAttachmentInfo) -> Result<Arc<Self>, ObjectCreateError> {
+        for attachment in color_attachments {
+            if attachment.is_integer() && attachment.blend_state.blend_enable != vk::FALSE {
+                log::error!("Blending is not supported for integer color attachment format {:?}", attachment.format);
+                panic!()
+            }
+        }
+
         let concurrent_passes = 2usize;
-        let depth_format = vk::Format::D32_SFLOAT;
+        let depth_format = depth_attachment.format;
+        let depth_only = mode == DebugPipelineMode::Depth && color_attachments.is_empty();
 
         let device = emulator.get_device();
 
+        let depth_clip_control = depth_clip_range == DepthClipRange::NegativeOneToOne && device.supports_depth_clip_control();
+
+        let required = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+        if !device.get_optimal_format_features(depth_format).contains(required) {
+            log::error!("Depth attachment format {:?} does not support required features {:?}", depth_format, required);
+            return Err(ObjectCreateError::Vulkan(vk::Result::ERROR_FORMAT_NOT_SUPPORTED));
+        }
+
+        for attachment in color_attachments {
+            let required = attachment.get_required_format_features();
+            if !device.get_optimal_format_features(attachment.format).contains(required) {
+                log::error!("Color attachment format {:?} does not support required features {:?}", attachment.format, required);
+                return Err(ObjectCreateError::Vulkan(vk::Result::ERROR_FORMAT_NOT_SUPPORTED));
+            }
+        }
+
+        let bindless_set = emulator.get_bindless_textures().map(|table| (table.get_set_layout(), table.get_descriptor_set()));
+        if mode == DebugPipelineMode::Bindless && bindless_set.is_none() {
+            log::error!("Bindless debug mode requires descriptor indexing support");
+            return Err(ObjectCreateError::Vulkan(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+        }
+
         let mut shader_modules = ShaderModules::new(device, mode)?;
 
-        let render_pass = match Self::create_render_pass(&device, depth_format) {
+        let default_attachment_ops = make_default_attachment_ops(color_attachments.len());
+        let render_pass = match Self::create_render_pass(&device, depth_format, depth_only, color_attachments, &default_attachment_ops) {
             Ok(render_pass) => render_pass,
             Err(err) => {
                 shader_modules.destroy(device);
@@ -101,10 +463,10 @@ impl DebugPipeline {
             }
         };
 
-        let mut draw_pipeline = match DrawPipeline::new(device) {
+        let mut draw_pipeline = match DrawPipeline::new(device, bindless_set) {
             Ok(pipeline) => pipeline,
             Err(err) => {
-                unsafe { device.vk().destroy_render_pass(render_pass, None) };
+                unsafe { device.vk().destroy_render_pass(render_pass, device.get_allocation_callbacks()) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
@@ -114,54 +476,38 @@ impl DebugPipeline {
             Ok(pipeline) => pipeline,
             Err(err) => {
                 draw_pipeline.destroy(device);
-                unsafe { device.vk().destroy_render_pass(render_pass, None) };
+                unsafe { device.vk().destroy_render_pass(render_pass, device.get_allocation_callbacks()) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
 
-        let descriptor_pool = match Self::create_descriptor_pool(device, concurrent_passes) {
-            Ok(pool) => pool,
+        let (descriptor_pools, descriptor_sets) = match Self::allocate_background_sets(device, background_pipeline.descriptor_set_layout, concurrent_passes, DESCRIPTOR_POOL_SETS) {
+            Ok(result) => result,
             Err(err) => {
                 background_pipeline.destroy(device);
                 draw_pipeline.destroy(device);
-                unsafe { device.vk().destroy_render_pass(render_pass, None) };
+                unsafe { device.vk().destroy_render_pass(render_pass, device.get_allocation_callbacks()) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
+        let descriptor_pools = descriptor_pools.into_boxed_slice();
 
-        let layouts: Box<[_]> = std::iter::repeat(background_pipeline.descriptor_set_layout).take(concurrent_passes).collect();
-        let info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&layouts);
-
-        let descriptor_sets = match unsafe {
-            device.vk().allocate_descriptor_sets(&info)
-        } {
-            Ok(layouts) => layouts,
-            Err(err) => {
-                unsafe { device.vk().destroy_descriptor_pool(descriptor_pool, None) };
-                background_pipeline.destroy(device);
-                draw_pipeline.destroy(device);
-                unsafe { device.vk().destroy_render_pass(render_pass, None) };
-                shader_modules.destroy(device);
-                return Err(ObjectCreateError::Vulkan(err));
-            }
-        };
-
-        let mut pass_objects: Vec<PassObjects> = Vec::with_capacity(layouts.len());
+        let mut pass_objects: Vec<PassObjects> = Vec::with_capacity(descriptor_sets.len());
         for descriptor_set in descriptor_sets {
-            let objects = match PassObjects::new(device, framebuffer_size, depth_format, vk::Format::R8G8B8A8_SRGB, render_pass, descriptor_set) {
+            let objects = match PassObjects::new(device, framebuffer_size, &depth_attachment, vk::Format::R8G8B8A8_SRGB, color_attachments, render_pass, descriptor_set) {
                 Ok(objects) => objects,
                 Err(err) => {
                     for mut pass_object in pass_objects {
                         pass_object.destroy(device);
                     }
-                    unsafe { device.vk().destroy_descriptor_pool(descriptor_pool, None) };
+                    for pool in descriptor_pools.iter() {
+                        unsafe { device.vk().destroy_descriptor_pool(*pool, device.get_allocation_callbacks()) };
+                    }
                     background_pipeline.destroy(device);
                     draw_pipeline.destroy(device);
-                    unsafe { device.vk().destroy_render_pass(render_pass, None) };
+                    unsafe { device.vk().destroy_render_pass(render_pass, device.get_allocation_callbacks()) };
                     shader_modules.destroy(device);
                     return Err(err);
                 }
@@ -176,27 +522,132 @@ impl DebugPipeline {
             pass_objects.iter().map(|obj| obj.output_view).collect()
         };
 
+        let depth_usage = get_depth_image_usage(device.get_optimal_format_features(depth_format));
+        let depth_output_images: Box<_> = if depth_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            pass_objects.iter().map(|obj| obj.depth_image).collect()
+        } else {
+            Box::new([])
+        };
+        let color_output_images: Box<_> = (0..color_attachments.len()).map(|attachment| {
+            pass_objects.iter().map(|obj| obj.color_attachments[attachment].0).collect()
+        }).collect();
+
         Ok(Arc::new_cyclic(|weak| {
             Self {
                 emulator,
                 weak: weak.clone(),
 
                 framebuffer_size,
+                depth_attachment,
+                depth_only,
+                depth_convention,
+                depth_clip_range,
+                depth_clip_control,
 
                 shader_modules,
                 render_pass,
+                default_attachment_ops,
+                render_pass_variants: Mutex::new(HashMap::new()),
                 draw_pipeline,
                 background_pipeline,
-                descriptor_pool,
+                background_style: Mutex::new(BackgroundStyle::default()),
+                command_log: Mutex::new(None),
+                descriptor_pools,
 
                 pipelines: Mutex::new(HashMap::new()),
                 next_index: AtomicUsize::new(0),
                 pass_objects,
-                output_views
+                output_views,
+                depth_output_images,
+                color_output_images,
+                color_attachments: color_attachments.into()
             }
         }))
     }
 
+    /// Configures the background drawn behind all geometry. Passes started after this call use the
+    /// new background.
+    pub fn set_background(&self, style: BackgroundStyle) {
+        *self.background_style.lock().unwrap() = style;
+    }
+
+    /// Enables or disables logging of the commands recorded by passes of this pipeline. Useful to
+    /// find redundant state changes. Only passes started after logging has been enabled are
+    /// logged. Disabling logging discards all logged commands.
+    pub fn set_command_log_enabled(&self, enabled: bool) {
+        let mut guard = self.command_log.lock().unwrap();
+        if enabled {
+            guard.get_or_insert_with(Vec::new);
+        } else {
+            *guard = None;
+        }
+    }
+
+    /// Returns and clears the commands logged by all passes which have finished recording since
+    /// the last call in the order they have been recorded.
+    pub fn take_command_log(&self) -> Vec<RecordedCommand> {
+        self.command_log.lock().unwrap().as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn get_depth_convention(&self) -> DepthConvention {
+        self.depth_convention
+    }
+
+    pub fn get_depth_clip_range(&self) -> DepthClipRange {
+        self.depth_clip_range
+    }
+
+    /// Returns true if the depth clip range is configured using VK_EXT_depth_clip_control. If false
+    /// and the range is [`DepthClipRange::NegativeOneToOne`] projection matrices are adjusted
+    /// instead.
+    pub fn uses_depth_clip_control(&self) -> bool {
+        self.depth_clip_control
+    }
+
+    /// Maps a projection or mvp matrix into the 0 to 1 depth range if the clip range is not
+    /// configured in the pipelines and reverses the depth for [`DepthConvention::ReverseZ`].
+    fn adjust_projection(&self, matrix: &Mat4f32) -> Mat4f32 {
+        make_projection_adjustment(self.depth_convention, self.depth_clip_range, self.depth_clip_control) * matrix
+    }
+
+    /// Returns true if the draw subpass of this pipeline only writes depth.
+    pub fn is_depth_only(&self) -> bool {
+        self.depth_only
+    }
+
+    /// Returns the number of additional color attachments of this pipeline.
+    pub fn get_color_attachment_count(&self) -> usize {
+        self.color_attachments.len()
+    }
+
+    /// Returns the image views of an additional color attachment. Like [`EmulatorPipeline::get_output`]
+    /// one view is returned for each possible output index. The views are in the
+    /// `SHADER_READ_ONLY_OPTIMAL` layout after a pass completes.
+    pub fn get_color_attachment_views(&self, attachment: usize) -> Box<[vk::ImageView]> {
+        self.pass_objects.iter().map(|obj| obj.color_attachments[attachment].1).collect()
+    }
+
+    /// Returns a render pass using the specified attachment ops. All returned render passes are
+    /// compatible with the framebuffers and pipelines of this pipeline.
+    fn get_render_pass(&self, ops: &[AttachmentOps]) -> vk::RenderPass {
+        if ops == &*self.default_attachment_ops {
+            return self.render_pass;
+        }
+
+        let mut guard = self.render_pass_variants.lock().unwrap();
+        if let Some(render_pass) = guard.get(ops) {
+            return *render_pass;
+        }
+
+        let render_pass = Self::create_render_pass(self.emulator.get_device(), self.depth_attachment.format, self.depth_only, &self.color_attachments, ops).unwrap_or_else(|err| {
+            log::error!("Failed to create render pass variant {:?}: {:?}", ops, err);
+            panic!()
+        });
+        guard.insert(ops.into(), render_pass);
+
+        render_pass
+    }
+
     /// Returns the next index to be used for a pass and increments the internal counter.
     fn next_index(&self) -> usize {
         loop {
@@ -210,7 +661,7 @@ impl DebugPipeline {
 
     /// Returns the pipeline to be used for a specific configuration. If the pipeline doesnt exits
     /// yet a new one is created.
-    fn get_pipeline(&self, shader: ShaderId, config: &PipelineConfig) -> vk::Pipeline {
+    fn get_pipeline(&self, shader: ShaderId, config: &PipelineConfig) -> Result<vk::Pipeline, PipelineCreateError> {
         let mut guard = self.pipelines.lock().unwrap();
         let pipelines = guard.get_mut(&shader).unwrap_or_else(|| {
             log::error!("Called get_pipeline for unregistered shader {:?}", shader);
@@ -220,16 +671,42 @@ impl DebugPipeline {
         pipelines.get_or_create_pipeline(config, |format| self.create_pipeline(config, format))
     }
 
-    fn create_pipeline(&self, config: &PipelineConfig, vertex_format: &VertexFormat) -> vk::Pipeline {
+    /// Returns the stride of the per instance vertex binding of a shader if its vertex format has
+    /// a instance divisor.
+    fn get_instance_stride(&self, shader: ShaderId) -> Option<u32> {
+        let guard = self.pipelines.lock().unwrap();
+        let vertex_format = &guard.get(&shader)?.vertex_format;
+        vertex_format.instance_divisor.map(|_| vertex_format.stride)
+    }
+
+    fn create_pipeline(&self, config: &PipelineConfig, vertex_format: &VertexFormat) -> Result<vk::Pipeline, PipelineCreateError> {
         let alloc = Bump::new();
         let (shader_stages, input_state) = self.shader_modules.configure_pipeline(vertex_format, &alloc);
 
+        self.create_pipeline_with_stages(config, shader_stages, input_state)
+    }
+
+    /// Creates a draw pipeline using the provided shader stages. The stages and the tessellation
+    /// configuration are validated against the enabled device features before the pipeline is
+    /// created.
+    fn create_pipeline_with_stages(&self, config: &PipelineConfig, shader_stages: &[vk::PipelineShaderStageCreateInfo], input_state: &vk::PipelineVertexInputStateCreateInfo) -> Result<vk::Pipeline, PipelineCreateError> {
+        let device = self.emulator.get_device();
+        validate_graphics_shader_stages(shader_stages)?;
+        validate_geometry_stage(shader_stages, device.supports_geometry_shader())?;
+        let tessellation_state = make_tessellation_state(shader_stages, config.primitive_topology, config.patch_control_points, device.supports_tessellation_shader(), device.get_limits().max_tessellation_patch_size)?;
+
         let viewport = make_full_viewport(self.framebuffer_size);
         let scissor = make_full_rect(self.framebuffer_size);
 
-        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        let mut depth_clip_control = vk::PipelineViewportDepthClipControlCreateInfoEXT::builder()
+            .negative_one_to_one(true);
+
+        let mut viewport_state = vk::PipelineViewportStateCreateInfo::builder()
             .viewports(std::slice::from_ref(&viewport))
             .scissors(std::slice::from_ref(&scissor));
+        if self.depth_clip_control {
+            viewport_state = viewport_state.push_next(&mut depth_clip_control);
+        }
 
         let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
             .polygon_mode(vk::PolygonMode::FILL)
@@ -241,35 +718,46 @@ impl DebugPipeline {
             .rasterization_samples(vk::SampleCountFlags::TYPE_1)
             .sample_shading_enable(false);
 
-        let attachment_blend_state = [
-            vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(true)
-                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .build(),
-        ];
+        let attachment_blend_state = make_draw_blend_states(self.depth_only, &self.color_attachments);
 
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .attachments(&attachment_blend_state);
 
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder();
+        let dynamic_states = [
+            vk::DynamicState::STENCIL_REFERENCE,
+            vk::DynamicState::LINE_WIDTH,
+            vk::DynamicState::BLEND_CONSTANTS,
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+        ];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
 
         let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(config.primitive_topology)
             .primitive_restart_enable(false);
 
+        // Draws only cover pixels whose stencil value is smaller or equal to the reference and
+        // replace it with the reference
+        let stencil_op_state = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::REPLACE,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            compare_mask: !0,
+            write_mask: !0,
+            reference: 0
+        };
         let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(config.depth_test_enable)
             .depth_write_enable(config.depth_write_enable)
-            .depth_compare_op(vk::CompareOp::LESS);
+            .depth_compare_op(self.depth_convention.get_compare_op())
+            .stencil_test_enable(self.depth_attachment.has_stencil())
+            .front(stencil_op_state)
+            .back(stencil_op_state);
 
-        let info = vk::GraphicsPipelineCreateInfo::builder()
+        let mut info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(shader_stages)
             .vertex_input_state(input_state)
             .input_assembly_state(&input_assembly_state)
@@ -282,56 +770,26 @@ impl DebugPipeline {
             .layout(self.draw_pipeline.pipeline_layout)
             .render_pass(self.render_pass)
             .subpass(0);
+        if let Some(tessellation_state) = &tessellation_state {
+            info = info.tessellation_state(tessellation_state);
+        }
 
-        let pipeline = *unsafe {
-            self.emulator.get_device().vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
-        }.unwrap_or_else(|(_, err)| {
-            log::error!("Failed to create graphics pipeline {:?}", err);
-            panic!();
-        }).get(0).unwrap();
+        let pipelines = unsafe {
+            device.vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), device.get_allocation_callbacks())
+        }.map_err(|(_, err)| PipelineCreateError::Vulkan(err))?;
 
-        pipeline
+        Ok(pipelines[0])
     }
 
-    fn create_render_pass(device: &DeviceContext, depth_format: vk::Format) -> Result<vk::RenderPass, ObjectCreateError> {
-        let attachments = [
-            vk::AttachmentDescription::builder()
-                .format(depth_format)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .build(),
-            vk::AttachmentDescription::builder()
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::GENERAL)
-                .build(),
-            vk::AttachmentDescription::builder()
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .build()
-        ];
+    fn create_render_pass(device: &DeviceContext, depth_format: vk::Format, depth_only: bool, color_attachments: &[ColorAttachmentInfo], ops: &[AttachmentOps]) -> Result<vk::RenderPass, ObjectCreateError> {
+        let attachments = make_attachment_descriptions(depth_format, color_attachments, ops);
 
         let pass_0_depth = vk::AttachmentReference {
             attachment: 0,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
         };
 
-        let pass_0_color = [
-            vk::AttachmentReference {
-                attachment: 1,
-                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-            },
-        ];
+        let pass_0_color = make_draw_color_references(depth_only, color_attachments.len());
 
         let pass_1_input = [
             vk::AttachmentReference {
@@ -378,7 +836,7 @@ impl DebugPipeline {
             .dependencies(&subpass_dependencies);
 
         let render_pass = unsafe {
-            device.vk().create_render_pass(&info, None)
+            device.vk().create_render_pass(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreateRenderPass returned {:?} in DebugPipeline::create_render_pass", err);
             err
@@ -392,22 +850,55 @@ impl DebugPipeline {
         Ok(render_pass)
     }
 
-    fn create_descriptor_pool(device: &DeviceContext, concurrent_passes: usize) -> Result<vk::DescriptorPool, ObjectCreateError> {
-        let concurrent_passes = concurrent_passes as u32;
+    /// Allocates `count` background descriptor sets from pools with space for `sets_per_pool` sets
+    /// each. A additional pool is created whenever the last pool is exhausted. If allocation fails
+    /// all created pools are destroyed.
+    fn allocate_background_sets(device: &DeviceContext, layout: vk::DescriptorSetLayout, count: usize, sets_per_pool: usize) -> Result<(Vec<vk::DescriptorPool>, Vec<vk::DescriptorSet>), ObjectCreateError> {
+        let mut pools = Vec::new();
+        let mut sets = Vec::with_capacity(count);
+        for _ in 0..count {
+            let result = allocate_with_growth(
+                &mut pools,
+                |pool| {
+                    let info = vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(pool)
+                        .set_layouts(std::slice::from_ref(&layout));
+
+                    unsafe { device.vk().allocate_descriptor_sets(&info) }.map(|allocated| allocated[0])
+                },
+                || Self::create_descriptor_pool(device, sets_per_pool)
+            );
+
+            match result {
+                Ok(set) => sets.push(set),
+                Err(err) => {
+                    for pool in pools {
+                        unsafe { device.vk().destroy_descriptor_pool(pool, device.get_allocation_callbacks()) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok((pools, sets))
+    }
+
+    fn create_descriptor_pool(device: &DeviceContext, max_sets: usize) -> Result<vk::DescriptorPool, ObjectCreateError> {
+        let max_sets = max_sets as u32;
 
         let sizes = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::INPUT_ATTACHMENT,
-                descriptor_count: concurrent_passes
+                descriptor_count: max_sets
             },
         ];
 
         let info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(concurrent_passes)
+            .max_sets(max_sets)
             .pool_sizes(&sizes);
 
         let descriptor_pool = unsafe {
-            device.vk().create_descriptor_pool(&info, None)
+            device.vk().create_descriptor_pool(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreateDescriptorPool returned {:?} in DebugPipeline::create_descriptor_pool", err);
             err
@@ -429,6 +920,23 @@ impl EmulatorPipeline for DebugPipeline {
         (self.framebuffer_size, &self.output_views)
     }
 
+    fn get_depth_output(&self) -> Option<(vk::Format, &[vk::Image])> {
+        if self.depth_output_images.is_empty() {
+            None
+        } else {
+            Some((self.depth_attachment.format, &self.depth_output_images))
+        }
+    }
+
+    fn get_color_attachment_output(&self, attachment: usize) -> Option<(vk::Format, &[vk::Image])> {
+        let images = self.color_output_images.get(attachment)?;
+        Some((self.color_attachments[attachment].format, images))
+    }
+
+    fn supports_attachment_ops(&self, attachment: PassAttachment) -> bool {
+        is_configurable_attachment(attachment, self.default_attachment_ops.len())
+    }
+
     fn inc_shader_used(&self, shader: ShaderId) {
         let mut guard = self.pipelines.lock().unwrap();
         if let Some(pipelines) = guard.get_mut(&shader) {
@@ -486,22 +994,208 @@ impl Drop for DebugPipeline {
         }
         self.pipelines.get_mut().unwrap().clear();
         unsafe {
-            device.vk().destroy_descriptor_pool(self.descriptor_pool, None);
+            for pool in self.descriptor_pools.iter() {
+                device.vk().destroy_descriptor_pool(*pool, device.get_allocation_callbacks());
+            }
         }
         self.background_pipeline.destroy(device);
         self.draw_pipeline.destroy(device);
         unsafe {
-            device.vk().destroy_render_pass(self.render_pass, None);
+            for (_, render_pass) in self.render_pass_variants.get_mut().unwrap().drain() {
+                device.vk().destroy_render_pass(render_pass, device.get_allocation_callbacks());
+            }
+            device.vk().destroy_render_pass(self.render_pass, device.get_allocation_callbacks());
         }
         self.shader_modules.destroy(device);
     }
 }
 
+/// Allocates an object from the last pool in `pools`. If the pool is exhausted or no pool exists
+/// a new pool is created with `create_pool` and the allocation is retried once.
+fn allocate_with_growth<P: Copy, T, E: From<vk::Result>>(
+    pools: &mut Vec<P>,
+    mut allocate: impl FnMut(P) -> Result<T, vk::Result>,
+    mut create_pool: impl FnMut() -> Result<P, E>
+) -> Result<T, E> {
+    if let Some(pool) = pools.last() {
+        match allocate(*pool) {
+            Ok(object) => return Ok(object),
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                log::info!("Descriptor pool exhausted. Allocating additional pool");
+            }
+            Err(err) => return Err(E::from(err)),
+        }
+    }
+
+    let pool = create_pool()?;
+    pools.push(pool);
+    allocate(pool).map_err(E::from)
+}
+
+/// Returns the attachment ops used by the render pass if no ops are configured for a pass.
+fn make_default_attachment_ops(color_attachment_count: usize) -> Box<[AttachmentOps]> {
+    let mut ops = vec![
+        AttachmentOps::CLEAR_STORE,
+        AttachmentOps::new(vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::DONT_CARE),
+        AttachmentOps::new(vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::STORE),
+    ];
+    ops.resize(3 + color_attachment_count, AttachmentOps::CLEAR_STORE);
+    ops.into_boxed_slice()
+}
+
+/// Returns the index of a pass attachment in the render pass.
+///
+/// Attachment 2 is the output of the background subpass and cannot be configured.
+fn get_attachment_index(attachment: PassAttachment) -> usize {
+    match attachment {
+        PassAttachment::Depth => 0,
+        PassAttachment::Color(0) => 1,
+        PassAttachment::Color(index) => 2 + (index as usize),
+    }
+}
+
+/// Returns true if the ops of `attachment` can be configured in a render pass with
+/// `attachment_count` attachments.
+fn is_configurable_attachment(attachment: PassAttachment, attachment_count: usize) -> bool {
+    let index = get_attachment_index(attachment);
+    index != 2 && index < attachment_count
+}
+
+/// Creates the attachment descriptions of the render pass. The contents of attachments are never
+/// preserved between passes so a `LOAD` op behaves like `DONT_CARE`.
+fn make_attachment_descriptions(depth_format: vk::Format, color_attachments: &[ColorAttachmentInfo], ops: &[AttachmentOps]) -> Vec<vk::AttachmentDescription> {
+    let mut attachments = vec![
+        vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(ops[0].load_op)
+            .store_op(ops[0].store_op)
+            .stencil_load_op(ops[0].load_op)
+            .stencil_store_op(ops[0].store_op)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build(),
+        vk::AttachmentDescription::builder()
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(ops[1].load_op)
+            .store_op(ops[1].store_op)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::GENERAL)
+            .build(),
+        vk::AttachmentDescription::builder()
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(ops[2].load_op)
+            .store_op(ops[2].store_op)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    ];
+    for (attachment, ops) in color_attachments.iter().zip(&ops[3..]) {
+        attachments.push(vk::AttachmentDescription::builder()
+            .format(attachment.format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(ops.load_op)
+            .store_op(ops.store_op)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+        );
+    }
+
+    attachments
+}
+
+/// Returns the color attachment references of the draw subpass. A depth only subpass has no color
+/// attachments.
+fn make_draw_color_references(depth_only: bool, color_attachment_count: usize) -> Vec<vk::AttachmentReference> {
+    if depth_only {
+        return Vec::new();
+    }
+
+    let mut references = Vec::with_capacity(1 + color_attachment_count);
+    references.push(vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    });
+    for index in 0..color_attachment_count {
+        references.push(vk::AttachmentReference {
+            attachment: 3 + (index as u32),
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        });
+    }
+
+    references
+}
+
+/// Returns the blend states of the draw pipelines. Must match the color attachments returned by
+/// [`make_draw_color_references`]. Additional attachments which the debug fragment shaders do not
+/// write have all color writes disabled.
+fn make_draw_blend_states(depth_only: bool, color_attachments: &[ColorAttachmentInfo]) -> Vec<vk::PipelineColorBlendAttachmentState> {
+    if depth_only {
+        return Vec::new();
+    }
+
+    let mut blend_states = Vec::with_capacity(1 + color_attachments.len());
+    blend_states.push(vk::PipelineColorBlendAttachmentState::builder()
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+        .build()
+    );
+    blend_states.extend(color_attachments.iter().enumerate().map(|(index, attachment)| {
+        if attachment.is_integer() || index >= DEBUG_SHADER_EXTRA_OUTPUTS {
+            vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::empty())
+                .build()
+        } else {
+            attachment.blend_state
+        }
+    }));
+
+    blend_states
+}
+
+/// Returns the clear values of all attachments of the render pass. Additional color attachments are
+/// cleared using the clear value type of their format.
+fn make_clear_values(color_attachments: &[ColorAttachmentInfo], depth_convention: DepthConvention, depth_attachment: &DepthAttachmentInfo) -> Vec<vk::ClearValue> {
+    let mut clear_values = vec![
+        vk::ClearValue {
+            depth_stencil: depth_attachment.make_clear_value(depth_convention)
+        },
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0f32, 0f32, 0f32, 0f32],
+            }
+        },
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0f32, 0f32, 0f32, 0f32],
+            }
+        }
+    ];
+    for attachment in color_attachments {
+        clear_values.push(vk::ClearValue {
+            color: attachment.get_clear_color_type().make_zero_clear()
+        });
+    }
+
+    clear_values
+}
+
 /// The shader modules needed to create vulkan pipelines for the debug pipeline
 struct ShaderModules {
     mode: DebugPipelineMode,
     vertex_module: vk::ShaderModule,
     null_module: vk::ShaderModule,
+    procedural_module: vk::ShaderModule,
     fragment_module: vk::ShaderModule,
     texture_module: Option<vk::ShaderModule>,
 }
@@ -510,8 +1204,16 @@ impl ShaderModules {
     fn new(device: &DeviceContext, mode: DebugPipelineMode) -> Result<Self, ObjectCreateError> {
         let null_module = try_create_shader_module(device, DEBUG_NULL_VERTEX_BIN, "null_vertex")?;
 
-        let fragment_module = try_create_shader_module(device, DEBUG_FRAGMENT_BIN, "fragment").map_err(|err| {
-            unsafe { device.vk().destroy_shader_module(null_module, None) };
+        let procedural_module = try_create_shader_module(device, DEBUG_PROCEDURAL_VERTEX_BIN, "procedural_vertex").map_err(|err| {
+            unsafe { device.vk().destroy_shader_module(null_module, device.get_allocation_callbacks()) };
+            err
+        })?;
+
+        let fragment_module = try_create_shader_module(device, DEBUG_FRAGMENT_BIN, "fragment").map_err(|err| {
+            unsafe {
+                device.vk().destroy_shader_module(null_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(procedural_module, device.get_allocation_callbacks());
+            }
             err
         })?;
 
@@ -525,23 +1227,29 @@ impl ShaderModules {
             DebugPipelineMode::UV2 |
             DebugPipelineMode::Textured0 |
             DebugPipelineMode::Textured1 |
-            DebugPipelineMode::Textured2 => try_create_shader_module(device, DEBUG_UV_VERTEX_BIN, "uv_vertex"),
+            DebugPipelineMode::Textured2 |
+            DebugPipelineMode::Bindless => try_create_shader_module(device, DEBUG_UV_VERTEX_BIN, "uv_vertex"),
         }.map_err(|err| {
             unsafe {
-                device.vk().destroy_shader_module(null_module, None);
-                device.vk().destroy_shader_module(fragment_module, None);
+                device.vk().destroy_shader_module(null_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(procedural_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(fragment_module, device.get_allocation_callbacks());
             }
             err
         })?;
 
         let texture_module = match mode {
-            DebugPipelineMode::Textured0 => try_create_shader_module(device, TEXTURED_FRAGMENT_BIN, "textured_fragment").map(|val| Some(val)),
+            DebugPipelineMode::Textured0 |
+            DebugPipelineMode::Textured1 |
+            DebugPipelineMode::Textured2 => try_create_shader_module(device, TEXTURED_FRAGMENT_BIN, "textured_fragment").map(|val| Some(val)),
+            DebugPipelineMode::Bindless => try_create_shader_module(device, BINDLESS_FRAGMENT_BIN, "bindless_fragment").map(|val| Some(val)),
             _ => Ok(None),
         }.map_err(|err| {
             unsafe {
-                device.vk().destroy_shader_module(null_module, None);
-                device.vk().destroy_shader_module(fragment_module, None);
-                device.vk().destroy_shader_module(vertex_module, None);
+                device.vk().destroy_shader_module(null_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(procedural_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(fragment_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(vertex_module, device.get_allocation_callbacks());
             }
             err
         })?;
@@ -550,24 +1258,60 @@ impl ShaderModules {
             mode,
             vertex_module,
             null_module,
+            procedural_module,
             fragment_module,
             texture_module,
         })
     }
 
+    /// Divisors of the vertex format must have been validated with
+    /// [`VertexFormat::validate_instance_divisor`] when the shader was created.
     fn configure_pipeline<'s, 'a: 's>(&'s self, vertex_format: &VertexFormat, alloc: &'a Bump) -> (&'a [vk::PipelineShaderStageCreateInfo], &'a vk::PipelineVertexInputStateCreateInfo) {
-        let input_bindings: &[_] = alloc.alloc([
-            vk::VertexInputBindingDescription {
-                binding: 0,
-                stride: vertex_format.stride,
-                input_rate: vk::VertexInputRate::VERTEX
+        if vertex_format.is_empty() {
+            return self.configure_procedural_pipeline(alloc);
+        }
+
+        // Per instance attributes are read through a second binding of the same buffer so that
+        // the position is still consumed per vertex
+        let vertex_binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: vertex_format.stride,
+            input_rate: vk::VertexInputRate::VERTEX
+        };
+        let (input_bindings, attribute_binding): (&[_], _) = if vertex_format.instance_divisor.is_some() {
+            (alloc.alloc([
+                vertex_binding,
+                vk::VertexInputBindingDescription {
+                    binding: INSTANCE_BINDING,
+                    stride: vertex_format.stride,
+                    input_rate: vk::VertexInputRate::INSTANCE
+                }
+            ]), INSTANCE_BINDING)
+        } else {
+            (alloc.alloc([vertex_binding]), 0)
+        };
+
+        let input_divisors: Option<&[_]> = match vertex_format.instance_divisor {
+            Some(divisor) if divisor != 1 => {
+                Some(alloc.alloc([
+                    vk::VertexInputBindingDivisorDescriptionEXT {
+                        binding: INSTANCE_BINDING,
+                        divisor
+                    }
+                ]))
             }
-        ]);
+            _ => None,
+        };
 
         let vertex_module;
         let input_attributes: &[_];
         let vertex_format_supported;
-        if let Some(entry) = self.process_vertex_format(vertex_format) {
+        let entry = match vertex_format.validate_attributes(&self.get_consumed_attributes()) {
+            Ok(_) => self.process_vertex_format(vertex_format),
+            // Missing attributes would be read as undefined data. Use the null shader instead
+            Err(_) => None,
+        };
+        if let Some(entry) = entry {
             vertex_format_supported = true;
             vertex_module = self.vertex_module;
 
@@ -580,7 +1324,7 @@ impl ShaderModules {
                 },
                 vk::VertexInputAttributeDescription {
                     location: 1,
-                    binding: 0,
+                    binding: attribute_binding,
                     format: entry.format,
                     offset: entry.offset
                 }
@@ -603,24 +1347,24 @@ impl ShaderModules {
             (DebugPipelineMode::Textured0, true) |
             (DebugPipelineMode::Textured1, true) |
             (DebugPipelineMode::Textured2, true) => {
-                let data = alloc.alloc(match self.mode {
+                let image_index = match self.mode {
                     DebugPipelineMode::Textured0 => 0u32,
                     DebugPipelineMode::Textured1 => 1u32,
                     DebugPipelineMode::Textured2 => 2u32,
                     _ => panic!(),
-                });
-                let entries = alloc.alloc([
-                    vk::SpecializationMapEntry {
-                        constant_id: 0,
-                        offset: 0,
-                        size: 4
-                    }
-                ]);
+                };
+                // IMAGE_INDEX in textured.frag
+                let specialization = SpecializationBuilder::new().set_u32(0, image_index);
+                let entries = alloc.alloc_slice_copy(specialization.get_entries());
+                let data = alloc.alloc_slice_copy(specialization.get_data());
                 (*self.texture_module.as_ref().unwrap(), alloc.alloc(vk::SpecializationInfo::builder()
                     .map_entries(entries)
-                    .data(bytes_of(data))
+                    .data(data)
                 ))
             }
+            (DebugPipelineMode::Bindless, true) => {
+                (*self.texture_module.as_ref().unwrap(), alloc.alloc(vk::SpecializationInfo::builder()))
+            }
             _ => {
                 (self.fragment_module, alloc.alloc(vk::SpecializationInfo::builder()))
             }
@@ -640,15 +1384,59 @@ impl ShaderModules {
                 .build(),
         ]);
 
-        let input_state: &_ = alloc.alloc(vk::PipelineVertexInputStateCreateInfo::builder()
+        let mut input_state = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_binding_descriptions(input_bindings)
-            .vertex_attribute_descriptions(input_attributes)
-            .build()
-        );
+            .vertex_attribute_descriptions(input_attributes);
+
+        if let Some(input_divisors) = input_divisors {
+            let divisor_state = alloc.alloc(vk::PipelineVertexInputDivisorStateCreateInfoEXT::builder()
+                .vertex_binding_divisors(input_divisors)
+            );
+            input_state = input_state.push_next(divisor_state);
+        }
+
+        let input_state: &_ = alloc.alloc(input_state.build());
+
+        (shader_stages, input_state)
+    }
+
+    fn configure_procedural_pipeline<'s, 'a: 's>(&'s self, alloc: &'a Bump) -> (&'a [vk::PipelineShaderStageCreateInfo], &'a vk::PipelineVertexInputStateCreateInfo) {
+        let shader_stages: &[_] = alloc.alloc([
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(self.procedural_module)
+                .name(SHADER_ENTRY)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.fragment_module)
+                .name(SHADER_ENTRY)
+                .build(),
+        ]);
+
+        let input_state: &_ = alloc.alloc(vk::PipelineVertexInputStateCreateInfo::builder().build());
 
         (shader_stages, input_state)
     }
 
+    /// Returns the vertex attributes consumed by the vertex shader of the current mode.
+    fn get_consumed_attributes(&self) -> [VertexAttribute; 2] {
+        let attribute = match self.mode {
+            DebugPipelineMode::Depth |
+            DebugPipelineMode::Position => VertexAttribute::Position,
+            DebugPipelineMode::Color => VertexAttribute::Color,
+            DebugPipelineMode::Normal => VertexAttribute::Normal,
+            DebugPipelineMode::UV0 |
+            DebugPipelineMode::Textured0 |
+            DebugPipelineMode::Bindless => VertexAttribute::UV0,
+            DebugPipelineMode::UV1 |
+            DebugPipelineMode::Textured1 => VertexAttribute::UV1,
+            DebugPipelineMode::UV2 |
+            DebugPipelineMode::Textured2 => VertexAttribute::UV2,
+        };
+        [VertexAttribute::Position, attribute]
+    }
+
     fn process_vertex_format<'a>(&self, vertex_format: &'a VertexFormat) -> Option<&'a VertexFormatEntry> {
         match self.mode {
             DebugPipelineMode::Depth |
@@ -656,7 +1444,8 @@ impl ShaderModules {
             DebugPipelineMode::Color => vertex_format.color.as_ref(),
             DebugPipelineMode::Normal => vertex_format.normal.as_ref(),
             DebugPipelineMode::UV0 |
-            DebugPipelineMode::Textured0 => vertex_format.uv0.as_ref(),
+            DebugPipelineMode::Textured0 |
+            DebugPipelineMode::Bindless => vertex_format.uv0.as_ref(),
             DebugPipelineMode::UV1 |
             DebugPipelineMode::Textured1 => vertex_format.uv1.as_ref(),
             DebugPipelineMode::UV2 |
@@ -666,11 +1455,12 @@ impl ShaderModules {
 
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
-            device.vk().destroy_shader_module(self.vertex_module, None);
-            device.vk().destroy_shader_module(self.null_module, None);
-            device.vk().destroy_shader_module(self.fragment_module, None);
+            device.vk().destroy_shader_module(self.vertex_module, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(self.null_module, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(self.procedural_module, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(self.fragment_module, device.get_allocation_callbacks());
             if let Some(texture_module) = self.texture_module.take() {
-                device.vk().destroy_shader_module(texture_module, None);
+                device.vk().destroy_shader_module(texture_module, device.get_allocation_callbacks());
             }
         }
     }
@@ -679,10 +1469,15 @@ impl ShaderModules {
 struct DrawPipeline {
     set0_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
+    /// The descriptor set of the bindless texture table bound as set 1 if the table exists.
+    bindless_set: Option<vk::DescriptorSet>,
 }
 
 impl DrawPipeline {
-    fn new(device: &DeviceContext) -> Result<Self, ObjectCreateError> {
+    /// Creates the draw pipeline layout. If `bindless_set` contains the layout and descriptor set
+    /// of the bindless texture table it is added as set 1 and the bindless texture index is
+    /// accessible to fragment shaders as a push constant following [`PushConstants`].
+    fn new(device: &DeviceContext, bindless_set: Option<(vk::DescriptorSetLayout, vk::DescriptorSet)>) -> Result<Self, ObjectCreateError> {
         let bindings = [
             vk::DescriptorSetLayoutBinding {
                 binding: 0,
@@ -705,44 +1500,52 @@ impl DrawPipeline {
             .bindings(&bindings);
 
         let set0_layout = unsafe {
-            device.vk().create_descriptor_set_layout(&info, None)
+            device.vk().create_descriptor_set_layout(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreateDescriptorSetLayout returned {:?} in DrawPipeline::new when creating set 0 layout", err);
             err
         })?;
 
-        let push_constant_range = vk::PushConstantRange {
+        let mut push_constant_ranges = vec![vk::PushConstantRange {
             stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
             offset: 0,
-            size: std::mem::size_of::<PushConstants>() as u32,
-        };
+            size: DRAW_PUSH_CONSTANTS_SIZE,
+        }];
+        let mut layouts = vec![set0_layout];
+        if let Some((bindless_layout, _)) = bindless_set {
+            push_constant_ranges.push(BINDLESS_PUSH_CONSTANT_RANGE);
+            layouts.push(bindless_layout);
+        }
 
-        let layouts = [
-            set0_layout
-        ];
+        if let Err(err) = validate_push_constant_ranges(&push_constant_ranges, device.get_max_push_constants_size()) {
+            log::error!("Draw pipeline push constants exceed the device limit {:?}", err);
+            unsafe { device.vk().destroy_descriptor_set_layout(set0_layout, device.get_allocation_callbacks()) };
+            return Err(err.into());
+        }
 
         let info = vk::PipelineLayoutCreateInfo::builder()
-            .push_constant_ranges(std::slice::from_ref(&push_constant_range))
+            .push_constant_ranges(&push_constant_ranges)
             .set_layouts(&layouts);
 
         let pipeline_layout = unsafe {
-            device.vk().create_pipeline_layout(&info, None)
+            device.vk().create_pipeline_layout(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreatePipelineLayout returned {:?} in DrawPipeline::new", err);
-            unsafe { device.vk().destroy_descriptor_set_layout(set0_layout, None) };
+            unsafe { device.vk().destroy_descriptor_set_layout(set0_layout, device.get_allocation_callbacks()) };
             err
         })?;
 
         Ok(Self {
             set0_layout,
-            pipeline_layout
+            pipeline_layout,
+            bindless_set: bindless_set.map(|(_, set)| set),
         })
     }
 
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
-            device.vk().destroy_pipeline_layout(self.pipeline_layout, None);
-            device.vk().destroy_descriptor_set_layout(self.set0_layout, None);
+            device.vk().destroy_pipeline_layout(self.pipeline_layout, device.get_allocation_callbacks());
+            device.vk().destroy_descriptor_set_layout(self.set0_layout, device.get_allocation_callbacks());
         }
     }
 }
@@ -755,41 +1558,30 @@ struct BackgroundPipeline {
 
 impl BackgroundPipeline {
     fn new(device: &DeviceContext, render_pass: vk::RenderPass, subpass: u32, framebuffer_size: Vec2u32) -> Result<Self, ObjectCreateError> {
-        let bindings = [
-            vk::DescriptorSetLayoutBinding {
-                binding: 0,
-                descriptor_type: vk::DescriptorType::INPUT_ATTACHMENT,
-                descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::FRAGMENT,
-                p_immutable_samplers: std::ptr::null()
-            },
-        ];
-
-        let info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&bindings);
+        let descriptor_set_layout = Self::create_descriptor_set_layout(device)?;
 
-        let descriptor_set_layout = unsafe {
-            device.vk().create_descriptor_set_layout(&info, None)
-        }.map_err(|err| {
-            log::error!("vkCreateDescriptorSetLayout returned {:?} in BackgroundPipeline::new", err);
-            err
-        })?;
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<BackgroundPushConstants>() as u32
+        };
 
         let info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(std::slice::from_ref(&push_constant_range));
 
         let pipeline_layout = unsafe {
-            device.vk().create_pipeline_layout(&info, None)
+            device.vk().create_pipeline_layout(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreatePipelineLayout returned {:?} in BackgroundPipeline::new", err);
-            unsafe { device.vk().destroy_descriptor_set_layout(descriptor_set_layout, None) };
+            unsafe { device.vk().destroy_descriptor_set_layout(descriptor_set_layout, device.get_allocation_callbacks()) };
             err
         })?;
 
         let pipeline = Self::create_pipeline(device, pipeline_layout, render_pass, subpass, framebuffer_size).map_err(|err| {
             unsafe {
-                device.vk().destroy_pipeline_layout(pipeline_layout, None);
-                device.vk().destroy_descriptor_set_layout(descriptor_set_layout, None);
+                device.vk().destroy_pipeline_layout(pipeline_layout, device.get_allocation_callbacks());
+                device.vk().destroy_descriptor_set_layout(descriptor_set_layout, device.get_allocation_callbacks());
             }
             err
         })?;
@@ -803,16 +1595,42 @@ impl BackgroundPipeline {
 
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
-            device.vk().destroy_pipeline(self.pipeline, None);
-            device.vk().destroy_pipeline_layout(self.pipeline_layout, None);
-            device.vk().destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.vk().destroy_pipeline(self.pipeline, device.get_allocation_callbacks());
+            device.vk().destroy_pipeline_layout(self.pipeline_layout, device.get_allocation_callbacks());
+            device.vk().destroy_descriptor_set_layout(self.descriptor_set_layout, device.get_allocation_callbacks());
         }
     }
 
+    /// Creates the layout of the per pass descriptor set containing the draw output as a input
+    /// attachment.
+    fn create_descriptor_set_layout(device: &DeviceContext) -> Result<vk::DescriptorSetLayout, ObjectCreateError> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: std::ptr::null()
+            },
+        ];
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings);
+
+        let descriptor_set_layout = unsafe {
+            device.vk().create_descriptor_set_layout(&info, device.get_allocation_callbacks())
+        }.map_err(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in BackgroundPipeline::create_descriptor_set_layout", err);
+            err
+        })?;
+
+        Ok(descriptor_set_layout)
+    }
+
     fn create_pipeline(device: &DeviceContext, layout: vk::PipelineLayout, render_pass: vk::RenderPass, subpass: u32, framebuffer_size: Vec2u32) -> Result<vk::Pipeline, ObjectCreateError> {
         let vertex_module = try_create_shader_module(device, BACKGROUND_VERTEX_BIN, "background_vert")?;
         let fragment_module = try_create_shader_module(device, BACKGROUND_FRAGMENT_BIN, "background_frag").map_err(|err| {
-            unsafe { device.vk().destroy_shader_module(vertex_module, None) };
+            unsafe { device.vk().destroy_shader_module(vertex_module, device.get_allocation_callbacks()) };
             err
         })?;
 
@@ -905,19 +1723,19 @@ impl BackgroundPipeline {
             .subpass(subpass);
 
         let pipeline = *unsafe {
-            device.vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+            device.vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), device.get_allocation_callbacks())
         }.map_err(|(_, err)| {
             log::error!("vkCreateGraphicsPipelines returned {:?} in BackgroundPipeline::create_pipeline", err);
             unsafe {
-                device.vk().destroy_shader_module(vertex_module, None);
-                device.vk().destroy_shader_module(fragment_module, None);
+                device.vk().destroy_shader_module(vertex_module, device.get_allocation_callbacks());
+                device.vk().destroy_shader_module(fragment_module, device.get_allocation_callbacks());
             }
             err
         })?.get(0).unwrap();
 
         unsafe {
-            device.vk().destroy_shader_module(vertex_module, None);
-            device.vk().destroy_shader_module(fragment_module, None);
+            device.vk().destroy_shader_module(vertex_module, device.get_allocation_callbacks());
+            device.vk().destroy_shader_module(fragment_module, device.get_allocation_callbacks());
         }
         drop(specialization_info);
 
@@ -938,6 +1756,8 @@ struct PassObjects {
     output_image: vk::Image,
     output_view: vk::ImageView,
 
+    color_attachments: Vec<(vk::Image, vk::ImageView)>,
+
     bg_descriptor_set: vk::DescriptorSet,
     framebuffer: vk::Framebuffer,
 
@@ -945,7 +1765,7 @@ struct PassObjects {
 }
 
 impl PassObjects {
-    fn new(device: &DeviceContext, framebuffer_size: Vec2u32, depth_format: vk::Format, color_format: vk::Format, render_pass: vk::RenderPass, bg_descriptor_set: vk::DescriptorSet) -> Result<Self, ObjectCreateError> {
+    fn new(device: &DeviceContext, framebuffer_size: Vec2u32, depth_attachment: &DepthAttachmentInfo, color_format: vk::Format, color_attachments: &[ColorAttachmentInfo], render_pass: vk::RenderPass, bg_descriptor_set: vk::DescriptorSet) -> Result<Self, ObjectCreateError> {
         let mut result = PassObjects {
             ready: AtomicBool::new(true),
 
@@ -959,17 +1779,22 @@ impl PassObjects {
             output_image: vk::Image::null(),
             output_view: vk::ImageView::null(),
 
+            color_attachments: Vec::with_capacity(color_attachments.len()),
+
             bg_descriptor_set,
             framebuffer: vk::Framebuffer::null(),
 
-            allocations: Vec::with_capacity(3)
+            allocations: Vec::with_capacity(3 + color_attachments.len())
         };
 
-        let (depth_image, allocation) = Self::create_image(device, framebuffer_size, depth_format, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)?;
+        let depth_format = depth_attachment.format;
+        let depth_usage = get_depth_image_usage(device.get_optimal_format_features(depth_format));
+        let (depth_image, allocation) = Self::create_image(device, framebuffer_size, depth_format, depth_usage)?;
         result.depth_image = depth_image;
         result.allocations.push(allocation);
 
-        let depth_framebuffer_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, false).map_err(|err| {
+        // Attachment views of combined depth stencil formats must include both aspects
+        let depth_framebuffer_view = Self::create_image_view(device, depth_image, depth_format, depth_attachment.get_aspect_mask(), false).map_err(|err| {
             result.destroy(device);
             err
         })?;
@@ -1007,7 +1832,26 @@ impl PassObjects {
         })?;
         result.output_view = output_view;
 
-        let framebuffer = Self::create_framebuffer(device, framebuffer_size, depth_framebuffer_view, pass_view, output_view, render_pass).map_err(|err| {
+        for attachment in color_attachments {
+            let (image, allocation) = Self::create_image(device, framebuffer_size, attachment.format, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.allocations.push(allocation);
+
+            let view = match Self::create_image_view(device, image, attachment.format, vk::ImageAspectFlags::COLOR, false) {
+                Ok(view) => view,
+                Err(err) => {
+                    unsafe { device.vk().destroy_image(image, device.get_allocation_callbacks()) };
+                    result.destroy(device);
+                    return Err(err);
+                }
+            };
+            result.color_attachments.push((image, view));
+        }
+
+        let attachment_views: Box<[_]> = result.color_attachments.iter().map(|(_, view)| *view).collect();
+        let framebuffer = Self::create_framebuffer(device, framebuffer_size, depth_framebuffer_view, pass_view, output_view, &attachment_views, render_pass).map_err(|err| {
             result.destroy(device);
             err
         })?;
@@ -1048,28 +1892,32 @@ impl PassObjects {
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
             if self.framebuffer != vk::Framebuffer::null() {
-                device.vk().destroy_framebuffer(self.framebuffer, None);
+                device.vk().destroy_framebuffer(self.framebuffer, device.get_allocation_callbacks());
+            }
+            for (image, view) in std::mem::replace(&mut self.color_attachments, Vec::new()) {
+                device.vk().destroy_image_view(view, device.get_allocation_callbacks());
+                device.vk().destroy_image(image, device.get_allocation_callbacks());
             }
             if self.output_view != vk::ImageView::null() {
-                device.vk().destroy_image_view(self.output_view, None);
+                device.vk().destroy_image_view(self.output_view, device.get_allocation_callbacks());
             }
             if self.output_image != vk::Image::null() {
-                device.vk().destroy_image(self.output_image, None);
+                device.vk().destroy_image(self.output_image, device.get_allocation_callbacks());
             }
             if self.pass_view != vk::ImageView::null() {
-                device.vk().destroy_image_view(self.pass_view, None);
+                device.vk().destroy_image_view(self.pass_view, device.get_allocation_callbacks());
             }
             if self.pass_image != vk::Image::null() {
-                device.vk().destroy_image(self.pass_image, None);
+                device.vk().destroy_image(self.pass_image, device.get_allocation_callbacks());
             }
             if self.depth_sampler_view != vk::ImageView::null() {
-                device.vk().destroy_image_view(self.depth_sampler_view, None);
+                device.vk().destroy_image_view(self.depth_sampler_view, device.get_allocation_callbacks());
             }
             if self.depth_framebuffer_view != vk::ImageView::null() {
-                device.vk().destroy_image_view(self.depth_framebuffer_view, None);
+                device.vk().destroy_image_view(self.depth_framebuffer_view, device.get_allocation_callbacks());
             }
             if self.depth_image != vk::Image::null() {
-                device.vk().destroy_image(self.depth_image, None);
+                device.vk().destroy_image(self.depth_image, device.get_allocation_callbacks());
             }
             device.get_allocator().free_memory_pages(&self.allocations);
         }
@@ -1129,7 +1977,7 @@ impl PassObjects {
             });
 
         let image_view = unsafe {
-            device.vk().create_image_view(&info, None)
+            device.vk().create_image_view(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreateImageView returned {:?} in PassObjects::create_image_view", err);
             err
@@ -1138,10 +1986,11 @@ impl PassObjects {
         Ok(image_view)
     }
 
-    fn create_framebuffer(device: &DeviceContext, size: Vec2u32, depth_view: vk::ImageView, pass_view: vk::ImageView, output_view: vk::ImageView, render_pass: vk::RenderPass) -> Result<vk::Framebuffer, ObjectCreateError> {
-        let attachments = [
+    fn create_framebuffer(device: &DeviceContext, size: Vec2u32, depth_view: vk::ImageView, pass_view: vk::ImageView, output_view: vk::ImageView, color_attachment_views: &[vk::ImageView], render_pass: vk::RenderPass) -> Result<vk::Framebuffer, ObjectCreateError> {
+        let mut attachments = vec![
             depth_view, pass_view, output_view
         ];
+        attachments.extend_from_slice(color_attachment_views);
 
         let info = vk::FramebufferCreateInfo::builder()
             .render_pass(render_pass)
@@ -1151,7 +2000,7 @@ impl PassObjects {
             .layers(1);
 
         let framebuffer = unsafe {
-            device.vk().create_framebuffer(&info, None)
+            device.vk().create_framebuffer(&info, device.get_allocation_callbacks())
         }.map_err(|err| {
             log::error!("vkCreateFramebuffer returned {:?} in PassObjects::create_framebuffer", err);
             err
@@ -1166,6 +2015,9 @@ struct PipelineConfig {
     primitive_topology: vk::PrimitiveTopology,
     depth_test_enable: bool,
     depth_write_enable: bool,
+    /// The number of control points per patch. Only used if the shader has tessellation stages
+    /// and must be 0 otherwise.
+    patch_control_points: u32,
 }
 
 struct ShaderPipelines {
@@ -1192,13 +2044,15 @@ impl ShaderPipelines {
         }
     }
 
-    fn get_or_create_pipeline<T: FnOnce(&VertexFormat) -> vk::Pipeline>(&mut self, config: &PipelineConfig, create_fn: T) -> vk::Pipeline {
+    /// Returns the pipeline for a configuration creating it if necessary. Failed creations are not
+    /// cached.
+    fn get_or_create_pipeline<T: FnOnce(&VertexFormat) -> Result<vk::Pipeline, PipelineCreateError>>(&mut self, config: &PipelineConfig, create_fn: T) -> Result<vk::Pipeline, PipelineCreateError> {
         if let Some(pipeline) = self.pipelines.get(config) {
-            *pipeline
+            Ok(*pipeline)
         } else {
-            let pipeline = create_fn(&self.vertex_format);
+            let pipeline = create_fn(&self.vertex_format)?;
             self.pipelines.insert(*config, pipeline);
-            pipeline
+            Ok(pipeline)
         }
     }
 
@@ -1223,7 +2077,7 @@ impl Drop for ShaderPipelines {
     fn drop(&mut self) {
         for pipeline in self.pipelines.values() {
             unsafe {
-                self.device.vk().destroy_pipeline(*pipeline, None);
+                self.device.vk().destroy_pipeline(*pipeline, self.device.get_allocation_callbacks());
             }
         }
     }
@@ -1238,13 +2092,32 @@ struct DebugPipelinePass {
     shader_uniforms: HashMap<ShaderId, UniformStateTracker>,
 
     command_buffer: Option<vk::CommandBuffer>,
+    attachment_ops: Box<[AttachmentOps]>,
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+    /// The mvp matrix set with [`PipelineTask::SetMvp`] if any.
+    mvp: Option<Mat4f32>,
+    /// The bindless texture index set with [`PipelineTask::SetBindlessTexture`] which has not been
+    /// pushed yet.
+    pending_bindless_texture: Option<u32>,
+    render_pass_started: bool,
     current_pipeline: Option<(ShaderId, PipelineConfig)>,
     current_vertex_buffer: Option<vk::Buffer>,
     current_index_buffer: Option<vk::Buffer>,
+    /// The stride of the per instance binding of the current pipeline if it has one.
+    instance_stride: Option<u32>,
+    /// The commands recorded by this pass. [`None`] if command logging was disabled when the pass
+    /// was created.
+    command_log: Option<Vec<RecordedCommand>>,
 }
 
 impl DebugPipelinePass {
     fn new(parent: Arc<DebugPipeline>, index: usize) -> Self {
+        let attachment_ops = parent.default_attachment_ops.clone();
+        let viewport = parent.depth_convention.apply_to_viewport(make_full_viewport(parent.framebuffer_size));
+        let scissor = make_full_rect(parent.framebuffer_size);
+        let command_log = parent.command_log.lock().unwrap().as_ref().map(|_| Vec::new());
+
         Self {
             parent,
             index,
@@ -1254,9 +2127,23 @@ impl DebugPipelinePass {
             shader_uniforms: HashMap::new(),
 
             command_buffer: None,
+            attachment_ops,
+            viewport,
+            scissor,
+            mvp: None,
+            pending_bindless_texture: None,
+            render_pass_started: false,
             current_pipeline: None,
             current_vertex_buffer: None,
-            current_index_buffer: None
+            current_index_buffer: None,
+            instance_stride: None,
+            command_log,
+        }
+    }
+
+    fn log_command(&mut self, command: RecordedCommand) {
+        if let Some(log) = &mut self.command_log {
+            log.push(command);
         }
     }
 
@@ -1266,7 +2153,11 @@ impl DebugPipelinePass {
             self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
         }
         let tracker = self.shader_uniforms.get_mut(&shader).unwrap();
-        tracker.update_uniform(data);
+        if let McUniformData::ProjectionMatrix(mat) = data {
+            tracker.update_uniform(&McUniformData::ProjectionMatrix(self.parent.adjust_projection(mat)));
+        } else {
+            tracker.update_uniform(data);
+        }
     }
 
     fn update_texture(&mut self, shader: ShaderId, index: u32, view: vk::ImageView, sampler: vk::Sampler) {
@@ -1279,30 +2170,184 @@ impl DebugPipelinePass {
     }
 
     fn draw(&mut self, task: &DrawTask, obj: &mut PooledObjectProvider) {
-        let device = self.parent.emulator.get_device();
+        let device = self.parent.emulator.get_device().clone();
+        let cmd = *self.command_buffer.as_ref().unwrap();
+
+        let pipeline_config = PipelineConfig {
+            primitive_topology: task.primitive_topology,
+            depth_test_enable: true,
+            depth_write_enable: task.depth_write_enable,
+            // The emulator shaders have no tessellation stages
+            patch_control_points: 0
+        };
+
+        if !self.prepare_draw(task.shader, pipeline_config, obj) {
+            return;
+        }
+
+        if self.current_vertex_buffer != Some(task.vertex_buffer) {
+            unsafe {
+                device.vk().cmd_bind_vertex_buffers(
+                    cmd,
+                    0,
+                    std::slice::from_ref(&task.vertex_buffer),
+                    std::slice::from_ref(&0)
+                );
+            }
+            self.current_vertex_buffer = Some(task.vertex_buffer);
+        }
+
+        if let Some(stride) = self.instance_stride {
+            // Per instance attributes start at the first vertex of the draw
+            let offset = (task.vertex_offset.max(0) as vk::DeviceSize) * (stride as vk::DeviceSize);
+            unsafe {
+                device.vk().cmd_bind_vertex_buffers(
+                    cmd,
+                    INSTANCE_BINDING,
+                    std::slice::from_ref(&task.vertex_buffer),
+                    std::slice::from_ref(&offset)
+                );
+            }
+        }
+
+        if self.current_index_buffer != Some(task.index_buffer) {
+            unsafe {
+                device.vk().cmd_bind_index_buffer(cmd, task.index_buffer, 0, task.index_type);
+            }
+            self.current_index_buffer = Some(task.index_buffer);
+        }
+
+        unsafe {
+            device.vk().cmd_draw_indexed(cmd, task.index_count, 1, task.first_index, task.vertex_offset, 0);
+        }
+        self.log_command(RecordedCommand::Draw(task.shader));
+    }
+
+    fn draw_procedural(&mut self, task: &ProceduralDrawTask, obj: &mut PooledObjectProvider) {
+        let device = self.parent.emulator.get_device().clone();
         let cmd = *self.command_buffer.as_ref().unwrap();
 
         let pipeline_config = PipelineConfig {
             primitive_topology: task.primitive_topology,
             depth_test_enable: true,
-            depth_write_enable: task.depth_write_enable
+            depth_write_enable: task.depth_write_enable,
+            // The emulator shaders have no tessellation stages
+            patch_control_points: 0
         };
 
-        if self.current_pipeline != Some((task.shader, pipeline_config)) {
-            self.current_pipeline = Some((task.shader, pipeline_config));
+        if !self.prepare_draw(task.shader, pipeline_config, obj) {
+            return;
+        }
+
+        unsafe {
+            device.vk().cmd_draw(cmd, task.vertex_count, task.instance_count, 0, 0);
+        }
+        self.log_command(RecordedCommand::Draw(task.shader));
+    }
+
+    fn set_attachment_ops(&mut self, attachment: PassAttachment, ops: AttachmentOps) {
+        if self.render_pass_started {
+            log::error!("Attempted to set attachment ops for {:?} after the render pass has been started", attachment);
+            panic!()
+        }
+
+        if !is_configurable_attachment(attachment, self.attachment_ops.len()) {
+            log::error!("Attempted to set attachment ops for invalid attachment {:?}", attachment);
+            panic!()
+        }
+        self.attachment_ops[get_attachment_index(attachment)] = ops;
+    }
+
+    fn set_pass_viewport(&mut self, viewport: vk::Viewport) {
+        if self.render_pass_started {
+            log::error!("Attempted to set the pass viewport after the render pass has been started");
+            panic!()
+        }
+        self.viewport = self.parent.depth_convention.apply_to_viewport(viewport);
+    }
+
+    fn set_pass_scissor(&mut self, scissor: vk::Rect2D) {
+        if self.render_pass_started {
+            log::error!("Attempted to set the pass scissor after the render pass has been started");
+            panic!()
+        }
+        self.scissor = scissor;
+    }
+
+    /// Begins the render pass if it has not been started yet. The render pass is started lazily
+    /// so that the attachment ops can be configured after the pass has been initialized.
+    fn begin_render_pass(&mut self) {
+        if self.render_pass_started {
+            return;
+        }
+        self.render_pass_started = true;
+
+        let device = self.parent.emulator.get_device();
+        let cmd = *self.command_buffer.as_ref().unwrap();
+
+        let clear_values = make_clear_values(&self.parent.color_attachments, self.parent.depth_convention, &self.parent.depth_attachment);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.parent.get_render_pass(&self.attachment_ops))
+            .framebuffer(self.parent.pass_objects[self.index].framebuffer)
+            .render_area(make_full_rect(self.parent.framebuffer_size))
+            .clear_values(&clear_values);
+
+        unsafe {
+            device.vk().cmd_begin_render_pass(cmd, &info, vk::SubpassContents::INLINE);
+            device.vk().cmd_set_viewport(cmd, 0, std::slice::from_ref(&self.viewport));
+            device.vk().cmd_set_scissor(cmd, 0, std::slice::from_ref(&self.scissor));
+        }
+    }
+
+    /// Binds the pipeline and updates all uniforms and textures for a draw using the shader.
+    ///
+    /// Returns false if the pipeline could not be created. The error is reported to the emulator
+    /// and the draw must be skipped.
+    fn prepare_draw(&mut self, shader: ShaderId, pipeline_config: PipelineConfig, obj: &mut PooledObjectProvider) -> bool {
+        self.begin_render_pass();
+
+        let device = self.parent.emulator.get_device().clone();
+        let cmd = *self.command_buffer.as_ref().unwrap();
+
+        if self.current_pipeline != Some((shader, pipeline_config)) {
+            let new_pipeline = match self.parent.get_pipeline(shader, &pipeline_config) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    self.parent.emulator.push_task_error(EmulatorTaskError {
+                        task: EmulatorTaskKind::Draw(shader),
+                        reason: format!("Failed to create pipeline for {:?}: {:?}", pipeline_config, err)
+                    });
+                    return false;
+                }
+            };
+            self.current_pipeline = Some((shader, pipeline_config));
 
-            let new_pipeline = self.parent.get_pipeline(task.shader, &pipeline_config);
             unsafe {
                 device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, new_pipeline);
             }
+            self.log_command(RecordedCommand::BindPipeline(shader));
+            self.instance_stride = self.parent.get_instance_stride(shader);
+        }
+
+        if let Some(index) = self.pending_bindless_texture.take() {
+            unsafe {
+                device.vk().cmd_push_constants(
+                    cmd,
+                    self.parent.draw_pipeline.pipeline_layout,
+                    BINDLESS_PUSH_CONSTANT_RANGE.stage_flags,
+                    BINDLESS_PUSH_CONSTANT_RANGE.offset,
+                    bytes_of(&index)
+                );
+            }
         }
 
-        if !self.shader_uniforms.contains_key(&task.shader) {
+        if !self.shader_uniforms.contains_key(&shader) {
             log::warn!("Called draw without any shader uniforms. Using default values!");
-            let uniforms = self.parent.pipelines.lock().unwrap().get(&task.shader).unwrap().used_uniforms;
-            self.shader_uniforms.insert(task.shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
+            let uniforms = self.parent.pipelines.lock().unwrap().get(&shader).unwrap().used_uniforms;
+            self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
         }
-        if let Some(tracker) = self.shader_uniforms.get_mut(&task.shader) {
+        if let Some(tracker) = self.shader_uniforms.get_mut(&shader) {
+            tracker.set_mvp(self.mvp);
             if let Some(push_constants) = tracker.validate_push_constants() {
                 unsafe {
                     device.vk().cmd_push_constants(
@@ -1315,101 +2360,32 @@ impl DebugPipelinePass {
                 }
             }
 
+            let mut batch = DescriptorWriteBatch::new();
             if let Some(static_uniforms) = tracker.validate_static_uniforms() {
                 let (buffer, offset) = obj.allocate_uniform(bytes_of(static_uniforms));
-                let buffer_info = vk::DescriptorBufferInfo {
+                batch.set_uniform_buffer(vk::DescriptorBufferInfo {
                     buffer,
                     offset,
                     range: std::mem::size_of::<StaticUniforms>() as vk::DeviceSize
-                };
-                let write = vk::WriteDescriptorSet::builder()
-                    .dst_binding(0)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&buffer_info));
-
-                unsafe {
-                    device.push_descriptor_khr().cmd_push_descriptor_set(
-                        self.command_buffer.unwrap(),
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.parent.draw_pipeline.pipeline_layout,
-                        0,
-                        std::slice::from_ref(&write)
-                    );
-                }
+                });
             }
-
             if let Some(textures) = tracker.validate_textures() {
-                let image_info0 = vk::DescriptorImageInfo {
-                    sampler: textures[0].1,
-                    image_view: textures[0].0,
-                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-                };
-                let image_info1 = vk::DescriptorImageInfo {
-                    sampler: textures[1].1,
-                    image_view: textures[1].0,
-                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-                };
-                let image_info2 = vk::DescriptorImageInfo {
-                    sampler: textures[2].1,
-                    image_view: textures[2].0,
-                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-                };
-                let writes = [
-                    vk::WriteDescriptorSet::builder()
-                        .dst_binding(1)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .image_info(std::slice::from_ref(&image_info0))
-                        .build(),
-                    vk::WriteDescriptorSet::builder()
-                        .dst_binding(1)
-                        .dst_array_element(1)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .image_info(std::slice::from_ref(&image_info1))
-                        .build(),
-                    vk::WriteDescriptorSet::builder()
-                        .dst_binding(1)
-                        .dst_array_element(2)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .image_info(std::slice::from_ref(&image_info2))
-                        .build(),
-                ];
-
-                unsafe {
-                    device.push_descriptor_khr().cmd_push_descriptor_set(
-                        self.command_buffer.unwrap(),
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.parent.draw_pipeline.pipeline_layout,
-                        0,
-                        &writes
-                    );
-                }
+                batch.set_textures(make_texture_image_infos(textures));
             }
-        }
 
-        if self.current_vertex_buffer != Some(task.vertex_buffer) {
-            unsafe {
-                device.vk().cmd_bind_vertex_buffers(
+            // All changed descriptors are pushed with a single call
+            batch.flush(|writes| unsafe {
+                device.push_descriptor_khr().cmd_push_descriptor_set(
                     cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.parent.draw_pipeline.pipeline_layout,
                     0,
-                    std::slice::from_ref(&task.vertex_buffer),
-                    std::slice::from_ref(&0)
+                    writes
                 );
-            }
-            self.current_vertex_buffer = Some(task.vertex_buffer);
-        }
-
-        if self.current_index_buffer != Some(task.index_buffer) {
-            unsafe {
-                device.vk().cmd_bind_index_buffer(cmd, task.index_buffer, 0, task.index_type);
-            }
-            self.current_index_buffer = Some(task.index_buffer);
+            });
         }
 
-        unsafe {
-            device.vk().cmd_draw_indexed(cmd, task.index_count, 1, task.first_index, task.vertex_offset, 0);
-        }
+        true
     }
 }
 
@@ -1421,34 +2397,22 @@ impl EmulatorPipelinePass for DebugPipelinePass {
         let cmd = obj.get_begin_command_buffer().unwrap();
         self.command_buffer = Some(cmd);
 
-        let device = self.parent.emulator.get_device();
+        let device = self.parent.emulator.get_device().clone();
+        unsafe {
+            device.vk().cmd_set_stencil_reference(cmd, vk::StencilFaceFlags::FRONT_AND_BACK, 0);
+            device.vk().cmd_set_line_width(cmd, 1f32);
+            device.vk().cmd_set_blend_constants(cmd, &[0f32; 4]);
+        }
+        self.log_command(RecordedCommand::SetStencilReference(0));
+        self.log_command(RecordedCommand::SetLineWidth(1f32));
+        self.log_command(RecordedCommand::SetBlendConstants([0f32; 4]));
 
-        let clear_values = [
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0
-                }
-            },
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0f32, 0f32, 0f32, 0f32],
-                }
-            },
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0f32, 0f32, 0f32, 0f32],
-                }
+        if let Some(bindless_set) = self.parent.draw_pipeline.bindless_set {
+            unsafe {
+                device.vk().cmd_bind_descriptor_sets(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.draw_pipeline.pipeline_layout, 1, std::slice::from_ref(&bindless_set), &[]);
             }
-        ];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.parent.render_pass)
-            .framebuffer(self.parent.pass_objects[self.index].framebuffer)
-            .render_area(make_full_rect(self.parent.framebuffer_size))
-            .clear_values(&clear_values);
-
-        unsafe {
-            device.vk().cmd_begin_render_pass(cmd, &info, vk::SubpassContents::INLINE);
+            // Keeps the index defined for draws before the first bindless texture is set
+            self.pending_bindless_texture = Some(0);
         }
     }
 
@@ -1460,26 +2424,75 @@ impl EmulatorPipelinePass for DebugPipelinePass {
             PipelineTask::UpdateTexture(shader, index, view, sampler) => {
                 self.update_texture(*shader, *index, *view, *sampler);
             }
+            PipelineTask::SetStencilReference(reference) => {
+                let cmd = *self.command_buffer.as_ref().unwrap();
+                unsafe {
+                    self.parent.emulator.get_device().vk().cmd_set_stencil_reference(cmd, vk::StencilFaceFlags::FRONT_AND_BACK, *reference);
+                }
+                self.log_command(RecordedCommand::SetStencilReference(*reference));
+            }
+            PipelineTask::SetLineWidth(width) => {
+                let cmd = *self.command_buffer.as_ref().unwrap();
+                unsafe {
+                    self.parent.emulator.get_device().vk().cmd_set_line_width(cmd, *width);
+                }
+                self.log_command(RecordedCommand::SetLineWidth(*width));
+            }
+            PipelineTask::SetBlendConstants(constants) => {
+                let cmd = *self.command_buffer.as_ref().unwrap();
+                unsafe {
+                    self.parent.emulator.get_device().vk().cmd_set_blend_constants(cmd, constants);
+                }
+                self.log_command(RecordedCommand::SetBlendConstants(*constants));
+            }
+            PipelineTask::SetMvp(mvp) => {
+                // Pushed with the shader push constants before the next draw
+                self.mvp = Some(self.parent.adjust_projection(mvp));
+            }
+            PipelineTask::SetBindlessTexture(index) => {
+                if self.parent.draw_pipeline.bindless_set.is_none() {
+                    log::error!("Called SetBindlessTexture on a pipeline without a bindless texture table");
+                    panic!()
+                }
+                // Pushed before the next draw
+                self.pending_bindless_texture = Some(*index);
+            }
+            PipelineTask::SetAttachmentOps(attachment, ops) => {
+                self.set_attachment_ops(*attachment, *ops);
+            }
+            PipelineTask::SetPassViewport(viewport) => {
+                self.set_pass_viewport(*viewport);
+            }
+            PipelineTask::SetPassScissor(scissor) => {
+                self.set_pass_scissor(*scissor);
+            }
             PipelineTask::Draw(task) => {
                 self.draw(task, obj);
             }
+            PipelineTask::DrawProcedural(task) => {
+                self.draw_procedural(task, obj);
+            }
         }
     }
 
     fn record<'a>(&mut self, _: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        self.begin_render_pass();
+
         let device = self.parent.emulator.get_device();
         let cmd = self.command_buffer.take().unwrap();
 
         let bg_descriptor_sets = [self.parent.pass_objects[self.index].bg_descriptor_set];
+        let bg_push_constants = self.parent.background_style.lock().unwrap().to_push_constants();
 
         unsafe {
             device.vk().cmd_next_subpass(cmd, vk::SubpassContents::INLINE);
             device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.background_pipeline.pipeline);
             device.vk().cmd_bind_descriptor_sets(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.background_pipeline.pipeline_layout, 0, &bg_descriptor_sets, &[]);
+            device.vk().cmd_push_constants(cmd, self.parent.background_pipeline.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, bytes_of(&bg_push_constants));
             device.vk().cmd_draw(cmd, 4, 1, 0, 0);
         }
 
-        let image_barrier = [
+        let mut image_barrier = vec![
             vk::ImageMemoryBarrier2::builder()
                 .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
                 .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
@@ -1491,7 +2504,7 @@ impl EmulatorPipelinePass for DebugPipelinePass {
                 .dst_queue_family_index(0)
                 .image(self.parent.pass_objects[self.index].depth_image)
                 .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
+                    aspect_mask: self.parent.depth_attachment.get_aspect_mask(),
                     base_mip_level: 0,
                     level_count: 1,
                     base_array_layer: 0,
@@ -1517,6 +2530,27 @@ impl EmulatorPipelinePass for DebugPipelinePass {
                 })
                 .build(),
         ];
+        for (image, _) in &self.parent.pass_objects[self.index].color_attachments {
+            image_barrier.push(vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(0)
+                .dst_queue_family_index(0)
+                .image(*image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1
+                })
+                .build()
+            );
+        }
 
         let info = vk::DependencyInfo::builder()
             .image_memory_barriers(&image_barrier);
@@ -1536,6 +2570,12 @@ impl EmulatorPipelinePass for DebugPipelinePass {
         submits.push(vk::SubmitInfo2::builder()
             .command_buffer_infos(std::slice::from_ref(command_buffer_info))
         );
+
+        if let Some(log) = self.command_log.take() {
+            if let Some(parent_log) = self.parent.command_log.lock().unwrap().as_mut() {
+                parent_log.extend(log);
+            }
+        }
     }
 
     fn get_output_index(&self) -> usize {
@@ -1545,6 +2585,10 @@ impl EmulatorPipelinePass for DebugPipelinePass {
     fn get_internal_fences(&self, _: &mut Vec<vk::Fence>) {
         todo!()
     }
+
+    fn get_breadcrumb_command_buffer(&self) -> Option<vk::CommandBuffer> {
+        self.command_buffer
+    }
 }
 
 impl Drop for DebugPipelinePass {
@@ -1558,6 +2602,10 @@ struct UniformStateTracker {
     push_constants_dirty: bool,
     static_uniforms_dirty: bool,
     textures_dirty: bool,
+    /// The model view matrix of the shader. Stored separately since the push constant slot holds
+    /// the mvp matrix while it is enabled.
+    model_view_matrix: Mat4f32,
+    mvp: Option<Mat4f32>,
     push_constant_cache: PushConstants,
     static_uniform_cache: StaticUniforms,
     textures: [(vk::ImageView, vk::Sampler); 3],
@@ -1570,10 +2618,12 @@ impl UniformStateTracker {
             push_constants_dirty: true,
             static_uniforms_dirty: true,
             textures_dirty: true,
+            model_view_matrix: Mat4f32::identity(),
+            mvp: None,
             push_constant_cache: PushConstants {
                 model_view_matrix: Mat4f32::identity(),
                 chunk_offset: Vec3f32::zeros(),
-                _padding0: Default::default(),
+                mvp_enabled: 0,
             },
             static_uniform_cache: StaticUniforms {
                 projection_matrix: Mat4f32::identity(),
@@ -1593,8 +2643,11 @@ impl UniformStateTracker {
         match data {
             McUniformData::ModelViewMatrix(mat) => {
                 if self.used_uniforms.contains(&McUniform::MODEL_VIEW_MATRIX) {
-                    self.push_constant_cache.model_view_matrix = *mat;
-                    self.push_constants_dirty = true;
+                    self.model_view_matrix = *mat;
+                    if self.mvp.is_none() {
+                        self.push_constant_cache.model_view_matrix = *mat;
+                        self.push_constants_dirty = true;
+                    }
                 }
             }
             McUniformData::ProjectionMatrix(mat) => {
@@ -1672,6 +2725,18 @@ impl UniformStateTracker {
         }
     }
 
+    /// Sets the mvp matrix used instead of the projection and model view matrices. The mvp matrix
+    /// replaces the model view matrix in the push constants so that the range fits into the 128
+    /// bytes guaranteed by vulkan. Passing [`None`] restores the model view matrix.
+    fn set_mvp(&mut self, mvp: Option<Mat4f32>) {
+        if self.mvp != mvp {
+            self.mvp = mvp;
+            self.push_constant_cache.model_view_matrix = mvp.unwrap_or(self.model_view_matrix);
+            self.push_constant_cache.mvp_enabled = mvp.is_some() as u32;
+            self.push_constants_dirty = true;
+        }
+    }
+
     fn validate_push_constants(&mut self) -> Option<&PushConstants> {
         if self.push_constants_dirty {
             self.push_constants_dirty = false;
@@ -1700,23 +2765,127 @@ impl UniformStateTracker {
     }
 }
 
+/// Collects the descriptor writes needed before a draw so that they can be pushed with a single
+/// call.
+struct DescriptorWriteBatch {
+    uniform_buffer: Option<vk::DescriptorBufferInfo>,
+    textures: Option<[vk::DescriptorImageInfo; 3]>,
+}
+
+impl DescriptorWriteBatch {
+    fn new() -> Self {
+        Self {
+            uniform_buffer: None,
+            textures: None,
+        }
+    }
+
+    fn set_uniform_buffer(&mut self, info: vk::DescriptorBufferInfo) {
+        self.uniform_buffer = Some(info);
+    }
+
+    fn set_textures(&mut self, infos: [vk::DescriptorImageInfo; 3]) {
+        self.textures = Some(infos);
+    }
+
+    /// Calls `push` once with all collected writes. Returns false without calling `push` if no
+    /// writes have been collected.
+    fn flush<F: FnOnce(&[vk::WriteDescriptorSet])>(&self, push: F) -> bool {
+        let mut writes = Vec::with_capacity(2);
+        if let Some(uniform_buffer) = &self.uniform_buffer {
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(std::slice::from_ref(uniform_buffer))
+                .build()
+            );
+        }
+        if let Some(textures) = &self.textures {
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(textures)
+                .build()
+            );
+        }
+
+        if writes.is_empty() {
+            return false;
+        }
+        push(&writes);
+        true
+    }
+}
+
+/// Returns the descriptor image infos for the texture array. Every texture is paired with its own
+/// sampler so textures bound to the same shader may use different filtering and address modes.
+fn make_texture_image_infos(textures: &[(vk::ImageView, vk::Sampler); 3]) -> [vk::DescriptorImageInfo; 3] {
+    textures.map(|(image_view, sampler)| {
+        vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        }
+    })
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Default)]
 struct PushConstants {
-    #[allow(unused)]
+    /// The model view matrix or the mvp matrix if `mvp_enabled` is not 0.
     model_view_matrix: Mat4f32,
 
     #[allow(unused)]
     chunk_offset: Vec3f32,
 
-    _padding0: [u8; 4],
+    mvp_enabled: u32,
 }
 const_assert_eq!(std::mem::size_of::<PushConstants>(), 80);
 const_assert_eq!(std::mem::size_of::<PushConstants>() % 16, 0);
 
+/// The size of the push constant range of the draw pipeline layout. Must not exceed the 128 bytes
+/// guaranteed by vulkan.
+const DRAW_PUSH_CONSTANTS_SIZE: u32 = std::mem::size_of::<PushConstants>() as u32;
+const_assert!(DRAW_PUSH_CONSTANTS_SIZE <= 128);
+
+/// The vertex input binding of attributes consumed per instance. Uses the same buffer as binding 0.
+const INSTANCE_BINDING: u32 = 1;
+
+/// The number of additional color outputs declared by the debug fragment shaders following the
+/// main color output.
+const DEBUG_SHADER_EXTRA_OUTPUTS: usize = 3;
+
+/// The number of background descriptor sets each descriptor pool of a [`DebugPipeline`] has space
+/// for. Pipelines with more concurrent passes allocate additional pools.
+const DESCRIPTOR_POOL_SETS: usize = 2;
+
+/// The push constant range of the bindless texture index accessed by fragment shaders. Follows
+/// the draw push constants (see `_BindlessPushConstant` in `bindless.frag`).
+const BINDLESS_PUSH_CONSTANT_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    offset: DRAW_PUSH_CONSTANTS_SIZE,
+    size: 4,
+};
+const_assert!(DRAW_PUSH_CONSTANTS_SIZE + 4 <= 128);
+
 unsafe impl Zeroable for PushConstants {}
 unsafe impl Pod for PushConstants {}
 
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct BackgroundPushConstants {
+    color0: Vec4f32,
+    color1: Vec4f32,
+    style: u32,
+    _padding0: [u8; 12],
+}
+const_assert_eq!(std::mem::size_of::<BackgroundPushConstants>(), 48);
+
+unsafe impl Zeroable for BackgroundPushConstants {}
+unsafe impl Pod for BackgroundPushConstants {}
+
 #[repr(C)]
 #[derive(Copy, Clone, Default)]
 struct StaticUniforms {
@@ -1760,9 +2929,1026 @@ const SHADER_ENTRY: &'static CStr = unsafe { CStr::from_bytes_with_nul_unchecked
 static DEBUG_POSITION_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/position_vert.spv"));
 static DEBUG_COLOR_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/color_vert.spv"));
 static DEBUG_UV_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/uv_vert.spv"));
+static DEBUG_PROCEDURAL_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/procedural_vert.spv"));
 static DEBUG_NULL_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/null_vert.spv"));
 static DEBUG_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/debug_frag.spv"));
 static TEXTURED_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/textured_frag.spv"));
+static BINDLESS_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/bindless_frag.spv"));
 
 static BACKGROUND_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_vert.spv"));
-static BACKGROUND_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_frag.spv"));
\ No newline at end of file
+static BACKGROUND_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_frag.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::device_utils::ShaderStageError;
+    use crate::device::init::FeatureFlag;
+    use crate::renderer::emulator::{ExternalImageState, FlushError, GlobalImage, ImageData, ImageReadRegion, MeshData, PassRecorder, SamplerInfo};
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct TestVertex {
+        position: [f32; 3],
+        uv: [f32; 2],
+    }
+
+    unsafe impl Zeroable for TestVertex {}
+    unsafe impl Pod for TestVertex {}
+
+    const TEST_VERTEX_FORMAT: VertexFormat = VertexFormat {
+        stride: std::mem::size_of::<TestVertex>() as u32,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: None,
+        color: None,
+        uv0: Some(VertexFormatEntry { offset: 12, format: vk::Format::R32G32_SFLOAT }),
+        uv1: None,
+        uv2: None,
+        instance_divisor: None,
+    };
+
+    const NEAREST_SAMPLER: SamplerInfo = SamplerInfo {
+        mag_filter: vk::Filter::NEAREST,
+        min_filter: vk::Filter::NEAREST,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        anisotropy_enable: false,
+    };
+
+    /// Returns a front facing quad covering the full framebuffer height between `x0` and `x1` in
+    /// normalized device coordinates.
+    fn make_test_quad(x0: f32, x1: f32) -> [TestVertex; 4] {
+        [
+            TestVertex { position: [x0, -1.0, 0.5], uv: [0.0, 0.0] },
+            TestVertex { position: [x1, -1.0, 0.5], uv: [1.0, 0.0] },
+            TestVertex { position: [x1, 1.0, 0.5], uv: [1.0, 1.0] },
+            TestVertex { position: [x0, 1.0, 0.5], uv: [0.0, 1.0] },
+        ]
+    }
+
+    fn draw_test_quad(recorder: &mut PassRecorder, shader: ShaderId, x0: f32, x1: f32) {
+        let id = recorder.upload_immediate_quads(cast_slice(&make_test_quad(x0, x1)), TEST_VERTEX_FORMAT.stride);
+        recorder.draw_immediate(id, shader, true);
+    }
+
+    /// Records a pass with `record` and returns the `R8G8B8A8_UNORM` texels of its output.
+    fn render_test_pass<F: FnOnce(&mut PassRecorder)>(emulator: &Arc<EmulatorRenderer>, pipeline: Arc<DebugPipeline>, record: F) -> Box<[u8]> {
+        let size = pipeline.framebuffer_size;
+        let target = emulator.create_global_image_array(size, 1, &Format::R8G8B8A8_UNORM);
+        let output = emulator.create_layer_output(target.clone(), pipeline);
+
+        let mut recorder = emulator.start_pass_into_layer(&output, 0);
+        record(&mut recorder);
+        drop(recorder);
+
+        let token = target.read_sub_image_zeroed(&[ImageReadRegion::new_full(size)], (size.x * size.y * 4) as usize);
+        emulator.flush().unwrap();
+        token.wait()
+    }
+
+    fn make_test_texture(emulator: &EmulatorRenderer, color: [f32; 4]) -> Arc<GlobalImage> {
+        emulator.create_global_image_cleared(Vec2u32::new(1, 1), 1, &Format::R8G8B8A8_UNORM, vk::ClearColorValue { float32: color })
+    }
+
+    #[test]
+    fn bindless_draw_samples_registered_textures() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        if !device.supports_descriptor_indexing() {
+            return;
+        }
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let red = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+        let blue = make_test_texture(&emulator, [0.0, 0.0, 1.0, 1.0]);
+        let red_index = emulator.register_bindless_texture(&red, &NEAREST_SAMPLER).unwrap();
+        let blue_index = emulator.register_bindless_texture(&blue, &NEAREST_SAMPLER).unwrap();
+        assert_ne!(red_index, blue_index);
+
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Bindless, Vec2u32::new(2, 1)).unwrap();
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.set_bindless_texture(red_index, &red);
+            draw_test_quad(recorder, shader, -1.0, 0.0);
+            recorder.set_bindless_texture(blue_index, &blue);
+            draw_test_quad(recorder, shader, 0.0, 1.0);
+        });
+
+        assert_eq!(&texels[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&texels[4..8], &[0, 0, 255, 255]);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn flush_during_pass_fails() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let image = emulator.create_global_image(Vec2u32::new(1, 1), &Format::R32_UINT);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(1, 1)).unwrap();
+
+        let recorder = emulator.start_pass(pipeline);
+        assert_eq!(emulator.flush(), Err(FlushError::PassRunning));
+        assert_eq!(emulator.read_pixel_u32(&image, Vec2u32::new(0, 0)), Err(FlushError::PassRunning));
+        drop(recorder);
+
+        let value = emulator.flush().unwrap();
+        emulator.wait_for_value(value);
+        assert!(emulator.is_value_reached(value));
+    }
+
+    #[test]
+    fn exported_image_round_trip() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let texture = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let render = || {
+            let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Textured0, Vec2u32::new(1, 1)).unwrap();
+            render_test_pass(&emulator, pipeline, |recorder| {
+                recorder.update_texture(0, &texture, &NEAREST_SAMPLER, shader);
+                draw_test_quad(recorder, shader, -1.0, 1.0);
+            })
+        };
+        assert_eq!(&render()[0..4], &[255, 0, 0, 255]);
+
+        // The export must wait for the pass above and the reacquire must return the image into a
+        // state where it can be written and sampled again
+        let state = ExternalImageState {
+            layout: vk::ImageLayout::GENERAL,
+            stage_mask: vk::PipelineStageFlags2::TRANSFER,
+            access_mask: vk::AccessFlags2::TRANSFER_READ,
+        };
+        emulator.export_global_image(&texture, state);
+        assert_eq!(texture.get_external_state(), Some(state));
+        emulator.flush().unwrap();
+
+        emulator.reacquire_global_image(&texture);
+        assert_eq!(texture.get_external_state(), None);
+
+        let blue = [0u8, 0, 255, 255];
+        texture.update_regions(&[ImageData::new_full(&blue, Vec2u32::new(1, 1))]);
+        assert_eq!(&render()[0..4], &blue);
+
+        let value = emulator.flush().unwrap();
+        emulator.wait_for_value(value);
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn sampled_image_idle_after_free_unused() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let texture = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Textured0, Vec2u32::new(1, 1)).unwrap();
+        let target = emulator.create_global_image_array(Vec2u32::new(1, 1), 1, &Format::R8G8B8A8_UNORM);
+        let output = emulator.create_layer_output(target, pipeline);
+        assert!(emulator.is_resource_idle(&texture));
+
+        let mut recorder = emulator.start_pass_into_layer(&output, 0);
+        recorder.update_texture(0, &texture, &NEAREST_SAMPLER, shader);
+        draw_test_quad(&mut recorder, shader, -1.0, 1.0);
+        assert_eq!(texture.last_use_value(), recorder.get_id().get_raw());
+
+        // The pass cannot complete before it has been submitted
+        assert!(!emulator.is_resource_idle(&texture));
+        drop(recorder);
+
+        emulator.free_unused_now();
+        assert!(emulator.is_resource_idle(&texture));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn stencil_reference_set_per_draw() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let depth_format = match choose_depth_format(&device, true) {
+            Some(format) => format,
+            None => return,
+        };
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let red = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+        let blue = make_test_texture(&emulator, [0.0, 0.0, 1.0, 1.0]);
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let config = DebugPipelineConfig {
+            depth_attachment: Some(DepthAttachmentInfo::new(depth_format, 0)),
+            ..Default::default()
+        };
+        let pipeline = DebugPipeline::new_with_config(emulator.clone(), DebugPipelineMode::Textured0, Vec2u32::new(2, 1), &config).unwrap();
+        pipeline.set_command_log_enabled(true);
+
+        // Depth writes are disabled so that only the stencil test rejects the second quad
+        let texels = render_test_pass(&emulator, pipeline.clone(), |recorder| {
+            recorder.set_stencil_reference(5);
+            recorder.update_texture(0, &red, &NEAREST_SAMPLER, shader);
+            let id = recorder.upload_immediate_quads(cast_slice(&make_test_quad(-1.0, 0.0)), TEST_VERTEX_FORMAT.stride);
+            recorder.draw_immediate(id, shader, false);
+
+            recorder.set_stencil_reference(3);
+            recorder.update_texture(0, &blue, &NEAREST_SAMPLER, shader);
+            let id = recorder.upload_immediate_quads(cast_slice(&make_test_quad(-1.0, 1.0)), TEST_VERTEX_FORMAT.stride);
+            recorder.draw_immediate(id, shader, false);
+        });
+
+        assert_eq!(&texels[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&texels[4..8], &[0, 0, 255, 255]);
+
+        let commands: Vec<_> = pipeline.take_command_log().into_iter().filter(|command| {
+            matches!(command, RecordedCommand::SetStencilReference(_) | RecordedCommand::Draw(_))
+        }).collect();
+        assert_eq!(commands, vec![
+            RecordedCommand::SetStencilReference(0),
+            RecordedCommand::SetStencilReference(5),
+            RecordedCommand::Draw(shader),
+            RecordedCommand::SetStencilReference(3),
+            RecordedCommand::Draw(shader),
+        ]);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn batch_binds_pipeline_once() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX | McUniform::CHUNK_OFFSET);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(1, 1)).unwrap();
+        pipeline.set_command_log_enabled(true);
+
+        let meshes: Vec<_> = (0..100).map(|i| {
+            let mesh = emulator.create_global_quad_mesh(cast_slice(&make_test_quad(-1.0, 1.0)), TEST_VERTEX_FORMAT.stride).unwrap();
+            (mesh, Vec3f32::new(i as f32, 0.0, 0.0))
+        }).collect();
+
+        render_test_pass(&emulator, pipeline.clone(), |recorder| {
+            recorder.draw_global_batch(&meshes, shader, true);
+        });
+
+        let commands = pipeline.take_command_log();
+        assert_eq!(commands.iter().filter(|command| **command == RecordedCommand::Draw(shader)).count(), 100);
+        assert_eq!(commands.iter().filter(|command| matches!(command, RecordedCommand::BindPipeline(_))).count(), 1);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn procedural_triangle_covers_target() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&VertexFormat::empty(), McUniform::empty());
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(4, 4)).unwrap();
+
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.draw_procedural(3, 1, shader, true);
+        });
+
+        // The background is replaced by the white triangle everywhere
+        assert!(texels.chunks_exact(4).all(|texel| texel == [255, 255, 255, 255]));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn free_unused_now_releases_meshes() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX | McUniform::CHUNK_OFFSET);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(1, 1)).unwrap();
+
+        // The shared quad index buffer stays allocated after all quad meshes have been dropped
+        drop(emulator.create_global_quad_mesh(cast_slice(&make_test_quad(-1.0, 1.0)), TEST_VERTEX_FORMAT.stride).unwrap());
+        let base_usage = emulator.get_tracked_memory_usage();
+
+        let meshes: Vec<_> = (0..64).map(|_| {
+            let mesh = emulator.create_global_quad_mesh(cast_slice(&make_test_quad(-1.0, 1.0)), TEST_VERTEX_FORMAT.stride).unwrap();
+            (mesh, Vec3f32::zeros())
+        }).collect();
+        render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.draw_global_batch(&meshes, shader, true);
+        });
+        assert!(emulator.get_tracked_memory_usage() > base_usage);
+        let allocated = emulator.get_device().get_allocator().get_fragmentation_stats().allocation_bytes;
+
+        // The last references are now held by the completed pass
+        drop(meshes);
+        emulator.free_unused_now();
+        assert_eq!(emulator.get_tracked_memory_usage(), base_usage);
+        assert!(emulator.get_device().get_allocator().get_fragmentation_stats().allocation_bytes < allocated);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn cleared_image_sampled_without_upload() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let texture = emulator.create_global_image_cleared(Vec2u32::new(4, 4), 1, &Format::R8G8B8A8_UNORM, vk::ClearColorValue { float32: [0.0, 1.0, 1.0, 1.0] });
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Textured0, Vec2u32::new(2, 2)).unwrap();
+
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.update_texture(0, &texture, &NEAREST_SAMPLER, shader);
+            draw_test_quad(recorder, shader, -1.0, 1.0);
+        });
+        assert!(texels.chunks_exact(4).all(|texel| texel == [0, 255, 255, 255]));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn texture_index_specialization() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let red = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+        let blue = make_test_texture(&emulator, [0.0, 0.0, 1.0, 1.0]);
+
+        let vertex_format = VertexFormat { uv1: TEST_VERTEX_FORMAT.uv0, ..TEST_VERTEX_FORMAT };
+        let shader = emulator.create_shader(&vertex_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+
+        // Both modes use the same fragment shader specialized with a different texture index
+        let render = |mode: DebugPipelineMode| {
+            let pipeline = DebugPipeline::new(emulator.clone(), mode, Vec2u32::new(1, 1)).unwrap();
+            render_test_pass(&emulator, pipeline, |recorder| {
+                recorder.update_texture(0, &red, &NEAREST_SAMPLER, shader);
+                recorder.update_texture(1, &blue, &NEAREST_SAMPLER, shader);
+                draw_test_quad(recorder, shader, -1.0, 1.0);
+            })
+        };
+
+        assert_eq!(&render(DebugPipelineMode::Textured0)[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&render(DebugPipelineMode::Textured1)[0..4], &[0, 0, 255, 255]);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn depth_only_subpass() {
+        assert!(make_draw_color_references(true, 0).is_empty());
+        assert!(make_draw_blend_states(true, &[]).is_empty());
+
+        let attachments = [ColorAttachmentInfo::new_opaque(vk::Format::R32_UINT)];
+        let references = make_draw_color_references(false, attachments.len());
+        assert_eq!(references.iter().map(|r| r.attachment).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(make_draw_blend_states(false, &attachments).len(), references.len());
+    }
+
+    #[test]
+    fn instance_attributes_use_separate_binding() {
+        let modules = ShaderModules {
+            mode: DebugPipelineMode::Color,
+            vertex_module: vk::ShaderModule::null(),
+            null_module: vk::ShaderModule::null(),
+            procedural_module: vk::ShaderModule::null(),
+            fragment_module: vk::ShaderModule::null(),
+            texture_module: None,
+        };
+        let vertex_format = VertexFormat {
+            color: Some(VertexFormatEntry { offset: 12, format: vk::Format::R8G8B8A8_UNORM }),
+            uv0: None,
+            instance_divisor: Some(2),
+            ..TEST_VERTEX_FORMAT
+        };
+
+        let alloc = Bump::new();
+        let (_, input_state) = modules.configure_pipeline(&vertex_format, &alloc);
+        let bindings = unsafe { std::slice::from_raw_parts(input_state.p_vertex_binding_descriptions, input_state.vertex_binding_description_count as usize) };
+        assert_eq!(bindings.len(), 2);
+        assert_eq!((bindings[0].binding, bindings[0].input_rate), (0, vk::VertexInputRate::VERTEX));
+        assert_eq!((bindings[1].binding, bindings[1].input_rate), (INSTANCE_BINDING, vk::VertexInputRate::INSTANCE));
+        let attributes = unsafe { std::slice::from_raw_parts(input_state.p_vertex_attribute_descriptions, input_state.vertex_attribute_description_count as usize) };
+        assert_eq!(attributes[0].binding, 0);
+        assert_eq!(attributes[1].binding, INSTANCE_BINDING);
+
+        assert!(!input_state.p_next.is_null());
+        let divisor_state = unsafe { &*(input_state.p_next as *const vk::PipelineVertexInputDivisorStateCreateInfoEXT) };
+        assert_eq!(divisor_state.s_type, vk::StructureType::PIPELINE_VERTEX_INPUT_DIVISOR_STATE_CREATE_INFO_EXT);
+        let divisors = unsafe { std::slice::from_raw_parts(divisor_state.p_vertex_binding_divisors, divisor_state.vertex_binding_divisor_count as usize) };
+        assert_eq!(divisors.len(), 1);
+        assert_eq!((divisors[0].binding, divisors[0].divisor), (INSTANCE_BINDING, 2));
+
+        // A divisor of 1 is the default of instance rate bindings
+        let (_, input_state) = modules.configure_pipeline(&VertexFormat { instance_divisor: Some(1), ..vertex_format }, &alloc);
+        assert_eq!(input_state.vertex_binding_description_count, 2);
+        assert!(input_state.p_next.is_null());
+
+        let (_, input_state) = modules.configure_pipeline(&VertexFormat { instance_divisor: None, ..vertex_format }, &alloc);
+        assert_eq!(input_state.vertex_binding_description_count, 1);
+        assert!(input_state.p_next.is_null());
+    }
+
+    #[test]
+    fn unwritten_attachments_masked() {
+        let attachments = [
+            ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM),
+            ColorAttachmentInfo::new_opaque(vk::Format::R32_UINT),
+            ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM),
+            ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM),
+        ];
+        let blend_states = make_draw_blend_states(false, &attachments);
+
+        assert_eq!(blend_states[1].color_write_mask, vk::ColorComponentFlags::RGBA);
+        assert_eq!(blend_states[2].color_write_mask, vk::ColorComponentFlags::empty());
+        assert_eq!(blend_states[3].color_write_mask, vk::ColorComponentFlags::RGBA);
+        assert_eq!(blend_states[4].color_write_mask, vk::ColorComponentFlags::empty());
+    }
+
+    #[test]
+    fn extra_attachments_written() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let size = Vec2u32::new(1, 1);
+        let attachments = [
+            ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM),
+            ColorAttachmentInfo::new_opaque(vk::Format::R32_UINT),
+        ];
+        let config = DebugPipelineConfig {
+            color_attachments: attachments.to_vec(),
+            ..Default::default()
+        };
+        let pipeline = DebugPipeline::new_with_config(emulator.clone(), DebugPipelineMode::Textured0, size, &config).unwrap();
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let red = make_test_texture(&emulator, [1.0, 0.0, 0.0, 1.0]);
+
+        let color_image = emulator.create_global_image(size, &Format::R8G8B8A8_UNORM);
+        let uint_image = emulator.create_global_image(size, &Format::R32_UINT);
+        let color_output = emulator.create_attachment_output(color_image.clone(), pipeline.clone(), 0);
+        let uint_output = emulator.create_attachment_output(uint_image.clone(), pipeline.clone(), 1);
+
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.update_texture(0, &red, &NEAREST_SAMPLER, shader);
+            draw_test_quad(recorder, shader, -1.0, 1.0);
+            recorder.copy_attachment_into(&color_output);
+            recorder.copy_attachment_into(&uint_output);
+        });
+        assert_eq!(&texels[0..4], &[255, 0, 0, 255]);
+
+        let token = color_image.read_sub_image_zeroed(&[ImageReadRegion::new_full(size)], 4);
+        emulator.flush().unwrap();
+        assert_eq!(&token.wait()[0..4], &[255, 0, 0, 255]);
+
+        // Integer attachments are not written by the debug shaders
+        assert_eq!(emulator.read_pixel_u32(&uint_image, Vec2u32::new(0, 0)), Ok(0));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn blend_constants_set_per_draw() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let size = Vec2u32::new(2, 1);
+        let config = DebugPipelineConfig {
+            color_attachments: vec![ColorAttachmentInfo::new_constant_blend(vk::Format::R8G8B8A8_UNORM)],
+            ..Default::default()
+        };
+        let pipeline = DebugPipeline::new_with_config(emulator.clone(), DebugPipelineMode::Textured0, size, &config).unwrap();
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let white = make_test_texture(&emulator, [1.0, 1.0, 1.0, 1.0]);
+
+        let image = emulator.create_global_image(size, &Format::R8G8B8A8_UNORM);
+        let output = emulator.create_attachment_output(image.clone(), pipeline.clone(), 0);
+
+        // The attachment is cleared to 0 so each half stores white weighted by the constants of its
+        // draw
+        render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.update_texture(0, &white, &NEAREST_SAMPLER, shader);
+            recorder.set_blend_constants([0.25, 0.25, 0.25, 1.0]);
+            draw_test_quad(recorder, shader, -1.0, 0.0);
+            recorder.set_blend_constants([0.75, 0.75, 0.75, 1.0]);
+            draw_test_quad(recorder, shader, 0.0, 1.0);
+            recorder.copy_attachment_into(&output);
+        });
+
+        let token = image.read_sub_image_zeroed(&[ImageReadRegion::new_full(size)], 8);
+        emulator.flush().unwrap();
+        let texels = token.wait();
+
+        let near = |value: u8, expected: u8| (value as i32 - expected as i32).abs() <= 1;
+        assert!(texels[0..3].iter().all(|c| near(*c, 64)));
+        assert!(texels[4..7].iter().all(|c| near(*c, 191)));
+        assert_eq!((texels[3], texels[7]), (255, 255));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn background_styles() {
+        let checkerboard = BackgroundStyle::default().to_push_constants();
+        let solid = BackgroundStyle::Solid(Vec3f32::new(1.0, 0.0, 0.0)).to_push_constants();
+        let gradient = BackgroundStyle::Gradient(Vec3f32::new(1.0, 0.0, 0.0), Vec3f32::new(0.0, 0.0, 1.0)).to_push_constants();
+
+        assert_ne!(checkerboard.style, solid.style);
+        assert_ne!(solid.style, gradient.style);
+        assert_eq!(solid.color0, Vec4f32::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(gradient.color1, Vec4f32::new(0.0, 0.0, 1.0, 1.0));
+        assert_ne!(bytes_of(&checkerboard), bytes_of(&solid));
+    }
+
+    #[test]
+    fn mvp_push_constants() {
+        let placeholder = (vk::ImageView::null(), vk::Sampler::null());
+        let mut tracker = UniformStateTracker::new(McUniform::MODEL_VIEW_MATRIX, placeholder.0, placeholder.1);
+        let model_view = Mat4f32::new_translation(&Vec3f32::new(1.0, 2.0, 3.0));
+        tracker.update_uniform(&McUniformData::ModelViewMatrix(model_view));
+
+        // Returns the bytes recorded with vkCmdPushConstants before a draw if any
+        fn record(tracker: &mut UniformStateTracker, mvp: Option<Mat4f32>) -> Option<Vec<u8>> {
+            tracker.set_mvp(mvp);
+            tracker.validate_push_constants().map(|push| bytes_of(push).to_vec())
+        }
+
+        let recorded = record(&mut tracker, None).unwrap();
+        assert_eq!(&recorded[0..64], cast_slice::<f32, u8>(model_view.as_slice()));
+        assert_eq!(&recorded[76..80], &0u32.to_ne_bytes());
+
+        let mvp = Mat4f32::new_scaling(2.0);
+        let recorded = record(&mut tracker, Some(mvp)).unwrap();
+        assert!(recorded.len() <= 128 && recorded.len() as u32 == DRAW_PUSH_CONSTANTS_SIZE);
+        assert_eq!(&recorded[0..64], cast_slice::<f32, u8>(mvp.as_slice()));
+        assert_eq!(&recorded[76..80], &1u32.to_ne_bytes());
+
+        // Model view updates while the mvp is enabled are not pushed but restored afterwards
+        assert!(record(&mut tracker, Some(mvp)).is_none());
+        let model_view = Mat4f32::new_scaling(3.0);
+        tracker.update_uniform(&McUniformData::ModelViewMatrix(model_view));
+        assert!(tracker.validate_push_constants().is_none());
+        let recorded = record(&mut tracker, None).unwrap();
+        assert_eq!(&recorded[0..64], cast_slice::<f32, u8>(model_view.as_slice()));
+        assert_eq!(&recorded[76..80], &0u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn descriptor_pool_growth() {
+        // Each pool is identified by its index and has space for a single allocation
+        let mut used = Vec::new();
+        let mut pools: Vec<usize> = Vec::new();
+
+        let allocate = |pools: &mut Vec<usize>, used: &mut Vec<bool>| {
+            let next_pool = pools.len();
+            allocate_with_growth::<_, _, vk::Result>(
+                pools,
+                |pool: usize| {
+                    if used[pool] {
+                        Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                    } else {
+                        used[pool] = true;
+                        Ok(pool)
+                    }
+                },
+                || Ok(next_pool)
+            )
+        };
+
+        used.push(false);
+        assert_eq!(allocate(&mut pools, &mut used), Ok(0));
+        used.push(false);
+        assert_eq!(allocate(&mut pools, &mut used), Ok(1));
+        assert_eq!(pools, vec![0, 1]);
+    }
+
+    #[test]
+    fn background_sets_grow_pools() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let layout = BackgroundPipeline::create_descriptor_set_layout(&device).unwrap();
+
+        // A second concurrent pass does not fit into a single set pool
+        let (pools, sets) = DebugPipeline::allocate_background_sets(&device, layout, 2, 1).unwrap();
+        assert_eq!(pools.len(), 2);
+        assert_eq!(sets.len(), 2);
+        assert_ne!(sets[0], sets[1]);
+
+        unsafe {
+            for pool in pools {
+                device.vk().destroy_descriptor_pool(pool, device.get_allocation_callbacks());
+            }
+            device.vk().destroy_descriptor_set_layout(layout, device.get_allocation_callbacks());
+        }
+    }
+
+    #[test]
+    fn dont_care_color_load() {
+        let mut ops = make_default_attachment_ops(1);
+        ops[get_attachment_index(PassAttachment::Color(0))] = AttachmentOps::new(vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::STORE);
+        ops[get_attachment_index(PassAttachment::Color(1))] = AttachmentOps::new(vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::DONT_CARE);
+
+        let color_attachments = [ColorAttachmentInfo::new_opaque(vk::Format::R32_UINT)];
+        let descriptions = make_attachment_descriptions(vk::Format::D32_SFLOAT, &color_attachments, &ops);
+        assert_eq!(descriptions.len(), 4);
+
+        assert_eq!(descriptions[0].load_op, vk::AttachmentLoadOp::CLEAR);
+        assert_eq!(descriptions[0].store_op, vk::AttachmentStoreOp::STORE);
+        assert_eq!(descriptions[1].load_op, vk::AttachmentLoadOp::DONT_CARE);
+        assert_eq!(descriptions[1].store_op, vk::AttachmentStoreOp::STORE);
+        assert_eq!(descriptions[3].format, vk::Format::R32_UINT);
+        assert_eq!(descriptions[3].load_op, vk::AttachmentLoadOp::DONT_CARE);
+        assert_eq!(descriptions[3].store_op, vk::AttachmentStoreOp::DONT_CARE);
+    }
+
+    #[test]
+    fn configurable_attachments() {
+        let attachment_count = make_default_attachment_ops(1).len();
+        assert!(is_configurable_attachment(PassAttachment::Depth, attachment_count));
+        assert!(is_configurable_attachment(PassAttachment::Color(0), attachment_count));
+        assert!(is_configurable_attachment(PassAttachment::Color(1), attachment_count));
+        assert!(!is_configurable_attachment(PassAttachment::Color(2), attachment_count));
+        assert!(!is_configurable_attachment(PassAttachment::Color(u32::MAX), attachment_count));
+    }
+
+    #[test]
+    fn per_texture_samplers() {
+        use ash::vk::Handle;
+
+        let placeholder = (vk::ImageView::from_raw(1), vk::Sampler::from_raw(1));
+        let mut tracker = UniformStateTracker::new(McUniform::empty(), placeholder.0, placeholder.1);
+
+        let clamp = (vk::ImageView::from_raw(2), vk::Sampler::from_raw(2));
+        let repeat = (vk::ImageView::from_raw(3), vk::Sampler::from_raw(3));
+        tracker.update_texture(0, clamp.0, clamp.1);
+        tracker.update_texture(2, repeat.0, repeat.1);
+
+        let infos = make_texture_image_infos(tracker.validate_textures().unwrap());
+        assert_eq!((infos[0].image_view, infos[0].sampler), clamp);
+        assert_eq!((infos[1].image_view, infos[1].sampler), placeholder);
+        assert_eq!((infos[2].image_view, infos[2].sampler), repeat);
+        assert!(tracker.validate_textures().is_none());
+    }
+
+    #[test]
+    fn reverse_z_stores_far_smaller() {
+        let (near, far) = (0.05f32, 1000.0f32);
+        let gl_projection = Mat4f32::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, -(far + near) / (far - near), -2.0 * far * near / (far - near),
+            0.0, 0.0, -1.0, 0.0,
+        );
+        // Emulates the perspective divide and viewport transform of the depth of a vertex
+        let stored_depth = |adjustment: &Mat4f32, viewport: &vk::Viewport, distance: f32| {
+            let clip = adjustment * gl_projection * Vec4f32::new(0.0, 0.0, -distance, 1.0);
+            viewport.min_depth + (clip[2] / clip[3]) * (viewport.max_depth - viewport.min_depth)
+        };
+
+        let convention = DepthConvention::from_reverse_z(true);
+        let viewport = convention.apply_to_viewport(make_full_viewport(Vec2u32::new(800, 600)));
+        assert_eq!((viewport.min_depth, viewport.max_depth), (0.0, 1.0));
+        for depth_clip_control in [false, true] {
+            let adjustment = make_projection_adjustment(convention, DepthClipRange::NegativeOneToOne, depth_clip_control);
+            if depth_clip_control {
+                // The pipeline maps -1..1 into 0..1
+                let pipeline_depth = |distance: f32| (stored_depth(&adjustment, &viewport, distance) + 1.0) / 2.0;
+                assert!((pipeline_depth(near) - 1.0).abs() < 1e-4);
+                assert!(pipeline_depth(far).abs() < 1e-4);
+                assert!(pipeline_depth(500.0) < pipeline_depth(10.0));
+            } else {
+                assert!((stored_depth(&adjustment, &viewport, near) - 1.0).abs() < 1e-4);
+                assert!(stored_depth(&adjustment, &viewport, far).abs() < 1e-4);
+                assert!(stored_depth(&adjustment, &viewport, 500.0) < stored_depth(&adjustment, &viewport, 10.0));
+            }
+        }
+        assert_eq!(convention.get_compare_op(), vk::CompareOp::GREATER);
+        assert_eq!(convention.get_clear_depth(), 0.0);
+
+        // The reversed depth is produced before the divide so distant depths remain distinguishable
+        let adjustment = make_projection_adjustment(convention, DepthClipRange::NegativeOneToOne, false);
+        assert!(stored_depth(&adjustment, &viewport, 900.0) > stored_depth(&adjustment, &viewport, 900.5));
+
+        let clear_values = make_clear_values(&[], convention, &DepthAttachmentInfo::default());
+        assert_eq!(unsafe { clear_values[0].depth_stencil.depth }, 0.0);
+
+        // Partial viewports keep their depth range mirrored into the reversed convention
+        let viewport = vk::Viewport { min_depth: 0.25, max_depth: 0.5, ..make_full_viewport(Vec2u32::new(800, 600)) };
+        let reversed = convention.apply_to_viewport(viewport);
+        assert_eq!((reversed.min_depth, reversed.max_depth), (0.5, 0.75));
+
+        let convention = DepthConvention::default();
+        let adjustment = make_projection_adjustment(convention, DepthClipRange::ZeroToOne, false);
+        assert_eq!(adjustment, Mat4f32::identity());
+        assert_eq!(convention.get_compare_op(), vk::CompareOp::LESS);
+    }
+
+    #[test]
+    fn near_plane_visible_in_both_clip_ranges() {
+        // Clip space depth of a vertex on the near plane is visible if it lies within 0..w
+        let is_visible = |clip: Vec4f32| clip[2] >= 0.0 && clip[2] <= clip[3];
+
+        let (near, far) = (0.05f32, 100.0f32);
+        // OpenGL style projection producing -1..1 depth
+        let gl_projection = Mat4f32::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, -(far + near) / (far - near), -2.0 * far * near / (far - near),
+            0.0, 0.0, -1.0, 0.0,
+        );
+        // Vulkan style projection producing 0..1 depth
+        let vk_projection = Mat4f32::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, far / (near - far), far * near / (near - far),
+            0.0, 0.0, -1.0, 0.0,
+        );
+
+        // The corners of a quad on the near plane
+        for (x, y) in [(-0.01f32, -0.01f32), (0.01, -0.01), (0.01, 0.01), (-0.01, 0.01)] {
+            let vertex = Vec4f32::new(x, y, -near, 1.0);
+
+            let clip = DepthClipRange::ZeroToOne.get_projection_adjustment() * vk_projection * vertex;
+            assert!(is_visible(clip));
+
+            let clip = gl_projection * vertex;
+            assert!(clip[2] < 0.0);
+            let clip = DepthClipRange::NegativeOneToOne.get_projection_adjustment() * clip;
+            assert!(is_visible(clip));
+            assert!((clip[2] / clip[3]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn many_textures_pushed_once() {
+        use ash::vk::Handle;
+
+        let placeholder = (vk::ImageView::from_raw(1), vk::Sampler::from_raw(1));
+        let mut tracker = UniformStateTracker::new(McUniform::empty(), placeholder.0, placeholder.1);
+
+        // Binds 16 textures between two draws
+        for texture in 0..16u64 {
+            tracker.update_texture((texture % 3) as u32, vk::ImageView::from_raw(texture + 2), vk::Sampler::from_raw(texture + 2));
+        }
+
+        let mut batch = DescriptorWriteBatch::new();
+        batch.set_uniform_buffer(vk::DescriptorBufferInfo {
+            buffer: vk::Buffer::from_raw(1),
+            offset: 0,
+            range: std::mem::size_of::<StaticUniforms>() as vk::DeviceSize
+        });
+        batch.set_textures(make_texture_image_infos(tracker.validate_textures().unwrap()));
+
+        let mut calls = 0;
+        assert!(batch.flush(|writes| {
+            calls += 1;
+            assert_eq!(writes.len(), 2);
+            assert_eq!(writes[1].descriptor_count, 3);
+            let infos = unsafe { std::slice::from_raw_parts(writes[1].p_image_info, 3) };
+            assert_eq!(infos[0].image_view, vk::ImageView::from_raw(17));
+        }));
+        assert_eq!(calls, 1);
+
+        assert!(!DescriptorWriteBatch::new().flush(|_| panic!()));
+    }
+
+    #[test]
+    fn integer_attachment_clear() {
+        let color_attachments = [
+            ColorAttachmentInfo::new_opaque(vk::Format::R32_UINT),
+            ColorAttachmentInfo::new_opaque(vk::Format::R32_SINT),
+        ];
+        assert!(color_attachments[0].is_integer());
+        assert!(color_attachments[1].is_integer());
+        assert!(!ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM).is_integer());
+
+        let clear_values = make_clear_values(&color_attachments, DepthConvention::Standard, &DepthAttachmentInfo::default());
+        assert_eq!(clear_values.len(), 5);
+        assert_eq!(unsafe { clear_values[3].color.uint32 }, [0u32; 4]);
+        assert_eq!(unsafe { clear_values[4].color.int32 }, [0i32; 4]);
+    }
+
+    #[test]
+    fn d24s8_clears_depth_and_stencil() {
+        let depth_attachment = DepthAttachmentInfo::new(vk::Format::D24_UNORM_S8_UINT, 0x80);
+        assert_eq!(depth_attachment.get_aspect_mask(), vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL);
+
+        let clear_values = make_clear_values(&[], DepthConvention::Standard, &depth_attachment);
+        let clear = unsafe { clear_values[0].depth_stencil };
+        assert_eq!(clear.depth, 1.0);
+        assert_eq!(clear.stencil, 0x80);
+
+        let descriptions = make_attachment_descriptions(depth_attachment.format, &[], &make_default_attachment_ops(0));
+        assert_eq!(descriptions[0].format, vk::Format::D24_UNORM_S8_UINT);
+        assert_eq!(descriptions[0].stencil_load_op, vk::AttachmentLoadOp::CLEAR);
+        assert_eq!(descriptions[0].stencil_store_op, descriptions[0].store_op);
+
+        // Depth only formats ignore the stencil clear value
+        let depth_attachment = DepthAttachmentInfo::new(vk::Format::D32_SFLOAT, 0x80);
+        assert!(!depth_attachment.has_stencil());
+        assert_eq!(depth_attachment.make_clear_value(DepthConvention::ReverseZ).stencil, 0);
+    }
+
+    #[test]
+    fn copyable_depth_images() {
+        let attachment = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+        assert!(!get_depth_image_usage(attachment).contains(vk::ImageUsageFlags::TRANSFER_SRC));
+
+        let usage = get_depth_image_usage(attachment | vk::FormatFeatureFlags::TRANSFER_SRC);
+        assert!(usage.contains(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC));
+    }
+
+    #[test]
+    fn depth_format_fallback() {
+        let supported = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+
+        // Emulates a device without any D32 formats
+        let no_d32 = |format: vk::Format| match format {
+            vk::Format::D24_UNORM_S8_UINT | vk::Format::D16_UNORM => supported,
+            _ => vk::FormatFeatureFlags::empty(),
+        };
+        assert_eq!(select_depth_format(false, no_d32), Some(vk::Format::D24_UNORM_S8_UINT));
+        assert_eq!(select_depth_format(true, no_d32), Some(vk::Format::D24_UNORM_S8_UINT));
+
+        // Sampling is required since the depth is read by the output subpass
+        let attachment_only = |format: vk::Format| match format {
+            vk::Format::D16_UNORM => supported,
+            _ => vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        };
+        assert_eq!(select_depth_format(false, attachment_only), Some(vk::Format::D16_UNORM));
+        assert_eq!(select_depth_format(true, attachment_only), None);
+
+        assert_eq!(select_depth_format(false, |_| supported), Some(vk::Format::D32_SFLOAT));
+        assert_eq!(select_depth_format(true, |_| supported), Some(vk::Format::D32_SFLOAT_S8_UINT));
+    }
+
+    fn make_test_pipeline_config(primitive_topology: vk::PrimitiveTopology) -> PipelineConfig {
+        PipelineConfig {
+            primitive_topology,
+            depth_test_enable: true,
+            depth_write_enable: true,
+            patch_control_points: 0
+        }
+    }
+
+    #[test]
+    fn pipeline_creation_rejects_invalid_stages() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(1, 1)).unwrap();
+        let config = make_test_pipeline_config(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let alloc = Bump::new();
+        let (stages, input_state) = pipeline.shader_modules.configure_pipeline(&TEST_VERTEX_FORMAT, &alloc);
+        let vertex = *stages.iter().find(|stage| stage.stage == vk::ShaderStageFlags::VERTEX).unwrap();
+
+        let duplicate_vertex: Vec<_> = stages.iter().copied().chain(std::iter::once(vertex)).collect();
+        assert_eq!(pipeline.create_pipeline_with_stages(&config, &duplicate_vertex, input_state), Err(PipelineCreateError::InvalidStages(ShaderStageError::Duplicate(vk::ShaderStageFlags::VERTEX))));
+
+        let missing_fragment: Vec<_> = stages.iter().copied().filter(|stage| stage.stage != vk::ShaderStageFlags::FRAGMENT).collect();
+        assert_eq!(pipeline.create_pipeline_with_stages(&config, &missing_fragment, input_state), Err(PipelineCreateError::InvalidStages(ShaderStageError::Missing(vk::ShaderStageFlags::FRAGMENT))));
+    }
+
+    #[test]
+    fn pipeline_creation_rejects_disabled_geometry_stage() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        if device.supports_geometry_shader() {
+            return;
+        }
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(1, 1)).unwrap();
+        let config = make_test_pipeline_config(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let alloc = Bump::new();
+        let (stages, input_state) = pipeline.shader_modules.configure_pipeline(&TEST_VERTEX_FORMAT, &alloc);
+        let mut geometry = *stages.iter().find(|stage| stage.stage == vk::ShaderStageFlags::VERTEX).unwrap();
+        geometry.stage = vk::ShaderStageFlags::GEOMETRY;
+
+        // The feature is validated before the module is passed to vulkan
+        let with_geometry: Vec<_> = stages.iter().copied().chain(std::iter::once(geometry)).collect();
+        assert_eq!(pipeline.create_pipeline_with_stages(&config, &with_geometry, input_state), Err(PipelineCreateError::MissingFeature(FeatureFlag::GeometryShader)));
+    }
+
+    #[test]
+    fn patch_list_draw_without_tessellation_reported() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(2, 1)).unwrap();
+
+        let config = make_test_pipeline_config(vk::PrimitiveTopology::PATCH_LIST);
+        assert_eq!(pipeline.create_pipeline(&config, &TEST_VERTEX_FORMAT), Err(PipelineCreateError::InvalidTopology(vk::PrimitiveTopology::PATCH_LIST)));
+
+        let vertices = make_test_quad(-1.0, 0.0);
+        let indices = [0u16, 1, 2, 2, 3, 0];
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            let id = recorder.upload_immediate(&MeshData {
+                vertex_data: cast_slice(&vertices),
+                index_data: cast_slice(&indices),
+                vertex_stride: TEST_VERTEX_FORMAT.stride,
+                index_count: indices.len() as u32,
+                index_type: vk::IndexType::UINT16,
+                primitive_topology: vk::PrimitiveTopology::PATCH_LIST
+            });
+            recorder.draw_immediate(id, shader, true);
+            draw_test_quad(recorder, shader, 0.0, 1.0);
+        });
+
+        // Only the patch list draw is skipped
+        assert_ne!(&texels[0..4], &[255, 255, 255, 255]);
+        assert_eq!(&texels[4..8], &[255, 255, 255, 255]);
+
+        let errors = emulator.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].task, EmulatorTaskKind::Draw(shader));
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn global_quad_meshes_share_index_buffer() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(4, 1)).unwrap();
+
+        let left = emulator.create_global_quad_mesh(cast_slice(&make_test_quad(-1.0, -0.5)), TEST_VERTEX_FORMAT.stride).unwrap();
+        let single = emulator.create_global_quad_mesh(cast_slice(&make_test_quad(-1.0, -0.5)), TEST_VERTEX_FORMAT.stride).unwrap();
+        assert_eq!(left.get_draw_info().index_buffer, single.get_draw_info().index_buffer);
+        assert_ne!(left.get_draw_info().index_buffer, left.get_draw_info().buffer);
+
+        // Needs more indices than the current index buffer holds
+        let quads: Vec<_> = make_test_quad(0.0, 0.5).into_iter().chain(make_test_quad(0.5, 1.0)).collect();
+        let right = emulator.create_global_quad_mesh(cast_slice(&quads), TEST_VERTEX_FORMAT.stride).unwrap();
+        assert_ne!(left.get_draw_info().index_buffer, right.get_draw_info().index_buffer);
+        drop(single);
+
+        let texels = render_test_pass(&emulator, pipeline, |recorder| {
+            recorder.draw_global(left, shader, true);
+            recorder.draw_global(right, shader, true);
+        });
+
+        let white = [255u8, 255, 255, 255];
+        assert_eq!(&texels[0..4], &white);
+        assert_ne!(&texels[4..8], &white);
+        assert_eq!(&texels[8..12], &white);
+        assert_eq!(&texels[12..16], &white);
+
+        emulator.drop_shader(shader);
+    }
+
+    #[test]
+    fn line_width_set_per_draw() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        if !device.supports_wide_lines() || device.get_limits().line_width_range[1] < 3.0 {
+            return;
+        }
+        let emulator = Arc::new(EmulatorRenderer::new(device));
+
+        let shader = emulator.create_shader(&TEST_VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+        let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, Vec2u32::new(8, 8)).unwrap();
+        pipeline.set_command_log_enabled(true);
+
+        // Horizontal lines through the center of the 5th row
+        let make_line = |x0: f32, x1: f32| [
+            TestVertex { position: [x0, 0.125, 0.5], uv: [0.0, 0.0] },
+            TestVertex { position: [x1, 0.125, 0.5], uv: [1.0, 0.0] },
+        ];
+        let left = make_line(-1.0, 0.0);
+        let right = make_line(0.0, 1.0);
+        let indices = [0u16, 1];
+
+        let texels = render_test_pass(&emulator, pipeline.clone(), |recorder| {
+            for (vertices, width) in [(&left, 3.0), (&right, 1.0)] {
+                let id = recorder.upload_immediate(&MeshData {
+                    vertex_data: cast_slice(vertices),
+                    index_data: cast_slice(&indices),
+                    vertex_stride: TEST_VERTEX_FORMAT.stride,
+                    index_count: indices.len() as u32,
+                    index_type: vk::IndexType::UINT16,
+                    primitive_topology: vk::PrimitiveTopology::LINE_LIST
+                });
+                recorder.set_line_width(width);
+                recorder.draw_immediate(id, shader, true);
+            }
+        });
+
+        let covered_rows = |column: usize| (0..8).filter(|row| {
+            let offset = (row * 8 + column) * 4;
+            texels[offset..(offset + 4)] == [255, 255, 255, 255]
+        }).count();
+        assert_eq!(covered_rows(1), 3);
+        assert_eq!(covered_rows(6), 1);
+
+        let commands: Vec<_> = pipeline.take_command_log().into_iter().filter(|command| {
+            matches!(command, RecordedCommand::SetLineWidth(_) | RecordedCommand::Draw(_))
+        }).collect();
+        assert_eq!(commands, vec![
+            RecordedCommand::SetLineWidth(1.0),
+            RecordedCommand::SetLineWidth(3.0),
+            RecordedCommand::Draw(shader),
+            RecordedCommand::SetLineWidth(1.0),
+            RecordedCommand::Draw(shader),
+        ]);
+
+        emulator.drop_shader(shader);
+    }
+}
@@ -2,17 +2,19 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex, Weak};
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 
 use ash::vk;
 use crate::allocator::Allocation;
 use crate::define_uuid_type;
 
 use crate::renderer::emulator::{MeshData, PassId};
+use crate::renderer::emulator::capture::{CapturedImageRegion, CapturedTask};
 
 use crate::prelude::*;
+use crate::renderer::emulator::readback::{ChannelOrder, ImageReadRegion, ImageReadToken, prefill_staging};
 use crate::renderer::emulator::share::Share;
-use crate::renderer::emulator::worker::{GlobalImageClear, GlobalImageWrite, GlobalMeshWrite, WorkerTask};
+use crate::renderer::emulator::worker::{GlobalImageClear, GlobalImageRead, GlobalImageWrite, GlobalMeshWrite, WorkerTask};
 use crate::util::alloc::next_aligned;
 use crate::util::format::Format;
 
@@ -22,6 +24,11 @@ define_uuid_type!(pub, GlobalMeshId);
 pub enum GlobalObjectCreateError {
     Vulkan(vk::Result),
     Allocation,
+    /// The object would exceed the memory limit set with
+    /// [`EmulatorRenderer::set_memory_limit`](super::EmulatorRenderer::set_memory_limit).
+    OutOfDeviceMemory,
+    /// The provided data cannot be used to create the object.
+    InvalidData,
 }
 
 impl From<vk::Result> for GlobalObjectCreateError {
@@ -31,24 +38,47 @@ impl From<vk::Result> for GlobalObjectCreateError {
 }
 
 pub struct GlobalMesh {
+    weak: Weak<Self>,
     share: Arc<Share>,
     id: GlobalMeshId,
 
     last_used_pass: AtomicU64,
+    created_frame: u64,
 
     buffer: vk::Buffer,
     allocation: Allocation,
     buffer_size: vk::DeviceSize,
 
+    /// The mesh providing the index buffer if the indices are shared with other meshes.
+    index_mesh: Option<Arc<GlobalMesh>>,
     draw_info: GlobalMeshDrawInfo,
 }
+assert_impl_all!(GlobalMesh: Send, Sync);
 
 impl GlobalMesh {
     pub(super) fn new(share: Arc<Share>, data: &MeshData) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        Self::create(share, data, None)
+    }
+
+    /// Creates a mesh which is drawn using the index buffer of `index_mesh` instead of its own
+    /// index data. The index data of `data` must be empty. The index mesh is kept alive as long
+    /// as the new mesh.
+    pub(super) fn new_with_shared_indices(share: Arc<Share>, data: &MeshData, index_mesh: Arc<GlobalMesh>) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        Self::create(share, data, Some(index_mesh))
+    }
+
+    fn create(share: Arc<Share>, data: &MeshData, index_mesh: Option<Arc<GlobalMesh>>) -> Result<Arc<Self>, GlobalObjectCreateError> {
         let index_offset = next_aligned(data.vertex_data.len() as vk::DeviceSize, data.get_index_size() as vk::DeviceSize);
         let required_size = index_offset + (data.index_data.len() as vk::DeviceSize);
 
-        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size)?;
+        if !share.get_memory_budget().try_reserve(required_size) {
+            return Err(GlobalObjectCreateError::OutOfDeviceMemory);
+        }
+
+        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size).map_err(|err| {
+            share.get_memory_budget().release(required_size);
+            err
+        })?;
 
         let (staging, staging_allocation) = share.get_staging_pool().lock().unwrap_or_else(|_| {
             log::error!("Poisoned staging memory mutex in GlobalMesh::new");
@@ -62,24 +92,40 @@ impl GlobalMesh {
             dst[(index_offset as usize)..].copy_from_slice(data.index_data);
         }
 
-        let draw_info = GlobalMeshDrawInfo {
-            buffer,
-            first_index: (index_offset / (data.get_index_size() as vk::DeviceSize)) as u32,
-            index_type: data.index_type,
-            index_count: data.index_count,
-            primitive_topology: data.primitive_topology
+        let draw_info = match &index_mesh {
+            Some(index_mesh) => GlobalMeshDrawInfo {
+                buffer,
+                index_buffer: index_mesh.draw_info.index_buffer,
+                first_index: index_mesh.draw_info.first_index,
+                index_type: index_mesh.draw_info.index_type,
+                index_count: data.index_count,
+                primitive_topology: data.primitive_topology
+            },
+            None => GlobalMeshDrawInfo {
+                buffer,
+                index_buffer: buffer,
+                first_index: (index_offset / (data.get_index_size() as vk::DeviceSize)) as u32,
+                index_type: data.index_type,
+                index_count: data.index_count,
+                primitive_topology: data.primitive_topology
+            },
         };
 
-        let mesh = Arc::new(GlobalMesh {
+        let created_frame = share.get_frame_counter().get_current_frame();
+        let id = GlobalMeshId::from_uuid(share.next_object_id());
+        let mesh = Arc::new_cyclic(|weak| GlobalMesh {
+            weak: weak.clone(),
             share,
-            id: GlobalMeshId::new(),
+            id,
 
             last_used_pass: AtomicU64::new(0),
+            created_frame,
 
             buffer,
             allocation,
             buffer_size: required_size,
 
+            index_mesh,
             draw_info
         });
 
@@ -100,6 +146,10 @@ impl GlobalMesh {
     }
 
     pub(super) fn update_used_in(&self, pass: PassId) {
+        if let Some(index_mesh) = &self.index_mesh {
+            index_mesh.update_used_in(pass);
+        }
+
         let pass = pass.get_raw();
         loop {
             let val = self.last_used_pass.load(std::sync::atomic::Ordering::Acquire);
@@ -112,10 +162,72 @@ impl GlobalMesh {
         }
     }
 
+    /// Writes multiple possibly non contiguous ranges of the mesh buffer. The data of all updates
+    /// is packed into a single staging allocation and copied with one copy command. The write is
+    /// executed after the last pass which used the mesh.
+    ///
+    /// Each update is a byte offset into the mesh buffer and the data to write at that offset.
+    /// Updates without data are ignored. All updates must lie within the mesh buffer.
+    pub fn update_scattered(&self, updates: &[(vk::DeviceSize, &[u8])]) {
+        let updates = filter_scattered_updates(self.buffer_size, updates).unwrap_or_else(|(offset, len)| {
+            log::error!("Scattered update of {:?} bytes at offset {:?} exceeds mesh buffer size {:?}", len, offset, self.buffer_size);
+            panic!()
+        });
+        let updates = updates.as_slice();
+        if updates.is_empty() {
+            return;
+        }
+
+        self.share.capture_task(|| CapturedTask::WriteGlobalMesh(
+            self.id,
+            updates.iter().map(|(offset, data)| (*offset, Box::<[u8]>::from(*data))).collect()
+        ));
+
+        let required_memory = updates.iter().map(|(_, data)| data.len()).sum::<usize>() as vk::DeviceSize;
+
+        let (staging, allocation) = self.share.get_staging_pool().lock().unwrap_or_else(|_| {
+            log::error!("Poisoned staging memory mutex in GlobalMesh::update_scattered");
+            panic!()
+        }).allocate(required_memory, 1);
+
+        unsafe {
+            let dst = std::slice::from_raw_parts_mut(staging.mapped.as_ptr(), required_memory as usize);
+            pack_scattered_updates(dst, updates);
+        }
+
+        self.share.push_task(WorkerTask::WriteGlobalMesh(GlobalMeshWrite {
+            after_pass: PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)),
+            staging_allocation: allocation,
+            staging_range: (staging.offset, required_memory),
+            staging_buffer: staging.buffer,
+            dst_mesh: self.weak.upgrade().unwrap(),
+            regions: make_scattered_copies(staging.offset, updates)
+        }, false));
+    }
+
+    pub fn get_id(&self) -> GlobalMeshId {
+        self.id
+    }
+
+    /// Returns the frame during which this mesh was created. Useful to find the origin of leaked
+    /// meshes.
+    pub fn get_created_frame(&self) -> u64 {
+        self.created_frame
+    }
+
+    /// Returns the memory property flags of the memory the mesh buffer has been placed in.
+    pub fn get_memory_properties(&self) -> vk::MemoryPropertyFlags {
+        unsafe { self.share.get_device().get_allocator().get_memory_properties(self.allocation) }
+    }
+
     pub(super) fn get_buffer_handle(&self) -> vk::Buffer {
         self.buffer
     }
 
+    pub(super) fn get_buffer_size(&self) -> vk::DeviceSize {
+        self.buffer_size
+    }
+
     pub(super) fn get_draw_info(&self) -> &GlobalMeshDrawInfo {
         &self.draw_info
     }
@@ -164,17 +276,79 @@ impl Drop for GlobalMesh {
         unsafe {
             self.share.get_device().get_allocator().destroy_buffer(self.buffer, self.allocation)
         }
+        self.share.get_memory_budget().release(self.buffer_size);
     }
 }
 
 pub(super) struct GlobalMeshDrawInfo {
     pub(super) buffer: vk::Buffer,
+    pub(super) index_buffer: vk::Buffer,
     pub(super) first_index: u32,
     pub(super) index_count: u32,
     pub(super) index_type: vk::IndexType,
     pub(super) primitive_topology: vk::PrimitiveTopology,
 }
 
+/// Calculates the size of a mip level of a image with the specified base size.
+pub(super) fn get_mip_extent(size: Vec2u32, mip_level: u32) -> Vec2u32 {
+    Vec2u32::new(
+        std::cmp::max(size[0] >> mip_level, 1),
+        std::cmp::max(size[1] >> mip_level, 1)
+    )
+}
+
+/// Removes updates without data and checks that all remaining updates lie within a buffer of
+/// `buffer_size` bytes. Returns the offset and length of the first out of bounds update on failure.
+fn filter_scattered_updates<'a>(buffer_size: vk::DeviceSize, updates: &[(vk::DeviceSize, &'a [u8])]) -> Result<Vec<(vk::DeviceSize, &'a [u8])>, (vk::DeviceSize, usize)> {
+    let mut result = Vec::with_capacity(updates.len());
+    for (offset, data) in updates {
+        if data.is_empty() {
+            continue;
+        }
+        match offset.checked_add(data.len() as vk::DeviceSize) {
+            Some(end) if end <= buffer_size => result.push((*offset, *data)),
+            _ => return Err((*offset, data.len())),
+        }
+    }
+    Ok(result)
+}
+
+/// Packs the data of scattered buffer updates tightly into the staging memory in the order of the
+/// updates.
+fn pack_scattered_updates(staging: &mut [u8], updates: &[(vk::DeviceSize, &[u8])]) {
+    let mut current_offset = 0;
+    for (_, data) in updates {
+        staging[current_offset..(current_offset + data.len())].copy_from_slice(data);
+        current_offset += data.len();
+    }
+}
+
+/// Generates the copy regions for scattered buffer updates packed by [`pack_scattered_updates`]
+/// into staging memory starting at `staging_offset`.
+fn make_scattered_copies(staging_offset: vk::DeviceSize, updates: &[(vk::DeviceSize, &[u8])]) -> Box<[vk::BufferCopy]> {
+    let mut current_offset = staging_offset;
+    updates.iter().map(|(dst_offset, data)| {
+        let copy = vk::BufferCopy {
+            src_offset: current_offset,
+            dst_offset: *dst_offset,
+            size: data.len() as vk::DeviceSize
+        };
+        current_offset += data.len() as vk::DeviceSize;
+        copy
+    }).collect()
+}
+
+/// Creates read regions covering all faces of a cube map packed one after another. Returns the
+/// regions and the total number of bytes.
+fn make_face_read_regions(face_size: u32, texel_size: u32) -> ([ImageReadRegion; 6], usize) {
+    let face_bytes = (face_size as usize) * (face_size as usize) * (texel_size as usize);
+    let regions = CubeFace::ALL.map(|face| {
+        ImageReadRegion::new_face((face.get_layer() as usize) * face_bytes, face, face_size)
+    });
+
+    (regions, face_bytes * 6)
+}
+
 pub struct ImageData<'a> {
     /// The image data
     pub data: &'a [u8],
@@ -187,6 +361,9 @@ pub struct ImageData<'a> {
 
     /// The size of the upload region in the image.
     pub extent: Vec2u32,
+
+    /// The array layer to upload to. For cube maps this is the index of the face.
+    pub layer: u32,
 }
 
 impl<'a> ImageData<'a> {
@@ -196,6 +373,7 @@ impl<'a> ImageData<'a> {
             row_stride: 0,
             offset: Vec2u32::new(0, 0),
             extent: size,
+            layer: 0,
         }
     }
 
@@ -205,6 +383,7 @@ impl<'a> ImageData<'a> {
             row_stride,
             offset: Vec2u32::new(0, 0),
             extent: size,
+            layer: 0,
         }
     }
 
@@ -213,7 +392,19 @@ impl<'a> ImageData<'a> {
             data,
             row_stride: 0,
             offset,
-            extent
+            extent,
+            layer: 0,
+        }
+    }
+
+    /// Creates a upload of a full face of a cube map.
+    pub fn new_face(data: &'a [u8], face: CubeFace, face_size: u32) -> Self {
+        Self {
+            data,
+            row_stride: 0,
+            offset: Vec2u32::new(0, 0),
+            extent: Vec2u32::new(face_size, face_size),
+            layer: face.get_layer(),
         }
     }
 
@@ -222,7 +413,42 @@ impl<'a> ImageData<'a> {
             data,
             row_stride,
             offset,
-            extent
+            extent,
+            layer: 0,
+        }
+    }
+}
+
+/// The faces of a cube map in the order of their array layers.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Returns the array layer of the face in a cube map image.
+    pub const fn get_layer(&self) -> u32 {
+        match self {
+            CubeFace::PositiveX => 0,
+            CubeFace::NegativeX => 1,
+            CubeFace::PositiveY => 2,
+            CubeFace::NegativeY => 3,
+            CubeFace::PositiveZ => 4,
+            CubeFace::NegativeZ => 5,
         }
     }
 }
@@ -235,39 +461,111 @@ pub struct GlobalImage {
     id: GlobalImageId,
 
     last_used_pass: AtomicU64,
+    created_frame: u64,
 
     image: vk::Image,
     sampler_view: vk::ImageView,
     allocation: Allocation,
     size: Vec2u32,
     mip_levels: u32,
+    array_layers: u32,
+    cube: bool,
+    format: &'static Format,
+    /// True if the image can be used as the destination of a fullscreen blit.
+    blit_dst: bool,
+    /// The aspects of the format. Used for barriers and clears.
+    aspect_mask: vk::ImageAspectFlags,
+
+    /// Set by the worker once it recorded the first transition out of the uninitialized state.
+    /// Until then the image is in the `UNDEFINED` layout.
+    initialized: AtomicBool,
+
+    /// The state the image has been exported in if it is currently owned by external code.
+    external_state: Mutex<Option<ExternalImageState>>,
 
     sampler_database: Mutex<HashMap<SamplerInfo, vk::Sampler>>,
 }
+assert_impl_all!(GlobalImage: Send, Sync);
 
 impl GlobalImage {
     pub(super) fn new(share: Arc<Share>, size: Vec2u32, mip_levels: u32, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
-        let (image, allocation, sampler_view) = Self::create_image(share.get_device(), format.into(), size, mip_levels)?;
+        Self::new_internal(share, size, mip_levels, 1, false, format, None)
+    }
+
+    /// Creates a image whose mip levels are all cleared to `clear_value` before the image is first
+    /// used. Images created by the other constructors are cleared to zero.
+    pub(super) fn new_cleared(share: Arc<Share>, size: Vec2u32, mip_levels: u32, format: &'static Format, clear_value: vk::ClearColorValue) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        Self::new_internal(share, size, mip_levels, 1, false, format, Some(clear_value))
+    }
+
+    pub(super) fn new_cube(share: Arc<Share>, size: u32, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        Self::new_internal(share, Vec2u32::new(size, size), 1, 6, true, format, None)
+    }
+
+    pub(super) fn new_array(share: Arc<Share>, size: Vec2u32, array_layers: u32, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        if array_layers == 0 || array_layers > share.get_device().get_limits().max_image_array_layers {
+            log::error!("Global image array layer count {:?} is not supported", array_layers);
+            return Err(GlobalObjectCreateError::Vulkan(vk::Result::ERROR_FORMAT_NOT_SUPPORTED));
+        }
+        Self::new_internal(share, size, 1, array_layers, false, format, None)
+    }
+
+    /// Creates a image with a depth or depth stencil format which can be sampled like a regular
+    /// texture. The sampler view only accesses the depth aspect. The image is cleared to a depth of
+    /// 0 and can be filled with the depth attachment of a pass using a
+    /// [`DepthImageOutput`](super::pipeline::DepthImageOutput).
+    pub(super) fn new_depth(share: Arc<Share>, size: Vec2u32, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        if !format.get_aspect_mask().contains(vk::ImageAspectFlags::DEPTH) {
+            log::error!("Depth image format {:?} has no depth aspect", format);
+            return Err(GlobalObjectCreateError::Vulkan(vk::Result::ERROR_FORMAT_NOT_SUPPORTED));
+        }
+        Self::new_internal(share, size, 1, 1, false, format, None)
+    }
+
+    fn new_internal(share: Arc<Share>, size: Vec2u32, mip_levels: u32, array_layers: u32, cube: bool, format: &'static Format, clear_value: Option<vk::ClearColorValue>) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        let required = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_SRC | vk::FormatFeatureFlags::TRANSFER_DST;
+        let supported = share.get_device().get_optimal_format_features(format.get_format());
+        if !supported.contains(required) {
+            log::error!("Global image format {:?} does not support required features {:?}", format, required);
+            return Err(GlobalObjectCreateError::Vulkan(vk::Result::ERROR_FORMAT_NOT_SUPPORTED));
+        }
+        let blit_dst = !cube && supported.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT);
+        let aspect_mask = format.get_aspect_mask();
+
+        let (image, allocation, sampler_view) = Self::create_image(share.get_device(), format.into(), size, mip_levels, array_layers, cube, blit_dst, get_sampled_aspect(aspect_mask))?;
 
+        let created_frame = share.get_frame_counter().get_current_frame();
+        let id = GlobalImageId::from_uuid(share.next_object_id());
         let image = Arc::new_cyclic(|weak| GlobalImage {
             weak: weak.clone(),
             share,
-            id: GlobalImageId::new(),
+            id,
 
             last_used_pass: AtomicU64::new(0),
+            created_frame,
 
             image,
             sampler_view,
             allocation,
             size,
             mip_levels,
+            array_layers,
+            cube,
+            format,
+            blit_dst,
+            aspect_mask,
+
+            initialized: AtomicBool::new(false),
+
+            external_state: Mutex::new(None),
 
             sampler_database: Mutex::new(HashMap::new())
         });
 
         image.share.push_task(WorkerTask::ClearGlobalImage(GlobalImageClear {
             after_pass: PassId::from_raw(0),
-            clear_value: format.get_clear_color_type().unwrap().make_zero_clear(),
+            // Depth images ignore the color clear value
+            clear_value: clear_value.unwrap_or_else(|| format.get_clear_color_type().map(|ty| ty.make_zero_clear()).unwrap_or_default()),
             dst_image: image.clone()
         }, true));
 
@@ -287,18 +585,82 @@ impl GlobalImage {
         }
     }
 
+    /// Returns the id of the last pass which used this image. Returns 0 if the image has not been
+    /// used by any pass yet.
+    pub fn last_use_value(&self) -> u64 {
+        self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)
+    }
+
     pub fn get_id(&self) -> GlobalImageId {
         self.id
     }
 
+    /// Returns the frame during which this image was created. Useful to find the origin of leaked
+    /// images.
+    pub fn get_created_frame(&self) -> u64 {
+        self.created_frame
+    }
+
+    /// Returns the memory property flags of the memory the image has been placed in.
+    pub fn get_memory_properties(&self) -> vk::MemoryPropertyFlags {
+        unsafe { self.share.get_device().get_allocator().get_memory_properties(self.allocation) }
+    }
+
     pub fn get_size(&self) -> Vec2u32 {
         self.size
     }
 
+    pub fn get_format(&self) -> &'static Format {
+        self.format
+    }
+
+    /// Returns true if the image is a cube map created with
+    /// [`EmulatorRenderer::create_global_cube_image`](super::EmulatorRenderer::create_global_cube_image).
+    pub fn is_cube(&self) -> bool {
+        self.cube
+    }
+
+    /// Returns the number of array layers of the image. Cube maps have 6 layers.
+    pub fn get_array_layer_count(&self) -> u32 {
+        self.array_layers
+    }
+
+    /// Returns true if the image can be used as the destination of
+    /// [`EmulatorRenderer::blit_fullscreen`](super::EmulatorRenderer::blit_fullscreen). Requires
+    /// a format usable as a color attachment and is never true for cube maps. Images which can be
+    /// blit destinations can also be rendered to with
+    /// [`EmulatorRenderer::start_pass_into_layer`](super::EmulatorRenderer::start_pass_into_layer).
+    pub fn is_blit_dst(&self) -> bool {
+        self.blit_dst
+    }
+
+    /// Returns true if the image has a depth or depth stencil format. The sampler view of such
+    /// images only accesses the depth aspect.
+    pub fn is_depth(&self) -> bool {
+        self.aspect_mask.contains(vk::ImageAspectFlags::DEPTH)
+    }
+
+    /// Returns all aspects of the image format.
+    pub fn get_aspect_mask(&self) -> vk::ImageAspectFlags {
+        self.aspect_mask
+    }
+
+    /// Returns the order in which the color channels of the image are stored. Data returned by a
+    /// readback uses this order unless it is converted with [`ImageReadToken::wait_rgba`].
+    pub fn channel_order(&self) -> ChannelOrder {
+        ChannelOrder::from_format(self.format.get_format())
+    }
+
     pub fn update_regions(&self, regions: &[ImageData]) {
         if regions.is_empty() {
             return;
         }
+        self.assert_not_exported("GlobalImage::update_regions");
+
+        self.share.capture_task(|| CapturedTask::WriteGlobalImage(
+            self.id,
+            regions.iter().map(CapturedImageRegion::from_image_data).collect()
+        ));
 
         let required_memory = regions.iter().map(|r| r.data.len()).sum::<usize>() as u64;
 
@@ -312,9 +674,9 @@ impl GlobalImage {
                 buffer_row_length: region.row_stride,
                 buffer_image_height: 0,
                 image_subresource: vk::ImageSubresourceLayers {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    aspect_mask: get_sampled_aspect(self.aspect_mask),
                     mip_level: 0,
-                    base_array_layer: 0,
+                    base_array_layer: region.layer,
                     layer_count: 1
                 },
                 image_offset: vk::Offset3D { x: region.offset[0] as i32, y: region.offset[1] as i32, z: 0 },
@@ -343,6 +705,166 @@ impl GlobalImage {
         }));
     }
 
+    /// Reads regions of the image back into host memory. The read is executed after the last pass
+    /// which used the image. It is submitted with the next pass or [`EmulatorRenderer::flush`](super::EmulatorRenderer::flush)
+    /// so waiting on the token without starting another pass or flushing never returns.
+    ///
+    /// The full content of `dst` is copied into the staging memory before the readback is
+    /// recorded. Bytes of `dst` which are not covered by any region are therefore returned with
+    /// their previous content. Use [`GlobalImage::read_sub_image_zeroed`] if uncovered bytes should
+    /// be zero instead.
+    pub fn read_sub_image(&self, regions: &[ImageReadRegion], dst: Box<[u8]>) -> ImageReadToken {
+        let size = dst.len();
+        self.read_regions(regions, Some(dst), size)
+    }
+
+    /// Reads regions of the image back into a new host buffer of `size` bytes. Bytes which are not
+    /// covered by any region are zero. Like [`GlobalImage::read_sub_image`] the read is only
+    /// submitted with the next pass or flush.
+    pub fn read_sub_image_zeroed(&self, regions: &[ImageReadRegion], size: usize) -> ImageReadToken {
+        self.read_regions(regions, None, size)
+    }
+
+    /// Reads a single texel of a `R32_UINT` or `R32_SINT` image. The value can be retrieved with
+    /// [`ImageReadToken::wait_u32`]. Signed values are returned with their bits reinterpreted. This
+    /// is intended for object picking where ids are rendered into a integer image.
+    ///
+    /// The read is executed after the last pass which used the image and only completes once a
+    /// later pass or a flush is submitted. [`EmulatorRenderer::read_pixel_u32`](super::EmulatorRenderer::read_pixel_u32)
+    /// flushes and waits for the value.
+    pub fn read_pixel_u32(&self, position: Vec2u32) -> ImageReadToken {
+        let format = self.format.get_format();
+        if format != vk::Format::R32_UINT && format != vk::Format::R32_SINT {
+            log::error!("Called read_pixel_u32 on image with unsupported format {:?}", format);
+            panic!()
+        }
+        if position[0] >= self.size[0] || position[1] >= self.size[1] {
+            log::error!("Pixel position {:?} is out of bounds for image of size {:?}", position, self.size);
+            panic!()
+        }
+
+        let region = ImageReadRegion::new_extent(0, position, Vec2u32::new(1, 1));
+        self.read_sub_image_zeroed(std::slice::from_ref(&region), 4)
+    }
+
+    /// Returns the size of a mip level of the image.
+    pub fn get_mip_size(&self, mip_level: u32) -> Vec2u32 {
+        get_mip_extent(self.size, mip_level)
+    }
+
+    /// Reads a full mip level of the image into `dst`. The data is tightly packed. The read is
+    /// executed after the last pass which used the image.
+    ///
+    /// `dst` must be exactly large enough to hold the mip level.
+    pub fn read_mip(&self, mip_level: u32, dst: Box<[u8]>) -> ImageReadToken {
+        if mip_level >= self.mip_levels {
+            log::error!("Called read_mip with mip level {:?} on image with {:?} mip levels", mip_level, self.mip_levels);
+            panic!()
+        }
+
+        let texel_size = self.format.get_compatibility_class().get_texel_size().unwrap_or_else(|| {
+            log::error!("Called read_mip on image with unsupported format {:?}", self.format);
+            panic!()
+        });
+
+        let mip_size = self.get_mip_size(mip_level);
+        let required_size = (mip_size[0] as usize) * (mip_size[1] as usize) * (texel_size as usize);
+        if dst.len() != required_size {
+            log::error!("Destination size {:?} does not match size {:?} of mip level {:?} with extent {:?}", dst.len(), required_size, mip_level, mip_size);
+            panic!()
+        }
+
+        let region = ImageReadRegion::new_mip(mip_level, mip_size);
+        self.read_sub_image(std::slice::from_ref(&region), dst)
+    }
+
+    /// Reads all 6 faces of a cube map. The faces are tightly packed in the order of
+    /// [`CubeFace::ALL`].
+    pub fn read_cube_faces(&self) -> ImageReadToken {
+        if !self.is_cube() {
+            log::error!("Called read_cube_faces on image which is not a cube map");
+            panic!()
+        }
+
+        let texel_size = self.format.get_compatibility_class().get_texel_size().unwrap_or_else(|| {
+            log::error!("Called read_cube_faces on image with unsupported format {:?}", self.format);
+            panic!()
+        });
+
+        let (regions, size) = make_face_read_regions(self.size[0], texel_size);
+        self.read_sub_image_zeroed(&regions, size)
+    }
+
+    fn read_regions(&self, regions: &[ImageReadRegion], dst: Option<Box<[u8]>>, size: usize) -> ImageReadToken {
+        self.assert_not_exported("GlobalImage::read_sub_image");
+        let after_pass = PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire));
+        let (token, task) = self.prepare_read(regions, dst, size, after_pass);
+        self.share.push_task(task);
+
+        token
+    }
+
+    /// Allocates the staging memory of a readback and creates the read task without submitting
+    /// it. `after_pass` must not be smaller than the last pass which used the image.
+    pub(super) fn prepare_read(&self, regions: &[ImageReadRegion], dst: Option<Box<[u8]>>, size: usize, after_pass: PassId) -> (ImageReadToken, WorkerTask) {
+        let (token, state) = ImageReadToken::new(self.channel_order());
+
+        let (staging, allocation) = self.share.get_staging_pool().lock().unwrap_or_else(|_| {
+            log::error!("Poisoned staging memory mutex in GlobalImage::read_regions");
+            panic!()
+        }).allocate(size as u64, 16);
+
+        unsafe {
+            let mapped = std::slice::from_raw_parts_mut(staging.mapped.as_ptr(), size);
+            prefill_staging(mapped, dst.as_deref());
+        }
+
+        // Formats without a texel size cannot be read back so only the offset can be checked
+        let texel_size = self.format.get_compatibility_class().get_texel_size();
+
+        let mut copies = Vec::with_capacity(regions.len());
+        for region in regions {
+            let end = region.dst_offset + texel_size.map_or(1, |texel_size| region.get_byte_size(texel_size));
+            if end > size {
+                log::error!("Read region writing bytes {:?}..{:?} is out of bounds for destination size {:?}", region.dst_offset, end, size);
+                panic!();
+            }
+
+            copies.push(vk::BufferImageCopy {
+                buffer_offset: staging.offset + (region.dst_offset as u64),
+                buffer_row_length: region.row_stride,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: get_sampled_aspect(self.aspect_mask),
+                    mip_level: region.mip_level,
+                    base_array_layer: region.layer,
+                    layer_count: 1
+                },
+                image_offset: vk::Offset3D { x: region.offset[0] as i32, y: region.offset[1] as i32, z: 0 },
+                image_extent: vk::Extent3D {
+                    width: region.extent[0],
+                    height: region.extent[1],
+                    depth: 1
+                }
+            });
+        }
+
+        let dst = dst.unwrap_or_else(|| vec![0u8; size].into_boxed_slice());
+
+        let task = WorkerTask::ReadGlobalImage(GlobalImageRead {
+            after_pass,
+            staging_allocation: allocation,
+            staging_range: (staging.offset, size as u64),
+            staging,
+            src_image: self.weak.upgrade().unwrap(),
+            regions: copies.into_boxed_slice(),
+            dst,
+            target: state
+        });
+
+        (token, task)
+    }
+
     pub(super) fn get_image_handle(&self) -> vk::Image {
         self.image
     }
@@ -351,44 +873,72 @@ impl GlobalImage {
         self.mip_levels
     }
 
+    /// Returns true if the worker has recorded the initial transition of the image. Commands
+    /// recorded after that transition can assume the image is in the ready state between
+    /// operations.
+    pub(super) fn is_initialized(&self) -> bool {
+        self.initialized.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    pub(super) fn mark_initialized(&self) {
+        self.initialized.store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    /// Returns the state the image has been exported in if it is currently used by external code.
+    /// See [`EmulatorRenderer::export_global_image`](super::EmulatorRenderer::export_global_image).
+    pub fn get_external_state(&self) -> Option<ExternalImageState> {
+        *self.lock_external_state()
+    }
+
+    /// Panics if the image is currently exported to external code. `caller` names the function
+    /// used to access the image in the error message.
+    pub(super) fn assert_not_exported(&self, caller: &str) {
+        if self.get_external_state().is_some() {
+            log::error!("Called {} with image {:?} which is exported to external code", caller, self.id);
+            panic!()
+        }
+    }
+
+    /// Replaces the external state and returns the previous state.
+    pub(super) fn set_external_state(&self, state: Option<ExternalImageState>) -> Option<ExternalImageState> {
+        std::mem::replace(&mut *self.lock_external_state(), state)
+    }
+
+    fn lock_external_state(&self) -> std::sync::MutexGuard<Option<ExternalImageState>> {
+        self.external_state.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned external state mutex in GlobalImage");
+            panic!()
+        })
+    }
+
     pub(super) fn get_sampler_view(&self) -> vk::ImageView {
         self.sampler_view
     }
 
     pub(super) fn get_sampler(&self, sampler_info: &SamplerInfo) -> vk::Sampler {
+        // The database is keyed by the resolved info so that changing the filter override creates
+        // new samplers
+        let sampler_info = self.share.get_sampler_cache().resolve_info(sampler_info);
+
         let mut guard = self.sampler_database.lock().unwrap();
-        if let Some(sampler) = guard.get(sampler_info) {
+        if let Some(sampler) = guard.get(&sampler_info) {
             *sampler
         } else {
-            let info = vk::SamplerCreateInfo::builder()
-                .mag_filter(sampler_info.mag_filter)
-                .min_filter(sampler_info.min_filter)
-                .mipmap_mode(sampler_info.mipmap_mode)
-                .address_mode_u(sampler_info.address_mode_u)
-                .address_mode_v(sampler_info.address_mode_v)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .mip_lod_bias(0f32)
-                .anisotropy_enable(sampler_info.anisotropy_enable)
-                .max_anisotropy(0f32)
-                .compare_enable(false)
-                .min_lod(0f32)
-                .max_lod(vk::LOD_CLAMP_NONE)
-                .unnormalized_coordinates(false);
-
-            let sampler = unsafe {
-                self.share.get_device().vk().create_sampler(&info, None)
-            }.unwrap_or_else(|err| {
-                log::error!("vkCreateSampler returned {:?} in GlobalImage::get_sampler", err);
-                panic!()
-            });
+            // Samplers are shared between all images and released when the image is dropped
+            let sampler = self.share.get_sampler_cache().acquire(self.share.get_device(), &sampler_info);
 
-            guard.insert(*sampler_info, sampler);
+            guard.insert(sampler_info, sampler);
             sampler
         }
     }
 
-    fn create_image(device: &DeviceContext, format: vk::Format, size: Vec2u32, mip_levels: u32) -> Result<(vk::Image, Allocation, vk::ImageView), GlobalObjectCreateError> {
+    fn create_image(device: &DeviceContext, format: vk::Format, size: Vec2u32, mip_levels: u32, array_layers: u32, cube: bool, blit_dst: bool, sampled_aspect: vk::ImageAspectFlags) -> Result<(vk::Image, Allocation, vk::ImageView), GlobalObjectCreateError> {
+        let (flags, view_type) = get_image_view_type(array_layers, cube);
+
+        let usage = get_image_usage(blit_dst);
+
         let info = vk::ImageCreateInfo::builder()
+            .flags(flags)
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
             .extent(vk::Extent3D {
@@ -397,10 +947,10 @@ impl GlobalImage {
                 depth: 1
             })
             .mip_levels(mip_levels)
-            .array_layers(1)
+            .array_layers(array_layers)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .initial_layout(vk::ImageLayout::UNDEFINED);
 
@@ -410,7 +960,7 @@ impl GlobalImage {
 
         let info = vk::ImageViewCreateInfo::builder()
             .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(view_type)
             .format(format)
             .components(vk::ComponentMapping {
                 r: vk::ComponentSwizzle::IDENTITY,
@@ -419,15 +969,15 @@ impl GlobalImage {
                 a: vk::ComponentSwizzle::IDENTITY
             })
             .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: sampled_aspect,
                 base_mip_level: 0,
                 level_count: mip_levels,
                 base_array_layer: 0,
-                layer_count: 1
+                layer_count: array_layers
             });
 
         let sampler_view = match unsafe {
-            device.vk().create_image_view(&info, None)
+            device.vk().create_image_view(&info, device.get_allocation_callbacks())
         } {
             Ok(view) => view,
             Err(err) => {
@@ -471,13 +1021,69 @@ impl Hash for GlobalImage {
 impl Drop for GlobalImage {
     fn drop(&mut self) {
         let device = self.share.get_device();
+        for sampler_info in self.sampler_database.get_mut().unwrap().keys() {
+            self.share.get_sampler_cache().release(device, sampler_info);
+        }
         unsafe {
-            device.vk().destroy_image_view(self.sampler_view, None);
+            device.vk().destroy_image_view(self.sampler_view, device.get_allocation_callbacks());
             device.get_allocator().destroy_image(self.image, self.allocation);
         }
     }
 }
 
+/// Returns the usage of a global image. Readbacks are recorded without checking the usage of the
+/// image so every global image must be usable as a transfer source.
+fn get_image_usage(blit_dst: bool) -> vk::ImageUsageFlags {
+    let mut usage = vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+    if blit_dst {
+        usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    }
+    usage
+}
+
+/// Returns the image create flags and the type of the sampler view of a global image.
+fn get_image_view_type(array_layers: u32, cube: bool) -> (vk::ImageCreateFlags, vk::ImageViewType) {
+    if cube {
+        (vk::ImageCreateFlags::CUBE_COMPATIBLE, vk::ImageViewType::CUBE)
+    } else if array_layers > 1 {
+        (vk::ImageCreateFlags::empty(), vk::ImageViewType::TYPE_2D_ARRAY)
+    } else {
+        (vk::ImageCreateFlags::empty(), vk::ImageViewType::TYPE_2D)
+    }
+}
+
+/// Returns the aspect accessed by sampler views and buffer copies of a image with the specified
+/// aspects. Sampler views of depth stencil images may only access a single aspect so the depth
+/// aspect is used.
+fn get_sampled_aspect(aspect_mask: vk::ImageAspectFlags) -> vk::ImageAspectFlags {
+    if aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        aspect_mask
+    }
+}
+
+/// Returns the subresource range of the first mip level of a single array layer.
+pub(super) fn make_layer_subresource_range(array_layer: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: array_layer,
+        layer_count: 1
+    }
+}
+
+/// The layout and access scope in which a exported global image is handed to external code. The
+/// emulator transitions the image into this state before the export completes and assumes the
+/// image is still in this state when it is reacquired.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ExternalImageState {
+    pub layout: vk::ImageLayout,
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct SamplerInfo {
     pub mag_filter: vk::Filter,
@@ -486,4 +1092,144 @@ pub struct SamplerInfo {
     pub address_mode_u: vk::SamplerAddressMode,
     pub address_mode_v: vk::SamplerAddressMode,
     pub anisotropy_enable: bool,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_ids_are_unique() {
+        let threads: Vec<_> = (0..8).map(|_| {
+            std::thread::spawn(|| {
+                (0..1000).map(|_| (GlobalMeshId::new(), GlobalImageId::new())).collect::<Vec<_>>()
+            })
+        }).collect();
+
+        let mut meshes = std::collections::HashSet::new();
+        let mut images = std::collections::HashSet::new();
+        for thread in threads {
+            for (mesh, image) in thread.join().unwrap() {
+                assert!(meshes.insert(mesh));
+                assert!(images.insert(image));
+            }
+        }
+        assert_eq!(meshes.len(), 8000);
+    }
+
+    #[test]
+    fn mip_extent() {
+        let size = Vec2u32::new(64, 32);
+        assert_eq!(get_mip_extent(size, 0), size);
+        assert_eq!(get_mip_extent(size, 2), Vec2u32::new(16, 8));
+        assert_eq!(get_mip_extent(size, 6), Vec2u32::new(1, 1));
+
+        let texel_size = Format::R8G8B8A8_UNORM.get_compatibility_class().get_texel_size().unwrap();
+        let mip = get_mip_extent(size, 2);
+        assert_eq!((mip[0] * mip[1] * texel_size) as usize, 64 * 32 * 4 / 16);
+    }
+
+    #[test]
+    fn two_layer_array_targets() {
+        assert_eq!(get_image_view_type(2, false).1, vk::ImageViewType::TYPE_2D_ARRAY);
+        assert_eq!(get_image_view_type(1, false).1, vk::ImageViewType::TYPE_2D);
+        assert_eq!(get_image_view_type(6, true).1, vk::ImageViewType::CUBE);
+
+        // Every layer is rendered through its own single layer view
+        let layer0 = make_layer_subresource_range(0);
+        let layer1 = make_layer_subresource_range(1);
+        assert_eq!((layer0.base_array_layer, layer0.layer_count), (0, 1));
+        assert_eq!((layer1.base_array_layer, layer1.layer_count), (1, 1));
+        assert_eq!((layer1.base_mip_level, layer1.level_count), (0, 1));
+    }
+
+    #[test]
+    fn readable_usage() {
+        for blit_dst in [false, true] {
+            assert!(get_image_usage(blit_dst).contains(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED));
+        }
+        assert!(get_image_usage(true).contains(vk::ImageUsageFlags::COLOR_ATTACHMENT));
+        assert!(!get_image_usage(false).contains(vk::ImageUsageFlags::COLOR_ATTACHMENT));
+    }
+
+    #[test]
+    fn depth_sampled_aspect() {
+        assert_eq!(get_sampled_aspect(Format::R8G8B8A8_UNORM.get_aspect_mask()), vk::ImageAspectFlags::COLOR);
+        assert_eq!(get_sampled_aspect(Format::D32_SFLOAT.get_aspect_mask()), vk::ImageAspectFlags::DEPTH);
+
+        // The stencil aspect is still transitioned but never sampled
+        let combined = Format::D24_UNORM_S8_UINT.get_aspect_mask();
+        assert!(combined.contains(vk::ImageAspectFlags::STENCIL));
+        assert_eq!(get_sampled_aspect(combined), vk::ImageAspectFlags::DEPTH);
+    }
+
+    #[test]
+    fn cube_face_regions() {
+        let face_size = 4u32;
+        let texel_size = Format::R8G8B8A8_UNORM.get_compatibility_class().get_texel_size().unwrap();
+        let (regions, size) = make_face_read_regions(face_size, texel_size);
+        assert_eq!(size, 6 * 4 * 4 * 4);
+
+        // Emulates the gpu copying a image with a distinct color on every face
+        let mut staging = vec![0u8; size];
+        for region in &regions {
+            let face_bytes = (region.extent[0] * region.extent[1] * texel_size) as usize;
+            staging[region.dst_offset..(region.dst_offset + face_bytes)].fill(region.layer as u8 + 1);
+        }
+
+        for (face, data) in CubeFace::ALL.iter().zip(staging.chunks_exact(size / 6)) {
+            assert!(data.iter().all(|b| *b == face.get_layer() as u8 + 1));
+        }
+
+        let upload = ImageData::new_face(&staging[0..64], CubeFace::NegativeZ, face_size);
+        assert_eq!(upload.layer, 5);
+        assert_eq!(upload.extent, Vec2u32::new(4, 4));
+    }
+
+    #[test]
+    fn scattered_update_bounds() {
+        let updates: [(vk::DeviceSize, &[u8]); 3] = [
+            (0, &[1, 2]),
+            (200, &[]),
+            (60, &[3, 4, 5, 6]),
+        ];
+        let filtered = filter_scattered_updates(64, &updates).unwrap();
+        assert_eq!(filtered, vec![(0, &[1u8, 2][..]), (60, &[3u8, 4, 5, 6][..])]);
+
+        let updates: [(vk::DeviceSize, &[u8]); 2] = [(0, &[1]), (62, &[2, 3, 4])];
+        assert_eq!(filter_scattered_updates(64, &updates), Err((62, 3)));
+        let updates: [(vk::DeviceSize, &[u8]); 1] = [(u64::MAX, &[1])];
+        assert_eq!(filter_scattered_updates(64, &updates), Err((u64::MAX, 1)));
+    }
+
+    #[test]
+    fn scattered_updates() {
+        let updates: [(vk::DeviceSize, &[u8]); 5] = [
+            (4, &[1, 2, 3, 4]),
+            (64, &[5]),
+            (20, &[6, 7, 8]),
+            (100, &[9, 10, 11, 12, 13, 14, 15, 16]),
+            (40, &[17, 18]),
+        ];
+
+        let mut staging = vec![0u8; 64];
+        let size = updates.iter().map(|(_, data)| data.len()).sum::<usize>();
+        pack_scattered_updates(&mut staging[16..(16 + size)], &updates);
+
+        let copies = make_scattered_copies(16, &updates);
+        assert_eq!(copies.len(), 5);
+
+        // Emulates the gpu copy
+        let mut buffer = vec![0u8; 128];
+        for copy in copies.iter() {
+            let src = &staging[(copy.src_offset as usize)..((copy.src_offset + copy.size) as usize)];
+            buffer[(copy.dst_offset as usize)..((copy.dst_offset + copy.size) as usize)].copy_from_slice(src);
+        }
+
+        for (offset, data) in &updates {
+            assert_eq!(&buffer[(*offset as usize)..(*offset as usize + data.len())], *data);
+        }
+        assert_eq!(buffer[0], 0);
+        assert_eq!(buffer[8], 0);
+    }
+}
@@ -2,10 +2,13 @@ use std::collections::VecDeque;
 use std::panic::RefUnwindSafe;
 use std::ptr::NonNull;
 use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use ash::vk;
 use crate::allocator::{Allocation, HostAccess};
 
+use crate::renderer::emulator::pass::PassId;
+use crate::renderer::emulator::share::Share;
 use crate::util::alloc::next_aligned;
 
 use crate::prelude::*;
@@ -13,6 +16,7 @@ use crate::prelude::*;
 pub(super) struct ImmediatePool {
     buffer_queue: Mutex<VecDeque<Box<ImmediateBuffer>>>,
     ready_condvar: Condvar,
+    block_size: AtomicU64,
 }
 
 impl ImmediatePool {
@@ -25,16 +29,28 @@ impl ImmediatePool {
         Self {
             buffer_queue: Mutex::new(buffer_queue),
             ready_condvar: Condvar::new(),
+            block_size: AtomicU64::new(ImmediateBuffer::DEFAULT_BLOCK_SIZE),
         }
     }
 
+    /// Sets the minimum size of newly allocated buffers. Data larger than the block size is
+    /// placed into a dedicated buffer. Already allocated buffers are not affected.
+    pub(super) fn set_block_size(&self, block_size: vk::DeviceSize) {
+        if block_size == 0 {
+            log::error!("Immediate buffer block size must not be 0");
+            panic!()
+        }
+        self.block_size.store(block_size, Ordering::Release);
+    }
+
     pub(super) fn get_next_buffer(&self) -> Box<ImmediateBuffer> {
         let mut guard = self.buffer_queue.lock().unwrap_or_else(|_| {
             log::error!("Poisoned queue mutex in ImmediatePool::get_next_buffer");
             panic!()
         });
         loop {
-            if let Some(next) = guard.pop_front() {
+            if let Some(mut next) = guard.pop_front() {
+                next.block_size = self.block_size.load(Ordering::Acquire);
                 return next;
             }
 
@@ -69,19 +85,21 @@ pub(super) struct ImmediateBuffer {
     device: Arc<DeviceContext>,
     current_buffer: Buffer,
     old_buffers: Vec<Buffer>,
+    block_size: vk::DeviceSize,
 }
 
 impl ImmediateBuffer {
-    const MIN_BUFFER_SIZE: vk::DeviceSize = 2u64.pow(24); // 16MB
+    const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 2u64.pow(24); // 16MB
     const OVER_ALLOCATION: u8 = 77; // 30%
 
     fn new(device: Arc<DeviceContext>) -> Self {
-        let current_buffer = Buffer::new(device.clone(), Self::MIN_BUFFER_SIZE);
+        let current_buffer = Buffer::new(device.clone(), Self::DEFAULT_BLOCK_SIZE);
 
         Self {
             device,
             current_buffer,
             old_buffers: Vec::new(),
+            block_size: Self::DEFAULT_BLOCK_SIZE,
         }
     }
 
@@ -92,6 +110,15 @@ impl ImmediateBuffer {
         }
     }
 
+    /// Returns the handles of all buffers which may be referenced by allocations of this
+    /// immediate buffer.
+    pub(super) fn get_buffer_handles(&self) -> Vec<vk::Buffer> {
+        let mut handles = Vec::with_capacity(1 + self.old_buffers.len());
+        handles.push(self.current_buffer.main_buffer);
+        handles.extend(self.old_buffers.iter().map(|buffer| buffer.main_buffer));
+        handles
+    }
+
     pub(super) fn reset(&mut self) {
         self.current_buffer.reset();
         self.old_buffers.clear();
@@ -99,12 +126,17 @@ impl ImmediateBuffer {
 
     pub(super) fn allocate(&mut self, data: &[u8], alignment: vk::DeviceSize) -> (vk::Buffer, vk::DeviceSize) {
         if let Some(info) = self.current_buffer.allocate(data, alignment) {
+            info
+        } else if (data.len() as vk::DeviceSize) > self.block_size {
+            // Place the data into a dedicated buffer so that the current buffer can still be used
+            // by following allocations
+            let mut dedicated = Buffer::new(self.device.clone(), data.len() as vk::DeviceSize);
+            let info = dedicated.allocate(data, alignment).unwrap();
+            self.old_buffers.push(dedicated);
+
             info
         } else {
-            let usage = self.get_current_usage();
-            let alloc_size = usage + (usage * (Self::OVER_ALLOCATION as u64) / (u8::MAX as u64));
-            let alloc_size = std::cmp::max(alloc_size, data.len() as u64);
-            let alloc_size = std::cmp::max(alloc_size, Self::MIN_BUFFER_SIZE);
+            let alloc_size = Self::next_buffer_size(self.get_current_usage(), self.block_size);
 
             let new_buffer = Buffer::new(self.device.clone(), alloc_size);
             self.old_buffers.push(std::mem::replace(&mut self.current_buffer, new_buffer));
@@ -113,6 +145,13 @@ impl ImmediateBuffer {
         }
     }
 
+    /// Returns the size of a new buffer if the current buffer is full. The size is based on the
+    /// current usage to reduce the number of buffers needed in following frames.
+    fn next_buffer_size(usage: vk::DeviceSize, block_size: vk::DeviceSize) -> vk::DeviceSize {
+        let alloc_size = usage + (usage * (Self::OVER_ALLOCATION as u64) / (u8::MAX as u64));
+        std::cmp::max(alloc_size, block_size)
+    }
+
     fn get_current_usage(&self) -> vk::DeviceSize {
         let mut usage = self.current_buffer.get_current_used_bytes();
         for old_buffer in &self.old_buffers {
@@ -123,6 +162,150 @@ impl ImmediateBuffer {
     }
 }
 
+/// A persistently mapped host visible buffer used as a ring for immediate meshes. Allocations
+/// advance a write cursor and are written directly into the mapped memory, so no copy commands are
+/// needed. When the cursor wraps around, regions are only reused once the pass which used them has
+/// completed.
+///
+/// The buffer itself is immutable so only the allocator state is behind a mutex. The worker reads
+/// the buffer handle without ever locking the ring.
+pub(super) struct ImmediateRing {
+    device: Arc<DeviceContext>,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped_memory: NonNull<u8>,
+    ring: Mutex<RingAllocator>,
+}
+
+impl ImmediateRing {
+    pub(super) fn new(device: Arc<DeviceContext>, size: vk::DeviceSize) -> Self {
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("ImmediateRingBuffer"))
+        }.unwrap_or_else(|| {
+            log::error!("Failed to create immediate ring buffer");
+            panic!()
+        });
+
+        Self {
+            device,
+            buffer,
+            allocation,
+            mapped_memory: mapped.unwrap(),
+            ring: Mutex::new(RingAllocator::new(size)),
+        }
+    }
+
+    pub(super) fn get_buffer_handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Writes `data` into the ring for use by the pass `pass` and returns the buffer and offset of
+    /// the data. Blocks if the data would overwrite a region of a pass which has not completed yet.
+    pub(super) fn allocate(&self, share: &Share, data: &[u8], alignment: vk::DeviceSize, pass: PassId) -> (vk::Buffer, vk::DeviceSize) {
+        let offset = loop {
+            let wait_pass = {
+                let mut ring = self.ring.lock().unwrap_or_else(|_| {
+                    log::error!("Poisoned immediate ring mutex in ImmediateRing::allocate");
+                    panic!()
+                });
+                ring.release_completed(share.get_last_completed_pass());
+                match ring.try_alloc(data.len() as vk::DeviceSize, alignment, pass.get_raw()) {
+                    Ok(offset) => break offset,
+                    Err(wait_pass) => wait_pass,
+                }
+            };
+
+            // The lock must not be held while waiting. Completing the pass may require the worker
+            // to submit a pass which uses this ring.
+            share.wait_for_pass_completed(wait_pass);
+        };
+
+        // The region is reserved for this allocation so it can be written without the lock
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(self.mapped_memory.as_ptr().add(offset as usize), data.len())
+        };
+        dst.copy_from_slice(data);
+
+        (self.buffer, offset)
+    }
+}
+
+unsafe impl Send for ImmediateRing { // Needed because of NonNull<u8>
+}
+
+unsafe impl Sync for ImmediateRing { // Mapped memory is only written in regions reserved through the ring mutex
+}
+
+impl Drop for ImmediateRing {
+    fn drop(&mut self) {
+        // Every pass using the ring keeps it alive until the pass has completed
+        unsafe {
+            self.device.get_allocator().destroy_buffer(self.buffer, self.allocation);
+        }
+    }
+}
+
+/// Tracks the regions of a ring buffer which are in use by passes that have not completed yet.
+struct RingAllocator {
+    size: vk::DeviceSize,
+    cursor: vk::DeviceSize,
+    /// The in flight regions as (offset, end, pass) ordered from oldest to newest.
+    in_flight: VecDeque<(vk::DeviceSize, vk::DeviceSize, u64)>,
+}
+
+impl RingAllocator {
+    fn new(size: vk::DeviceSize) -> Self {
+        Self {
+            size,
+            cursor: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    /// Allocates a region and returns its offset. If the region overlaps in flight regions the
+    /// pass of the oldest overlapping region is returned as the error. The caller must wait for
+    /// that pass to complete, call [`RingAllocator::release_completed`] and try again.
+    fn try_alloc(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize, pass: u64) -> Result<vk::DeviceSize, u64> {
+        if size > self.size {
+            log::error!("Immediate ring allocation of {:?} bytes exceeds ring size {:?}", size, self.size);
+            panic!()
+        }
+
+        let mut offset = next_aligned(self.cursor, alignment);
+        if offset + size > self.size {
+            offset = 0;
+        }
+        let end = offset + size;
+
+        if let Some((_, _, oldest)) = self.in_flight.iter().find(|(start, region_end, _)| *start < end && offset < *region_end) {
+            if *oldest == pass {
+                log::error!("Immediate ring of {:?} bytes is too small for the data of a single pass", self.size);
+                panic!()
+            }
+            return Err(*oldest);
+        }
+
+        self.cursor = end;
+        self.in_flight.push_back((offset, end, pass));
+        Ok(offset)
+    }
+
+    /// Frees all regions used by passes up to and including `completed_pass`.
+    fn release_completed(&mut self, completed_pass: u64) {
+        while let Some((_, _, pass)) = self.in_flight.front() {
+            if *pass > completed_pass {
+                break;
+            }
+            self.in_flight.pop_front();
+        }
+    }
+}
+
 struct Buffer {
     device: Arc<DeviceContext>,
 
@@ -137,18 +320,20 @@ struct Buffer {
 
 impl Buffer {
     fn new(device: Arc<DeviceContext>, size: vk::DeviceSize) -> Self {
-        let (main_buffer, main_allocation, main_mapped) = Self::create_main_buffer(&device, size);
+        Self::new_with_host_access(device, size, HostAccess::RandomOptional)
+    }
+
+    fn new_with_host_access(device: Arc<DeviceContext>, size: vk::DeviceSize, host_access: HostAccess) -> Self {
+        let (main_buffer, main_allocation, main_mapped) = Self::create_main_buffer(&device, size, host_access);
 
         let (staging, mapped_memory) = if let Some(mapped) = main_mapped {
-            log::info!("Immediate buffer uses mapped memory");
             (None, mapped)
         } else {
-            log::info!("Immediate buffer uses staging memory");
             let (staging_buffer, staging_allocation, staging_mapped) = Self::create_staging_buffer(&device, size);
             (Some((staging_buffer, staging_allocation)), staging_mapped)
         };
 
-        Self {
+        let buffer = Self {
             device,
             main_buffer,
             mapped_memory,
@@ -156,7 +341,15 @@ impl Buffer {
             current_offset: 0,
             main_allocation,
             staging
+        };
+
+        if buffer.is_host_visible() {
+            log::info!("Immediate buffer uses mapped memory");
+        } else {
+            log::info!("Immediate buffer uses staging memory");
         }
+
+        buffer
     }
 
     fn generate_copy_commands(&self, cmd: vk::CommandBuffer) {
@@ -203,14 +396,29 @@ impl Buffer {
         self.current_offset
     }
 
-    fn create_main_buffer(device: &DeviceContext, size: vk::DeviceSize) -> (vk::Buffer, Allocation, Option<NonNull<u8>>) {
+    /// Returns true if the main buffer is placed in host visible memory and written directly.
+    pub fn is_host_visible(&self) -> bool {
+        self.mapped_ptr().is_some()
+    }
+
+    /// Returns the mapped memory of the main buffer. Returns [`None`] if the main buffer is not
+    /// host visible and data is written through a staging buffer instead.
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        if self.staging.is_none() {
+            Some(self.mapped_memory)
+        } else {
+            None
+        }
+    }
+
+    fn create_main_buffer(device: &DeviceContext, size: vk::DeviceSize, host_access: HostAccess) -> (vk::Buffer, Allocation, Option<NonNull<u8>>) {
         let info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
         let (buffer, allocation, mapped) = unsafe {
-            device.get_allocator().create_buffer(&info, HostAccess::RandomOptional, &format_args!("ImmediateMainBuffer"))
+            device.get_allocator().create_buffer(&info, host_access, &format_args!("ImmediateMainBuffer"))
         }.unwrap_or_else(|| {
             log::error!("Failed to create main buffer.");
             panic!()
@@ -251,4 +459,81 @@ impl Drop for Buffer {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_buffer_size() {
+        assert_eq!(ImmediateBuffer::next_buffer_size(0, 1024), 1024);
+        assert_eq!(ImmediateBuffer::next_buffer_size(255, 16), 255 + 77);
+    }
+
+    #[test]
+    fn buffer_host_visibility() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+
+        let mut mapped = Buffer::new_with_host_access(device.clone(), 1024, HostAccess::Random);
+        assert!(mapped.is_host_visible());
+        let ptr = mapped.mapped_ptr().unwrap();
+
+        let (_, offset) = mapped.allocate(&[1u8, 2, 3, 4], 16).unwrap();
+        let written = unsafe { std::slice::from_raw_parts(ptr.as_ptr().offset(offset as isize), 4) };
+        assert_eq!(written, &[1u8, 2, 3, 4]);
+
+        let staged = Buffer::new_with_host_access(device, 1024, HostAccess::None);
+        assert!(!staged.is_host_visible());
+        assert!(staged.mapped_ptr().is_none());
+    }
+
+    /// Mirrors the retry loop of [`ImmediateRing::allocate`] with waits completing immediately.
+    fn alloc_waiting(ring: &mut RingAllocator, size: vk::DeviceSize, pass: u64, waited: &mut Vec<u64>) -> vk::DeviceSize {
+        loop {
+            match ring.try_alloc(size, 4, pass) {
+                Ok(offset) => return offset,
+                Err(wait_pass) => {
+                    waited.push(wait_pass);
+                    ring.release_completed(wait_pass);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ring_waits_for_oldest_before_wrapping() {
+        let mut ring = RingAllocator::new(256);
+        let mut waited = Vec::new();
+
+        assert_eq!(alloc_waiting(&mut ring, 100, 1, &mut waited), 0);
+        assert_eq!(alloc_waiting(&mut ring, 100, 2, &mut waited), 100);
+        assert!(waited.is_empty());
+
+        // Does not fit behind the cursor so the ring wraps and must wait for pass 1
+        assert_eq!(alloc_waiting(&mut ring, 80, 3, &mut waited), 0);
+        assert_eq!(waited, vec![1]);
+
+        // Overlaps the region of pass 2
+        assert_eq!(alloc_waiting(&mut ring, 40, 3, &mut waited), 80);
+        assert_eq!(waited, vec![1, 2]);
+
+        // Completed passes are released without waiting
+        ring.release_completed(3);
+        assert_eq!(alloc_waiting(&mut ring, 200, 4, &mut waited), 0);
+        assert_eq!(waited, vec![1, 2]);
+    }
+
+    #[test]
+    fn ring_reports_wait_without_blocking() {
+        let mut ring = RingAllocator::new(128);
+        assert_eq!(ring.try_alloc(100, 4, 1), Ok(0));
+
+        // The caller is told which pass to wait for and the ring state is left untouched
+        assert_eq!(ring.try_alloc(100, 4, 2), Err(1));
+        assert_eq!(ring.try_alloc(100, 4, 2), Err(1));
+
+        ring.release_completed(1);
+        assert_eq!(ring.try_alloc(100, 4, 2), Ok(0));
+    }
+}
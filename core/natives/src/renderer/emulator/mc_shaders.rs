@@ -25,9 +25,13 @@ pub struct Shader {
 
 impl Shader {
     pub fn new(vertex_format: VertexFormat, used_uniforms: McUniform) -> Arc<Self> {
+        Self::new_with_id(ShaderId::new(), vertex_format, used_uniforms)
+    }
+
+    pub(super) fn new_with_id(id: ShaderId, vertex_format: VertexFormat, used_uniforms: McUniform) -> Arc<Self> {
         Arc::new_cyclic(|weak| {
             Self {
-                id: ShaderId::new(),
+                id,
                 vertex_format,
                 used_uniforms,
                 weak: weak.clone(),
@@ -133,6 +137,62 @@ impl McUniform {
     pub const LINE_WIDTH: Self = Self::from_raw(1u64 << 12);
     pub const GAME_TIME: Self = Self::from_raw(1u64 << 13);
     pub const CHUNK_OFFSET: Self = Self::from_raw(1u64 << 14);
+
+    /// All uniforms in the order of their bits.
+    pub const ALL: [Self; 15] = [
+        Self::MODEL_VIEW_MATRIX,
+        Self::PROJECTION_MATRIX,
+        Self::INVERSE_VIEW_ROTATION_MATRIX,
+        Self::TEXTURE_MATRIX,
+        Self::SCREEN_SIZE,
+        Self::COLOR_MODULATOR,
+        Self::LIGHT0_DIRECTION,
+        Self::LIGHT1_DIRECTION,
+        Self::FOG_START,
+        Self::FOG_END,
+        Self::FOG_COLOR,
+        Self::FOG_SHAPE,
+        Self::LINE_WIDTH,
+        Self::GAME_TIME,
+        Self::CHUNK_OFFSET,
+    ];
+
+    /// Returns the block, byte offset and size of a single uniform as declared in
+    /// `mc_uniforms.glsl`. Returns [`None`] if this is not exactly one uniform or the uniform is
+    /// not part of the shader interface.
+    pub const fn get_block_range(&self) -> Option<(McUniformBlock, u32, u32)> {
+        match self.0 {
+            0x0001 => Some((McUniformBlock::PushConstants, 0, 64)),
+            0x0002 => Some((McUniformBlock::StaticUniforms, 0, 64)),
+            0x0010 => Some((McUniformBlock::StaticUniforms, 96, 8)),
+            0x0100 => Some((McUniformBlock::StaticUniforms, 80, 4)),
+            0x0200 => Some((McUniformBlock::StaticUniforms, 84, 4)),
+            0x0400 => Some((McUniformBlock::StaticUniforms, 64, 16)),
+            0x0800 => Some((McUniformBlock::StaticUniforms, 92, 4)),
+            0x2000 => Some((McUniformBlock::StaticUniforms, 88, 4)),
+            0x4000 => Some((McUniformBlock::PushConstants, 64, 12)),
+            _ => None,
+        }
+    }
+}
+
+/// A block of the shader interface declared in `mc_uniforms.glsl` which stores uniforms.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum McUniformBlock {
+    /// The `_PushConstant` push constant block.
+    PushConstants,
+    /// The std140 `_McStaticUniforms` uniform buffer.
+    StaticUniforms,
+}
+
+impl McUniformBlock {
+    /// Returns the size of the block in bytes.
+    pub const fn get_size(&self) -> usize {
+        match self {
+            McUniformBlock::PushConstants => 80,
+            McUniformBlock::StaticUniforms => 104,
+        }
+    }
 }
 
 impl BitOr for McUniform {
@@ -211,6 +271,160 @@ pub enum McUniformData {
     ChunkOffset(Vec3f32),
 }
 
+impl McUniformData {
+    /// Returns the uniform this data updates.
+    pub fn get_uniform(&self) -> McUniform {
+        match self {
+            McUniformData::ModelViewMatrix(_) => McUniform::MODEL_VIEW_MATRIX,
+            McUniformData::ProjectionMatrix(_) => McUniform::PROJECTION_MATRIX,
+            McUniformData::InverseViewRotationMatrix(_) => McUniform::INVERSE_VIEW_ROTATION_MATRIX,
+            McUniformData::TextureMatrix(_) => McUniform::TEXTURE_MATRIX,
+            McUniformData::ScreenSize(_) => McUniform::SCREEN_SIZE,
+            McUniformData::ColorModulator(_) => McUniform::COLOR_MODULATOR,
+            McUniformData::Light0Direction(_) => McUniform::LIGHT0_DIRECTION,
+            McUniformData::Light1Direction(_) => McUniform::LIGHT1_DIRECTION,
+            McUniformData::FogStart(_) => McUniform::FOG_START,
+            McUniformData::FogEnd(_) => McUniform::FOG_END,
+            McUniformData::FogColor(_) => McUniform::FOG_COLOR,
+            McUniformData::FogShape(_) => McUniform::FOG_SHAPE,
+            McUniformData::LineWidth(_) => McUniform::LINE_WIDTH,
+            McUniformData::GameTime(_) => McUniform::GAME_TIME,
+            McUniformData::ChunkOffset(_) => McUniform::CHUNK_OFFSET,
+        }
+    }
+
+    fn write_bytes(&self, dst: &mut [u8]) {
+        match self {
+            McUniformData::ModelViewMatrix(mat) |
+            McUniformData::ProjectionMatrix(mat) |
+            McUniformData::InverseViewRotationMatrix(mat) |
+            McUniformData::TextureMatrix(mat) => dst.copy_from_slice(bytemuck::cast_slice(mat.as_slice())),
+            McUniformData::ScreenSize(vec) => dst.copy_from_slice(bytemuck::cast_slice(vec.as_slice())),
+            McUniformData::ColorModulator(vec) |
+            McUniformData::FogColor(vec) => dst.copy_from_slice(bytemuck::cast_slice(vec.as_slice())),
+            McUniformData::Light0Direction(vec) |
+            McUniformData::Light1Direction(vec) |
+            McUniformData::ChunkOffset(vec) => dst.copy_from_slice(bytemuck::cast_slice(vec.as_slice())),
+            McUniformData::FogStart(val) |
+            McUniformData::FogEnd(val) |
+            McUniformData::LineWidth(val) |
+            McUniformData::GameTime(val) => dst.copy_from_slice(&val.to_ne_bytes()),
+            McUniformData::FogShape(val) => dst.copy_from_slice(&val.to_ne_bytes()),
+        }
+    }
+}
+
+/// A write of a contiguous byte range of a block of the shader interface.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct McUniformWrite {
+    pub block: McUniformBlock,
+    pub offset: u32,
+    pub data: Box<[u8]>,
+}
+
+/// Stores the current value of all uniforms laid out like the blocks of `mc_uniforms.glsl` and
+/// tracks which uniforms have been modified since the last flush.
+///
+/// This allows callers to only upload uniforms that have actually changed instead of updating
+/// every uniform for every draw call.
+pub struct McUniformTracker {
+    values: [Option<McUniformData>; 15],
+    push_constants: [u8; McUniformBlock::PushConstants.get_size()],
+    static_uniforms: [u8; McUniformBlock::StaticUniforms.get_size()],
+    dirty: McUniform,
+}
+
+impl McUniformTracker {
+    pub fn new() -> Self {
+        Self {
+            values: [None; 15],
+            push_constants: [0u8; McUniformBlock::PushConstants.get_size()],
+            static_uniforms: [0u8; McUniformBlock::StaticUniforms.get_size()],
+            dirty: McUniform::empty(),
+        }
+    }
+
+    /// Updates a uniform and marks it as dirty. Uniforms which are not part of the shader
+    /// interface are stored but never produce a block write.
+    pub fn set(&mut self, data: McUniformData) {
+        let uniform = data.get_uniform();
+        if let Some((block, offset, size)) = uniform.get_block_range() {
+            data.write_bytes(&mut self.get_block_mut(block)[(offset as usize)..((offset + size) as usize)]);
+        }
+
+        self.values[uniform.as_raw().trailing_zeros() as usize] = Some(data);
+        self.dirty |= uniform;
+    }
+
+    /// Returns the last value set for a single uniform.
+    pub fn get(&self, uniform: McUniform) -> Option<McUniformData> {
+        if uniform.as_raw().count_ones() != 1 {
+            return None;
+        }
+        *self.values.get(uniform.as_raw().trailing_zeros() as usize)?
+    }
+
+    /// Returns the current content of a block.
+    pub fn get_block(&self, block: McUniformBlock) -> &[u8] {
+        match block {
+            McUniformBlock::PushConstants => &self.push_constants,
+            McUniformBlock::StaticUniforms => &self.static_uniforms,
+        }
+    }
+
+    fn get_block_mut(&mut self, block: McUniformBlock) -> &mut [u8] {
+        match block {
+            McUniformBlock::PushConstants => &mut self.push_constants,
+            McUniformBlock::StaticUniforms => &mut self.static_uniforms,
+        }
+    }
+
+    /// Returns all uniforms modified since the last flush.
+    pub fn get_dirty(&self) -> McUniform {
+        self.dirty
+    }
+
+    /// Returns the data of all dirty uniforms and clears the dirty state.
+    pub fn take_dirty_data(&mut self) -> Vec<McUniformData> {
+        let dirty = std::mem::replace(&mut self.dirty, McUniform::empty());
+        McUniform::ALL.iter().filter(|uniform| dirty.contains(*uniform)).filter_map(|uniform| self.get(*uniform)).collect()
+    }
+
+    /// Returns the minimal set of block writes covering all dirty uniforms and clears the dirty
+    /// state. Adjacent dirty uniforms of the same block are merged into a single write.
+    pub fn flush_dirty(&mut self) -> Vec<McUniformWrite> {
+        let dirty = std::mem::replace(&mut self.dirty, McUniform::empty());
+
+        let mut dirty_ranges: Vec<_> = McUniform::ALL.iter()
+            .filter(|uniform| dirty.contains(*uniform))
+            .filter_map(McUniform::get_block_range)
+            .collect();
+        dirty_ranges.sort();
+
+        let mut ranges: Vec<(McUniformBlock, u32, u32)> = Vec::new();
+        for (block, offset, size) in dirty_ranges {
+            match ranges.last_mut() {
+                Some((last_block, last_offset, last_size)) if *last_block == block && *last_offset + *last_size == offset => {
+                    *last_size += size;
+                }
+                _ => ranges.push((block, offset, size)),
+            }
+        }
+
+        ranges.into_iter().map(|(block, offset, size)| McUniformWrite {
+            block,
+            offset,
+            data: Box::from(&self.get_block(block)[(offset as usize)..((offset + size) as usize)]),
+        }).collect()
+    }
+}
+
+impl Default for McUniformTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default)]
 pub struct DevUniform {
@@ -228,6 +442,28 @@ pub struct DevUniform {
 const_assert_eq!(std::mem::size_of::<DevUniform>(), 144);
 const_assert_eq!(std::mem::size_of::<DevUniform>() % 16, 0); // std140 size must be multiple of vec4
 
+/// A vertex attribute which may be provided by a [`VertexFormat`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum VertexAttribute {
+    Position,
+    Normal,
+    Color,
+    UV0,
+    UV1,
+    UV2,
+}
+
+impl VertexAttribute {
+    pub const ALL: [VertexAttribute; 6] = [
+        VertexAttribute::Position,
+        VertexAttribute::Normal,
+        VertexAttribute::Color,
+        VertexAttribute::UV0,
+        VertexAttribute::UV1,
+        VertexAttribute::UV2,
+    ];
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct VertexFormatEntry {
     pub offset: u32,
@@ -243,4 +479,253 @@ pub struct VertexFormat {
     pub uv0: Option<VertexFormatEntry>,
     pub uv1: Option<VertexFormatEntry>,
     pub uv2: Option<VertexFormatEntry>,
-}
\ No newline at end of file
+
+    /// If set all attributes except the position are consumed once per instance instead of once
+    /// per vertex. The value is the number of instances that share the same attribute data.
+    /// Divisors other than 1 require the VK_EXT_vertex_attribute_divisor extension and a divisor
+    /// of 0 additionally requires the vertexAttributeInstanceRateZeroDivisor feature.
+    pub instance_divisor: Option<u32>,
+}
+
+/// Describes why a [`VertexFormat`] cannot be used on a device.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum VertexFormatError {
+    /// The instance divisor is not 1 but VK_EXT_vertex_attribute_divisor is not supported.
+    DivisorUnsupported(u32),
+    /// The instance divisor is 0 but the vertexAttributeInstanceRateZeroDivisor feature is not
+    /// supported.
+    ZeroDivisorUnsupported,
+}
+
+impl VertexFormat {
+    /// Creates a vertex format without any vertex input. Shaders using this format must generate
+    /// their geometry from the vertex index and can only be drawn with
+    /// [`PassRecorder::draw_procedural`](super::PassRecorder::draw_procedural).
+    pub const fn empty() -> Self {
+        Self {
+            stride: 0,
+            position: VertexFormatEntry {
+                offset: 0,
+                format: vk::Format::UNDEFINED
+            },
+            normal: None,
+            color: None,
+            uv0: None,
+            uv1: None,
+            uv2: None,
+            instance_divisor: None
+        }
+    }
+
+    /// Returns true if this format does not consume any vertex input.
+    pub fn is_empty(&self) -> bool {
+        self.stride == 0 && self.position.format == vk::Format::UNDEFINED
+    }
+
+    /// Returns the entry of a attribute or [`None`] if this format does not provide it.
+    pub fn get_entry(&self, attribute: VertexAttribute) -> Option<&VertexFormatEntry> {
+        match attribute {
+            VertexAttribute::Position => {
+                if self.is_empty() {
+                    None
+                } else {
+                    Some(&self.position)
+                }
+            }
+            VertexAttribute::Normal => self.normal.as_ref(),
+            VertexAttribute::Color => self.color.as_ref(),
+            VertexAttribute::UV0 => self.uv0.as_ref(),
+            VertexAttribute::UV1 => self.uv1.as_ref(),
+            VertexAttribute::UV2 => self.uv2.as_ref(),
+        }
+    }
+
+    /// Checks that the instance divisor of this format is supported by a device with the given
+    /// divisor features.
+    pub fn validate_instance_divisor(&self, supports_divisor: bool, supports_zero_divisor: bool) -> Result<(), VertexFormatError> {
+        match self.instance_divisor {
+            None | Some(1) => Ok(()),
+            Some(0) if !supports_zero_divisor => Err(VertexFormatError::ZeroDivisorUnsupported),
+            Some(divisor) if !supports_divisor => Err(VertexFormatError::DivisorUnsupported(divisor)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Cross references the attributes consumed by a pipeline against the attributes provided by
+    /// this format.
+    ///
+    /// Provided attributes which are never consumed only waste bandwidth and are reported with a
+    /// warning. They are returned if all consumed attributes are provided. Consumed attributes
+    /// which are not provided would be read as undefined data so they are reported as a error
+    /// and returned as the [`Err`] value.
+    pub fn validate_attributes(&self, consumed: &[VertexAttribute]) -> Result<Vec<VertexAttribute>, Vec<VertexAttribute>> {
+        let missing: Vec<_> = consumed.iter().copied().filter(|attribute| self.get_entry(*attribute).is_none()).collect();
+        if !missing.is_empty() {
+            log::error!("Vertex format {:?} does not provide consumed attributes {:?}", self, missing);
+            return Err(missing);
+        }
+
+        let unused: Vec<_> = VertexAttribute::ALL.iter().copied()
+            .filter(|attribute| self.get_entry(*attribute).is_some() && !consumed.contains(attribute))
+            .collect();
+        if !unused.is_empty() {
+            log::warn!("Vertex format {:?} provides attributes {:?} which are never consumed", self, unused);
+        }
+
+        Ok(unused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_divisors_rejected() {
+        let with_divisor = |instance_divisor| VertexFormat { instance_divisor, ..VertexFormat::empty() };
+
+        assert_eq!(with_divisor(None).validate_instance_divisor(false, false), Ok(()));
+        assert_eq!(with_divisor(Some(1)).validate_instance_divisor(false, false), Ok(()));
+        assert_eq!(with_divisor(Some(2)).validate_instance_divisor(false, false), Err(VertexFormatError::DivisorUnsupported(2)));
+        assert_eq!(with_divisor(Some(2)).validate_instance_divisor(true, false), Ok(()));
+        assert_eq!(with_divisor(Some(0)).validate_instance_divisor(true, false), Err(VertexFormatError::ZeroDivisorUnsupported));
+        assert_eq!(with_divisor(Some(0)).validate_instance_divisor(true, true), Ok(()));
+    }
+
+    #[test]
+    fn flush_only_dirty_ranges() {
+        let mut tracker = McUniformTracker::new();
+        tracker.set(McUniformData::ProjectionMatrix(Mat4f32::identity()));
+        tracker.set(McUniformData::FogEnd(1.0));
+        tracker.flush_dirty();
+
+        tracker.set(McUniformData::FogColor(Vec4f32::new(1.0, 0.5, 0.25, 1.0)));
+        tracker.set(McUniformData::GameTime(2.0));
+        tracker.set(McUniformData::ChunkOffset(Vec3f32::new(16.0, 0.0, 32.0)));
+
+        let writes = tracker.flush_dirty();
+        assert_eq!(writes.len(), 3);
+        assert_eq!((writes[0].block, writes[0].offset), (McUniformBlock::PushConstants, 64));
+        assert_eq!(writes[0].data.len(), 12);
+        assert_eq!((writes[1].block, writes[1].offset), (McUniformBlock::StaticUniforms, 64));
+        assert_eq!(writes[1].data.len(), 16);
+        assert_eq!((writes[2].block, writes[2].offset), (McUniformBlock::StaticUniforms, 88));
+        assert_eq!(writes[2].data.as_ref(), &2.0f32.to_ne_bytes());
+
+        assert!(tracker.flush_dirty().is_empty());
+
+        // Not part of the shader interface
+        tracker.set(McUniformData::LineWidth(2.0));
+        assert!(tracker.flush_dirty().is_empty());
+        assert!(matches!(tracker.get(McUniform::LINE_WIDTH), Some(McUniformData::LineWidth(_))));
+    }
+
+    /// Returns the std140 base alignment and size of a glsl type used in `mc_uniforms.glsl`.
+    fn get_std140_layout(glsl_type: &str) -> (u32, u32) {
+        match glsl_type {
+            "mat4" => (16, 64),
+            "vec4" => (16, 16),
+            "vec3" => (16, 12),
+            "vec2" => (8, 8),
+            "float" | "uint" | "int" => (4, 4),
+            _ => panic!("Unknown glsl type {}", glsl_type),
+        }
+    }
+
+    /// Parses the members of a block declared in `mc_uniforms.glsl` and returns their name,
+    /// offset and size.
+    fn parse_block(source: &str, block_name: &str) -> Vec<(String, u32, u32)> {
+        let start = source.find(&format!("uniform {} {{", block_name)).unwrap();
+        let body = &source[start..];
+        let body = &body[(body.find('{').unwrap() + 1)..body.find('}').unwrap()];
+
+        let mut offset = 0;
+        body.lines().filter_map(|line| {
+            let line = line.split("//").next().unwrap().trim().strip_suffix(';')?;
+            let (glsl_type, name) = line.split_once(' ')?;
+            let (alignment, size) = get_std140_layout(glsl_type);
+            offset = ((offset + alignment - 1) / alignment) * alignment;
+            let member = (String::from(name.trim()), offset, size);
+            offset += size;
+            Some(member)
+        }).collect()
+    }
+
+    #[test]
+    fn block_ranges_match_shader_layout() {
+        let source = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/src/emulator/mc_uniforms.glsl"));
+
+        fn check(source: &str, block: McUniformBlock, block_name: &str, members: &[(&str, &[McUniform])]) {
+            let layout = parse_block(source, block_name);
+            for (name, uniforms) in members {
+                let (_, offset, size) = layout.iter().find(|(member, _, _)| member == name).unwrap_or_else(|| panic!("Missing member {}", name));
+                // Vector members may pack multiple scalar uniforms
+                let mut expected_offset = *offset;
+                for uniform in uniforms.iter() {
+                    let (uniform_block, uniform_offset, uniform_size) = uniform.get_block_range().unwrap();
+                    assert_eq!((uniform_block, uniform_offset), (block, expected_offset), "Wrong offset for {}", name);
+                    expected_offset += uniform_size;
+                }
+                assert_eq!(expected_offset, offset + size, "Wrong size for {}", name);
+            }
+
+            let (_, last_offset, last_size) = layout.last().unwrap();
+            assert!((last_offset + last_size) as usize <= block.get_size());
+        }
+
+        check(source, McUniformBlock::StaticUniforms, "_McStaticUniforms", &[
+            ("projection_matrix", &[McUniform::PROJECTION_MATRIX]),
+            ("fog_color", &[McUniform::FOG_COLOR]),
+            ("fog_range_and_game_time", &[McUniform::FOG_START, McUniform::FOG_END, McUniform::GAME_TIME]),
+            ("fog_shape", &[McUniform::FOG_SHAPE]),
+            ("screen_size", &[McUniform::SCREEN_SIZE]),
+        ]);
+        check(source, McUniformBlock::PushConstants, "_PushConstant", &[
+            ("model_view_matrix", &[McUniform::MODEL_VIEW_MATRIX]),
+            ("chunk_offset", &[McUniform::CHUNK_OFFSET]),
+        ]);
+    }
+
+    fn make_format(color: bool, uv0: bool) -> VertexFormat {
+        let entry = |offset| VertexFormatEntry { offset, format: vk::Format::R32G32_SFLOAT };
+        VertexFormat {
+            stride: 28,
+            position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+            color: if color { Some(VertexFormatEntry { offset: 12, format: vk::Format::R8G8B8A8_UNORM }) } else { None },
+            uv0: if uv0 { Some(entry(16)) } else { None },
+            ..VertexFormat::empty()
+        }
+    }
+
+    #[test]
+    fn extra_attribute_is_unused() {
+        let format = make_format(true, true);
+        let unused = format.validate_attributes(&[VertexAttribute::Position, VertexAttribute::Color]).unwrap();
+        assert_eq!(unused, vec![VertexAttribute::UV0]);
+
+        assert!(format.validate_attributes(&[VertexAttribute::Position, VertexAttribute::Color, VertexAttribute::UV0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_attribute_is_error() {
+        let format = make_format(true, false);
+        let missing = format.validate_attributes(&[VertexAttribute::Position, VertexAttribute::UV0]).unwrap_err();
+        assert_eq!(missing, vec![VertexAttribute::UV0]);
+
+        // Procedural formats do not provide a position
+        let missing = VertexFormat::empty().validate_attributes(&[VertexAttribute::Position]).unwrap_err();
+        assert_eq!(missing, vec![VertexAttribute::Position]);
+    }
+
+    #[test]
+    fn flush_merges_adjacent_ranges() {
+        let mut tracker = McUniformTracker::new();
+        tracker.set(McUniformData::FogStart(0.0));
+        tracker.set(McUniformData::FogEnd(1.0));
+
+        let writes = tracker.flush_dirty();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].offset, 80);
+        assert_eq!(writes[0].data.len(), 8);
+    }
+}
@@ -23,47 +23,118 @@ pub mod mc_shaders;
 mod descriptors;
 mod share;
 mod staging;
+mod transient;
+mod readback;
+mod bindless;
+mod budget;
+mod registry;
+mod samplers;
+mod quads;
+mod state;
+mod blit;
+mod breadcrumbs;
+mod capture;
 
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::panic::RefUnwindSafe;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use ash::vk;
 use bytemuck::cast_slice;
 
-use crate::renderer::emulator::worker::run_worker;
-use crate::renderer::emulator::pipeline::EmulatorPipeline;
+use crate::renderer::emulator::worker::{DeferredRelease, GlobalImageBlit, GlobalImageExport, run_worker, WorkerTask};
+use crate::renderer::emulator::capture::ReplayTarget;
+use crate::renderer::emulator::pipeline::{ColorAttachmentOutput, DepthImageOutput, EmulatorPipeline, ImageLayerOutput};
+use crate::renderer::emulator::registry::ImageRegistry;
+use crate::objects::sync::SemaphoreOp;
 
 use crate::prelude::*;
 
-pub use global_objects::{GlobalMesh, GlobalImage, ImageData, SamplerInfo};
+pub use global_objects::{CubeFace, ExternalImageState, GlobalMesh, GlobalMeshId, GlobalImage, GlobalImageId, GlobalObjectCreateError, ImageData, SamplerInfo};
 
 pub use pass::PassId;
 pub use pass::PassRecorder;
 pub use pass::ImmediateMeshId;
 
+pub use transient::TransientImage;
+
+pub use readback::{ChannelOrder, ImageBatchReadToken, ImageReadRegion, ImageReadToken, ReadTokenSet};
+
+pub use bindless::BindlessTextureTable;
+
+pub use registry::ImageInfo;
+
+pub use state::{BlendFunc, EmulationState, EmulationStateSnapshot};
+
+pub use blit::BlitOptions;
+
+pub use samplers::FilterOverride;
+
+pub use breadcrumbs::Checkpoint;
+
+pub use capture::{CapturedImageRegion, CapturedTask, FrameCapture, ReplayError};
+
+pub use worker::{EmulatorTaskError, EmulatorTaskKind};
+
 use share::Share;
-use crate::renderer::emulator::mc_shaders::{McUniform, Shader, ShaderId, VertexFormat};
+use crate::renderer::emulator::mc_shaders::{McUniform, Shader, ShaderId, VertexFormat, VertexFormatError};
+use crate::renderer::emulator::transient::TransientImagePool;
+use crate::renderer::emulator::quads::QuadIndexBuffer;
 use crate::util::format::Format;
 
+/// The emulator renderer.
+///
+/// # Thread safety
+/// All functions take `&self` and may be called concurrently from any thread. Staging memory,
+/// uniform memory and the task queue of the worker are each protected by their own mutex which is
+/// only held for the duration of a single allocation or push. Tasks pushed by one thread are
+/// executed in the order they were pushed, but there is no ordering between tasks of different
+/// threads unless they are synchronized externally. A [`PassRecorder`] may be sent to other
+/// threads but only used by one thread at a time.
 pub struct EmulatorRenderer {
     share: Arc<Share>,
+    transient_images: Arc<TransientImagePool>,
     placeholder_image: Arc<GlobalImage>,
     placeholder_sampler: SamplerInfo,
-    worker: std::thread::JoinHandle<()>,
+    quad_indices: QuadIndexBuffer,
+    bindless_textures: Option<BindlessTextureTable>,
+    image_registry: Mutex<ImageRegistry<Arc<GlobalImage>>>,
+    default_pipeline: DefaultPipeline,
+    /// The worker thread. [`None`] once the worker has been shut down or detached.
+    worker: Option<std::thread::JoinHandle<()>>,
 }
 
 impl EmulatorRenderer {
+    /// The name of the worker thread. Used to identify the thread in profilers and crash reports.
+    pub const WORKER_THREAD_NAME: &'static str = "b4d-emulator";
+
     pub(crate) fn new(device: Arc<DeviceContext>) -> Self {
+        Self::new_with_worker_priority(device, None)
+    }
+
+    /// Creates a emulator whose worker thread runs with the specified os scheduling priority. On
+    /// linux the priority is a nice value where lower values are scheduled first. Other platforms
+    /// are not supported. If the priority cannot be set an error is logged and the worker keeps
+    /// the default priority.
+    pub fn new_with_worker_priority(device: Arc<DeviceContext>, worker_priority: Option<i32>) -> Self {
         let share = Arc::new(Share::new(device.clone()));
+        let transient_images = Arc::new(TransientImagePool::new(device.clone()));
 
         let share2 = share.clone();
-        let worker = std::thread::spawn(move || {
+        let worker = std::thread::Builder::new().name(String::from(Self::WORKER_THREAD_NAME)).spawn(move || {
+            if let Some(priority) = worker_priority {
+                apply_worker_priority(priority, set_current_thread_priority);
+            }
             std::panic::catch_unwind(|| {
                 run_worker(device,share2);
             }).unwrap_or_else(|_| {
                 log::error!("Emulator worker panicked!");
                 std::process::exit(1);
             })
+        }).unwrap_or_else(|err| {
+            log::error!("Failed to spawn emulator worker thread {:?}", err);
+            panic!()
         });
 
         let placeholder_image = Self::create_placeholder_image(share.clone());
@@ -76,11 +147,25 @@ impl EmulatorRenderer {
             anisotropy_enable: false
         };
 
+        let bindless_textures = if share.get_device().supports_descriptor_indexing() {
+            Some(BindlessTextureTable::new(share.clone()).unwrap_or_else(|err| {
+                log::error!("Failed to create bindless texture table {:?}", err);
+                panic!()
+            }))
+        } else {
+            None
+        };
+
         Self {
             share,
+            transient_images,
             placeholder_image,
             placeholder_sampler,
-            worker,
+            quad_indices: QuadIndexBuffer::new(),
+            bindless_textures,
+            image_registry: Mutex::new(ImageRegistry::new()),
+            default_pipeline: DefaultPipeline::new(),
+            worker: Some(worker),
         }
     }
 
@@ -88,8 +173,50 @@ impl EmulatorRenderer {
         self.share.get_device()
     }
 
-    pub fn create_global_mesh(&self, data: &MeshData) -> Arc<GlobalMesh> {
-        GlobalMesh::new(self.share.clone(), data).unwrap()
+    pub fn get_worker_thread(&self) -> &std::thread::Thread {
+        // The worker is only taken while the emulator is being shut down or dropped
+        self.worker.as_ref().unwrap().thread()
+    }
+
+    /// Creates a global mesh. If a memory limit is set and the mesh would exceed it
+    /// [`GlobalObjectCreateError::OutOfDeviceMemory`] is returned without attempting the allocation.
+    pub fn create_global_mesh(&self, data: &MeshData) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
+        GlobalMesh::new(self.share.clone(), data)
+    }
+
+    /// Creates a global mesh from quads. Every 4 vertices form a quad which is drawn as 2
+    /// triangles using a device local index buffer shared between all quad meshes. If the stride
+    /// is 0 or the data does not hold a non zero multiple of 4 vertices
+    /// [`GlobalObjectCreateError::InvalidData`] is returned.
+    pub fn create_global_quad_mesh(&self, vertex_data: &[u8], vertex_stride: u32) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
+        let quad_count = quads::get_quad_count(vertex_data.len(), vertex_stride).map_err(|err| {
+            log::error!("Invalid quad data passed to EmulatorRenderer::create_global_quad_mesh: {}", err);
+            GlobalObjectCreateError::InvalidData
+        })?;
+        let index_mesh = self.quad_indices.get_index_mesh(&self.share, quad_count)?;
+
+        let data = MeshData {
+            vertex_data,
+            index_data: &[],
+            vertex_stride,
+            index_count: quad_count * 6,
+            index_type: vk::IndexType::UINT32,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST
+        };
+
+        GlobalMesh::new_with_shared_indices(self.share.clone(), &data, index_mesh)
+    }
+
+    /// Sets a soft limit in bytes for the device memory used by global meshes. Mesh creation which
+    /// would exceed the limit fails so that callers can throttle streaming. If [`None`] is passed
+    /// no limit is enforced.
+    pub fn set_memory_limit(&self, limit: Option<vk::DeviceSize>) {
+        self.share.get_memory_budget().set_limit(limit);
+    }
+
+    /// Returns the number of bytes of device memory currently used by global meshes.
+    pub fn get_tracked_memory_usage(&self) -> vk::DeviceSize {
+        self.share.get_memory_budget().get_used()
     }
 
     pub fn create_global_image(&self, size: Vec2u32, format: &'static Format) -> Arc<GlobalImage> {
@@ -100,8 +227,489 @@ impl EmulatorRenderer {
         GlobalImage::new(self.share.clone(), size, mip_levels, format).unwrap()
     }
 
+    /// Creates a global image which is filled with `clear_value` before any pass can use it. The
+    /// image can be sampled immediately without uploading any data. The clear value must match the
+    /// numeric type of the format.
+    pub fn create_global_image_cleared(&self, size: Vec2u32, mip_levels: u32, format: &'static Format, clear_value: vk::ClearColorValue) -> Arc<GlobalImage> {
+        GlobalImage::new_cleared(self.share.clone(), size, mip_levels, format, clear_value).unwrap()
+    }
+
+    /// Enables or disables validation of the resources referenced by passes. If enabled the worker
+    /// verifies before submission that every buffer and image view used by a pass is kept alive by
+    /// the pass and panics otherwise. Enabled by default in debug builds.
+    pub fn set_resource_validation(&self, enable: bool) {
+        self.share.set_resource_validation(enable);
+    }
+
+    /// Creates a global image which is kept alive by the renderer until it is removed with
+    /// [`EmulatorRenderer::drop_images_where`]. The tag can be used to select images to drop.
+    pub fn create_registered_image(&self, size: Vec2u32, format: &'static Format, tag: Option<String>) -> Arc<GlobalImage> {
+        let image = self.create_global_image(size, format);
+        let info = ImageInfo {
+            id: image.get_id(),
+            size,
+            format,
+            memory_properties: image.get_memory_properties(),
+            tag
+        };
+
+        self.image_registry.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned image registry mutex in EmulatorRenderer::create_registered_image");
+            panic!()
+        }).insert(info, image.clone());
+
+        image
+    }
+
+    /// Removes all registered images for which the predicate returns true and returns the number
+    /// of removed images. The images are freed once they are no longer referenced anywhere else
+    /// and all passes using them have completed.
+    pub fn drop_images_where<F: Fn(&ImageInfo) -> bool>(&self, predicate: F) -> usize {
+        let removed = self.image_registry.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned image registry mutex in EmulatorRenderer::drop_images_where");
+            panic!()
+        }).remove_where(predicate);
+
+        // Dropped outside of the lock since the last reference may destroy the image
+        removed.len()
+    }
+
+    /// Creates a cube map with 6 square faces of `size` texels. The image is sampled using a cube
+    /// view. Faces are uploaded and read back by their array layer, see [`CubeFace`].
+    pub fn create_global_cube_image(&self, size: u32, format: &'static Format) -> Arc<GlobalImage> {
+        GlobalImage::new_cube(self.share.clone(), size, format).unwrap()
+    }
+
+    /// Creates a image with a depth or depth stencil format which can be bound as a texture like any
+    /// other global image. Sampling returns the depth values. The image can be filled with the
+    /// depth attachment of a pass with [`EmulatorRenderer::start_pass_into_depth`].
+    pub fn create_global_depth_image(&self, size: Vec2u32, format: &'static Format) -> Arc<GlobalImage> {
+        GlobalImage::new_depth(self.share.clone(), size, format).unwrap()
+    }
+
+    /// Creates a 2D array image. Array images which can be used as color attachments can be
+    /// rendered to layer by layer with [`EmulatorRenderer::start_pass_into_layer`].
+    pub fn create_global_image_array(&self, size: Vec2u32, array_layers: u32, format: &'static Format) -> Arc<GlobalImage> {
+        GlobalImage::new_array(self.share.clone(), size, array_layers, format).unwrap()
+    }
+
+    /// Creates a image whose backing memory may be aliased with other transient images that are
+    /// not alive at the same time. The content of the image is undefined after creation.
+    pub fn create_transient_image(&self, size: Vec2u32, format: &'static Format) -> Result<TransientImage, GlobalObjectCreateError> {
+        self.transient_images.create_image(size, format)
+    }
+
+    /// Returns the index of the current frame. Global objects are tagged with the frame they were
+    /// created in.
+    pub fn current_frame(&self) -> u64 {
+        self.share.get_frame_counter().get_current_frame()
+    }
+
+    /// Marks a frame boundary and returns the index of the new frame. Should be called once per
+    /// frame.
+    pub fn advance_frame(&self) -> u64 {
+        self.share.get_frame_counter().advance()
+    }
+
+    /// Frees all transient image memory that has not been used since the last call. Should be called
+    /// once per frame.
+    pub fn reset_transient_images(&self) {
+        self.transient_images.reset()
+    }
+
+    /// Sets the minimum size in bytes of newly allocated immediate mesh buffers. Immediate meshes
+    /// larger than the block size are uploaded into a dedicated buffer. Defaults to 16MB.
+    pub fn set_immediate_buffer_block_size(&self, bytes: u64) {
+        self.share.set_immediate_block_size(bytes)
+    }
+
+    /// Makes passes write immediate meshes into a persistently mapped ring buffer of `bytes` bytes
+    /// instead of the immediate buffers. If [`None`] the immediate buffers are used, which is the
+    /// default.
+    ///
+    /// The ring never grows. Uploads block until the passes which used the space they overwrite
+    /// have completed, and all immediate meshes of a single pass must fit into the ring. Only
+    /// passes started after this call are affected.
+    pub fn set_immediate_ring_size(&self, bytes: Option<u64>) {
+        if bytes == Some(0) {
+            log::error!("Immediate ring size must not be 0");
+            panic!()
+        }
+        self.share.set_immediate_ring_size(bytes)
+    }
+
+    /// Sets how many empty staging buffers and how many bytes of staging memory are kept alive for
+    /// reuse once they are no longer used. Defaults to 2 buffers and 64MB. Setting either limit to
+    /// 0 destroys empty staging buffers immediately.
+    pub fn set_staging_pool(&self, max_buffers: usize, max_bytes: vk::DeviceSize) {
+        self.share.get_staging_pool().lock().unwrap_or_else(|_| {
+            log::error!("Poisoned staging memory mutex in EmulatorRenderer::set_staging_pool");
+            panic!()
+        }).set_reuse_limits(max_buffers, max_bytes);
+    }
+
+    /// Returns all recoverable errors of worker tasks which occurred since the last call. Failed
+    /// tasks are skipped by the worker.
+    pub fn take_errors(&self) -> Vec<EmulatorTaskError> {
+        self.share.take_errors()
+    }
+
+    /// Reports a recoverable error of a task recorded by a pipeline.
+    pub(super) fn push_task_error(&self, error: EmulatorTaskError) {
+        self.share.push_error(error);
+    }
+
+    /// Submits all pending global object operations which would otherwise wait for the next pass,
+    /// like uploads and readbacks. Returns a value which is reached once all passes and operations
+    /// submitted before this call have completed on the gpu. See
+    /// [`EmulatorRenderer::wait_for_value`] and [`EmulatorRenderer::is_value_reached`].
+    ///
+    /// Flushes use the same ids as passes. If a [`PassRecorder`] is alive
+    /// [`FlushError::PassRunning`] is returned and nothing is submitted. Deferred releases of
+    /// exported images are not covered.
+    pub fn flush(&self) -> Result<u64, FlushError> {
+        // The running pass would never free a slot
+        if self.share.get_current_pass_id().is_some() {
+            return Err(FlushError::PassRunning);
+        }
+        self.share.wait_for_pass_slot();
+        let id = self.share.try_start_pass_id().ok_or(FlushError::PassRunning)?;
+        self.share.push_task(WorkerTask::Flush(PassId::from_raw(id)));
+        self.share.end_pass_id();
+        Ok(id)
+    }
+
+    /// Blocks until the value returned by [`EmulatorRenderer::flush`] or the id of a pass has been
+    /// reached. Readbacks submitted before the value have completed once this function returns.
+    pub fn wait_for_value(&self, value: u64) {
+        self.share.wait_for_pass_completed(value);
+    }
+
+    /// Returns true if the value returned by [`EmulatorRenderer::flush`] or the id of a pass has
+    /// been reached.
+    pub fn is_value_reached(&self, value: u64) -> bool {
+        self.share.get_last_completed_pass() >= value
+    }
+
+    /// Waits for all submitted passes to complete and immediately releases the global objects
+    /// they used. Global meshes and images whose last reference was held by a completed pass are
+    /// freed before this function returns.
+    ///
+    /// Normally this happens lazily on the worker thread. This function can be used to reclaim
+    /// memory under memory pressure.
+    pub fn free_unused_now(&self) {
+        let (send, recv) = std::sync::mpsc::channel();
+        self.share.push_task(WorkerTask::FreeUnused(send));
+        recv.recv().unwrap_or_else(|_| {
+            log::error!("Worker dropped free unused signal in EmulatorRenderer::free_unused_now");
+            panic!()
+        });
+    }
+
+    /// Stops the worker thread and waits for it to exit. All passes submitted before this call are
+    /// waited on before the worker exits.
+    ///
+    /// Returns [`ShutdownError::Timeout`] if the worker did not exit within the timeout. In that
+    /// case the worker thread is detached. Returns [`ShutdownError::UnprocessedWork`] if the worker
+    /// had to discard operations which were never submitted. For example a pass which has been
+    /// started but not ended. Returns [`ShutdownError::WorkerPanicked`] if the worker did not exit
+    /// cleanly.
+    ///
+    /// Dropping the emulator without calling this function performs the same shutdown without a
+    /// timeout.
+    pub fn shutdown_and_wait(mut self, timeout: Duration) -> Result<(), ShutdownError> {
+        let discarded = self.stop_worker(Some(timeout))?;
+
+        if discarded == 0 {
+            Ok(())
+        } else {
+            Err(ShutdownError::UnprocessedWork(discarded))
+        }
+    }
+
+    /// Sends a shutdown task to the worker and joins it. If the worker does not respond within the
+    /// timeout it is detached. Returns the number of discarded operations. Does nothing if the
+    /// worker has already been stopped.
+    ///
+    /// If called on the worker thread itself, for example because the last reference to the
+    /// emulator was dropped by the worker, the worker is detached and exits once it processes the
+    /// shutdown task.
+    fn stop_worker(&mut self, timeout: Option<Duration>) -> Result<usize, ShutdownError> {
+        if let Some(worker) = self.worker.take() {
+            let share = &self.share;
+            shutdown_worker(worker, |send| share.push_task(WorkerTask::Shutdown(send)), timeout).map_err(|err| {
+                if err == ShutdownError::Timeout {
+                    log::warn!("Emulator worker did not shut down within {:?}", timeout);
+                }
+                err
+            })
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Returns the global bindless texture table. Returns [`None`] if the device does not support
+    /// descriptor indexing.
+    pub fn get_bindless_textures(&self) -> Option<&BindlessTextureTable> {
+        self.bindless_textures.as_ref()
+    }
+
+    /// Registers a texture in the global bindless texture table and returns the index which can
+    /// be used to access it in shaders.
+    ///
+    /// Returns [`None`] if the device does not support descriptor indexing or the table is full.
+    pub fn register_bindless_texture(&self, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo) -> Option<u32> {
+        self.bindless_textures.as_ref()?.register(image, sampler_info)
+    }
+
+    /// Removes a texture from the global bindless texture table.
+    pub fn unregister_bindless_texture(&self, index: u32) {
+        if let Some(table) = &self.bindless_textures {
+            table.unregister(index);
+        } else {
+            log::error!("Called EmulatorRenderer::unregister_bindless_texture but descriptor indexing is not supported");
+            panic!()
+        }
+    }
+
+    /// Returns true if all passes which used the image have completed on the gpu.
+    ///
+    /// Only the use in passes is considered. Pending uploads or readbacks of the image are not
+    /// tracked by this function.
+    pub fn is_resource_idle(&self, image: &GlobalImage) -> bool {
+        image.last_use_value() <= self.share.get_last_completed_pass()
+    }
+
+    /// Enables or disables writing gpu crash breadcrumbs before each emulator task. Returns false
+    /// if the device does not support VK_NV_device_diagnostic_checkpoints in which case
+    /// breadcrumbs stay disabled.
+    ///
+    /// Breadcrumbs add a command per task and should only be enabled while debugging.
+    pub fn set_breadcrumbs_enabled(&self, enable: bool) -> bool {
+        if enable && !self.share.get_device().supports_diagnostic_checkpoints() {
+            log::warn!("Called EmulatorRenderer::set_breadcrumbs_enabled but the device does not support VK_NV_device_diagnostic_checkpoints");
+            return false;
+        }
+        self.share.get_breadcrumbs().set_enabled(enable);
+        true
+    }
+
+    /// Returns the last breadcrumbs reached by the gpu on the main queue. Intended to be called
+    /// after a device lost error to determine which task caused the crash.
+    ///
+    /// Returns an empty list if breadcrumbs are disabled or unsupported.
+    pub fn last_checkpoints(&self) -> Vec<Checkpoint> {
+        if !self.share.get_breadcrumbs().is_enabled() {
+            return Vec::new();
+        }
+        let data = self.share.get_device().get_main_queue().get_checkpoint_data();
+        self.share.get_breadcrumbs().resolve(&data)
+    }
+
+    /// Sets a override for the filtering parameters of all samplers used by the emulator. If
+    /// [`None`] the sampler info passed when binding a texture is used unchanged.
+    ///
+    /// Only textures bound after this call are affected. Samplers for the new parameters are
+    /// created on demand, previously created samplers stay alive until their images are dropped.
+    pub fn set_global_filter_override(&self, filter_override: Option<FilterOverride>) {
+        self.share.get_sampler_cache().set_filter_override(filter_override);
+    }
+
+    /// Hands a global image to external code. After all passes which previously used the image
+    /// the image is transitioned into `state`. External work must wait on the emulator before
+    /// accessing the image.
+    ///
+    /// While exported the image must not be used by the emulator. Uploads, readbacks, blits, pass
+    /// outputs and texture bindings of the image panic until
+    /// [`EmulatorRenderer::reacquire_global_image`] is called once external code is done with the
+    /// image.
+    pub fn export_global_image(&self, image: &Arc<GlobalImage>, state: ExternalImageState) {
+        if let Some(old) = image.set_external_state(Some(state)) {
+            log::error!("Called EmulatorRenderer::export_global_image with image {:?} which is already exported in state {:?}", image.get_id(), old);
+            panic!()
+        }
+
+        self.share.push_task(WorkerTask::ExportGlobalImage(GlobalImageExport {
+            after_pass: PassId::from_raw(image.last_use_value()),
+            image: image.clone(),
+            state,
+        }));
+    }
+
+    /// Returns a exported global image to the emulator. The image must still be in the state it
+    /// was exported in.
+    pub fn reacquire_global_image(&self, image: &Arc<GlobalImage>) {
+        let state = image.set_external_state(None).unwrap_or_else(|| {
+            log::error!("Called EmulatorRenderer::reacquire_global_image with image {:?} which is not exported", image.get_id());
+            panic!()
+        });
+
+        self.share.push_task(WorkerTask::ReacquireGlobalImage(GlobalImageExport {
+            after_pass: PassId::from_raw(image.last_use_value()),
+            image: image.clone(),
+            state,
+        }));
+    }
+
+    /// Destroys a set of exported global images once `wait` has been signaled by external code.
+    /// The images are kept alive until then even if all other references are dropped, so a
+    /// consumer such as a compositor may still read them.
+    ///
+    /// The images must currently be exported and must not be reacquired afterwards. `wait` must be
+    /// a timeline semaphore operation.
+    pub fn destroy_exported_images_after(&self, images: Box<[Arc<GlobalImage>]>, wait: SemaphoreOp) {
+        for image in images.iter() {
+            if image.get_external_state().is_none() {
+                log::error!("Called EmulatorRenderer::destroy_exported_images_after with image {:?} which is not exported", image.get_id());
+                panic!()
+            }
+        }
+        let value = wait.value.unwrap_or_else(|| {
+            log::error!("Called EmulatorRenderer::destroy_exported_images_after with a binary semaphore");
+            panic!()
+        });
+
+        self.share.push_task(WorkerTask::ReleaseExportedImages(DeferredRelease {
+            semaphore: wait.semaphore.get_handle(),
+            value,
+            objects: images,
+        }));
+    }
+
+    /// Reads back regions of multiple images with a single token. Each read is a image, its read
+    /// regions and the size of the zeroed destination buffer. All reads are executed after the
+    /// last pass which used any of the images and are recorded into the same submission.
+    ///
+    /// The reads are submitted with the next pass or [`EmulatorRenderer::flush`]. Waiting on the
+    /// token before either happens never returns. Use [`EmulatorRenderer::read_images_and_wait`]
+    /// to flush and wait in one call.
+    pub fn read_images(&self, reads: &[(&Arc<GlobalImage>, &[ImageReadRegion], usize)]) -> ImageBatchReadToken {
+        let after_pass = reads.iter().map(|(image, _, _)| image.last_use_value()).max().unwrap_or(0);
+        let after_pass = PassId::from_raw(after_pass);
+
+        let mut tokens = Vec::with_capacity(reads.len());
+        let mut tasks = Vec::with_capacity(reads.len());
+        for (image, regions, size) in reads {
+            image.assert_not_exported("EmulatorRenderer::read_images");
+            let (token, task) = image.prepare_read(regions, None, *size, after_pass);
+            tokens.push(token);
+            tasks.push(task);
+        }
+        self.share.push_tasks(tasks);
+
+        ImageBatchReadToken::new(tokens.into_boxed_slice())
+    }
+
+    /// Like [`EmulatorRenderer::read_images`] but flushes the reads and blocks until they have
+    /// completed. Like [`EmulatorRenderer::flush`] this returns [`FlushError::PassRunning`] if a
+    /// [`PassRecorder`] is alive. The reads then complete after the running pass.
+    pub fn read_images_and_wait(&self, reads: &[(&Arc<GlobalImage>, &[ImageReadRegion], usize)]) -> Result<Box<[Box<[u8]>]>, FlushError> {
+        let token = self.read_images(reads);
+        self.flush()?;
+        Ok(token.wait())
+    }
+
+    /// Enables or disables sequential ids for global meshes, global images and shaders created by
+    /// this emulator. With sequential ids the same sequence of object creations always results in
+    /// the same ids which makes captures and logs comparable between runs. Random ids are used by
+    /// default.
+    ///
+    /// Should be enabled before any objects are created. Ids of objects created in random mode are
+    /// not guaranteed to differ from sequential ids.
+    pub fn set_sequential_ids(&self, sequential: bool) {
+        self.share.set_sequential_ids(sequential);
+    }
+
+    /// Starts capturing all global object writes submitted to this emulator, including their data.
+    /// The capture is retrieved by calling [`EmulatorRenderer::end_capture`].
+    ///
+    /// Panics if a capture is already active.
+    pub fn begin_capture(&self) {
+        if !self.share.begin_capture() {
+            log::error!("Called EmulatorRenderer::begin_capture while a capture is already active");
+            panic!()
+        }
+    }
+
+    /// Stops the active capture and returns the captured tasks in submission order.
+    ///
+    /// Panics if no capture is active.
+    pub fn end_capture(&self) -> FrameCapture {
+        let tasks = self.share.end_capture().unwrap_or_else(|| {
+            log::error!("Called EmulatorRenderer::end_capture without an active capture");
+            panic!()
+        });
+        FrameCapture::new(tasks)
+    }
+
+    /// Resubmits all tasks of a capture in order. The global objects referenced by the capture are
+    /// looked up by id in `meshes` and `images`. If any referenced object is missing an error is
+    /// returned and no task is submitted.
+    pub fn replay(&self, capture: &FrameCapture, meshes: &[Arc<GlobalMesh>], images: &[Arc<GlobalImage>]) -> Result<(), ReplayError> {
+        let meshes: HashMap<_, _> = meshes.iter().map(|mesh| (mesh.get_id(), mesh)).collect();
+        let images: HashMap<_, _> = images.iter().map(|image| (image.get_id(), image)).collect();
+
+        if let Some(id) = capture.get_used_meshes().into_iter().find(|id| !meshes.contains_key(id)) {
+            return Err(ReplayError::MissingMesh(id));
+        }
+        if let Some(id) = capture.get_used_images().into_iter().find(|id| !images.contains_key(id)) {
+            return Err(ReplayError::MissingImage(id));
+        }
+
+        capture.replay_into(&mut ReplayResources { meshes, images });
+        Ok(())
+    }
+
+    /// Draws the content of `src` into `dst` using a fullscreen triangle. The source is scaled to
+    /// the size of the destination using the sampler described by `sampler_info`, optionally
+    /// applying tonemapping and gamma correction. Only the first mip level of the destination is
+    /// written. The destination must have a float color format since the blit writes float colors.
+    ///
+    /// The blit is executed after all passes which previously used either of the images.
+    pub fn blit_fullscreen(&self, src: &Arc<GlobalImage>, dst: &Arc<GlobalImage>, sampler_info: &SamplerInfo, options: BlitOptions) {
+        if !dst.is_blit_dst() {
+            log::error!("Called EmulatorRenderer::blit_fullscreen with destination image {:?} which cannot be used as a color attachment", dst.get_id());
+            panic!()
+        }
+        if !blit::is_blit_dst_format(dst.get_format()) {
+            log::error!("Called EmulatorRenderer::blit_fullscreen with destination image {:?} of non float format {:?}", dst.get_id(), dst.get_format().get_format());
+            panic!()
+        }
+        if src.get_array_layer_count() != 1 {
+            log::error!("Called EmulatorRenderer::blit_fullscreen with source image {:?} which has {:?} array layers", src.get_id(), src.get_array_layer_count());
+            panic!()
+        }
+        if Arc::ptr_eq(src, dst) {
+            log::error!("Called EmulatorRenderer::blit_fullscreen with the same source and destination image {:?}", src.get_id());
+            panic!()
+        }
+        src.assert_not_exported("EmulatorRenderer::blit_fullscreen");
+        dst.assert_not_exported("EmulatorRenderer::blit_fullscreen");
+
+        let after_pass = std::cmp::max(src.last_use_value(), dst.last_use_value());
+        self.share.push_task(WorkerTask::BlitGlobalImage(GlobalImageBlit {
+            after_pass: PassId::from_raw(after_pass),
+            src_image: src.clone(),
+            dst_image: dst.clone(),
+            sampler: src.get_sampler(sampler_info),
+            options,
+        }));
+    }
+
+    /// Creates a new shader. Panics if the vertex format is not supported by the device, see
+    /// [`EmulatorRenderer::try_create_shader`].
     pub fn create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> ShaderId {
-        self.share.create_shader(vertex_format, used_uniforms)
+        self.try_create_shader(vertex_format, used_uniforms).unwrap_or_else(|err| {
+            log::error!("Failed to create shader for vertex format {:?}: {:?}", vertex_format, err);
+            panic!()
+        })
+    }
+
+    /// Creates a new shader if the instance divisor of `vertex_format` is supported by the device.
+    pub fn try_create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> Result<ShaderId, VertexFormatError> {
+        let device = self.share.get_device();
+        vertex_format.validate_instance_divisor(device.supports_vertex_attribute_divisor(), device.supports_vertex_attribute_zero_divisor())?;
+        Ok(self.share.create_shader(vertex_format, used_uniforms))
     }
 
     pub fn drop_shader(&self, id: ShaderId) {
@@ -112,10 +720,97 @@ impl EmulatorRenderer {
         self.share.get_shader(id)
     }
 
+    /// Limits the number of passes which may be in flight at the same time. If the limit is
+    /// reached starting a new pass blocks until a previous pass has completed on the gpu. [`None`]
+    /// removes the limit which is the default.
+    pub fn set_max_concurrent_passes(&self, max: Option<u64>) {
+        self.share.set_max_concurrent_passes(max);
+    }
+
+    /// Returns the number of passes which have been started but not yet completed on the gpu.
+    pub fn get_in_flight_pass_count(&self) -> u64 {
+        self.share.get_in_flight_pass_count()
+    }
+
     pub fn start_pass(&self, pipeline: Arc<dyn EmulatorPipeline>) -> PassRecorder {
         PassRecorder::new(self.share.clone(), pipeline, self.placeholder_image.clone(), &self.placeholder_sampler)
     }
 
+    /// Replaces the pipeline used by [`EmulatorRenderer::start_default_pass`] and returns the
+    /// previous one. The swap is atomic and only affects passes started after this call. Passes
+    /// which are currently recording keep using the pipeline they were started with until they are
+    /// dropped.
+    pub fn set_default_pipeline(&self, pipeline: Arc<dyn EmulatorPipeline>) -> Option<Arc<dyn EmulatorPipeline>> {
+        self.default_pipeline.set(pipeline)
+    }
+
+    pub fn get_default_pipeline(&self) -> Option<Arc<dyn EmulatorPipeline>> {
+        self.default_pipeline.get()
+    }
+
+    /// Starts a pass using the pipeline set with [`EmulatorRenderer::set_default_pipeline`] at the
+    /// time of this call. Panics if no default pipeline has been set.
+    pub fn start_default_pass(&self) -> PassRecorder {
+        let pipeline = self.default_pipeline.get().unwrap_or_else(|| {
+            log::error!("Called EmulatorRenderer::start_default_pass without a default pipeline");
+            panic!()
+        });
+        self.start_pass(pipeline)
+    }
+
+    /// Creates a output which writes the result of passes using `pipeline` into array layers of
+    /// `image`. The image must be usable as a blit destination.
+    pub fn create_layer_output(&self, image: Arc<GlobalImage>, pipeline: Arc<dyn EmulatorPipeline>) -> Arc<ImageLayerOutput> {
+        ImageLayerOutput::new(self.share.get_device().clone(), pipeline, image)
+    }
+
+    /// Starts a pass using the pipeline of `output` whose result is written into a single array
+    /// layer of the output image. The other layers of the image are not modified.
+    pub fn start_pass_into_layer(&self, output: &Arc<ImageLayerOutput>, array_layer: u32) -> PassRecorder {
+        output.get_image().assert_not_exported("EmulatorRenderer::start_pass_into_layer");
+        let mut recorder = self.start_pass(output.get_pipeline().clone());
+        recorder.use_output(output.for_layer(array_layer));
+        output.get_image().update_used_in(recorder.get_id());
+        recorder
+    }
+
+    /// Creates a output which copies the depth attachment of passes using `pipeline` into `image`.
+    /// The image must be a depth image with the same format and size as the depth attachment.
+    pub fn create_depth_output(&self, image: Arc<GlobalImage>, pipeline: Arc<dyn EmulatorPipeline>) -> Arc<DepthImageOutput> {
+        DepthImageOutput::new(self.share.get_device().clone(), pipeline, image)
+    }
+
+    /// Starts a pass using the pipeline of `output` whose depth attachment is copied into the
+    /// output image at the end of the pass. Later passes can sample the depth by binding the image
+    /// with [`PassRecorder::update_texture`]. The pass must not sample the output image itself.
+    pub fn start_pass_into_depth(&self, output: &Arc<DepthImageOutput>) -> PassRecorder {
+        output.get_image().assert_not_exported("EmulatorRenderer::start_pass_into_depth");
+        let mut recorder = self.start_pass(output.get_pipeline().clone());
+        recorder.use_output(output.make_output());
+        output.get_image().update_used_in(recorder.get_id());
+        recorder
+    }
+
+    /// Creates a output which copies the additional color attachment `attachment` of passes using
+    /// `pipeline` into `image`. The image must have the same format and size as the attachment.
+    /// The output is added to a pass with [`PassRecorder::copy_attachment_into`].
+    pub fn create_attachment_output(&self, image: Arc<GlobalImage>, pipeline: Arc<dyn EmulatorPipeline>, attachment: usize) -> Arc<ColorAttachmentOutput> {
+        ColorAttachmentOutput::new(self.share.get_device().clone(), pipeline, attachment, image)
+    }
+
+    /// Reads a single texel of a `R32_UINT` or `R32_SINT` image and blocks until the result is
+    /// available. See [`GlobalImage::read_pixel_u32`]. The readback is flushed so this function
+    /// does not depend on a later pass being submitted. Integer attachments of a pipeline can be
+    /// copied into a image with [`EmulatorRenderer::create_attachment_output`] first.
+    ///
+    /// Like [`EmulatorRenderer::flush`] this returns [`FlushError::PassRunning`] if a
+    /// [`PassRecorder`] is alive.
+    pub fn read_pixel_u32(&self, image: &GlobalImage, position: Vec2u32) -> Result<u32, FlushError> {
+        let token = image.read_pixel_u32(position);
+        self.flush()?;
+        Ok(token.wait_u32())
+    }
+
     fn create_placeholder_image(share: Arc<Share>) -> Arc<GlobalImage> {
         let size = Vec2u32::new(256, 256);
 
@@ -134,7 +829,8 @@ impl EmulatorRenderer {
             data: bytes,
             row_stride: 0,
             offset: Vec2u32::new(0, 0),
-            extent: size
+            extent: size,
+            layer: 0
         };
 
         let image = GlobalImage::new(share, size, 1, &Format::R8G8B8A8_SRGB).unwrap();
@@ -143,6 +839,40 @@ impl EmulatorRenderer {
     }
 }
 
+/// Resolves the ids of a replayed [`FrameCapture`] to global objects.
+struct ReplayResources<'a> {
+    meshes: HashMap<GlobalMeshId, &'a Arc<GlobalMesh>>,
+    images: HashMap<GlobalImageId, &'a Arc<GlobalImage>>,
+}
+
+impl<'a> ReplayTarget for ReplayResources<'a> {
+    fn write_mesh(&mut self, mesh: GlobalMeshId, updates: &[(vk::DeviceSize, &[u8])]) {
+        self.meshes[&mesh].update_scattered(updates);
+    }
+
+    fn write_image(&mut self, image: GlobalImageId, regions: &[ImageData]) {
+        self.images[&image].update_regions(regions);
+    }
+}
+
+/// Describes why [`EmulatorRenderer::shutdown_and_wait`] did not complete cleanly.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ShutdownError {
+    /// The worker did not exit within the timeout.
+    Timeout,
+    /// The worker exited but discarded the given number of unsubmitted operations.
+    UnprocessedWork(usize),
+    /// The worker panicked or exited without acknowledging the shutdown.
+    WorkerPanicked,
+}
+
+/// Describes why [`EmulatorRenderer::flush`] could not submit pending operations.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FlushError {
+    /// A [`PassRecorder`] is alive. Pending operations are submitted with the next pass or flush.
+    PassRunning,
+}
+
 impl PartialEq for EmulatorRenderer {
     fn eq(&self, other: &Self) -> bool {
         self.share.eq(&other.share)
@@ -155,6 +885,90 @@ impl Eq for EmulatorRenderer {
 impl RefUnwindSafe for EmulatorRenderer { // Join handle is making issues
 }
 
+assert_impl_all!(EmulatorRenderer: Send, Sync);
+
+impl Drop for EmulatorRenderer {
+    fn drop(&mut self) {
+        // The worker keeps the share alive so it must be stopped before the device can be destroyed
+        if let Err(ShutdownError::WorkerPanicked) = self.stop_worker(None) {
+            log::error!("Emulator worker panicked before the emulator was dropped");
+        }
+    }
+}
+
+/// Sets the priority of the worker thread using `set`. Failures are logged and false is returned.
+fn apply_worker_priority<F: FnOnce(i32) -> std::io::Result<()>>(priority: i32, set: F) -> bool {
+    match set(priority) {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!("Failed to set emulator worker thread priority to {:?}: {:?}", priority, err);
+            false
+        }
+    }
+}
+
+/// Sets the nice value of the calling thread.
+#[cfg(target_os = "linux")]
+fn set_current_thread_priority(priority: i32) -> std::io::Result<()> {
+    extern "C" {
+        fn setpriority(which: std::os::raw::c_int, who: std::os::raw::c_uint, prio: std::os::raw::c_int) -> std::os::raw::c_int;
+    }
+
+    // PRIO_PROCESS with a id of 0 only applies to the calling thread on linux
+    if unsafe { setpriority(0, 0, priority) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_priority(_: i32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Thread priorities are not supported on this platform"))
+}
+
+/// Requests the worker to shut down using `request` and joins it. If called on the worker thread
+/// itself the worker cannot be joined so it is detached instead and [`Ok`] with 0 discarded
+/// operations is returned.
+fn shutdown_worker<F: FnOnce(std::sync::mpsc::Sender<usize>)>(worker: std::thread::JoinHandle<()>, request: F, timeout: Option<Duration>) -> Result<usize, ShutdownError> {
+    let (send, recv) = std::sync::mpsc::channel();
+    request(send);
+
+    if worker.thread().id() == std::thread::current().id() {
+        // The worker exits once it reaches the shutdown task. Dropping the handle detaches it.
+        log::debug!("Emulator stopped from its own worker thread. Detaching worker");
+        drop(worker);
+        return Ok(0);
+    }
+
+    join_worker(worker, recv, timeout)
+}
+
+/// Waits for the worker to acknowledge a shutdown task and joins the thread. If `timeout` is
+/// [`None`] this blocks until the worker exits. On timeout the worker thread is detached.
+fn join_worker(worker: std::thread::JoinHandle<()>, signal: std::sync::mpsc::Receiver<usize>, timeout: Option<Duration>) -> Result<usize, ShutdownError> {
+    let discarded = match timeout {
+        Some(timeout) => signal.recv_timeout(timeout).map_err(|err| match err {
+            std::sync::mpsc::RecvTimeoutError::Timeout => ShutdownError::Timeout,
+            std::sync::mpsc::RecvTimeoutError::Disconnected => {
+                log::error!("Emulator worker dropped the shutdown signal");
+                ShutdownError::WorkerPanicked
+            }
+        })?,
+        None => signal.recv().map_err(|_| {
+            log::error!("Emulator worker dropped the shutdown signal");
+            ShutdownError::WorkerPanicked
+        })?,
+    };
+
+    worker.join().map_err(|_| {
+        log::error!("Emulator worker panicked during shutdown");
+        ShutdownError::WorkerPanicked
+    })?;
+
+    Ok(discarded)
+}
+
 pub struct MeshData<'a> {
     pub vertex_data: &'a [u8],
     pub index_data: &'a [u8],
@@ -190,3 +1004,229 @@ impl<'a> Debug for MeshData<'a> {
             .finish()
     }
 }
+
+/// The pipeline used by [`EmulatorRenderer::start_default_pass`]. Every pass takes its own
+/// reference so replacing the pipeline never affects passes which have already been started.
+struct DefaultPipeline(Mutex<Option<Arc<dyn EmulatorPipeline>>>);
+
+impl DefaultPipeline {
+    fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    fn set(&self, pipeline: Arc<dyn EmulatorPipeline>) -> Option<Arc<dyn EmulatorPipeline>> {
+        self.lock().replace(pipeline)
+    }
+
+    fn get(&self) -> Option<Arc<dyn EmulatorPipeline>> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<Option<Arc<dyn EmulatorPipeline>>> {
+        self.0.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned default pipeline mutex in EmulatorRenderer");
+            panic!()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use bumpalo::Bump;
+    use super::*;
+    use crate::device::device::Queue;
+    use crate::renderer::emulator::pipeline::{EmulatorPipelinePass, PipelineTask, PooledObjectProvider, SubmitRecorder};
+
+    struct TestPipeline;
+
+    /// A pass which ignores all tasks and records no submits.
+    struct TestPipelinePass;
+
+    impl EmulatorPipeline for TestPipeline {
+        fn start_pass(&self) -> Box<dyn EmulatorPipelinePass + Send> {
+            Box::new(TestPipelinePass)
+        }
+
+        fn get_output(&self) -> (Vec2u32, &[vk::ImageView]) {
+            (Vec2u32::new(1, 1), &[])
+        }
+
+        fn inc_shader_used(&self, _: ShaderId) {
+        }
+
+        fn dec_shader_used(&self, _: ShaderId) {
+        }
+    }
+
+    impl EmulatorPipelinePass for TestPipelinePass {
+        fn init(&mut self, _: &Queue, _: &mut PooledObjectProvider, _: vk::ImageView, _: vk::Sampler) {
+        }
+
+        fn process_task(&mut self, _: &PipelineTask, _: &mut PooledObjectProvider) {
+        }
+
+        fn record<'a>(&mut self, _: &mut PooledObjectProvider, _: &mut SubmitRecorder<'a>, _: &'a Bump) {
+        }
+
+        fn get_output_index(&self) -> usize {
+            0
+        }
+
+        fn get_internal_fences(&self, _: &mut Vec<vk::Fence>) {
+        }
+    }
+
+    /// Compares the data pointers only since vtable pointers are not guaranteed to be unique.
+    fn is_same_pipeline(a: &Arc<dyn EmulatorPipeline>, b: &Arc<dyn EmulatorPipeline>) -> bool {
+        std::ptr::eq(Arc::as_ptr(a) as *const (), Arc::as_ptr(b) as *const ())
+    }
+
+    #[test]
+    fn default_pipeline_swap_affects_next_pass() {
+        let a: Arc<dyn EmulatorPipeline> = Arc::new(TestPipeline);
+        let b: Arc<dyn EmulatorPipeline> = Arc::new(TestPipeline);
+
+        let default = DefaultPipeline::new();
+        assert!(default.get().is_none());
+        assert!(default.set(a.clone()).is_none());
+
+        // Stands in for the pipeline held by a recording pass
+        let first_pass = default.get().unwrap();
+        let previous = default.set(b.clone()).unwrap();
+        assert!(is_same_pipeline(&previous, &a));
+        assert!(is_same_pipeline(&first_pass, &a));
+        drop(first_pass);
+
+        let second_pass = default.get().unwrap();
+        assert!(is_same_pipeline(&second_pass, &b));
+    }
+
+    #[test]
+    fn worker_terminates_on_shutdown() {
+        let (task_send, task_recv) = std::sync::mpsc::channel::<std::sync::mpsc::Sender<usize>>();
+        let exited = Arc::new(AtomicBool::new(false));
+
+        // Stands in for the emulator worker loop
+        let worker = {
+            let exited = exited.clone();
+            std::thread::spawn(move || {
+                let signal = task_recv.recv().unwrap();
+                exited.store(true, Ordering::Release);
+                signal.send(0).unwrap();
+            })
+        };
+
+        let (send, recv) = std::sync::mpsc::channel();
+        task_send.send(send).unwrap();
+        assert_eq!(join_worker(worker, recv, None), Ok(0));
+        assert!(exited.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn unresponsive_worker_times_out() {
+        let (send, recv) = std::sync::mpsc::channel::<usize>();
+        let worker = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(500));
+            drop(send);
+        });
+
+        assert_eq!(join_worker(worker, recv, Some(Duration::from_millis(10))), Err(ShutdownError::Timeout));
+    }
+
+    #[test]
+    fn shutdown_from_worker_thread_detaches() {
+        let (handle_send, handle_recv) = std::sync::mpsc::channel::<std::thread::JoinHandle<()>>();
+        let (result_send, result_recv) = std::sync::mpsc::channel();
+
+        // Stands in for the worker dropping the last reference to the emulator
+        let worker = std::thread::spawn(move || {
+            let own_handle = handle_recv.recv().unwrap();
+            let result = shutdown_worker(own_handle, drop, None);
+            result_send.send(result).unwrap();
+        });
+        handle_send.send(worker).unwrap();
+
+        assert_eq!(result_recv.recv_timeout(Duration::from_secs(5)), Ok(Ok(0)));
+    }
+
+    #[test]
+    fn sequential_ids_repeat_between_runs() {
+        let run = || {
+            let (_, device) = crate::vk::test::make_headless_instance_device();
+            let emulator = EmulatorRenderer::new(device);
+            emulator.set_sequential_ids(true);
+
+            emulator.begin_capture();
+            let shader = emulator.create_shader(&VertexFormat::empty(), McUniform::empty());
+            let mesh = emulator.create_global_quad_mesh(&[0u8; 4 * 16], 16).unwrap();
+            let image = emulator.create_global_image(Vec2u32::new(1, 1), &Format::R8G8B8A8_UNORM);
+            image.update_regions(&[ImageData::new_full(&[0u8; 4], Vec2u32::new(1, 1))]);
+            let capture = emulator.end_capture();
+
+            emulator.drop_shader(shader);
+            (shader, mesh.get_id(), image.get_id(), capture)
+        };
+
+        let first = run();
+        assert!(first.3.get_used_images().contains(&first.2));
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn invalid_quad_mesh_rejected() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+
+        assert_eq!(emulator.create_global_quad_mesh(&[], 16).err(), Some(GlobalObjectCreateError::InvalidData));
+        assert_eq!(emulator.create_global_quad_mesh(&[0u8; 4 * 16], 0).err(), Some(GlobalObjectCreateError::InvalidData));
+        assert_eq!(emulator.create_global_quad_mesh(&[0u8; 3 * 16], 16).err(), Some(GlobalObjectCreateError::InvalidData));
+        assert_eq!(emulator.get_tracked_memory_usage(), 0);
+    }
+
+    #[test]
+    fn transient_images_created() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+
+        let first = emulator.create_transient_image(Vec2u32::new(64, 64), &Format::R8G8B8A8_UNORM).unwrap();
+        let second = emulator.create_transient_image(Vec2u32::new(64, 64), &Format::R8G8B8A8_UNORM).unwrap();
+        assert_ne!(first.get_image_handle(), vk::Image::null());
+        assert_ne!(first.get_image_handle(), second.get_image_handle());
+        assert_eq!(second.get_size(), Vec2u32::new(64, 64));
+        assert_eq!(second.get_format(), &Format::R8G8B8A8_UNORM);
+        drop(first);
+        drop(second);
+
+        emulator.reset_transient_images();
+        emulator.reset_transient_images();
+    }
+
+    #[test]
+    fn worker_thread_named() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+        assert_eq!(emulator.get_worker_thread().name(), Some(EmulatorRenderer::WORKER_THREAD_NAME));
+    }
+
+    #[test]
+    fn worker_priority_failure_reported() {
+        assert!(apply_worker_priority(5, |_| Ok(())));
+        assert!(!apply_worker_priority(5, |_| Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied"))));
+
+        // Lowering the priority of a thread is always permitted
+        #[cfg(target_os = "linux")]
+        assert!(std::thread::spawn(|| set_current_thread_priority(19)).join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn panicked_worker_does_not_panic_join() {
+        let (send, recv) = std::sync::mpsc::channel::<usize>();
+        let worker = std::thread::spawn(move || {
+            let _send = send;
+            panic!("worker panic");
+        });
+
+        assert_eq!(join_worker(worker, recv, None), Err(ShutdownError::WorkerPanicked));
+    }
+}
@@ -1,15 +1,20 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::sync::Arc;
 
 use ash::vk;
 
-use crate::renderer::emulator::immediate::ImmediateBuffer;
+use crate::prelude::*;
+use crate::objects::sync::SemaphoreOp;
+
+use crate::renderer::emulator::immediate::{ImmediateBuffer, ImmediateRing};
 use crate::renderer::emulator::{GlobalImage, GlobalMesh, MeshData};
 use crate::renderer::emulator::global_objects::{GlobalImageId, SamplerInfo};
 use crate::renderer::emulator::worker::WorkerTask;
 
-use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
-use crate::renderer::emulator::pipeline::{DrawTask, EmulatorOutput, EmulatorPipeline, PipelineTask};
+use crate::renderer::emulator::mc_shaders::{McUniformData, McUniformTracker, ShaderId};
+use crate::renderer::emulator::pipeline::{AttachmentOps, ColorAttachmentOutput, DrawTask, EmulatorOutput, EmulatorPipeline, PassAttachment, PipelineTask, ProceduralDrawTask};
+use crate::renderer::emulator::quads::get_quad_count;
 use crate::renderer::emulator::share::Share;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -43,17 +48,23 @@ pub struct PassRecorder {
     share: Arc<Share>,
 
     used_shaders: HashSet<ShaderId>,
-    used_global_image: HashSet<GlobalImageId>,
+    /// Strong references to all images bound in this pass. Keeps the descriptors valid even if
+    /// the caller drops its reference before the pass ends.
+    used_global_image: BoundResources<GlobalImageId, GlobalImage>,
     immediate_meshes: Vec<ImmediateMeshInfo>,
+    draw_recorded: bool,
 
     immediate_buffer: Option<Box<ImmediateBuffer>>,
+    /// If present immediate meshes are written into this ring instead of the immediate buffer.
+    immediate_ring: Option<Arc<ImmediateRing>>,
 
-    #[allow(unused)] // We just need to keep the pipeline alive
     pipeline: Arc<dyn EmulatorPipeline>,
 }
+assert_impl_all!(PassRecorder: Send);
 
 impl PassRecorder {
     pub(super) fn new(share: Arc<Share>, pipeline: Arc<dyn EmulatorPipeline>, placeholder_image: Arc<GlobalImage>, placeholder_sampler: &SamplerInfo) -> Self {
+        share.wait_for_pass_slot();
         let id = share.try_start_pass_id().unwrap_or_else(|| {
             log::error!("Attempted to start pass with an already running pass!");
             panic!();
@@ -61,6 +72,7 @@ impl PassRecorder {
         let id = PassId::from_raw(id);
 
         let immediate_buffer = Some(share.get_next_immediate_buffer());
+        let immediate_ring = share.get_immediate_ring();
 
         let placeholder_sampler = placeholder_image.get_sampler(placeholder_sampler);
         share.push_task(WorkerTask::StartPass(id, pipeline.clone(), pipeline.start_pass(), placeholder_image, placeholder_sampler));
@@ -70,42 +82,208 @@ impl PassRecorder {
             share,
 
             used_shaders: HashSet::new(),
-            used_global_image: HashSet::new(),
+            used_global_image: BoundResources::new(),
             immediate_meshes: Vec::with_capacity(128),
+            draw_recorded: false,
 
             immediate_buffer,
+            immediate_ring,
 
             pipeline,
         }
     }
 
+    pub fn get_id(&self) -> PassId {
+        self.id
+    }
+
     pub fn use_output(&mut self, output: Box<dyn EmulatorOutput + Send>) {
         self.share.push_task(WorkerTask::UseOutput(output));
     }
 
+    /// Copies an additional color attachment of this pass into the image of `output` at the end
+    /// of the pass. The output must have been created for the pipeline of this pass.
+    pub fn copy_attachment_into(&mut self, output: &Arc<ColorAttachmentOutput>) {
+        output.get_image().assert_not_exported("PassRecorder::copy_attachment_into");
+        self.use_output(output.make_output());
+        output.get_image().update_used_in(self.id);
+    }
+
+    /// Makes all commands of this pass at `stage_mask` wait on a semaphore. This can be used to
+    /// consume data uploaded on another queue, for example a async transfer queue.
+    ///
+    /// Buffers and images written on a queue of a different family must be released to the main
+    /// queue family by the other queue before the semaphore is signaled.
+    pub fn wait_semaphore(&mut self, op: SemaphoreOp, stage_mask: vk::PipelineStageFlags2) {
+        self.share.push_task(WorkerTask::WaitSemaphore(op, stage_mask));
+    }
+
     pub fn update_uniform(&mut self, data: &McUniformData, shader: ShaderId) {
         self.use_shader(shader);
         self.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, *data)))
     }
 
+    /// Updates only the uniforms of the tracker which have been modified since the last flush
+    /// and clears its dirty state.
+    pub fn update_dirty_uniforms(&mut self, tracker: &mut McUniformTracker, shader: ShaderId) {
+        for data in tracker.take_dirty_data() {
+            self.update_uniform(&data, shader);
+        }
+    }
+
     pub fn update_texture(&mut self, index: u32, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo, shader: ShaderId) {
+        image.assert_not_exported("PassRecorder::update_texture");
         self.use_shader(shader);
         let view = image.get_sampler_view();
         let sampler = image.get_sampler(sampler_info);
 
-        if self.used_global_image.insert(image.get_id()) {
+        if self.used_global_image.bind(image.get_id(), image) {
             self.share.push_task(WorkerTask::UseGlobalImage(image.clone()));
+            image.update_used_in(self.id);
         }
 
         self.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateTexture(shader, index, view, sampler)));
     }
 
+    /// Selects the texture at `index` of the bindless texture table for all following draws of
+    /// this pass. `image` must be the image registered at `index` so that the pass can wait for
+    /// pending writes to it. Requires descriptor indexing support.
+    pub fn set_bindless_texture(&mut self, index: u32, image: &Arc<GlobalImage>) {
+        if !self.share.get_device().supports_descriptor_indexing() {
+            log::error!("Called PassRecorder::set_bindless_texture without descriptor indexing support");
+            panic!()
+        }
+        image.assert_not_exported("PassRecorder::set_bindless_texture");
+
+        if self.used_global_image.bind(image.get_id(), image) {
+            self.share.push_task(WorkerTask::UseGlobalImage(image.clone()));
+            image.update_used_in(self.id);
+        }
+
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetBindlessTexture(index)));
+    }
+
+    /// Sets the stencil reference value used by all following draws of this pass. The initial
+    /// reference value of a pass is 0.
+    pub fn set_stencil_reference(&mut self, reference: u32) {
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetStencilReference(reference)));
+    }
+
+    /// Sets a combined model view projection matrix used by all following draws of this pass
+    /// instead of the projection and model view uniforms. The matrix is passed to shaders as a push
+    /// constant avoiding a uniform buffer write for every change.
+    pub fn set_mvp(&mut self, mvp: &Mat4f32) {
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetMvp(*mvp)));
+    }
+
+    /// Configures the load and store operation used for a attachment in this pass. All attachments
+    /// use [`AttachmentOps::CLEAR_STORE`] by default.
+    ///
+    /// Must be called before any draw is recorded in this pass and `attachment` must be supported
+    /// by the pipeline of the pass.
+    pub fn set_attachment_ops(&mut self, attachment: PassAttachment, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) {
+        if self.draw_recorded {
+            log::error!("Called set_attachment_ops for {:?} after a draw has been recorded", attachment);
+            panic!()
+        }
+        if !self.pipeline.supports_attachment_ops(attachment) {
+            log::error!("Called set_attachment_ops for {:?} which is not supported by the pipeline of the pass", attachment);
+            panic!()
+        }
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetAttachmentOps(attachment, AttachmentOps::new(load_op, store_op))));
+    }
+
+    /// Sets the viewport used by all draws of this pass. By default the viewport covers the full
+    /// framebuffer.
+    ///
+    /// Must be called before any draw is recorded in this pass.
+    pub fn set_pass_viewport(&mut self, viewport: vk::Viewport) {
+        if self.draw_recorded {
+            log::error!("Called set_pass_viewport after a draw has been recorded");
+            panic!()
+        }
+        if !is_valid_viewport(&viewport) {
+            log::error!("Called set_pass_viewport with invalid viewport {:?}", viewport);
+            panic!()
+        }
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetPassViewport(viewport)));
+    }
+
+    /// Sets the scissor rectangle used by all draws of this pass. By default the scissor covers
+    /// the full framebuffer.
+    ///
+    /// Must be called before any draw is recorded in this pass.
+    pub fn set_pass_scissor(&mut self, scissor: vk::Rect2D) {
+        if self.draw_recorded {
+            log::error!("Called set_pass_scissor after a draw has been recorded");
+            panic!()
+        }
+        if scissor.offset.x < 0 || scissor.offset.y < 0 {
+            log::error!("Called set_pass_scissor with negative offset {:?}", scissor.offset);
+            panic!()
+        }
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetPassScissor(scissor)));
+    }
+
+    /// Sets the line width used by all following draws of this pass. The initial line width of a
+    /// pass is 1.0. Widths other than 1.0 require the wideLines device feature (see
+    /// [`DeviceContext::supports_wide_lines`]) and must lie within the lineWidthRange limit of the
+    /// device.
+    ///
+    /// [`DeviceContext::supports_wide_lines`]: crate::device::device::DeviceContext::supports_wide_lines
+    pub fn set_line_width(&mut self, width: f32) {
+        let device = self.share.get_device();
+        if !is_valid_line_width(width, device.supports_wide_lines(), device.get_limits().line_width_range) {
+            log::error!("Called set_line_width with unsupported line width {:?}", width);
+            panic!()
+        }
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetLineWidth(width)));
+    }
+
+    /// Sets the blend constants used by all following draws of this pass. The constants are used
+    /// by attachments whose blend factors reference them, for example attachments created with
+    /// [`ColorAttachmentInfo::new_constant_blend`]. The initial blend constants of a pass are 0.
+    ///
+    /// [`ColorAttachmentInfo::new_constant_blend`]: crate::renderer::emulator::pipeline::ColorAttachmentInfo::new_constant_blend
+    pub fn set_blend_constants(&mut self, constants: [f32; 4]) {
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetBlendConstants(constants)));
+    }
+
+    /// Uploads a immediate mesh from quads. Every 4 vertices form a quad which is drawn as 2
+    /// triangles using a generated index buffer. The stride must not be 0 and the data must hold
+    /// a multiple of 4 vertices.
+    pub fn upload_immediate_quads(&mut self, vertex_data: &[u8], vertex_stride: u32) -> ImmediateMeshId {
+        let quad_count = get_quad_count(vertex_data.len(), vertex_stride).unwrap_or_else(|err| {
+            log::error!("Invalid quad data passed to PassRecorder::upload_immediate_quads: {}", err);
+            panic!()
+        });
+        let indices = self.share.get_quad_indices(quad_count);
+
+        self.upload_immediate(&MeshData {
+            vertex_data,
+            index_data: &indices[0..((quad_count as usize) * 6 * 4)],
+            vertex_stride,
+            index_count: quad_count * 6,
+            index_type: vk::IndexType::UINT32,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST
+        })
+    }
+
     pub fn upload_immediate(&mut self, data: &MeshData) -> ImmediateMeshId {
         let index_size = data.get_index_size();
 
-        let immediate = self.immediate_buffer.as_mut().unwrap();
-        let (vertex_buffer, vertex_offset) = immediate.allocate(data.vertex_data, data.vertex_stride as vk::DeviceSize);
-        let (index_buffer, index_offset) = immediate.allocate(data.index_data, index_size as vk::DeviceSize);
+        let ((vertex_buffer, vertex_offset), (index_buffer, index_offset)) = if let Some(ring) = &self.immediate_ring {
+            (
+                ring.allocate(&self.share, data.vertex_data, data.vertex_stride as vk::DeviceSize, self.id),
+                ring.allocate(&self.share, data.index_data, index_size as vk::DeviceSize, self.id)
+            )
+        } else {
+            let immediate = self.immediate_buffer.as_mut().unwrap();
+            (
+                immediate.allocate(data.vertex_data, data.vertex_stride as vk::DeviceSize),
+                immediate.allocate(data.index_data, index_size as vk::DeviceSize)
+            )
+        };
 
         let id = self.immediate_meshes.len() as u32;
         self.immediate_meshes.push(ImmediateMeshInfo {
@@ -123,6 +301,7 @@ impl PassRecorder {
 
     pub fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool) {
         self.use_shader(shader);
+        self.draw_recorded = true;
 
         let mesh_data = self.immediate_meshes.get(id.get_raw() as usize).unwrap();
 
@@ -144,12 +323,69 @@ impl PassRecorder {
         mesh.update_used_in(self.id);
 
         self.use_shader(shader);
+        self.draw_recorded = true;
+
+        let draw_task = Self::make_global_draw_task(&mesh, shader, depth_write_enable);
+
+        self.share.push_task(WorkerTask::UseGlobalMesh(mesh));
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+    }
+
+    /// Draws multiple global meshes using the same shader. The chunk offset uniform is updated to
+    /// the provided value before each mesh is drawn.
+    ///
+    /// Since all draws use the same shader the pipeline only needs to be bound once as long as all
+    /// meshes use the same primitive topology. Only the vertex buffers and push constants change
+    /// between draws.
+    pub fn draw_global_batch(&mut self, meshes: &[(Arc<GlobalMesh>, Vec3f32)], shader: ShaderId, depth_write_enable: bool) {
+        self.use_shader(shader);
+        self.draw_recorded = true;
+
+        for (mesh, chunk_offset) in meshes {
+            mesh.update_used_in(self.id);
+
+            let draw_task = Self::make_global_draw_task(mesh, shader, depth_write_enable);
+
+            self.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, McUniformData::ChunkOffset(*chunk_offset))));
+            self.share.push_task(WorkerTask::UseGlobalMesh(mesh.clone()));
+            self.share.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+        }
+    }
+
+    /// Draws `vertex_count` vertices without binding any vertex or index buffer. The shader must
+    /// have been created with an empty vertex format (see [`VertexFormat::empty`]) and generates
+    /// its geometry from the vertex index. Primitives are assembled as a triangle list.
+    ///
+    /// [`VertexFormat::empty`]: crate::renderer::emulator::mc_shaders::VertexFormat::empty
+    pub fn draw_procedural(&mut self, vertex_count: u32, instance_count: u32, shader: ShaderId, depth_write_enable: bool) {
+        let shader_obj = self.share.get_shader(shader).unwrap_or_else(|| {
+            log::error!("Called draw_procedural with unknown shader {:?}", shader);
+            panic!()
+        });
+        if !shader_obj.get_vertex_format().is_empty() {
+            log::error!("Called draw_procedural with shader {:?} which has a non empty vertex format", shader);
+            panic!()
+        }
+
+        self.use_shader(shader);
+        self.draw_recorded = true;
+
+        let draw_task = ProceduralDrawTask {
+            vertex_count,
+            instance_count,
+            shader,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            depth_write_enable,
+        };
+        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::DrawProcedural(draw_task)));
+    }
 
+    fn make_global_draw_task(mesh: &GlobalMesh, shader: ShaderId, depth_write_enable: bool) -> DrawTask {
         let draw_info = mesh.get_draw_info();
 
-        let draw_task = DrawTask {
+        DrawTask {
             vertex_buffer: draw_info.buffer,
-            index_buffer: draw_info.buffer,
+            index_buffer: draw_info.index_buffer,
             vertex_offset: 0,
             first_index: draw_info.first_index,
             index_type: draw_info.index_type,
@@ -157,10 +393,7 @@ impl PassRecorder {
             shader,
             primitive_topology: draw_info.primitive_topology,
             depth_write_enable,
-        };
-
-        self.share.push_task(WorkerTask::UseGlobalMesh(mesh));
-        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+        }
     }
 
     fn use_shader(&mut self, shader: ShaderId) {
@@ -173,11 +406,50 @@ impl PassRecorder {
 
 impl Drop for PassRecorder {
     fn drop(&mut self) {
-        self.share.push_task(WorkerTask::EndPass(self.immediate_buffer.take().unwrap()));
+        self.share.push_task(WorkerTask::EndPass(self.immediate_buffer.take().unwrap(), self.immediate_ring.take()));
         self.share.end_pass_id();
     }
 }
 
+/// Returns true if the line width may be used on a device. Widths other than 1.0 are only valid if
+/// the wideLines feature is supported and the width lies within `line_width_range`.
+fn is_valid_line_width(width: f32, wide_lines_supported: bool, line_width_range: [f32; 2]) -> bool {
+    if !width.is_finite() || width <= 0f32 {
+        return false;
+    }
+    width == 1f32 || (wide_lines_supported && width >= line_width_range[0] && width <= line_width_range[1])
+}
+
+fn is_valid_viewport(viewport: &vk::Viewport) -> bool {
+    let finite = [viewport.x, viewport.y, viewport.width, viewport.height].iter().all(|v| v.is_finite());
+    finite && viewport.width > 0f32 && viewport.height > 0f32 &&
+        (0f32..=1f32).contains(&viewport.min_depth) && (0f32..=1f32).contains(&viewport.max_depth)
+}
+
+/// Holds strong references to resources used by a pass until the pass is dropped.
+struct BoundResources<K, T> {
+    resources: HashMap<K, Arc<T>>,
+}
+
+impl<K: Eq + Hash, T> BoundResources<K, T> {
+    fn new() -> Self {
+        Self {
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Keeps the resource alive for the duration of the pass. Returns true if the resource had not
+    /// been bound before.
+    fn bind(&mut self, id: K, resource: &Arc<T>) -> bool {
+        if self.resources.contains_key(&id) {
+            false
+        } else {
+            self.resources.insert(id, resource.clone());
+            true
+        }
+    }
+}
+
 struct ImmediateMeshInfo {
     vertex_buffer: vk::Buffer,
     index_buffer: vk::Buffer,
@@ -186,4 +458,66 @@ struct ImmediateMeshInfo {
     index_type: vk::IndexType,
     index_count: u32,
     primitive_topology: vk::PrimitiveTopology,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_width_without_wide_lines() {
+        assert!(is_valid_line_width(1f32, false, [1f32, 1f32]));
+        assert!(!is_valid_line_width(2f32, false, [1f32, 8f32]));
+        assert!(!is_valid_line_width(0.5f32, false, [0.5f32, 8f32]));
+    }
+
+    #[test]
+    fn half_height_viewport() {
+        let mut viewport = crate::util::vk::make_full_viewport(Vec2u32::new(800, 600));
+        assert!(is_valid_viewport(&viewport));
+
+        viewport.height /= 2f32;
+        assert!(is_valid_viewport(&viewport));
+
+        viewport.height = 0f32;
+        assert!(!is_valid_viewport(&viewport));
+
+        viewport.height = 300f32;
+        viewport.max_depth = 2f32;
+        assert!(!is_valid_viewport(&viewport));
+    }
+
+    #[test]
+    fn bound_texture_outlives_caller() {
+        let mut bound = BoundResources::new();
+
+        let texture = Arc::new(vec![255u8, 0u8, 0u8, 255u8]);
+        let weak = Arc::downgrade(&texture);
+        assert!(bound.bind(1u64, &texture));
+        assert!(!bound.bind(1u64, &texture));
+
+        // The caller drops its reference while the pass is still recording
+        drop(texture);
+        let texture = weak.upgrade().expect("Bound texture was freed before the pass ended");
+        assert_eq!(texture.as_slice(), &[255u8, 0u8, 0u8, 255u8]);
+        drop(texture);
+
+        // Completing the pass releases the reference
+        drop(bound);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn line_width_with_wide_lines() {
+        let range = [0.5f32, 8f32];
+        assert!(is_valid_line_width(1f32, true, range));
+        assert!(is_valid_line_width(2.5f32, true, range));
+        assert!(is_valid_line_width(0.5f32, true, range));
+        assert!(!is_valid_line_width(0f32, true, range));
+        assert!(!is_valid_line_width(f32::NAN, true, range));
+
+        // Widths outside of the lineWidthRange limit
+        assert!(!is_valid_line_width(0.25f32, true, range));
+        assert!(!is_valid_line_width(8.5f32, true, range));
+    }
+}
@@ -1,6 +1,7 @@
 use std::hash::Hash;
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
 use ash::prelude::VkResult;
 
 use ash::vk;
@@ -10,7 +11,9 @@ use crate::device::device_utils::BlitPass;
 use crate::device::surface::{AcquiredImageInfo, SurfaceSwapchain};
 
 use crate::prelude::*;
+use crate::renderer::emulator::global_objects::{GlobalImage, make_layer_subresource_range};
 use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
+use crate::util::format::{ClearColorType, Format};
 
 pub use super::worker::SubmitRecorder;
 pub use super::worker::PooledObjectProvider;
@@ -36,6 +39,36 @@ pub trait EmulatorPipeline: Send + Sync + UnwindSafe + RefUnwindSafe {
     /// **This is a temporary api and needs a rework to improve flexibility and elegance**
     fn get_output(&self) -> (Vec2u32, &[vk::ImageView]);
 
+    /// Returns the format and the depth attachment images of the pipeline if they can be copied
+    /// into a depth [`GlobalImage`] with a [`DepthImageOutput`]. The images are indexed like the
+    /// views returned by [`EmulatorPipeline::get_output`]. After a pass has been recorded its depth
+    /// image must be in the `SHADER_READ_ONLY_OPTIMAL` layout and usable as a transfer source.
+    ///
+    /// The default implementation returns [`None`].
+    fn get_depth_output(&self) -> Option<(vk::Format, &[vk::Image])> {
+        None
+    }
+
+    /// Returns the format and the images of an additional color attachment if they can be copied
+    /// into a [`GlobalImage`] with a [`ColorAttachmentOutput`]. The images are indexed like the
+    /// views returned by [`EmulatorPipeline::get_output`]. After a pass has been recorded its
+    /// attachment image must be in the `SHADER_READ_ONLY_OPTIMAL` layout and usable as a transfer
+    /// source.
+    ///
+    /// The default implementation returns [`None`].
+    fn get_color_attachment_output(&self, _attachment: usize) -> Option<(vk::Format, &[vk::Image])> {
+        None
+    }
+
+    /// Returns true if the load and store operations of `attachment` can be configured with
+    /// [`PassRecorder::set_attachment_ops`](super::PassRecorder::set_attachment_ops). Called on the
+    /// thread recording the pass.
+    ///
+    /// The default implementation returns false.
+    fn supports_attachment_ops(&self, _attachment: PassAttachment) -> bool {
+        false
+    }
+
     /// Called internally by the emulator renderer when pass uses a shader for the first time.
     /// A corresponding call to [`dec_shader_used`] will be performed after the corresponding pass
     /// has been dropped.
@@ -104,13 +137,52 @@ pub trait EmulatorPipelinePass {
     ///
     /// TODO this is currently not used by the worker
     fn get_internal_fences(&self, fences: &mut Vec<vk::Fence>);
+
+    /// Returns the command buffer into which the next task will be recorded. Used to write gpu
+    /// crash breadcrumbs before each task. If [`None`] is returned no breadcrumbs are written for
+    /// this pass.
+    ///
+    /// Must only be called while the pass is in the recording state.
+    fn get_breadcrumb_command_buffer(&self) -> Option<vk::CommandBuffer> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum PipelineTask {
     UpdateUniform(ShaderId, McUniformData),
     UpdateTexture(ShaderId, u32, vk::ImageView, vk::Sampler),
+    SetStencilReference(u32),
+    SetLineWidth(f32),
+    SetBlendConstants([f32; 4]),
+    SetMvp(Mat4f32),
+    /// Selects the texture of the bindless texture table accessed by following draws.
+    SetBindlessTexture(u32),
+    SetAttachmentOps(PassAttachment, AttachmentOps),
+    SetPassViewport(vk::Viewport),
+    SetPassScissor(vk::Rect2D),
     Draw(DrawTask),
+    DrawProcedural(ProceduralDrawTask),
+}
+
+impl PipelineTask {
+    /// Returns the name of the task variant used to identify the task in gpu crash breadcrumbs.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            PipelineTask::UpdateUniform(_, _) => "UpdateUniform",
+            PipelineTask::UpdateTexture(_, _, _, _) => "UpdateTexture",
+            PipelineTask::SetStencilReference(_) => "SetStencilReference",
+            PipelineTask::SetLineWidth(_) => "SetLineWidth",
+            PipelineTask::SetBlendConstants(_) => "SetBlendConstants",
+            PipelineTask::SetMvp(_) => "SetMvp",
+            PipelineTask::SetBindlessTexture(_) => "SetBindlessTexture",
+            PipelineTask::SetAttachmentOps(_, _) => "SetAttachmentOps",
+            PipelineTask::SetPassViewport(_) => "SetPassViewport",
+            PipelineTask::SetPassScissor(_) => "SetPassScissor",
+            PipelineTask::Draw(_) => "Draw",
+            PipelineTask::DrawProcedural(_) => "DrawProcedural",
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -126,6 +198,149 @@ pub struct DrawTask {
     pub depth_write_enable: bool,
 }
 
+/// Identifies a attachment of a pass.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PassAttachment {
+    Depth,
+
+    /// A color attachment. Index 0 is the main color output, additional color attachments follow
+    /// in the order they have been specified when creating the pipeline.
+    Color(u32),
+}
+
+/// The load and store operation used for a attachment in a pass.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AttachmentOps {
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+}
+
+impl AttachmentOps {
+    pub const CLEAR_STORE: Self = Self::new(vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::STORE);
+
+    pub const fn new(load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+        Self {
+            load_op,
+            store_op
+        }
+    }
+}
+
+/// Describes an additional color attachment rendered by a pipeline. Fragment shaders write to
+/// additional attachments using the output locations following the main color output.
+#[derive(Copy, Clone, Debug)]
+pub struct ColorAttachmentInfo {
+    pub format: vk::Format,
+    pub blend_state: vk::PipelineColorBlendAttachmentState,
+}
+
+impl ColorAttachmentInfo {
+    /// Creates a attachment which does not use blending. This must be used for integer formats
+    /// like `R32_UINT` since they do not support blending.
+    pub fn new_opaque(format: vk::Format) -> Self {
+        Self {
+            format,
+            blend_state: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .build()
+        }
+    }
+
+    /// Creates a attachment which uses standard alpha blending.
+    pub fn new_alpha_blend(format: vk::Format) -> Self {
+        Self {
+            format,
+            blend_state: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .build()
+        }
+    }
+
+    /// Creates a attachment which blends the source and destination color using the blend
+    /// constants of the pass as weight. See [`PassRecorder::set_blend_constants`].
+    ///
+    /// [`PassRecorder::set_blend_constants`]: super::PassRecorder::set_blend_constants
+    pub fn new_constant_blend(format: vk::Format) -> Self {
+        Self {
+            format,
+            blend_state: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::CONSTANT_COLOR)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_CONSTANT_COLOR)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::CONSTANT_ALPHA)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .build()
+        }
+    }
+
+    /// Returns true if the blend state of this attachment references the blend constants.
+    pub fn uses_blend_constants(&self) -> bool {
+        if self.blend_state.blend_enable == vk::FALSE {
+            return false;
+        }
+
+        let is_constant = |factor: vk::BlendFactor| matches!(factor,
+            vk::BlendFactor::CONSTANT_COLOR |
+            vk::BlendFactor::ONE_MINUS_CONSTANT_COLOR |
+            vk::BlendFactor::CONSTANT_ALPHA |
+            vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA
+        );
+
+        is_constant(self.blend_state.src_color_blend_factor) ||
+            is_constant(self.blend_state.dst_color_blend_factor) ||
+            is_constant(self.blend_state.src_alpha_blend_factor) ||
+            is_constant(self.blend_state.dst_alpha_blend_factor)
+    }
+
+    /// Returns the type of clear value used by the format of this attachment.
+    pub fn get_clear_color_type(&self) -> ClearColorType {
+        Format::format_for(self.format).get_clear_color_type().unwrap_or_else(|| {
+            log::error!("Color attachment format {:?} does not support clearing", self.format);
+            panic!()
+        })
+    }
+
+    /// Returns true if the format of this attachment is a signed or unsigned integer format.
+    pub fn is_integer(&self) -> bool {
+        self.get_clear_color_type() != ClearColorType::Float
+    }
+
+    /// Returns the format features required to render into, sample and read back this attachment.
+    pub fn get_required_format_features(&self) -> vk::FormatFeatureFlags {
+        let mut features = vk::FormatFeatureFlags::COLOR_ATTACHMENT |
+            vk::FormatFeatureFlags::SAMPLED_IMAGE |
+            vk::FormatFeatureFlags::TRANSFER_SRC;
+
+        if self.blend_state.blend_enable != vk::FALSE {
+            features |= vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND;
+        }
+
+        features
+    }
+}
+
+/// A draw without any bound vertex or index buffer. The vertex shader must generate the geometry
+/// from the vertex and instance index.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct ProceduralDrawTask {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub shader: ShaderId,
+    pub primitive_topology: vk::PrimitiveTopology,
+    pub depth_write_enable: bool,
+}
+
 /// Used to process the output of a [`EmulatorPipelinePass`].
 ///
 /// Any instance of this struct will not be dropped until all submitted command buffers have
@@ -148,7 +363,6 @@ pub trait EmulatorOutput {
 
 /// A utility struct providing a [`BlitPass`] for the output of a [`EmulatorPipeline`].
 pub struct OutputUtil {
-    #[allow(unused)] // We just need to keep the pipeline alive
     pipeline: Arc<dyn EmulatorPipeline>,
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Box<[vk::DescriptorSet]>,
@@ -157,9 +371,14 @@ pub struct OutputUtil {
 
 impl OutputUtil {
     pub fn new(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, format: vk::Format, final_layout: vk::ImageLayout) -> Self {
+        Self::new_with_layouts(device, pipeline, format, vk::ImageLayout::UNDEFINED, final_layout)
+    }
+
+    /// Creates a output util whose blit pass expects the draw target to be in `initial_layout`.
+    pub fn new_with_layouts(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, format: vk::Format, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> Self {
         let (_, sampler_views) = pipeline.get_output();
 
-        let blit_pass = device.get_utils().blit_utils().create_blit_pass(format, vk::AttachmentLoadOp::DONT_CARE, vk::ImageLayout::UNDEFINED, final_layout);
+        let blit_pass = device.get_utils().blit_utils().create_blit_pass(format, vk::AttachmentLoadOp::DONT_CARE, initial_layout, final_layout);
 
         let descriptor_pool = Self::create_descriptor_pool(device, sampler_views.len());
         let descriptor_sets = blit_pass.create_descriptor_sets(descriptor_pool, sampler_views).unwrap().into_boxed_slice();
@@ -172,6 +391,10 @@ impl OutputUtil {
         }
     }
 
+    pub fn get_pipeline(&self) -> &Arc<dyn EmulatorPipeline> {
+        &self.pipeline
+    }
+
     /// Creates a framebuffer which can be used as a draw target for the blit pass.
     ///
     /// The returned framebuffer is fully owned by the calling code and must be destroyed before
@@ -206,7 +429,7 @@ impl OutputUtil {
             .pool_sizes(&sizes);
 
         unsafe {
-            device.vk().create_descriptor_pool(&info, None)
+            device.vk().create_descriptor_pool(&info, device.get_allocation_callbacks())
         }.unwrap()
     }
 }
@@ -214,7 +437,7 @@ impl OutputUtil {
 impl Drop for OutputUtil {
     fn drop(&mut self) {
         unsafe {
-            self.blit_pass.get_device().vk.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.blit_pass.get_device().vk.destroy_descriptor_pool(self.descriptor_pool, self.blit_pass.get_device().get_allocation_callbacks());
         }
     }
 }
@@ -226,6 +449,7 @@ pub struct SwapchainOutput {
     swapchain: Arc<SurfaceSwapchain>,
     util: OutputUtil,
     framebuffers: Box<[vk::Framebuffer]>,
+    recreate_required: AtomicBool,
 }
 
 impl SwapchainOutput {
@@ -240,28 +464,36 @@ impl SwapchainOutput {
             weak: weak.clone(),
             swapchain,
             util,
-            framebuffers
+            framebuffers,
+            recreate_required: AtomicBool::new(false),
         })
     }
 
-    /// Attempts to acquire a new image from the swapchain blocking until it does.
+    /// Returns true if a previous present reported the swapchain as suboptimal or out of date.
+    /// The swapchain should be recreated before the next frame.
+    pub fn is_recreate_required(&self) -> bool {
+        self.recreate_required.load(Ordering::Acquire)
+    }
+
+    /// Attempts to acquire a new image from the swapchain waiting at most `timeout` nanoseconds.
     ///
-    /// Returns [`None`] if the swapchain is out of date.
+    /// Returns [`vk::Result::TIMEOUT`] or [`vk::Result::NOT_READY`] if no image became available
+    /// in time, [`vk::Result::ERROR_OUT_OF_DATE_KHR`] if the swapchain is out of date and
+    /// [`vk::Result::ERROR_SURFACE_LOST_KHR`] if the surface has been lost. Any other error is
+    /// treated as fatal.
     ///
     /// If it successfully acquires a image returns a [`EmulatorOutput`] instance for the image as
     /// well as a boolean flag set to true if the swapchain is suboptimal.
-    pub fn next_image(&self) -> Option<(Box<dyn EmulatorOutput + Send>, bool)> {
-        loop {
-            let arc = self.weak.upgrade().unwrap();
-            match self.swapchain.acquire_next_image(1000000000, None) {
-                Ok((info, suboptimal)) =>
-                    return Some((Box::new(SwapchainOutputInstance::new(arc, info)), suboptimal)),
-                Err(vk::Result::TIMEOUT) =>
-                    log::warn!("1s timeout reached while waiting for next swapchain image in SwapchainOutput::next_image"),
-                Err(err) => {
-                    log::error!("vkAcquireNextImageKHR returned {:?} in SwapchainOutput::next_image", err);
-                    panic!()
-                }
+    pub fn next_image(&self, timeout: u64) -> VkResult<(Box<dyn EmulatorOutput + Send>, bool)> {
+        let arc = self.weak.upgrade().unwrap();
+        match self.swapchain.acquire_next_image(timeout, None) {
+            Ok((info, suboptimal)) =>
+                Ok((Box::new(SwapchainOutputInstance::new(arc, info)), suboptimal)),
+            Err(err @ (vk::Result::TIMEOUT | vk::Result::NOT_READY | vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::ERROR_SURFACE_LOST_KHR)) =>
+                Err(err),
+            Err(err) => {
+                log::error!("vkAcquireNextImageKHR returned {:?} in SwapchainOutput::next_image", err);
+                panic!()
             }
         }
     }
@@ -272,7 +504,7 @@ impl Drop for SwapchainOutput {
         let device = self.swapchain.get_device();
         unsafe {
             for framebuffer in self.framebuffers.iter() {
-                device.vk.destroy_framebuffer(*framebuffer, None);
+                device.vk.destroy_framebuffer(*framebuffer, device.get_allocation_callbacks());
             }
         }
     }
@@ -348,8 +580,554 @@ impl EmulatorOutput for SwapchainOutputInstance {
             .swapchains(std::slice::from_ref(&*guard))
             .image_indices(std::slice::from_ref(&self.image_info.image_index));
 
+        let result = match unsafe { queue.present(&present_info) } {
+            // Recreating the swapchain would not change anything
+            Ok(true) if self.output.swapchain.is_suboptimal_expected() => Ok(false),
+            result => result,
+        };
+
+        match check_swapchain_result(result) {
+            Ok(false) => {},
+            Ok(true) => self.output.recreate_required.store(true, Ordering::Release),
+            Err(err) => {
+                log::error!("vkQueuePresentKHR returned {:?} in SwapchainOutputInstance::on_post_submit", err);
+                panic!()
+            }
+        }
+    }
+}
+
+/// A [`EmulatorOutput`] implementation which copies the output image into a single array layer of
+/// a [`GlobalImage`]. The image must be usable as a blit destination (see
+/// [`GlobalImage::is_blit_dst`]).
+///
+/// The blit pass and a framebuffer for every layer are created once so the output can be reused
+/// across passes. Passes writing into a layer can be started with
+/// [`EmulatorRenderer::start_pass_into_layer`](super::EmulatorRenderer::start_pass_into_layer).
+pub struct ImageLayerOutput {
+    weak: Weak<Self>,
+    device: Arc<DeviceContext>,
+    image: Arc<GlobalImage>,
+    util: OutputUtil,
+    layer_views: Box<[vk::ImageView]>,
+    framebuffers: Box<[vk::Framebuffer]>,
+}
+
+impl ImageLayerOutput {
+    pub fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>, image: Arc<GlobalImage>) -> Arc<Self> {
+        if !image.is_blit_dst() {
+            log::error!("Called ImageLayerOutput::new with image {:?} which cannot be used as a color attachment", image.get_id());
+            panic!()
+        }
+
+        let format = image.get_format().get_format();
+        let util = OutputUtil::new_with_layouts(&device, pipeline, format, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let layer_views: Box<[_]> = (0..image.get_array_layer_count()).map(|layer| {
+            let info = vk::ImageViewCreateInfo::builder()
+                .image(image.get_image_handle())
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .components(vk::ComponentMapping::default())
+                .subresource_range(make_layer_subresource_range(layer));
+
+            unsafe {
+                device.vk().create_image_view(&info, device.get_allocation_callbacks())
+            }.unwrap_or_else(|err| {
+                log::error!("vkCreateImageView returned {:?} in ImageLayerOutput::new", err);
+                panic!()
+            })
+        }).collect();
+
+        let framebuffers = layer_views.iter().map(|view| {
+            util.create_framebuffer(*view, image.get_size()).unwrap_or_else(|err| {
+                log::error!("vkCreateFramebuffer returned {:?} in ImageLayerOutput::new", err);
+                panic!()
+            })
+        }).collect();
+
+        Arc::new_cyclic(|weak| Self {
+            weak: weak.clone(),
+            device,
+            image,
+            util,
+            layer_views,
+            framebuffers,
+        })
+    }
+
+    pub fn get_image(&self) -> &Arc<GlobalImage> {
+        &self.image
+    }
+
+    pub fn get_pipeline(&self) -> &Arc<dyn EmulatorPipeline> {
+        self.util.get_pipeline()
+    }
+
+    /// Creates a [`EmulatorOutput`] instance writing into the specified array layer.
+    pub fn for_layer(&self, array_layer: u32) -> Box<dyn EmulatorOutput + Send> {
+        if array_layer >= self.image.get_array_layer_count() {
+            log::error!("Called ImageLayerOutput::for_layer with layer {:?} but image only has {:?} layers", array_layer, self.image.get_array_layer_count());
+            panic!()
+        }
+
+        Box::new(ImageLayerOutputInstance {
+            output: self.weak.upgrade().unwrap(),
+            array_layer,
+            pipeline_index: None,
+        })
+    }
+}
+
+impl Drop for ImageLayerOutput {
+    fn drop(&mut self) {
         unsafe {
-            queue.present(&present_info)
+            for framebuffer in self.framebuffers.iter() {
+                self.device.vk().destroy_framebuffer(*framebuffer, self.device.get_allocation_callbacks());
+            }
+            for view in self.layer_views.iter() {
+                self.device.vk().destroy_image_view(*view, self.device.get_allocation_callbacks());
+            }
+        }
+    }
+}
+
+struct ImageLayerOutputInstance {
+    output: Arc<ImageLayerOutput>,
+    array_layer: u32,
+    pipeline_index: Option<usize>,
+}
+
+impl ImageLayerOutputInstance {
+    fn make_barrier(&self, src: (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout), dst: (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout)) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src.0)
+            .src_access_mask(src.1)
+            .old_layout(src.2)
+            .dst_stage_mask(dst.0)
+            .dst_access_mask(dst.1)
+            .new_layout(dst.2)
+            .image(self.output.image.get_image_handle())
+            .subresource_range(make_layer_subresource_range(self.array_layer))
+            .build()
+    }
+}
+
+impl EmulatorOutput for ImageLayerOutputInstance {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        self.pipeline_index = Some(pass.get_output_index());
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let device = &self.output.device;
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        let sampled = get_layer_output_src_state(true);
+        let attachment = (vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let pre_barrier = self.make_barrier(get_layer_output_src_state(self.output.image.is_initialized()), attachment);
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&pre_barrier)));
+        }
+
+        self.output.util.record(cmd, self.output.framebuffers[self.array_layer as usize], self.output.image.get_size(), self.pipeline_index.unwrap());
+
+        let post_barrier = self.make_barrier(attachment, sampled);
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&post_barrier)));
+            device.vk().end_command_buffer(cmd)
         }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
     }
-}
\ No newline at end of file
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+/// Returns the state a layer of a output image is in before the pass writes to it. Initialized
+/// global images are kept in the shader read only layout between operations. The blit overwrites
+/// the full layer so the content of a uninitialized image can be discarded.
+fn get_layer_output_src_state(initialized: bool) -> (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout) {
+    if initialized {
+        (vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    } else {
+        (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE, vk::ImageLayout::UNDEFINED)
+    }
+}
+
+/// A [`EmulatorOutput`] implementation which copies the depth attachment of a pass into a depth
+/// [`GlobalImage`] so that it can be sampled like a regular texture by later passes. The pipeline
+/// must provide its depth images through [`EmulatorPipeline::get_depth_output`] and the image must
+/// have the same format and size as the depth attachment.
+///
+/// Passes writing into the image can be started with
+/// [`EmulatorRenderer::start_pass_into_depth`](super::EmulatorRenderer::start_pass_into_depth).
+pub struct DepthImageOutput {
+    weak: Weak<Self>,
+    device: Arc<DeviceContext>,
+    pipeline: Arc<dyn EmulatorPipeline>,
+    image: Arc<GlobalImage>,
+}
+
+impl DepthImageOutput {
+    pub fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>, image: Arc<GlobalImage>) -> Arc<Self> {
+        if !image.is_depth() {
+            log::error!("Called DepthImageOutput::new with image {:?} which has no depth aspect", image.get_id());
+            panic!()
+        }
+
+        let (format, _) = pipeline.get_depth_output().unwrap_or_else(|| {
+            log::error!("Called DepthImageOutput::new with a pipeline which does not provide its depth attachment");
+            panic!()
+        });
+        if format != image.get_format().get_format() {
+            log::error!("Depth attachment format {:?} does not match format {:?} of image {:?}", format, image.get_format(), image.get_id());
+            panic!()
+        }
+
+        let (size, _) = pipeline.get_output();
+        if size != image.get_size() {
+            log::error!("Depth attachment size {:?} does not match size {:?} of image {:?}", size, image.get_size(), image.get_id());
+            panic!()
+        }
+
+        Arc::new_cyclic(|weak| Self {
+            weak: weak.clone(),
+            device,
+            pipeline,
+            image,
+        })
+    }
+
+    pub fn get_image(&self) -> &Arc<GlobalImage> {
+        &self.image
+    }
+
+    pub fn get_pipeline(&self) -> &Arc<dyn EmulatorPipeline> {
+        &self.pipeline
+    }
+
+    /// Creates a [`EmulatorOutput`] instance copying the depth attachment of a single pass.
+    pub fn make_output(&self) -> Box<dyn EmulatorOutput + Send> {
+        Box::new(DepthImageOutputInstance {
+            output: self.weak.upgrade().unwrap(),
+            pipeline_index: None,
+        })
+    }
+}
+
+struct DepthImageOutputInstance {
+    output: Arc<DepthImageOutput>,
+    pipeline_index: Option<usize>,
+}
+
+impl EmulatorOutput for DepthImageOutputInstance {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        self.pipeline_index = Some(pass.get_output_index());
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let device = &self.output.device;
+        let image = &self.output.image;
+        let (_, depth_images) = self.output.pipeline.get_depth_output().unwrap();
+        let src_image = depth_images[self.pipeline_index.unwrap()];
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        let barriers = make_attachment_copy_barriers(src_image, image.get_image_handle(), image.get_aspect_mask());
+        let copy = make_attachment_copy_region(image.get_aspect_mask(), image.get_size());
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(&barriers[0..2]));
+            device.vk().cmd_copy_image(cmd, src_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, image.get_image_handle(), vk::ImageLayout::TRANSFER_DST_OPTIMAL, std::slice::from_ref(&copy));
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(&barriers[2..4]));
+            device.vk().end_command_buffer(cmd)
+        }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+/// A [`EmulatorOutput`] implementation which copies an additional color attachment of a pass into a
+/// [`GlobalImage`] so that it can be read back or sampled by later passes. For example to read
+/// object ids rendered into a integer attachment. The pipeline must provide the attachment images
+/// through [`EmulatorPipeline::get_color_attachment_output`] and the image must have the same
+/// format and size as the attachment.
+///
+/// The output is added to a pass with
+/// [`PassRecorder::copy_attachment_into`](super::PassRecorder::copy_attachment_into).
+pub struct ColorAttachmentOutput {
+    weak: Weak<Self>,
+    device: Arc<DeviceContext>,
+    pipeline: Arc<dyn EmulatorPipeline>,
+    attachment: usize,
+    image: Arc<GlobalImage>,
+}
+
+impl ColorAttachmentOutput {
+    pub fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>, attachment: usize, image: Arc<GlobalImage>) -> Arc<Self> {
+        if image.is_depth() {
+            log::error!("Called ColorAttachmentOutput::new with depth image {:?}", image.get_id());
+            panic!()
+        }
+
+        let (format, _) = pipeline.get_color_attachment_output(attachment).unwrap_or_else(|| {
+            log::error!("Called ColorAttachmentOutput::new with a pipeline which does not provide color attachment {:?}", attachment);
+            panic!()
+        });
+        if format != image.get_format().get_format() {
+            log::error!("Color attachment format {:?} does not match format {:?} of image {:?}", format, image.get_format(), image.get_id());
+            panic!()
+        }
+
+        let (size, _) = pipeline.get_output();
+        if size != image.get_size() {
+            log::error!("Color attachment size {:?} does not match size {:?} of image {:?}", size, image.get_size(), image.get_id());
+            panic!()
+        }
+
+        Arc::new_cyclic(|weak| Self {
+            weak: weak.clone(),
+            device,
+            pipeline,
+            attachment,
+            image,
+        })
+    }
+
+    pub fn get_image(&self) -> &Arc<GlobalImage> {
+        &self.image
+    }
+
+    pub fn get_pipeline(&self) -> &Arc<dyn EmulatorPipeline> {
+        &self.pipeline
+    }
+
+    pub fn get_attachment(&self) -> usize {
+        self.attachment
+    }
+
+    /// Creates a [`EmulatorOutput`] instance copying the color attachment of a single pass.
+    pub fn make_output(&self) -> Box<dyn EmulatorOutput + Send> {
+        Box::new(ColorAttachmentOutputInstance {
+            output: self.weak.upgrade().unwrap(),
+            pipeline_index: None,
+        })
+    }
+}
+
+struct ColorAttachmentOutputInstance {
+    output: Arc<ColorAttachmentOutput>,
+    pipeline_index: Option<usize>,
+}
+
+impl EmulatorOutput for ColorAttachmentOutputInstance {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        self.pipeline_index = Some(pass.get_output_index());
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let device = &self.output.device;
+        let image = &self.output.image;
+        let (_, attachment_images) = self.output.pipeline.get_color_attachment_output(self.output.attachment).unwrap();
+        let src_image = attachment_images[self.pipeline_index.unwrap()];
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        let barriers = make_attachment_copy_barriers(src_image, image.get_image_handle(), vk::ImageAspectFlags::COLOR);
+        let copy = make_attachment_copy_region(vk::ImageAspectFlags::COLOR, image.get_size());
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(&barriers[0..2]));
+            device.vk().cmd_copy_image(cmd, src_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, image.get_image_handle(), vk::ImageLayout::TRANSFER_DST_OPTIMAL, std::slice::from_ref(&copy));
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &vk::DependencyInfo::builder().image_memory_barriers(&barriers[2..4]));
+            device.vk().end_command_buffer(cmd)
+        }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+/// Creates the barriers surrounding the copy of a pipeline attachment into a global image. The
+/// first 2 barriers transition both images into the transfer layouts and the last 2 return them to
+/// the shader read only layout. Global images are kept in this layout between operations and the
+/// attachment is left in it by the pass.
+fn make_attachment_copy_barriers(src_image: vk::Image, dst_image: vk::Image, aspect_mask: vk::ImageAspectFlags) -> [vk::ImageMemoryBarrier2; 4] {
+    let range = vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1
+    };
+    let make_barrier = |image: vk::Image, src: (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout), dst: (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout)| {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src.0)
+            .src_access_mask(src.1)
+            .old_layout(src.2)
+            .dst_stage_mask(dst.0)
+            .dst_access_mask(dst.1)
+            .new_layout(dst.2)
+            .image(image)
+            .subresource_range(range)
+            .build()
+    };
+
+    // The pass already made its depth writes visible to all commands
+    let attachment = (vk::PipelineStageFlags2::ALL_COMMANDS, vk::AccessFlags2::NONE, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    let sampled = (vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    let transfer_src = (vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    let transfer_dst = (vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+
+    [
+        make_barrier(src_image, attachment, transfer_src),
+        make_barrier(dst_image, sampled, transfer_dst),
+        make_barrier(src_image, transfer_src, attachment),
+        make_barrier(dst_image, transfer_dst, sampled),
+    ]
+}
+
+/// Creates the region copying `aspect_mask` of a full attachment image.
+fn make_attachment_copy_region(aspect_mask: vk::ImageAspectFlags, size: Vec2u32) -> vk::ImageCopy {
+    let subresource = vk::ImageSubresourceLayers {
+        aspect_mask,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1
+    };
+
+    vk::ImageCopy {
+        src_subresource: subresource,
+        src_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        dst_subresource: subresource,
+        dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        extent: vk::Extent3D {
+            width: size[0],
+            height: size[1],
+            depth: 1
+        }
+    }
+}
+
+/// Processes the result of a swapchain operation returning true if the swapchain should be
+/// recreated. Suboptimal and out of date swapchains are not considered errors.
+fn check_swapchain_result(result: VkResult<bool>) -> VkResult<bool> {
+    match result {
+        Ok(suboptimal) => Ok(suboptimal),
+        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suboptimal_schedules_recreate() {
+        assert_eq!(check_swapchain_result(Ok(false)), Ok(false));
+        assert_eq!(check_swapchain_result(Ok(true)), Ok(true));
+        assert_eq!(check_swapchain_result(Err(vk::Result::ERROR_OUT_OF_DATE_KHR)), Ok(true));
+        assert_eq!(check_swapchain_result(Err(vk::Result::ERROR_DEVICE_LOST)), Err(vk::Result::ERROR_DEVICE_LOST));
+    }
+
+    #[test]
+    fn layer_output_src_follows_initialization() {
+        let (_, _, layout) = get_layer_output_src_state(true);
+        assert_eq!(layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let (stage, access, layout) = get_layer_output_src_state(false);
+        assert_eq!(layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!((stage, access), (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE));
+    }
+
+    #[test]
+    fn depth_copy_layouts() {
+        use ash::vk::Handle;
+
+        let src = vk::Image::from_raw(1);
+        let dst = vk::Image::from_raw(2);
+        let aspect_mask = vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL;
+        let barriers = make_attachment_copy_barriers(src, dst, aspect_mask);
+
+        assert_eq!((barriers[0].image, barriers[0].new_layout), (src, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        assert_eq!((barriers[1].image, barriers[1].new_layout), (dst, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+
+        // Both images are sampled by later passes
+        for barrier in &barriers[2..4] {
+            assert_eq!(barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        }
+        assert_eq!(barriers[3].dst_access_mask, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        assert!(barriers.iter().all(|barrier| barrier.subresource_range.aspect_mask == aspect_mask));
+
+        let copy = make_attachment_copy_region(aspect_mask, Vec2u32::new(64, 32));
+        assert_eq!(copy.src_subresource.aspect_mask, aspect_mask);
+        assert_eq!((copy.extent.width, copy.extent.height, copy.extent.depth), (64, 32, 1));
+    }
+
+    #[test]
+    fn color_attachment_copy_layouts() {
+        use ash::vk::Handle;
+
+        let src = vk::Image::from_raw(1);
+        let dst = vk::Image::from_raw(2);
+        let barriers = make_attachment_copy_barriers(src, dst, vk::ImageAspectFlags::COLOR);
+
+        // The pass leaves integer attachments like any other attachment in the shader read only layout
+        assert_eq!((barriers[0].old_layout, barriers[0].new_layout), (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        assert_eq!((barriers[2].image, barriers[2].new_layout), (src, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+        assert!(barriers.iter().all(|barrier| barrier.subresource_range.aspect_mask == vk::ImageAspectFlags::COLOR));
+
+        let copy = make_attachment_copy_region(vk::ImageAspectFlags::COLOR, Vec2u32::new(16, 8));
+        assert_eq!(copy.dst_subresource.aspect_mask, vk::ImageAspectFlags::COLOR);
+        assert_eq!((copy.extent.width, copy.extent.height), (16, 8));
+    }
+
+    #[test]
+    fn hdr_attachment_features() {
+        let opaque = ColorAttachmentInfo::new_opaque(vk::Format::B10G11R11_UFLOAT_PACK32);
+        assert!(!opaque.is_integer());
+        assert!(!opaque.get_required_format_features().contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND));
+
+        let blend = ColorAttachmentInfo::new_alpha_blend(vk::Format::R16G16B16A16_SFLOAT);
+        assert!(!blend.is_integer());
+        assert!(blend.get_required_format_features().contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND));
+    }
+
+    #[test]
+    fn constant_blend_attachment() {
+        assert!(!ColorAttachmentInfo::new_opaque(vk::Format::R8G8B8A8_UNORM).uses_blend_constants());
+        assert!(!ColorAttachmentInfo::new_alpha_blend(vk::Format::R8G8B8A8_UNORM).uses_blend_constants());
+
+        let constant = ColorAttachmentInfo::new_constant_blend(vk::Format::R8G8B8A8_UNORM);
+        assert!(constant.uses_blend_constants());
+        assert!(constant.get_required_format_features().contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND));
+    }
+}
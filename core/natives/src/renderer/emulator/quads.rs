@@ -0,0 +1,162 @@
+//! Emulation of quad primitives using triangle lists.
+//!
+//! Vulkan has no quad topology. Quads are drawn as triangle lists using a index buffer which
+//! splits each group of 4 vertices into 2 triangles.
+
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+use bytemuck::cast_slice;
+
+use crate::renderer::emulator::MeshData;
+use crate::renderer::emulator::global_objects::{GlobalMesh, GlobalObjectCreateError};
+use crate::renderer::emulator::share::Share;
+
+/// Caches the index data used to draw immediate quads. The data is shared between all immediate
+/// quad meshes and only regenerated if more quads are needed than previously.
+pub(super) struct QuadIndexCache {
+    indices: Mutex<Arc<[u8]>>,
+}
+
+impl QuadIndexCache {
+    pub(super) fn new() -> Self {
+        Self {
+            indices: Mutex::new(Arc::from(Vec::new())),
+        }
+    }
+
+    /// Returns `UINT32` index data for at least `quad_count` quads. Only the first
+    /// `quad_count * 6` indices should be used.
+    pub(super) fn get_indices(&self, quad_count: u32) -> Arc<[u8]> {
+        let mut guard = self.indices.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned quad index mutex in QuadIndexCache::get_indices");
+            panic!()
+        });
+
+        let required = (quad_count as usize) * 6 * std::mem::size_of::<u32>();
+        if guard.len() < required {
+            let indices = generate_quad_indices(quad_count.next_power_of_two());
+            *guard = Arc::from(cast_slice::<u32, u8>(&indices));
+        }
+
+        guard.clone()
+    }
+}
+
+/// Caches the device local index buffer used to draw global quad meshes. The buffer is shared
+/// between all global quad meshes and only recreated if more quads are needed than previously.
+/// Meshes keep the buffer they have been created with alive.
+pub(super) struct QuadIndexBuffer {
+    /// The index mesh and the number of quads it holds indices for.
+    mesh: Mutex<Option<(Arc<GlobalMesh>, u32)>>,
+}
+
+impl QuadIndexBuffer {
+    pub(super) fn new() -> Self {
+        Self {
+            mesh: Mutex::new(None),
+        }
+    }
+
+    /// Returns a mesh holding `UINT32` index data for at least `quad_count` quads.
+    pub(super) fn get_index_mesh(&self, share: &Arc<Share>, quad_count: u32) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
+        let mut guard = self.mesh.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned quad index mutex in QuadIndexBuffer::get_index_mesh");
+            panic!()
+        });
+
+        if let Some((mesh, capacity)) = guard.as_ref() {
+            if *capacity >= quad_count {
+                return Ok(mesh.clone());
+            }
+        }
+
+        let capacity = quad_count.checked_next_power_of_two().unwrap_or(quad_count);
+        let indices = generate_quad_indices(capacity);
+        let mesh = GlobalMesh::new(share.clone(), &MeshData {
+            vertex_data: &[],
+            index_data: cast_slice(&indices),
+            vertex_stride: 0,
+            index_count: capacity * 6,
+            index_type: vk::IndexType::UINT32,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST
+        })?;
+        *guard = Some((mesh.clone(), capacity));
+
+        Ok(mesh)
+    }
+}
+
+/// Returns the number of quads formed by `data_len` bytes of vertex data. Returns an error if the
+/// stride is 0, the data is empty or not a whole number of vertices or the vertex count is not a
+/// multiple of 4.
+pub(super) fn get_quad_count(data_len: usize, vertex_stride: u32) -> Result<u32, String> {
+    if vertex_stride == 0 {
+        return Err(String::from("Quad vertex stride must not be 0"));
+    }
+    if data_len == 0 {
+        return Err(String::from("Quad vertex data must not be empty"));
+    }
+    if data_len % (vertex_stride as usize) != 0 {
+        return Err(format!("Quad vertex data length {:?} is not a multiple of the vertex stride {:?}", data_len, vertex_stride));
+    }
+
+    let vertex_count = data_len / (vertex_stride as usize);
+    if vertex_count % 4 != 0 {
+        return Err(format!("Quad vertex count {:?} is not a multiple of 4", vertex_count));
+    }
+    u32::try_from(vertex_count / 4).map_err(|_| format!("Quad vertex count {:?} is too large", vertex_count))
+}
+
+/// Generates the triangle list indices for a number of quads. The vertices of each quad are split
+/// into the triangles (0, 1, 2) and (2, 3, 0).
+fn generate_quad_indices(quad_count: u32) -> Vec<u32> {
+    let mut indices = Vec::with_capacity((quad_count as usize) * 6);
+    for quad in 0..quad_count {
+        let base = quad * 4;
+        indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the signed area of a triangle.
+    fn triangle_area(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+        ((b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1])) / 2.0
+    }
+
+    #[test]
+    fn quad_covered_by_two_triangles() {
+        let indices = generate_quad_indices(2);
+        assert_eq!(indices, vec![0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]);
+
+        // A 2x3 quad in counter clockwise order
+        let positions = [[0.0f32, 0.0], [2.0, 0.0], [2.0, 3.0], [0.0, 3.0]];
+        let areas: Vec<_> = indices[0..6].chunks_exact(3).map(|triangle| {
+            triangle_area(positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize])
+        }).collect();
+
+        // Both triangles keep the winding of the quad and together cover its full area
+        assert!(areas.iter().all(|area| *area > 0.0));
+        assert_eq!(areas.iter().sum::<f32>(), 6.0);
+
+        let cache = QuadIndexCache::new();
+        let data = cache.get_indices(3);
+        assert!(data.len() >= 3 * 6 * 4);
+        assert!(Arc::ptr_eq(&data, &cache.get_indices(2)));
+    }
+
+    #[test]
+    fn quad_count_validation() {
+        assert_eq!(get_quad_count(12 * 16, 16), Ok(3));
+        assert!(get_quad_count(0, 16).is_err());
+        assert!(get_quad_count(64, 0).is_err());
+
+        // 4 vertices and 8 trailing bytes
+        assert!(get_quad_count(4 * 16 + 8, 16).is_err());
+        assert!(get_quad_count(6 * 16, 16).is_err());
+    }
+}
@@ -0,0 +1,537 @@
+//! Readback of global object content into host memory.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::global_objects::CubeFace;
+use crate::renderer::emulator::worker::EmulatorTaskError;
+
+/// Describes a region of a [`GlobalImage`](super::GlobalImage) which should be read back into host
+/// memory.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ImageReadRegion {
+    /// The byte offset in the destination buffer the region is written to. Must be a multiple of
+    /// the texel size of the image format and 4.
+    pub dst_offset: usize,
+
+    /// The stride between 2 rows of image data in texels. If 0 the data is assumed to be tightly packed.
+    pub row_stride: u32,
+
+    /// The offset of the read region in the image.
+    pub offset: Vec2u32,
+
+    /// The size of the read region in the image.
+    pub extent: Vec2u32,
+
+    /// The mip level to read from. The offset and extent are relative to the size of the mip level.
+    pub mip_level: u32,
+
+    /// The array layer to read from. For cube maps this is the index of the face.
+    pub layer: u32,
+}
+
+impl ImageReadRegion {
+    pub fn new_full(size: Vec2u32) -> Self {
+        Self {
+            dst_offset: 0,
+            row_stride: 0,
+            offset: Vec2u32::new(0, 0),
+            extent: size,
+            mip_level: 0,
+            layer: 0,
+        }
+    }
+
+    /// Creates a region covering a full mip level of the given size.
+    pub fn new_mip(mip_level: u32, mip_size: Vec2u32) -> Self {
+        Self {
+            dst_offset: 0,
+            row_stride: 0,
+            offset: Vec2u32::new(0, 0),
+            extent: mip_size,
+            mip_level,
+            layer: 0,
+        }
+    }
+
+    /// Creates a region covering a full face of a cube map.
+    pub fn new_face(dst_offset: usize, face: CubeFace, face_size: u32) -> Self {
+        Self {
+            dst_offset,
+            row_stride: 0,
+            offset: Vec2u32::new(0, 0),
+            extent: Vec2u32::new(face_size, face_size),
+            mip_level: 0,
+            layer: face.get_layer(),
+        }
+    }
+
+    pub fn new_extent(dst_offset: usize, offset: Vec2u32, extent: Vec2u32) -> Self {
+        Self {
+            dst_offset,
+            row_stride: 0,
+            offset,
+            extent,
+            mip_level: 0,
+            layer: 0,
+        }
+    }
+
+    /// Returns the number of bytes written into the destination buffer starting at
+    /// [`ImageReadRegion::dst_offset`] for a format with the given texel size.
+    pub fn get_byte_size(&self, texel_size: u32) -> usize {
+        if self.extent[0] == 0 || self.extent[1] == 0 {
+            return 0;
+        }
+
+        let row_length = if self.row_stride == 0 { self.extent[0] } else { self.row_stride };
+        ((self.extent[1] as usize - 1) * (row_length as usize) + (self.extent[0] as usize)) * (texel_size as usize)
+    }
+}
+
+/// The order in which the color channels of a 4 channel 8 bit format are stored in memory.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+}
+
+impl ChannelOrder {
+    /// Returns the channel order of a format. Formats which are not stored in BGRA order are
+    /// reported as [`ChannelOrder::Rgba`].
+    pub fn from_format(format: vk::Format) -> Self {
+        match format {
+            vk::Format::B8G8R8A8_UNORM |
+            vk::Format::B8G8R8A8_SNORM |
+            vk::Format::B8G8R8A8_USCALED |
+            vk::Format::B8G8R8A8_SSCALED |
+            vk::Format::B8G8R8A8_UINT |
+            vk::Format::B8G8R8A8_SINT |
+            vk::Format::B8G8R8A8_SRGB => Self::Bgra,
+            _ => Self::Rgba,
+        }
+    }
+
+    /// Converts tightly packed 4 byte texels stored in this channel order into RGBA order in place.
+    pub fn swizzle_to_rgba(&self, data: &mut [u8]) {
+        if *self == Self::Bgra {
+            for texel in data.chunks_exact_mut(4) {
+                texel.swap(0, 2);
+            }
+        }
+    }
+}
+
+/// Decodes a 16 bit half precision float.
+pub fn f16_to_f32(bits: u16) -> f32 {
+    decode_small_float((bits & 0x8000) != 0, ((bits >> 10) & 0x1F) as u32, (bits & 0x3FF) as u32, 10)
+}
+
+/// Decodes tightly packed `R16G16B16A16_SFLOAT` texels stored in native byte order.
+pub fn decode_r16g16b16a16_sfloat(data: &[u8]) -> Box<[Vec4f32]> {
+    data.chunks_exact(8).map(|texel| {
+        let channel = |i: usize| f16_to_f32(u16::from_ne_bytes([texel[i * 2], texel[i * 2 + 1]]));
+        Vec4f32::new(channel(0), channel(1), channel(2), channel(3))
+    }).collect()
+}
+
+/// Decodes tightly packed `B10G11R11_UFLOAT_PACK32` texels stored in native byte order. The
+/// channels are returned in RGB order.
+pub fn decode_b10g11r11_ufloat(data: &[u8]) -> Box<[Vec3f32]> {
+    data.chunks_exact(4).map(|texel| {
+        let packed = u32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]);
+        let r = packed & 0x7FF;
+        let g = (packed >> 11) & 0x7FF;
+        let b = (packed >> 22) & 0x3FF;
+        Vec3f32::new(
+            decode_small_float(false, r >> 6, r & 0x3F, 6),
+            decode_small_float(false, g >> 6, g & 0x3F, 6),
+            decode_small_float(false, b >> 5, b & 0x1F, 5)
+        )
+    }).collect()
+}
+
+/// Decodes a float with a 5 bit exponent and the specified number of mantissa bits.
+fn decode_small_float(negative: bool, exponent: u32, mantissa: u32, mantissa_bits: u32) -> f32 {
+    let mantissa_scale = (1u32 << mantissa_bits) as f32;
+    let value = match exponent {
+        0 => (mantissa as f32 / mantissa_scale) * 2f32.powi(-14),
+        31 => if mantissa == 0 { f32::INFINITY } else { f32::NAN },
+        _ => (1.0 + (mantissa as f32 / mantissa_scale)) * 2f32.powi(exponent as i32 - 15),
+    };
+
+    if negative { -value } else { value }
+}
+
+/// Initializes the staging memory of a readback before any copy is recorded.
+///
+/// The gpu only writes the bytes covered by the read regions. All other bytes of the staging
+/// memory are returned to the caller unchanged. If `dst` is [`Some`] its content is copied into
+/// the staging memory so that uncovered bytes keep their previous value, otherwise the staging
+/// memory is zeroed.
+pub(super) fn prefill_staging(staging: &mut [u8], dst: Option<&[u8]>) {
+    match dst {
+        Some(dst) => staging.copy_from_slice(dst),
+        None => staging.fill(0),
+    }
+}
+
+/// A handle to a pending readback. The result becomes available once the gpu has finished
+/// executing the readback.
+pub struct ImageReadToken {
+    state: Arc<ReadState>,
+    channel_order: ChannelOrder,
+}
+
+impl ImageReadToken {
+    pub(super) fn new(channel_order: ChannelOrder) -> (Self, Arc<ReadState>) {
+        let state = Arc::new(ReadState {
+            data: Mutex::new(None),
+            signal: Condvar::new(),
+        });
+
+        (Self { state: state.clone(), channel_order }, state)
+    }
+
+    /// Returns the channel order of the data returned by [`ImageReadToken::wait`].
+    pub fn channel_order(&self) -> ChannelOrder {
+        self.channel_order
+    }
+
+    /// Returns true if the readback has completed and [`ImageReadToken::wait`] will not block.
+    pub fn is_complete(&self) -> bool {
+        self.state.data.lock().unwrap().is_some()
+    }
+
+    /// Blocks until the readback has completed and returns the read data. Panics if the readback
+    /// failed, see [`ImageReadToken::try_wait`].
+    pub fn wait(self) -> Box<[u8]> {
+        self.try_wait().unwrap_or_else(|err| {
+            log::error!("Image readback failed: {:?}", err);
+            panic!()
+        })
+    }
+
+    /// Blocks until the readback has completed and returns the read data. If the worker rejected
+    /// the readback the error is returned instead. The same error is also reported by
+    /// [`EmulatorRenderer::take_errors`](super::EmulatorRenderer::take_errors).
+    pub fn try_wait(self) -> Result<Box<[u8]>, EmulatorTaskError> {
+        let mut guard = self.state.data.lock().unwrap();
+        loop {
+            if let Some(result) = guard.take() {
+                return result;
+            }
+            guard = self.state.signal.wait(guard).unwrap();
+        }
+    }
+
+    /// Blocks until the readback has completed and returns the first 4 bytes of the read data as
+    /// a native endian `u32`. Used for single texel reads of `R32_UINT` or `R32_SINT` images.
+    pub fn wait_u32(self) -> u32 {
+        let data = self.wait();
+        u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+    }
+
+    /// Blocks until the readback has completed and returns the read data converted into RGBA
+    /// channel order. Must only be used if the data consists of tightly packed 4 byte texels.
+    pub fn wait_rgba(self) -> Box<[u8]> {
+        let channel_order = self.channel_order;
+        let mut data = self.wait();
+        channel_order.swizzle_to_rgba(&mut data);
+        data
+    }
+
+    /// Blocks until the readback has completed or the timeout has elapsed. If the timeout elapsed
+    /// the token is returned.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<Box<[u8]>, Self> {
+        let guard = self.state.data.lock().unwrap();
+        let (mut guard, _) = self.state.signal.wait_timeout_while(guard, timeout, |data| data.is_none()).unwrap();
+
+        if let Some(result) = guard.take() {
+            Ok(result.unwrap_or_else(|err| {
+                log::error!("Image readback failed: {:?}", err);
+                panic!()
+            }))
+        } else {
+            drop(guard);
+            Err(self)
+        }
+    }
+}
+
+/// A handle to multiple readbacks which have been submitted together. See
+/// [`EmulatorRenderer::read_images`](super::EmulatorRenderer::read_images).
+pub struct ImageBatchReadToken {
+    tokens: Box<[ImageReadToken]>,
+}
+assert_impl_all!(ImageReadToken: Send, Sync);
+assert_impl_all!(ImageBatchReadToken: Send, Sync);
+assert_impl_all!(ReadTokenSet: Send, Sync);
+
+impl ImageBatchReadToken {
+    pub(super) fn new(tokens: Box<[ImageReadToken]>) -> Self {
+        Self {
+            tokens
+        }
+    }
+
+    /// Returns the number of readbacks in this batch.
+    pub fn get_read_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns the channel order of the data of a readback in this batch.
+    pub fn channel_order(&self, index: usize) -> ChannelOrder {
+        self.tokens[index].channel_order()
+    }
+
+    /// Returns true if all readbacks have completed and [`ImageBatchReadToken::wait`] will not
+    /// block.
+    pub fn is_complete(&self) -> bool {
+        self.tokens.iter().all(ImageReadToken::is_complete)
+    }
+
+    /// Blocks until all readbacks have completed and returns the read data in the order the
+    /// reads were passed.
+    pub fn wait(self) -> Box<[Box<[u8]>]> {
+        self.tokens.into_vec().into_iter().map(ImageReadToken::wait).collect()
+    }
+}
+
+/// Collects readbacks which have been started independently so that they can be awaited together.
+///
+/// Readbacks are submitted together with the pass following the last use of the image, or with
+/// [`EmulatorRenderer::flush`](super::EmulatorRenderer::flush), and the data is copied into host
+/// memory by the worker once that submission has completed. All pass recorders using the images
+/// must therefore have been dropped and another pass or a flush submitted before calling
+/// [`ReadTokenSet::await_all`] or it will never return.
+pub struct ReadTokenSet {
+    tokens: Vec<ImageReadToken>,
+}
+
+impl ReadTokenSet {
+    pub fn new() -> Self {
+        Self {
+            tokens: Vec::new()
+        }
+    }
+
+    /// Adds a readback to the set and returns its index in the data returned by
+    /// [`ReadTokenSet::await_all`].
+    pub fn add(&mut self, token: ImageReadToken) -> usize {
+        self.tokens.push(token);
+        self.tokens.len() - 1
+    }
+
+    /// Adds all readbacks of a batch to the set and returns the index of the first one. The
+    /// readbacks keep their order.
+    pub fn add_batch(&mut self, batch: ImageBatchReadToken) -> usize {
+        let first = self.tokens.len();
+        self.tokens.extend(batch.tokens.into_vec());
+        first
+    }
+
+    /// Returns the number of readbacks in this set.
+    pub fn get_read_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns the channel order of the data of a readback in this set.
+    pub fn channel_order(&self, index: usize) -> ChannelOrder {
+        self.tokens[index].channel_order()
+    }
+
+    /// Returns true if all readbacks have completed and [`ReadTokenSet::await_all`] will not
+    /// block.
+    pub fn is_complete(&self) -> bool {
+        self.tokens.iter().all(ImageReadToken::is_complete)
+    }
+
+    /// Blocks until all readbacks have completed and returns the read data in the order the
+    /// readbacks were added.
+    ///
+    /// The readbacks complete independently on the worker thread so the total time spent waiting
+    /// is that of the slowest readback.
+    pub fn await_all(self) -> Box<[Box<[u8]>]> {
+        self.tokens.into_iter().map(ImageReadToken::wait).collect()
+    }
+}
+
+impl Default for ReadTokenSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(super) struct ReadState {
+    data: Mutex<Option<Result<Box<[u8]>, EmulatorTaskError>>>,
+    signal: Condvar,
+}
+
+impl ReadState {
+    pub(super) fn complete(&self, data: Box<[u8]>) {
+        self.set_result(Ok(data));
+    }
+
+    /// Completes the read without data. Waiting on the token returns the error.
+    pub(super) fn fail(&self, error: EmulatorTaskError) {
+        self.set_result(Err(error));
+    }
+
+    fn set_result(&self, result: Result<Box<[u8]>, EmulatorTaskError>) {
+        *self.data.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned read state mutex in ReadState::set_result");
+            panic!()
+        }) = Some(result);
+        self.signal.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Emulates the gpu copy of a region into the staging memory.
+    fn copy_region(staging: &mut [u8], offset: usize, data: &[u8]) {
+        staging[offset..(offset + data.len())].copy_from_slice(data);
+    }
+
+    #[test]
+    fn batch_completes_with_all_reads() {
+        let (tokens, states): (Vec<_>, Vec<_>) = (0..3).map(|_| ImageReadToken::new(ChannelOrder::Rgba)).unzip();
+        let batch = ImageBatchReadToken::new(tokens.into_boxed_slice());
+        assert_eq!(batch.get_read_count(), 3);
+
+        states[0].complete(Box::new([1u8; 4]));
+        states[2].complete(Box::new([3u8; 4]));
+        assert!(!batch.is_complete());
+
+        // All copies are part of the same submission so they complete together
+        states[1].complete(Box::new([2u8; 4]));
+        assert!(batch.is_complete());
+
+        let data = batch.wait();
+        assert_eq!(data.len(), 3);
+        for (index, data) in data.iter().enumerate() {
+            assert!(data.iter().all(|b| *b == (index as u8 + 1)));
+        }
+    }
+
+    #[test]
+    fn region_byte_size() {
+        assert_eq!(ImageReadRegion::new_extent(0, Vec2u32::new(0, 0), Vec2u32::new(4, 2)).get_byte_size(4), 32);
+        assert_eq!(ImageReadRegion::new_extent(0, Vec2u32::new(0, 0), Vec2u32::new(4, 0)).get_byte_size(4), 0);
+
+        // The last row is not padded to the full stride
+        let mut region = ImageReadRegion::new_extent(0, Vec2u32::new(0, 0), Vec2u32::new(4, 3));
+        region.row_stride = 8;
+        assert_eq!(region.get_byte_size(2), ((2 * 8) + 4) * 2);
+    }
+
+    #[test]
+    fn pixel_read_returns_u32() {
+        let (token, state) = ImageReadToken::new(ChannelOrder::Rgba);
+        state.complete(Box::new(0xDEADBEEFu32.to_ne_bytes()));
+        assert_eq!(token.wait_u32(), 0xDEADBEEF);
+
+        // Signed ids are returned with their bits reinterpreted
+        let (token, state) = ImageReadToken::new(ChannelOrder::Rgba);
+        state.complete(Box::new((-2i32).to_ne_bytes()));
+        assert_eq!(token.wait_u32() as i32, -2);
+    }
+
+    #[test]
+    fn set_awaits_all_reads() {
+        let mut set = ReadTokenSet::new();
+        let mut states = Vec::new();
+        for order in [ChannelOrder::Rgba, ChannelOrder::Bgra] {
+            let (token, state) = ImageReadToken::new(order);
+            assert_eq!(set.add(token), states.len());
+            states.push(state);
+        }
+
+        let (tokens, batch_states): (Vec<_>, Vec<_>) = (0..2).map(|_| ImageReadToken::new(ChannelOrder::Rgba)).unzip();
+        assert_eq!(set.add_batch(ImageBatchReadToken::new(tokens.into_boxed_slice())), 2);
+        states.extend(batch_states);
+
+        assert_eq!(set.get_read_count(), 4);
+        assert_eq!(set.channel_order(1), ChannelOrder::Bgra);
+        assert!(!set.is_complete());
+
+        // Emulates the worker completing all reads of a submission
+        let worker = std::thread::spawn(move || {
+            for (index, state) in states.iter().enumerate() {
+                state.complete(vec![index as u8; 4].into_boxed_slice());
+            }
+        });
+
+        let data = set.await_all();
+        worker.join().unwrap();
+        assert_eq!(data.len(), 4);
+        for (index, data) in data.iter().enumerate() {
+            assert!(data.iter().all(|b| *b == index as u8));
+        }
+    }
+
+    #[test]
+    fn zeroed_uncovered_tail() {
+        let mut staging = vec![0xAAu8; 64];
+        prefill_staging(&mut staging, None);
+        copy_region(&mut staging, 0, &[1u8; 16]);
+
+        assert!(staging[0..16].iter().all(|b| *b == 1));
+        assert!(staging[16..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn prefilled_uncovered_tail() {
+        let dst = vec![7u8; 64];
+        let mut staging = vec![0u8; 64];
+        prefill_staging(&mut staging, Some(&dst));
+        copy_region(&mut staging, 0, &[1u8; 16]);
+
+        assert!(staging[0..16].iter().all(|b| *b == 1));
+        assert!(staging[16..].iter().all(|b| *b == 7));
+    }
+
+    #[test]
+    fn hdr_float_values() {
+        assert_eq!(crate::util::format::Format::R16G16B16A16_SFLOAT.get_compatibility_class().get_texel_size(), Some(8));
+        assert_eq!(crate::util::format::Format::B10G11R11_UFLOAT_PACK32.get_compatibility_class().get_texel_size(), Some(4));
+
+        // 2.5, 16.0, -3.0 and 1.0 as written by the gpu into a R16G16B16A16_SFLOAT image
+        let mut data = Vec::new();
+        for bits in [0x4100u16, 0x4C00u16, 0xC200u16, 0x3C00u16] {
+            data.extend_from_slice(&bits.to_ne_bytes());
+        }
+        let texels = decode_r16g16b16a16_sfloat(&data);
+        assert_eq!(texels.len(), 1);
+        assert_eq!(texels[0], Vec4f32::new(2.5, 16.0, -3.0, 1.0));
+
+        // R = 2.5, G = 1.0, B = 4.0
+        let packed = 1040u32 | (960u32 << 11) | (544u32 << 22);
+        let texels = decode_b10g11r11_ufloat(&packed.to_ne_bytes());
+        assert_eq!(texels[0], Vec3f32::new(2.5, 1.0, 4.0));
+
+        assert_eq!(f16_to_f32(0x7C00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+    }
+
+    #[test]
+    fn bgra_swizzle_reports_red() {
+        let order = ChannelOrder::from_format(vk::Format::B8G8R8A8_UNORM);
+        assert_eq!(order, ChannelOrder::Bgra);
+
+        // A single red pixel as stored in a BGRA image
+        let mut data = [0u8, 0u8, 255u8, 255u8];
+        order.swizzle_to_rgba(&mut data);
+        assert_eq!(data, [255u8, 0u8, 0u8, 255u8]);
+
+        let mut data = [255u8, 0u8, 0u8, 255u8];
+        ChannelOrder::from_format(vk::Format::R8G8B8A8_UNORM).swizzle_to_rgba(&mut data);
+        assert_eq!(data, [255u8, 0u8, 0u8, 255u8]);
+    }
+}
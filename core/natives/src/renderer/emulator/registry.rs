@@ -0,0 +1,87 @@
+//! A registry of global images which can be dropped in bulk.
+
+use std::collections::HashMap;
+
+use ash::vk;
+
+use crate::renderer::emulator::global_objects::GlobalImageId;
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+/// Information about a image stored in the [`ImageRegistry`].
+#[derive(Clone, Debug)]
+pub struct ImageInfo {
+    pub id: GlobalImageId,
+    pub size: Vec2u32,
+    pub format: &'static Format,
+
+    /// The memory property flags of the memory the image has been placed in.
+    pub memory_properties: vk::MemoryPropertyFlags,
+
+    /// A user defined tag used to select images when dropping them.
+    pub tag: Option<String>,
+}
+
+/// Keeps registered images alive until they are removed from the registry.
+pub(super) struct ImageRegistry<T> {
+    images: HashMap<GlobalImageId, (ImageInfo, T)>,
+}
+
+impl<T> ImageRegistry<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            images: HashMap::new(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, info: ImageInfo, image: T) {
+        self.images.insert(info.id, (info, image));
+    }
+
+    /// Returns the number of registered images.
+    pub(super) fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Removes all images for which the predicate returns true and returns them. The images are
+    /// returned so that they can be dropped after any lock protecting the registry is released.
+    pub(super) fn remove_where<F: Fn(&ImageInfo) -> bool>(&mut self, predicate: F) -> Vec<T> {
+        let ids: Vec<_> = self.images.values().filter(|(info, _)| predicate(info)).map(|(info, _)| info.id).collect();
+        ids.into_iter().filter_map(|id| self.images.remove(&id)).map(|(_, image)| image).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_info(tag: &str) -> ImageInfo {
+        ImageInfo {
+            id: GlobalImageId::new(),
+            size: Vec2u32::new(16, 16),
+            format: &Format::R8G8B8A8_SRGB,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            tag: Some(String::from(tag))
+        }
+    }
+
+    #[test]
+    fn drop_tagged_images() {
+        let mut registry = ImageRegistry::new();
+        for i in 0..3u32 {
+            registry.insert(make_info("blocks"), i);
+        }
+        for i in 3..5u32 {
+            registry.insert(make_info("entities"), i);
+        }
+
+        let mut removed = registry.remove_where(|info| info.tag.as_deref() == Some("blocks"));
+        removed.sort();
+        assert_eq!(removed, vec![0, 1, 2]);
+        assert_eq!(registry.len(), 2);
+
+        assert!(registry.remove_where(|info| info.tag.as_deref() == Some("blocks")).is_empty());
+        assert_eq!(registry.len(), 2);
+    }
+}
@@ -0,0 +1,248 @@
+//! A cache sharing samplers between all global images.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use ash::vk;
+
+use crate::renderer::emulator::global_objects::SamplerInfo;
+
+use crate::prelude::*;
+
+/// The maximum anisotropy used by samplers with anisotropic filtering enabled. Clamped to the
+/// maxSamplerAnisotropy limit of the device.
+const MAX_ANISOTROPY: f32 = 16f32;
+
+/// Replaces the filtering parameters of all samplers created by the emulator. Used to implement a
+/// global texture filtering setting without changing the sampler info of every texture.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FilterOverride {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub anisotropy_enable: bool,
+}
+
+impl FilterOverride {
+    /// Returns the sampler info with the filtering parameters replaced by the override. The
+    /// address modes are kept.
+    pub fn apply(&self, sampler_info: &SamplerInfo) -> SamplerInfo {
+        SamplerInfo {
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_mode: self.mipmap_mode,
+            anisotropy_enable: self.anisotropy_enable,
+            ..*sampler_info
+        }
+    }
+}
+
+/// Shares a single [`vk::Sampler`] between all users of the same [`SamplerInfo`]. Samplers are
+/// reference counted and destroyed once the last user releases them.
+pub(super) struct SamplerCache {
+    samplers: Mutex<RefCountedMap<SamplerInfo, vk::Sampler>>,
+    filter_override: Mutex<Option<FilterOverride>>,
+}
+
+impl SamplerCache {
+    pub(super) fn new() -> Self {
+        Self {
+            samplers: Mutex::new(RefCountedMap::new()),
+            filter_override: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn set_filter_override(&self, filter_override: Option<FilterOverride>) {
+        *self.filter_override.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned filter override mutex in SamplerCache::set_filter_override");
+            panic!()
+        }) = filter_override;
+    }
+
+    /// Returns the sampler info which must be used to create a sampler for the requested info. If a
+    /// filter override is set it is applied to the info.
+    pub(super) fn resolve_info(&self, sampler_info: &SamplerInfo) -> SamplerInfo {
+        let filter_override = *self.filter_override.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned filter override mutex in SamplerCache::resolve_info");
+            panic!()
+        });
+
+        match filter_override {
+            Some(filter_override) => filter_override.apply(sampler_info),
+            None => *sampler_info,
+        }
+    }
+
+    /// Returns a sampler for the info and increments its reference count. Every call must be
+    /// matched by a call to [`SamplerCache::release`].
+    pub(super) fn acquire(&self, device: &DeviceContext, sampler_info: &SamplerInfo) -> vk::Sampler {
+        let mut guard = self.samplers.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned sampler cache mutex in SamplerCache::acquire");
+            panic!()
+        });
+
+        guard.acquire(*sampler_info, || Self::create_sampler(device, sampler_info))
+    }
+
+    /// Decrements the reference count of the sampler for the info and destroys it if it is no
+    /// longer used.
+    pub(super) fn release(&self, device: &DeviceContext, sampler_info: &SamplerInfo) {
+        let mut guard = self.samplers.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned sampler cache mutex in SamplerCache::release");
+            panic!()
+        });
+
+        if let Some(sampler) = guard.release(sampler_info) {
+            unsafe {
+                device.vk().destroy_sampler(sampler, device.get_allocation_callbacks());
+            }
+        }
+    }
+
+    /// Creates a sampler for the info. Anisotropic filtering is only enabled if the
+    /// samplerAnisotropy feature is enabled on the device.
+    fn create_sampler(device: &DeviceContext, sampler_info: &SamplerInfo) -> vk::Sampler {
+        let anisotropy_enable = sampler_info.anisotropy_enable && device.supports_sampler_anisotropy();
+        let max_anisotropy = clamp_max_anisotropy(MAX_ANISOTROPY, device.get_limits().max_sampler_anisotropy);
+
+        let info = vk::SamplerCreateInfo::builder()
+            .mag_filter(sampler_info.mag_filter)
+            .min_filter(sampler_info.min_filter)
+            .mipmap_mode(sampler_info.mipmap_mode)
+            .address_mode_u(sampler_info.address_mode_u)
+            .address_mode_v(sampler_info.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .mip_lod_bias(0f32)
+            .anisotropy_enable(anisotropy_enable)
+            .max_anisotropy(max_anisotropy)
+            .compare_enable(false)
+            .min_lod(0f32)
+            .max_lod(vk::LOD_CLAMP_NONE)
+            .unnormalized_coordinates(false);
+
+        unsafe {
+            device.vk().create_sampler(&info, device.get_allocation_callbacks())
+        }.unwrap_or_else(|err| {
+            log::error!("vkCreateSampler returned {:?} in SamplerCache::create_sampler", err);
+            panic!()
+        })
+    }
+}
+
+/// Clamps the anisotropy to the range between 1.0 and the maxSamplerAnisotropy limit.
+fn clamp_max_anisotropy(anisotropy: f32, limit: f32) -> f32 {
+    anisotropy.min(limit).max(1f32)
+}
+
+/// A map of reference counted values.
+struct RefCountedMap<K, V> {
+    entries: HashMap<K, (V, usize)>,
+}
+
+impl<K: Hash + Eq, V: Copy> RefCountedMap<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns the value for the key and increments its reference count. If no value exists it
+    /// is created.
+    fn acquire<F: FnOnce() -> V>(&mut self, key: K, create: F) -> V {
+        let entry = self.entries.entry(key).or_insert_with(|| (create(), 0));
+        entry.1 += 1;
+        entry.0
+    }
+
+    /// Decrements the reference count of the key. Returns the value if it is no longer referenced.
+    fn release(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key).unwrap_or_else(|| {
+            log::error!("Called RefCountedMap::release for key which is not present");
+            panic!()
+        });
+
+        entry.1 -= 1;
+        if entry.1 == 0 {
+            self.entries.remove(key).map(|(value, _)| value)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_info(filter: vk::Filter) -> SamplerInfo {
+        SamplerInfo {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            anisotropy_enable: false
+        }
+    }
+
+    #[test]
+    fn identical_info_shares_sampler() {
+        let mut map = RefCountedMap::new();
+        let mut next_handle = 1u64;
+        let mut create = || {
+            let handle = next_handle;
+            next_handle += 1;
+            handle
+        };
+
+        let a = map.acquire(make_info(vk::Filter::LINEAR), &mut create);
+        let b = map.acquire(make_info(vk::Filter::LINEAR), &mut create);
+        let c = map.acquire(make_info(vk::Filter::NEAREST), &mut create);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        assert_eq!(map.release(&make_info(vk::Filter::LINEAR)), None);
+        assert_eq!(map.release(&make_info(vk::Filter::LINEAR)), Some(a));
+        assert_eq!(map.release(&make_info(vk::Filter::NEAREST)), Some(c));
+    }
+
+    #[test]
+    fn nearest_override_replaces_linear() {
+        let cache = SamplerCache::new();
+        let linear = SamplerInfo {
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..make_info(vk::Filter::LINEAR)
+        };
+        assert_eq!(cache.resolve_info(&linear), linear);
+
+        cache.set_filter_override(Some(FilterOverride {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            anisotropy_enable: false
+        }));
+        let resolved = cache.resolve_info(&linear);
+        assert_eq!(resolved.mag_filter, vk::Filter::NEAREST);
+        assert_eq!(resolved.min_filter, vk::Filter::NEAREST);
+        assert_eq!(resolved.mipmap_mode, vk::SamplerMipmapMode::NEAREST);
+        assert_eq!(resolved.address_mode_u, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+
+        // Textures sampled with the override must not share the linear baseline sampler
+        let mut map = RefCountedMap::new();
+        let baseline = map.acquire(linear, || 1u64);
+        let overridden = map.acquire(resolved, || 2u64);
+        assert_ne!(baseline, overridden);
+
+        cache.set_filter_override(None);
+        assert_eq!(cache.resolve_info(&linear), linear);
+    }
+
+    #[test]
+    fn max_anisotropy_clamped_to_limit() {
+        assert_eq!(clamp_max_anisotropy(16f32, 16f32), 16f32);
+        assert_eq!(clamp_max_anisotropy(16f32, 4f32), 4f32);
+        assert_eq!(clamp_max_anisotropy(16f32, 0f32), 1f32);
+        assert_eq!(clamp_max_anisotropy(0f32, 16f32), 1f32);
+    }
+}
@@ -1,30 +1,55 @@
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 use std::panic::RefUnwindSafe;
 use std::collections::{HashMap, VecDeque};
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use ash::vk;
 
+use crate::renderer::emulator::blit::FullscreenBlit;
+use crate::renderer::emulator::breadcrumbs::BreadcrumbLog;
+use crate::renderer::emulator::capture::CapturedTask;
+use crate::renderer::emulator::budget::MemoryBudget;
 use crate::renderer::emulator::descriptors::DescriptorPool;
-use crate::renderer::emulator::worker::WorkerTask;
+use crate::renderer::emulator::pass::PassId;
+use crate::renderer::emulator::quads::QuadIndexCache;
+use crate::renderer::emulator::samplers::SamplerCache;
+use crate::renderer::emulator::worker::{EmulatorTaskError, WorkerTask};
 use crate::renderer::emulator::mc_shaders::{McUniform, Shader, ShaderId, VertexFormat};
 
 use crate::prelude::*;
-use crate::renderer::emulator::immediate::{ImmediateBuffer, ImmediatePool};
+use crate::renderer::emulator::immediate::{ImmediateBuffer, ImmediatePool, ImmediateRing};
 use crate::renderer::emulator::staging::StagingMemoryPool;
+use crate::util::id::UUIDSource;
 
 pub(super) struct Share {
     id: UUID,
     device: Arc<DeviceContext>,
     current_pass: AtomicU64,
+    completed_pass: AtomicU64,
+    frame_counter: FrameCounter,
+    pass_limiter: PassLimiter,
 
     staging_memory: Mutex<StagingMemoryPool>,
+    memory_budget: MemoryBudget,
+    sampler_cache: SamplerCache,
+    quad_indices: QuadIndexCache,
+    fullscreen_blit: FullscreenBlit,
+    breadcrumbs: BreadcrumbLog,
+    capture: Mutex<Option<Vec<CapturedTask>>>,
+    /// True while a capture is active. Allows [`Share::capture_task`] to skip locking the capture
+    /// mutex in the common case.
+    capture_active: AtomicBool,
+    resource_validation: AtomicBool,
     immediate_buffers: ImmediatePool,
+    immediate_ring: Mutex<Option<Arc<ImmediateRing>>>,
     shader_database: Mutex<HashMap<ShaderId, Arc<Shader>>>,
     descriptors: Mutex<DescriptorPool>,
     channel: Mutex<Channel>,
     signal: Condvar,
+    errors: Mutex<Vec<EmulatorTaskError>>,
+    object_ids: UUIDSource,
 }
+assert_impl_all!(Share: Send, Sync);
 
 impl Share {
     const PASS_ID_ACTIVE_BIT: u64 = 1u64 << 63;
@@ -35,18 +60,33 @@ impl Share {
         let staging_memory = StagingMemoryPool::new(device.clone());
         let immediate_buffers = ImmediatePool::new(device.clone());
         let descriptors = Mutex::new(DescriptorPool::new(device.clone()));
+        let fullscreen_blit = FullscreenBlit::new(device.clone());
 
         Self {
             id: UUID::new(),
             device,
             current_pass: AtomicU64::new(0),
+            completed_pass: AtomicU64::new(0),
+            frame_counter: FrameCounter::new(),
+            pass_limiter: PassLimiter::new(),
 
             staging_memory: Mutex::new(staging_memory),
+            memory_budget: MemoryBudget::new(),
+            sampler_cache: SamplerCache::new(),
+            quad_indices: QuadIndexCache::new(),
+            fullscreen_blit,
+            breadcrumbs: BreadcrumbLog::new(),
+            capture: Mutex::new(None),
+            capture_active: AtomicBool::new(false),
+            resource_validation: AtomicBool::new(cfg!(debug_assertions)),
             immediate_buffers,
+            immediate_ring: Mutex::new(None),
             shader_database: Mutex::new(HashMap::new()),
             descriptors,
             channel: Mutex::new(Channel::new()),
             signal: Condvar::new(),
+            errors: Mutex::new(Vec::new()),
+            object_ids: UUIDSource::new(),
         }
     }
 
@@ -58,8 +98,86 @@ impl Share {
         &self.staging_memory
     }
 
+    pub(super) fn get_memory_budget(&self) -> &MemoryBudget {
+        &self.memory_budget
+    }
+
+    pub(super) fn get_sampler_cache(&self) -> &SamplerCache {
+        &self.sampler_cache
+    }
+
+    /// Returns `UINT32` index data to draw at least `quad_count` quads as triangle lists.
+    pub(super) fn get_quad_indices(&self, quad_count: u32) -> Arc<[u8]> {
+        self.quad_indices.get_indices(quad_count)
+    }
+
+    pub(super) fn get_fullscreen_blit(&self) -> &FullscreenBlit {
+        &self.fullscreen_blit
+    }
+
+    pub(super) fn get_breadcrumbs(&self) -> &BreadcrumbLog {
+        &self.breadcrumbs
+    }
+
+    pub(super) fn get_frame_counter(&self) -> &FrameCounter {
+        &self.frame_counter
+    }
+
+    /// Starts recording captured tasks. Returns false if a capture is already active.
+    pub(super) fn begin_capture(&self) -> bool {
+        let mut guard = self.lock_capture();
+        if guard.is_some() {
+            return false;
+        }
+        *guard = Some(Vec::new());
+        self.capture_active.store(true, Ordering::Release);
+        true
+    }
+
+    /// Stops the active capture and returns the recorded tasks. Returns [`None`] if no capture is
+    /// active.
+    pub(super) fn end_capture(&self) -> Option<Vec<CapturedTask>> {
+        let mut guard = self.lock_capture();
+        self.capture_active.store(false, Ordering::Release);
+        guard.take()
+    }
+
+    /// Records a task if a capture is active. The task is only created if it is recorded.
+    pub(super) fn capture_task<F: FnOnce() -> CapturedTask>(&self, task: F) {
+        if !self.capture_active.load(Ordering::Acquire) {
+            return;
+        }
+        if let Some(tasks) = self.lock_capture().as_mut() {
+            tasks.push(task());
+        }
+    }
+
+    fn lock_capture(&self) -> std::sync::MutexGuard<Option<Vec<CapturedTask>>> {
+        self.capture.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned capture mutex in Share");
+            panic!()
+        })
+    }
+
+    pub(super) fn set_resource_validation(&self, enable: bool) {
+        self.resource_validation.store(enable, std::sync::atomic::Ordering::Release);
+    }
+
+    /// Returns a new id for a object created by this emulator. See [`UUIDSource`].
+    pub(super) fn next_object_id(&self) -> UUID {
+        self.object_ids.next_uuid()
+    }
+
+    pub(super) fn set_sequential_ids(&self, sequential: bool) {
+        self.object_ids.set_sequential(sequential);
+    }
+
+    pub(super) fn is_resource_validation_enabled(&self) -> bool {
+        self.resource_validation.load(std::sync::atomic::Ordering::Acquire)
+    }
+
     pub(super) fn create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> ShaderId {
-        let shader = Shader::new(*vertex_format, used_uniforms);
+        let shader = Shader::new_with_id(ShaderId::from_uuid(self.next_object_id()), *vertex_format, used_uniforms);
         let id = shader.get_id();
 
         let mut guard = self.shader_database.lock().unwrap();
@@ -120,10 +238,70 @@ impl Share {
         });
     }
 
+    /// Returns the id of the last pass which has been started. The pass may still be active.
+    /// Returns 0 if no pass has been started yet.
+    pub(super) fn get_last_started_pass(&self) -> u64 {
+        self.current_pass.load(std::sync::atomic::Ordering::Acquire) & !Self::PASS_ID_ACTIVE_BIT
+    }
+
+    /// Marks a pass and all passes before it as completed on the gpu.
+    pub(super) fn set_pass_completed(&self, id: PassId) {
+        self.completed_pass.fetch_max(id.get_raw(), std::sync::atomic::Ordering::AcqRel);
+        self.pass_limiter.notify();
+    }
+
+    /// Returns the number of passes which have been started but not yet completed on the gpu.
+    pub(super) fn get_in_flight_pass_count(&self) -> u64 {
+        self.get_last_started_pass() - self.get_last_completed_pass()
+    }
+
+    pub(super) fn set_max_concurrent_passes(&self, max: Option<u64>) {
+        self.pass_limiter.set_max(max);
+    }
+
+    /// Blocks until fewer passes than the configured maximum are in flight.
+    pub(super) fn wait_for_pass_slot(&self) {
+        self.pass_limiter.wait(|| self.get_in_flight_pass_count());
+    }
+
+    /// Blocks until the pass with the specified id has completed on the gpu.
+    pub(super) fn wait_for_pass_completed(&self, id: u64) {
+        self.pass_limiter.wait_until(|| self.get_last_completed_pass() >= id);
+    }
+
+    /// Returns the id of the last pass which is known to have completed on the gpu. Returns 0 if
+    /// no pass has completed yet.
+    pub(super) fn get_last_completed_pass(&self) -> u64 {
+        self.completed_pass.load(std::sync::atomic::Ordering::Acquire)
+    }
+
     pub(super) fn get_next_immediate_buffer(&self) -> Box<ImmediateBuffer> {
         self.immediate_buffers.get_next_buffer()
     }
 
+    pub(super) fn set_immediate_block_size(&self, block_size: vk::DeviceSize) {
+        self.immediate_buffers.set_block_size(block_size);
+    }
+
+    /// Returns the ring immediate meshes should be written into. Returns [`None`] if immediate
+    /// meshes use the immediate buffers.
+    pub(super) fn get_immediate_ring(&self) -> Option<Arc<ImmediateRing>> {
+        self.lock_immediate_ring().clone()
+    }
+
+    /// Replaces the immediate ring. Passes which already started keep using the previous ring.
+    pub(super) fn set_immediate_ring_size(&self, size: Option<vk::DeviceSize>) {
+        let ring = size.map(|size| Arc::new(ImmediateRing::new(self.device.clone(), size)));
+        *self.lock_immediate_ring() = ring;
+    }
+
+    fn lock_immediate_ring(&self) -> MutexGuard<Option<Arc<ImmediateRing>>> {
+        self.immediate_ring.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned immediate ring mutex");
+            panic!()
+        })
+    }
+
     pub(super) fn return_immediate_buffer(&self, buffer: Box<ImmediateBuffer>) {
         self.immediate_buffers.return_buffer(buffer);
     }
@@ -132,11 +310,36 @@ impl Share {
         self.descriptors.lock().unwrap().allocate_uniform(data)
     }
 
+    /// Queues a task for the worker. May be called from any thread. Tasks pushed by the same thread
+    /// are executed in order.
     pub(super) fn push_task(&self, task: WorkerTask) {
         self.channel.lock().unwrap().queue.push_back(task);
         self.signal.notify_one();
     }
 
+    /// Pushes multiple tasks such that no task of another thread is queued in between them.
+    pub(super) fn push_tasks<I: IntoIterator<Item=WorkerTask>>(&self, tasks: I) {
+        self.channel.lock().unwrap().queue.extend(tasks);
+        self.signal.notify_one();
+    }
+
+    /// Records a recoverable error of a worker task. The error can be retrieved by calling
+    /// [`Share::take_errors`].
+    pub(super) fn push_error(&self, error: EmulatorTaskError) {
+        log::warn!("Emulator task failed: {:?}", error);
+        self.errors.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned errors mutex in Share::push_error");
+            panic!()
+        }).push(error);
+    }
+
+    pub(super) fn take_errors(&self) -> Vec<EmulatorTaskError> {
+        std::mem::replace(&mut *self.errors.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned errors mutex in Share::take_errors");
+            panic!()
+        }), Vec::new())
+    }
+
     pub(super) fn try_get_next_task_timeout(&self, timeout: Duration) -> NextTaskResult {
         let start = Instant::now();
 
@@ -181,6 +384,93 @@ impl Eq for Share {
 impl RefUnwindSafe for Share {
 }
 
+/// A monotonic counter of frames used to tag resources with the frame they were created in.
+pub(super) struct FrameCounter {
+    frame: AtomicU64,
+}
+
+impl FrameCounter {
+    pub(super) fn new() -> Self {
+        Self {
+            frame: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn get_current_frame(&self) -> u64 {
+        self.frame.load(Ordering::Acquire)
+    }
+
+    /// Moves to the next frame and returns its index.
+    pub(super) fn advance(&self) -> u64 {
+        self.frame.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+/// Limits the number of passes in flight. Waiting threads are woken whenever a pass completes.
+pub(super) struct PassLimiter {
+    max: Mutex<Option<u64>>,
+    signal: Condvar,
+}
+
+impl PassLimiter {
+    pub(super) fn new() -> Self {
+        Self {
+            max: Mutex::new(None),
+            signal: Condvar::new(),
+        }
+    }
+
+    pub(super) fn set_max(&self, max: Option<u64>) {
+        if max == Some(0) {
+            log::error!("The maximum number of concurrent passes must be at least 1");
+            panic!()
+        }
+
+        *self.lock() = max;
+        // A larger limit may allow waiting threads to continue
+        self.signal.notify_all();
+    }
+
+    /// Blocks until `in_flight` returns a value smaller than the configured maximum.
+    pub(super) fn wait<F: Fn() -> u64>(&self, in_flight: F) {
+        let mut guard = self.lock();
+        while let Some(max) = *guard {
+            if in_flight() < max {
+                return;
+            }
+            // The timeout only guards against missed notifications
+            guard = self.signal.wait_timeout(guard, Duration::from_millis(100)).unwrap_or_else(|_| {
+                log::error!("Poisoned pass limiter mutex in PassLimiter::wait");
+                panic!()
+            }).0;
+        }
+    }
+
+    /// Blocks until `done` returns true. `done` is checked whenever a pass completes.
+    pub(super) fn wait_until<F: Fn() -> bool>(&self, done: F) {
+        let mut guard = self.lock();
+        while !done() {
+            guard = self.signal.wait_timeout(guard, Duration::from_millis(100)).unwrap_or_else(|_| {
+                log::error!("Poisoned pass limiter mutex in PassLimiter::wait_until");
+                panic!()
+            }).0;
+        }
+    }
+
+    /// Must be called after a pass completed.
+    pub(super) fn notify(&self) {
+        let _guard = self.lock();
+        self.signal.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<Option<u64>> {
+        self.max.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned pass limiter mutex");
+            panic!()
+        })
+    }
+}
+
 pub(in crate::renderer::emulator) enum NextTaskResult {
     Ok(WorkerTask),
     Timeout,
@@ -197,3 +487,102 @@ impl Channel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::emulator::EmulatorRenderer;
+    use crate::util::format::Format;
+
+    #[test]
+    fn resources_tagged_with_creation_frame() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+        let quad = [0u8; 4 * 16];
+
+        let first = emulator.current_frame();
+        let first_image = emulator.create_global_image(Vec2u32::new(1, 1), &Format::R8G8B8A8_UNORM);
+        let first_mesh = emulator.create_global_quad_mesh(&quad, 16).unwrap();
+
+        let second = emulator.advance_frame();
+        assert_eq!(second, first + 1);
+        let second_image = emulator.create_global_image(Vec2u32::new(1, 1), &Format::R8G8B8A8_UNORM);
+        let second_mesh = emulator.create_global_quad_mesh(&quad, 16).unwrap();
+
+        assert_eq!(first_image.get_created_frame(), first);
+        assert_eq!(first_mesh.get_created_frame(), first);
+        assert_eq!(second_image.get_created_frame(), second);
+        assert_eq!(second_mesh.get_created_frame(), second);
+    }
+
+    #[test]
+    fn resources_report_memory_properties() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+
+        let mesh = emulator.create_global_quad_mesh(&[0u8; 4 * 16], 16).unwrap();
+        assert!(mesh.get_memory_properties().contains(vk::MemoryPropertyFlags::DEVICE_LOCAL));
+
+        let image = emulator.create_registered_image(Vec2u32::new(1, 1), &Format::R8G8B8A8_UNORM, None);
+        assert!(image.get_memory_properties().contains(vk::MemoryPropertyFlags::DEVICE_LOCAL));
+        let dropped = emulator.drop_images_where(|info| {
+            assert_eq!(info.memory_properties, image.get_memory_properties());
+            true
+        });
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn capture_tasks_only_created_while_active() {
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+        let mesh = emulator.create_global_quad_mesh(&[0u8; 4 * 16], 16).unwrap();
+
+        let created = std::cell::Cell::new(0u32);
+        let task = || {
+            created.set(created.get() + 1);
+            CapturedTask::WriteGlobalMesh(mesh.get_id(), Box::new([]))
+        };
+
+        emulator.share.capture_task(task);
+        assert_eq!(created.get(), 0);
+
+        assert!(emulator.share.begin_capture());
+        emulator.share.capture_task(task);
+        assert_eq!(created.get(), 1);
+        assert_eq!(emulator.share.end_capture().unwrap().len(), 1);
+
+        emulator.share.capture_task(task);
+        assert_eq!(created.get(), 1);
+    }
+
+    #[test]
+    fn second_pass_blocks_until_first_completes() {
+        let limiter = Arc::new(PassLimiter::new());
+        limiter.set_max(Some(1));
+
+        // The first pass has been started but not completed
+        let started = 1u64;
+        let completed = Arc::new(AtomicU64::new(0));
+        let in_flight = {
+            let completed = completed.clone();
+            move || started - completed.load(Ordering::Acquire)
+        };
+
+        let (send, recv) = std::sync::mpsc::channel();
+        let thread = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || {
+                limiter.wait(in_flight);
+                send.send(()).unwrap();
+            })
+        };
+
+        assert!(recv.recv_timeout(Duration::from_millis(200)).is_err());
+
+        completed.store(1, Ordering::Release);
+        limiter.notify();
+        recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        thread.join().unwrap();
+    }
+}
@@ -19,6 +19,9 @@ pub struct StagingMemoryPool {
     current_buffer: StagingBuffer,
     old_buffers: Vec<(u16, StagingBuffer)>,
 
+    /// Empty backing buffers kept alive to be reused instead of allocating a new buffer.
+    free_buffers: ReusePool<StagingBuffer>,
+
     /// Multiplier applied to the size of a new backing buffer allocation.
     /// `0` is a multiplier of 1.0 and [`u8::MAX`] a multiplier of 2.0
     over_allocation: u8,
@@ -31,6 +34,8 @@ pub struct StagingMemoryPool {
 
 impl StagingMemoryPool {
     const MIN_BUFFER_SIZE: vk::DeviceSize = 2u64.pow(24); // 16MB
+    const DEFAULT_REUSE_MAX_BUFFERS: usize = 2;
+    const DEFAULT_REUSE_MAX_BYTES: vk::DeviceSize = 2u64.pow(26); // 64MB
 
     pub(super) fn new(device: Arc<DeviceContext>) -> Self {
         let current_buffer = StagingBuffer::new(device.clone(), Self::MIN_BUFFER_SIZE);
@@ -41,18 +46,25 @@ impl StagingMemoryPool {
             current_buffer_id: 0,
             current_buffer,
             old_buffers: Vec::new(),
+            free_buffers: ReusePool::new(Self::DEFAULT_REUSE_MAX_BUFFERS, Self::DEFAULT_REUSE_MAX_BYTES),
             over_allocation: 76,
             reduce_threshold: 127
         }
     }
 
+    /// Sets the maximum number of empty backing buffers and their total size in bytes which are
+    /// kept alive for reuse. Buffers exceeding the new limits are destroyed immediately.
+    pub(super) fn set_reuse_limits(&mut self, max_buffers: usize, max_bytes: vk::DeviceSize) {
+        self.free_buffers.set_limits(max_buffers, max_bytes);
+    }
+
     pub(super) fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> (StagingAllocation, StagingAllocationId) {
         if let Some((alloc, slot_id)) = self.current_buffer.try_allocate(size, alignment) {
             (alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id })
         } else {
             self.create_new_buffer(size);
             let (alloc, slot_id) = self.current_buffer.try_allocate(size, alignment).unwrap();
-            (alloc, StagingAllocationId{ buffer_id: 0, slot_id })
+            (alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id })
         }
     }
 
@@ -71,7 +83,8 @@ impl StagingMemoryPool {
                 }
             }
             if let Some(index) = delete {
-                self.old_buffers.swap_remove(index);
+                let (_, buffer) = self.old_buffers.swap_remove(index);
+                self.release_buffer(buffer);
             }
         }
     }
@@ -87,7 +100,7 @@ impl StagingMemoryPool {
         let new_size = std::cmp::max(new_size, Self::MIN_BUFFER_SIZE);
 
         // Yes this is slow but it shouldn't matter since we never have many buffers
-        while self.is_id_unused(self.next_buffer_id) {
+        while !self.is_id_unused(self.next_buffer_id) {
             // Technically there is a potential infinite loop here but at that point we would have
             // allocated at least 1TB of memory so i will accept this risk
             self.next_buffer_id = self.next_buffer_id.wrapping_add(1);
@@ -95,13 +108,23 @@ impl StagingMemoryPool {
         let id = self.next_buffer_id;
         self.next_buffer_id = self.next_buffer_id.wrapping_add(1);
 
-        let buffer = StagingBuffer::new(self.device.clone(), new_size);
+        let buffer = self.free_buffers.take(new_size).unwrap_or_else(|| StagingBuffer::new(self.device.clone(), new_size));
 
         let old = std::mem::replace(&mut self.current_buffer, buffer);
-        self.old_buffers.push((self.current_buffer_id, old));
+        if old.is_empty() {
+            self.release_buffer(old);
+        } else {
+            self.old_buffers.push((self.current_buffer_id, old));
+        }
         self.current_buffer_id = id;
     }
 
+    /// Returns a empty buffer to the reuse pool or destroys it if the pool is full.
+    fn release_buffer(&mut self, buffer: StagingBuffer) {
+        let size = buffer.size;
+        self.free_buffers.give(size, buffer);
+    }
+
     fn is_id_unused(&self, id: u16) -> bool {
         if id == self.current_buffer_id {
             return false;
@@ -121,6 +144,7 @@ struct StagingBuffer {
     mapped_ptr: NonNull<u8>,
     allocation: Allocation,
     allocator: RingAllocator,
+    size: vk::DeviceSize,
 }
 
 impl StagingBuffer {
@@ -139,7 +163,8 @@ impl StagingBuffer {
             buffer,
             mapped_ptr: mapped_ptr.unwrap(),
             allocation,
-            allocator: RingAllocator::new(size)
+            allocator: RingAllocator::new(size),
+            size,
         }
     }
 
@@ -192,4 +217,93 @@ pub(super) struct StagingAllocation {
 unsafe impl Send for StagingAllocation { // Needed because of NonNull<u8>
 }
 unsafe impl Sync for StagingAllocation { // Needed because of NonNull<u8>
-}
\ No newline at end of file
+}
+
+/// A bounded set of unused objects with a byte size which can be reused for requests of a
+/// compatible size.
+struct ReusePool<T> {
+    entries: Vec<(vk::DeviceSize, T)>,
+    max_entries: usize,
+    max_bytes: vk::DeviceSize,
+}
+
+impl<T> ReusePool<T> {
+    fn new(max_entries: usize, max_bytes: vk::DeviceSize) -> Self {
+        Self {
+            entries: Vec::new(),
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    fn set_limits(&mut self, max_entries: usize, max_bytes: vk::DeviceSize) {
+        self.max_entries = max_entries;
+        self.max_bytes = max_bytes;
+        while self.entries.len() > self.max_entries || self.used_bytes() > self.max_bytes {
+            // Drop the largest entries first
+            self.entries.sort_by_key(|(size, _)| *size);
+            self.entries.pop();
+        }
+    }
+
+    /// Takes the smallest entry which is at least `size` bytes but no more than twice as large to
+    /// avoid tying up large buffers with small requests.
+    fn take(&mut self, size: vk::DeviceSize) -> Option<T> {
+        let index = self.entries.iter().enumerate()
+            .filter(|(_, (entry_size, _))| *entry_size >= size && *entry_size <= size.saturating_mul(2))
+            .min_by_key(|(_, (entry_size, _))| *entry_size)
+            .map(|(index, _)| index)?;
+
+        Some(self.entries.swap_remove(index).1)
+    }
+
+    /// Adds a entry to the pool. If the pool limits would be exceeded the entry is dropped.
+    fn give(&mut self, size: vk::DeviceSize, entry: T) {
+        if self.entries.len() < self.max_entries && self.used_bytes() + size <= self.max_bytes {
+            self.entries.push((size, entry));
+        }
+    }
+
+    fn used_bytes(&self) -> vk::DeviceSize {
+        self.entries.iter().map(|(size, _)| *size).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn many_small_transfers_reuse_buffers() {
+        const BUFFER_SIZE: vk::DeviceSize = 1024;
+        let mut pool = ReusePool::new(2, 4 * BUFFER_SIZE);
+
+        // Emulates transfers which each need a new backing buffer that is freed once the transfer
+        // completed. The id of a buffer is the number of buffers created before it.
+        let mut created = 0u32;
+        let mut in_flight = Vec::new();
+        for transfer in 0..1000 {
+            let buffer = pool.take(BUFFER_SIZE).unwrap_or_else(|| {
+                created += 1;
+                created
+            });
+            in_flight.push(buffer);
+
+            // Up to 2 transfers are in flight at the same time
+            if transfer % 2 == 1 {
+                for buffer in in_flight.drain(..) {
+                    pool.give(BUFFER_SIZE, buffer);
+                }
+            }
+        }
+        assert_eq!(created, 2);
+
+        // Requests much smaller than the pooled buffers must not use them
+        assert_eq!(pool.take(BUFFER_SIZE / 4), None);
+
+        pool.set_limits(1, BUFFER_SIZE);
+        assert_eq!(pool.entries.len(), 1);
+        pool.give(BUFFER_SIZE, 3);
+        assert_eq!(pool.entries.len(), 1);
+    }
+}
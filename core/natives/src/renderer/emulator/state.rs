@@ -0,0 +1,119 @@
+//! Tracking of the OpenGL state emulated by b4d.
+
+use ash::vk;
+
+use crate::renderer::emulator::mc_shaders::ShaderId;
+
+/// The blend equation for the color and alpha channels.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlendFunc {
+    pub color_op: vk::BlendOp,
+    pub color_src_factor: vk::BlendFactor,
+    pub color_dst_factor: vk::BlendFactor,
+    pub alpha_op: vk::BlendOp,
+    pub alpha_src_factor: vk::BlendFactor,
+    pub alpha_dst_factor: vk::BlendFactor,
+}
+
+impl BlendFunc {
+    /// The default OpenGL blend function which passes the source color through unchanged.
+    pub const DEFAULT: Self = Self {
+        color_op: vk::BlendOp::ADD,
+        color_src_factor: vk::BlendFactor::ONE,
+        color_dst_factor: vk::BlendFactor::ZERO,
+        alpha_op: vk::BlendOp::ADD,
+        alpha_src_factor: vk::BlendFactor::ONE,
+        alpha_dst_factor: vk::BlendFactor::ZERO,
+    };
+}
+
+impl Default for BlendFunc {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The OpenGL state tracked by the emulator. Initialized to the OpenGL defaults.
+///
+/// Nested render operations can use [`EmulationState::snapshot`] and [`EmulationState::restore`]
+/// to save the state before modifying it and reset it afterwards.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct EmulationState {
+    pub depth_write_enable: bool,
+    pub depth_test_enable: bool,
+    /// The blend function. If [`None`] blending is disabled.
+    pub blend_func: Option<BlendFunc>,
+    pub cull_mode: vk::CullModeFlags,
+    /// The currently bound shader if any.
+    pub shader: Option<ShaderId>,
+}
+
+impl EmulationState {
+    pub fn new() -> Self {
+        Self {
+            depth_write_enable: true,
+            depth_test_enable: false,
+            blend_func: None,
+            cull_mode: vk::CullModeFlags::NONE,
+            shader: None,
+        }
+    }
+
+    /// Captures the current state.
+    pub fn snapshot(&self) -> EmulationStateSnapshot {
+        EmulationStateSnapshot(*self)
+    }
+
+    /// Resets the state to a previously captured snapshot.
+    pub fn restore(&mut self, snapshot: &EmulationStateSnapshot) {
+        *self = snapshot.0;
+    }
+}
+
+impl Default for EmulationState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A copy of a [`EmulationState`] created by [`EmulationState::snapshot`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct EmulationStateSnapshot(EmulationState);
+
+impl EmulationStateSnapshot {
+    pub fn get_state(&self) -> &EmulationState {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_recovers_snapshot() {
+        let shader = ShaderId::new();
+        let mut state = EmulationState::new();
+        state.depth_test_enable = true;
+        state.cull_mode = vk::CullModeFlags::BACK;
+        state.shader = Some(shader);
+        let original = state;
+
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.get_state(), &original);
+
+        state.depth_write_enable = false;
+        state.depth_test_enable = false;
+        state.blend_func = Some(BlendFunc {
+            color_src_factor: vk::BlendFactor::SRC_ALPHA,
+            color_dst_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ..BlendFunc::DEFAULT
+        });
+        state.cull_mode = vk::CullModeFlags::NONE;
+        state.shader = Some(ShaderId::new());
+        assert_ne!(state, original);
+
+        state.restore(&snapshot);
+        assert_eq!(state, original);
+    }
+}
@@ -0,0 +1,314 @@
+//! Transient images are short lived images which only need to exist for part of a frame (for
+//! example intermediate render targets). Since their lifetimes rarely overlap the memory of
+//! multiple transient images can be aliased to reduce memory usage.
+//!
+//! The [`TransientImagePool`] manages a set of memory blocks. When a transient image is created it
+//! is bound to any currently unused block which satisfies its memory requirements. Once the image
+//! is dropped the block becomes available again. Calling [`TransientImagePool::reset`] once per
+//! frame frees any block which has not been used since the last reset.
+
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+
+use crate::allocator::Allocation;
+use crate::renderer::emulator::global_objects::GlobalObjectCreateError;
+
+use crate::prelude::*;
+use crate::util::format::Format;
+
+pub(super) struct TransientImagePool {
+    device: Arc<DeviceContext>,
+    blocks: Mutex<TransientBlocks>,
+}
+
+impl TransientImagePool {
+    pub(super) fn new(device: Arc<DeviceContext>) -> Self {
+        Self {
+            device,
+            blocks: Mutex::new(TransientBlocks::new()),
+        }
+    }
+
+    pub(super) fn create_image(self: &Arc<Self>, size: Vec2u32, format: &'static Format) -> Result<TransientImage, GlobalObjectCreateError> {
+        let device = &self.device;
+
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format.into())
+            .extent(vk::Extent3D {
+                width: size[0],
+                height: size[1],
+                depth: 1
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = unsafe {
+            device.vk().create_image(&info, device.get_allocation_callbacks())
+        }.map_err(|err| {
+            log::error!("vkCreateImage returned {:?} in TransientImagePool::create_image", err);
+            err
+        })?;
+
+        let requirements = unsafe {
+            device.vk().get_image_memory_requirements(image)
+        };
+
+        let mut guard = self.blocks.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned transient blocks mutex in TransientImagePool::create_image");
+            panic!()
+        });
+
+        let block = match guard.acquire(&requirements) {
+            Some(block) => block,
+            None => {
+                let (allocation, binding_info) = match unsafe {
+                    device.get_allocator().allocate_aliasable_memory(&requirements, &format_args!("TransientImageBlock"))
+                } {
+                    Some(result) => result,
+                    None => {
+                        unsafe { device.vk().destroy_image(image, device.get_allocation_callbacks()) };
+                        return Err(GlobalObjectCreateError::Allocation);
+                    }
+                };
+                guard.insert(allocation, binding_info.get_size(), requirements.alignment, binding_info.get_memory_type_index())
+            }
+        };
+        let allocation = guard.get_allocation(block);
+        drop(guard);
+
+        if let Err(err) = unsafe { device.get_allocator().bind_image_memory(image, allocation) } {
+            log::error!("vmaBindImageMemory returned {:?} in TransientImagePool::create_image", err);
+            unsafe { device.vk().destroy_image(image, device.get_allocation_callbacks()) };
+            self.release(block);
+            return Err(GlobalObjectCreateError::Vulkan(err));
+        }
+
+        Ok(TransientImage {
+            pool: self.clone(),
+            block,
+            image,
+            size,
+            format,
+        })
+    }
+
+    /// Frees all memory blocks which have not been used since the last call to reset.
+    pub(super) fn reset(&self) {
+        let freed = self.blocks.lock().unwrap().reset();
+        if !freed.is_empty() {
+            unsafe {
+                self.device.get_allocator().free_memory_pages(&freed)
+            }
+        }
+    }
+
+    fn release(&self, block: usize) {
+        self.blocks.lock().unwrap().release(block);
+    }
+}
+
+impl Drop for TransientImagePool {
+    fn drop(&mut self) {
+        let allocations = self.blocks.get_mut().unwrap().take_all();
+        if !allocations.is_empty() {
+            unsafe {
+                self.device.get_allocator().free_memory_pages(&allocations)
+            }
+        }
+    }
+}
+
+/// A image whose backing memory may be aliased with other transient images.
+///
+/// The content of a transient image is undefined when it is created. The memory is returned to the
+/// pool when the image is dropped so the calling code must ensure that the image is no longer in use
+/// by the device at that point.
+pub struct TransientImage {
+    pool: Arc<TransientImagePool>,
+    block: usize,
+    image: vk::Image,
+    size: Vec2u32,
+    format: &'static Format,
+}
+
+impl TransientImage {
+    pub fn get_image_handle(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn get_size(&self) -> Vec2u32 {
+        self.size
+    }
+
+    pub fn get_format(&self) -> &'static Format {
+        self.format
+    }
+}
+
+impl Drop for TransientImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.pool.device.vk().destroy_image(self.image, self.pool.device.get_allocation_callbacks());
+        }
+        self.pool.release(self.block);
+    }
+}
+
+struct TransientBlock<A> {
+    allocation: A,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    memory_type: u32,
+    in_use: bool,
+    used_since_reset: bool,
+}
+
+/// Tracks which memory blocks are currently in use. Blocks are never removed while in use so block
+/// indices stay valid until the next reset.
+struct TransientBlocks<A = Allocation> {
+    blocks: Vec<Option<TransientBlock<A>>>,
+}
+
+impl<A: Copy> TransientBlocks<A> {
+    fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Searches for a currently unused block compatible with some requirements and marks it as in
+    /// use. Returns the index of the block or [`None`] if no compatible block exists.
+    fn acquire(&mut self, requirements: &vk::MemoryRequirements) -> Option<usize> {
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            if let Some(block) = block {
+                if !block.in_use
+                    && block.size >= requirements.size
+                    && (block.alignment % requirements.alignment) == 0
+                    && (requirements.memory_type_bits & (1u32 << block.memory_type)) != 0 {
+
+                    block.in_use = true;
+                    block.used_since_reset = true;
+                    return Some(index);
+                }
+            }
+        }
+        None
+    }
+
+    /// Inserts a new block and marks it as in use.
+    fn insert(&mut self, allocation: A, size: vk::DeviceSize, alignment: vk::DeviceSize, memory_type: u32) -> usize {
+        let block = TransientBlock {
+            allocation,
+            size,
+            alignment,
+            memory_type,
+            in_use: true,
+            used_since_reset: true,
+        };
+
+        if let Some(index) = self.blocks.iter().position(Option::is_none) {
+            self.blocks[index] = Some(block);
+            index
+        } else {
+            self.blocks.push(Some(block));
+            self.blocks.len() - 1
+        }
+    }
+
+    fn get_allocation(&self, index: usize) -> A {
+        self.blocks[index].as_ref().unwrap().allocation
+    }
+
+    fn release(&mut self, index: usize) {
+        let block = self.blocks[index].as_mut().unwrap_or_else(|| {
+            log::error!("Released transient block {:?} which does not exist", index);
+            panic!()
+        });
+        block.in_use = false;
+    }
+
+    /// Removes all blocks which are not in use and have not been used since the last reset.
+    /// Returns the allocations of the removed blocks.
+    fn reset(&mut self) -> Vec<A> {
+        let mut freed = Vec::new();
+        for entry in self.blocks.iter_mut() {
+            if let Some(block) = entry {
+                if !block.in_use && !block.used_since_reset {
+                    freed.push(block.allocation);
+                    *entry = None;
+                } else {
+                    block.used_since_reset = block.in_use;
+                }
+            }
+        }
+        freed
+    }
+
+    fn take_all(&mut self) -> Vec<A> {
+        self.blocks.drain(..).flatten().map(|block| block.allocation).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirements(size: vk::DeviceSize) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits: 0b11,
+        }
+    }
+
+    fn create(blocks: &mut TransientBlocks<u32>, next_allocation: &mut u32, size: vk::DeviceSize) -> usize {
+        blocks.acquire(&requirements(size)).unwrap_or_else(|| {
+            *next_allocation += 1;
+            blocks.insert(*next_allocation, size, 256, 0)
+        })
+    }
+
+    #[test]
+    fn non_overlapping_images_share_block() {
+        let mut blocks = TransientBlocks::<u32>::new();
+        let mut next_allocation = 0;
+
+        let a = create(&mut blocks, &mut next_allocation, 1024);
+        blocks.release(a);
+        let b = create(&mut blocks, &mut next_allocation, 1024);
+
+        assert_eq!(blocks.get_allocation(a), blocks.get_allocation(b));
+        assert_eq!(next_allocation, 1);
+    }
+
+    #[test]
+    fn overlapping_images_use_distinct_blocks() {
+        let mut blocks = TransientBlocks::<u32>::new();
+        let mut next_allocation = 0;
+
+        let a = create(&mut blocks, &mut next_allocation, 1024);
+        let b = create(&mut blocks, &mut next_allocation, 1024);
+
+        assert_ne!(blocks.get_allocation(a), blocks.get_allocation(b));
+        assert_eq!(next_allocation, 2);
+    }
+
+    #[test]
+    fn reset_frees_unused_blocks() {
+        let mut blocks = TransientBlocks::<u32>::new();
+        let mut next_allocation = 0;
+
+        let a = create(&mut blocks, &mut next_allocation, 1024);
+        blocks.release(a);
+
+        assert!(blocks.reset().is_empty());
+        assert_eq!(blocks.reset(), vec![1]);
+    }
+}
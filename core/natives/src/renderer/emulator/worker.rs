@@ -1,8 +1,9 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::time::Duration;
 
 use ash::prelude::VkResult;
@@ -11,28 +12,65 @@ use bumpalo::Bump;
 
 use crate::device::device::Queue;
 
+use crate::objects::sync::SemaphoreOp;
 use crate::renderer::emulator::pass::PassId;
-use crate::renderer::emulator::immediate::ImmediateBuffer;
+use crate::renderer::emulator::immediate::{ImmediateBuffer, ImmediateRing};
 use crate::renderer::emulator::pipeline::{EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, PipelineTask};
 
 use crate::prelude::*;
-use crate::renderer::emulator::global_objects::{GlobalImage, GlobalMesh};
+use crate::renderer::emulator::blit::{BlitObjects, BlitOptions};
+use crate::renderer::emulator::global_objects::{ExternalImageState, GlobalImage, GlobalImageId, GlobalMesh, GlobalMeshId};
 use crate::renderer::emulator::mc_shaders::ShaderId;
 use crate::renderer::emulator::share::{NextTaskResult, Share};
-use crate::renderer::emulator::staging::StagingAllocationId;
+use crate::renderer::emulator::readback::ReadState;
+use crate::renderer::emulator::staging::{StagingAllocation, StagingAllocationId};
 
 pub(super) enum WorkerTask {
     StartPass(PassId, Arc<dyn EmulatorPipeline>, Box<dyn EmulatorPipelinePass + Send>, Arc<GlobalImage>, vk::Sampler),
-    EndPass(Box<ImmediateBuffer>),
+    EndPass(Box<ImmediateBuffer>, Option<Arc<ImmediateRing>>),
     UseGlobalMesh(Arc<GlobalMesh>),
     UseGlobalImage(Arc<GlobalImage>),
     UseShader(ShaderId),
     UseOutput(Box<dyn EmulatorOutput + Send>),
+    /// Makes the current pass wait on a semaphore before any of its commands at the given stages
+    /// execute.
+    WaitSemaphore(SemaphoreOp, vk::PipelineStageFlags2),
     PipelineTask(PipelineTask),
     WriteGlobalMesh(GlobalMeshWrite, bool),
     ClearGlobalImage(GlobalImageClear, bool),
     WriteGlobalImage(GlobalImageWrite),
     GenerateGlobalImageMipmaps(Arc<GlobalImage>, PassId),
+    ReadGlobalImage(GlobalImageRead),
+    BlitGlobalImage(GlobalImageBlit),
+    ExportGlobalImage(GlobalImageExport),
+    ReacquireGlobalImage(GlobalImageExport),
+    /// Keeps exported global images alive until a external timeline semaphore has been signaled.
+    ReleaseExportedImages(DeferredRelease<Arc<GlobalImage>>),
+    FreeUnused(Sender<()>),
+    /// Submits all pending global object operations without a pass. The submission signals the
+    /// pass timeline with the id of the flush.
+    Flush(PassId),
+    /// Stops the worker once all previously submitted passes have completed. The number of
+    /// discarded pending operations is sent back before the worker exits.
+    Shutdown(Sender<usize>),
+}
+
+/// The task which caused a [`EmulatorTaskError`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum EmulatorTaskKind {
+    WriteGlobalMesh(GlobalMeshId),
+    WriteGlobalImage(GlobalImageId),
+    ReadGlobalImage(GlobalImageId),
+    /// A draw using the shader whose pipeline could not be created.
+    Draw(ShaderId),
+}
+
+/// A recoverable error of a task executed by the worker. The task which caused the error has been
+/// skipped.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct EmulatorTaskError {
+    pub task: EmulatorTaskKind,
+    pub reason: String,
 }
 
 pub(super) struct GlobalMeshWrite {
@@ -53,18 +91,85 @@ pub(super) struct GlobalImageWrite {
     pub(super) regions: Box<[vk::BufferImageCopy]>,
 }
 
+pub(super) struct GlobalImageRead {
+    pub(super) after_pass: PassId,
+    pub(super) staging_allocation: StagingAllocationId,
+    pub(super) staging_range: (vk::DeviceSize, vk::DeviceSize),
+    pub(super) staging: StagingAllocation,
+    pub(super) src_image: Arc<GlobalImage>,
+    pub(super) regions: Box<[vk::BufferImageCopy]>,
+    pub(super) dst: Box<[u8]>,
+    pub(super) target: Arc<ReadState>,
+}
+
+pub(super) struct GlobalImageBlit {
+    pub(super) after_pass: PassId,
+    pub(super) src_image: Arc<GlobalImage>,
+    pub(super) dst_image: Arc<GlobalImage>,
+    pub(super) sampler: vk::Sampler,
+    pub(super) options: BlitOptions,
+}
+
+/// Transitions a global image between the ready state and a external state.
+pub(super) struct GlobalImageExport {
+    pub(super) after_pass: PassId,
+    pub(super) image: Arc<GlobalImage>,
+    pub(super) state: ExternalImageState,
+}
+
+/// Objects which are dropped once a timeline semaphore reaches a value.
+pub(super) struct DeferredRelease<T> {
+    pub(super) semaphore: vk::Semaphore,
+    pub(super) value: u64,
+    pub(super) objects: Box<[T]>,
+}
+
+/// Drops all deferred releases whose semaphore has reached the required value.
+/// `get_value` returns the current value of a semaphore.
+fn process_deferred_releases<T, F: FnMut(vk::Semaphore) -> u64>(releases: &mut Vec<DeferredRelease<T>>, mut get_value: F) {
+    releases.retain(|release| get_value(release.semaphore) < release.value);
+}
+
+/// How long the worker waits for external semaphores of exported images during shutdown.
+const DEFERRED_RELEASE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Drops all remaining deferred releases during shutdown. `wait` waits for a semaphore to reach a
+/// value and returns false if it did not do so in time. The objects of such releases may still be
+/// in use externally and are leaked instead of dropped. Returns the number of leaked releases.
+fn finish_deferred_releases<T, F: FnMut(vk::Semaphore, u64) -> bool>(releases: Vec<DeferredRelease<T>>, mut wait: F) -> usize {
+    let mut leaked = 0;
+    for release in releases {
+        if wait(release.semaphore, release.value) {
+            drop(release);
+        } else {
+            leaked += 1;
+            std::mem::forget(release.objects);
+        }
+    }
+    leaked
+}
+
 pub(super) struct GlobalImageClear {
     pub(super) after_pass: PassId,
     pub(super) clear_value: vk::ClearColorValue,
     pub(super) dst_image: Arc<GlobalImage>,
 }
 
+/// The number of passes which may be executing on the gpu at the same time. Starting a new pass
+/// blocks until the pass submitted this many passes earlier has completed.
+const MAX_PASSES_IN_FLIGHT: u64 = 3;
+
 pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
     let queue = device.get_main_queue();
 
     let pool = Rc::new(RefCell::new(WorkerObjectPool::new(device.clone(), queue.get_queue_family_index())));
+    let timeline = Rc::new(PassTimeline::new(device.clone()));
     let mut current_pass: Option<PassState> = None;
     let mut old_frames = Vec::new();
+    let mut pending_releases: Vec<DeferredRelease<Arc<GlobalImage>>> = Vec::new();
+    let mut old_flushes: Vec<(PassId, Option<GlobalObjectsRecorder>)> = Vec::new();
+    // A flush received while a pass was running. Submitted once the pass has ended.
+    let mut deferred_flush: Option<PassId> = None;
 
     // A global objects recorder submitted before the current frame.
     // If no active pass exits this **must** be [`None`].
@@ -76,9 +181,37 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
     let queue = device.get_main_queue();
 
     loop {
-        old_frames.retain(|old: &PassState| {
-            !old.is_complete()
-        });
+        if !old_frames.is_empty() || !old_flushes.is_empty() {
+            // Passes and flushes must be retired using the same value. Otherwise a flush could be
+            // marked as completed while a earlier pass still holds pending readbacks.
+            let completed = timeline.get_completed_value();
+            let mut retired = None;
+            old_frames.retain(|old: &PassState| {
+                if old.pass_id.get_raw() <= completed {
+                    retired = std::cmp::max(retired, Some(old.pass_id));
+                    false
+                } else {
+                    true
+                }
+            });
+            retired = std::cmp::max(retired, retire_flushes(&mut old_flushes, completed));
+
+            // Only marked once the retired passes have been dropped and their readbacks completed
+            if let Some(id) = retired {
+                share.set_pass_completed(id);
+            }
+        }
+
+        if !pending_releases.is_empty() {
+            process_deferred_releases(&mut pending_releases, |semaphore| {
+                unsafe {
+                    device.timeline_semaphore_khr().get_semaphore_counter_value(semaphore)
+                }.unwrap_or_else(|err| {
+                    log::error!("vkGetSemaphoreCounterValue returned {:?} while processing deferred releases", err);
+                    panic!()
+                })
+            });
+        }
 
         let task = match share.try_get_next_task_timeout(Duration::from_micros(500)) {
             NextTaskResult::Ok(task) => task,
@@ -91,16 +224,24 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
                     log::error!("Worker received WorkerTask::StartPass when a pass is already running");
                     panic!()
                 }
-                let state = PassState::new(id, pipeline, pass, device.clone(), &queue, share.clone(), pool.clone(), placeholder_image, placeholder_sampler);
+                timeline.wait(get_reuse_wait_value(id.get_raw(), MAX_PASSES_IN_FLIGHT));
+                let state = PassState::new(id, pipeline, pass, device.clone(), &queue, share.clone(), pool.clone(), timeline.clone(), placeholder_image, placeholder_sampler);
                 current_pass = Some(state);
                 current_global_recorder = next_global_recorder.take();
             }
 
-            WorkerTask::EndPass(immediate_buffer) => {
+            WorkerTask::EndPass(immediate_buffer, immediate_ring) => {
                 if let Some(mut pass) = current_pass.take() {
                     pass.use_immediate_buffer(immediate_buffer);
+                    pass.immediate_ring = immediate_ring;
                     pass.submit(&queue, current_global_recorder.take());
                     old_frames.push(pass);
+
+                    if let Some(id) = deferred_flush.take() {
+                        let mut gob = next_global_recorder.take();
+                        submit_flush(&queue, &timeline, id, gob.as_mut());
+                        old_flushes.push((id, gob));
+                    }
                 } else {
                     log::error!("Worker received WorkerTask::EndPass when no active pass exists");
                     panic!()
@@ -143,6 +284,15 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
                 }
             }
 
+            WorkerTask::WaitSemaphore(op, stage_mask) => {
+                if let Some(pass) = &mut current_pass {
+                    pass.add_wait(op, stage_mask);
+                } else {
+                    log::error!("Worker received WorkerTask::WaitSemaphore when no active pass exists");
+                    panic!()
+                }
+            }
+
             WorkerTask::PipelineTask(task) => {
                 if let Some(pass) = &mut current_pass {
                     pass.process_task(&task)
@@ -199,6 +349,125 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
                     get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_generate_mipmaps(image);
                 }
             }
+
+            WorkerTask::ReadGlobalImage(read) => {
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > read.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_read(read);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_read(read);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_read(read);
+                }
+            }
+
+            WorkerTask::BlitGlobalImage(blit) => {
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > blit.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_blit(blit);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_blit(blit);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_blit(blit);
+                }
+            }
+
+            WorkerTask::ExportGlobalImage(export) => {
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > export.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_export(export);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_export(export);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_export(export);
+                }
+            }
+
+            WorkerTask::ReacquireGlobalImage(export) => {
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > export.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_reacquire(export);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_reacquire(export);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_reacquire(export);
+                }
+            }
+
+            WorkerTask::ReleaseExportedImages(release) => {
+                pending_releases.push(release);
+            }
+
+            WorkerTask::Flush(id) => {
+                if current_pass.is_some() {
+                    // The flush must be signaled after the running pass
+                    log::warn!("Worker received WorkerTask::Flush when a pass is running. Deferring until the pass ends");
+                    deferred_flush = Some(id);
+                } else {
+                    let mut gob = next_global_recorder.take();
+                    submit_flush(&queue, &timeline, id, gob.as_mut());
+                    old_flushes.push((id, gob));
+                }
+            }
+
+            WorkerTask::FreeUnused(signal) => {
+                for old in &old_frames {
+                    old.wait_complete();
+                    share.set_pass_completed(old.pass_id);
+                }
+                old_frames.clear();
+                wait_flushes(&mut old_flushes, &timeline, &share);
+
+                // The caller may have stopped waiting
+                let _ = signal.send(());
+            }
+
+            WorkerTask::Shutdown(signal) => {
+                for old in &old_frames {
+                    old.wait_complete();
+                    share.set_pass_completed(old.pass_id);
+                }
+                old_frames.clear();
+                wait_flushes(&mut old_flushes, &timeline, &share);
+
+                // Anything not yet submitted at this point is discarded
+                let discarded = current_pass.is_some() as usize +
+                    current_global_recorder.is_some() as usize +
+                    next_global_recorder.is_some() as usize;
+                if discarded != 0 {
+                    log::warn!("Emulator worker shutting down with {:?} unsubmitted operations", discarded);
+                }
+                let leaked = finish_deferred_releases(pending_releases, |semaphore, value| {
+                    let info = vk::SemaphoreWaitInfo::builder()
+                        .semaphores(std::slice::from_ref(&semaphore))
+                        .values(std::slice::from_ref(&value));
+
+                    match unsafe {
+                        device.timeline_semaphore_khr().wait_semaphores(&info, DEFERRED_RELEASE_SHUTDOWN_TIMEOUT.as_nanos() as u64)
+                    } {
+                        Ok(()) => true,
+                        Err(vk::Result::TIMEOUT) => false,
+                        Err(err) => {
+                            log::error!("vkWaitSemaphores returned {:?} while finishing deferred releases", err);
+                            false
+                        }
+                    }
+                });
+                if leaked != 0 {
+                    log::error!("Emulator worker shutting down with {:?} exported image sets still in use externally. The images are leaked", leaked);
+                }
+                drop(current_pass);
+                drop(current_global_recorder);
+                drop(next_global_recorder);
+
+                // The caller may have stopped waiting
+                let _ = signal.send(discarded);
+                return;
+            }
         }
     }
 }
@@ -212,6 +481,130 @@ fn get_or_create_recorder<'a>(recorder: &'a mut Option<GlobalObjectsRecorder>, s
     }
 }
 
+/// Submits the pending global object operations of a flush and signals the pass timeline with the
+/// id of the flush once they have completed.
+fn submit_flush(queue: &Queue, timeline: &PassTimeline, id: PassId, gob: Option<&mut GlobalObjectsRecorder>) {
+    let submit_alloc = Bump::new();
+    let mut submit_recorder = SubmitRecorder::new(8);
+
+    if let Some(gob) = gob {
+        gob.record(&mut submit_recorder, &submit_alloc);
+    }
+
+    let signal_infos = submit_alloc.alloc([
+        vk::SemaphoreSubmitInfo::builder()
+            .semaphore(timeline.get_semaphore())
+            .value(id.get_raw())
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .build()
+    ]);
+    submit_recorder.push(vk::SubmitInfo2::builder()
+        .signal_semaphore_infos(signal_infos));
+
+    unsafe {
+        queue.submit_2(submit_recorder.as_slice(), None)
+    }.unwrap();
+}
+
+/// Drops all flushes whose id has been reached by the pass timeline and returns the largest
+/// retired id. The recorders are dropped before the id is returned so that readbacks of a flush
+/// have completed once the id is marked as completed.
+fn retire_flushes<T>(flushes: &mut Vec<(PassId, T)>, completed: u64) -> Option<PassId> {
+    let mut retired = None;
+    flushes.retain(|(id, _)| {
+        if id.get_raw() <= completed {
+            retired = std::cmp::max(retired, Some(*id));
+            false
+        } else {
+            true
+        }
+    });
+    retired
+}
+
+/// Blocks until all flushes have completed and retires them.
+fn wait_flushes(flushes: &mut Vec<(PassId, Option<GlobalObjectsRecorder>)>, timeline: &PassTimeline, share: &Share) {
+    if let Some(last) = flushes.iter().map(|(id, _)| *id).max() {
+        timeline.wait(last.get_raw());
+        if let Some(id) = retire_flushes(flushes, last.get_raw()) {
+            share.set_pass_completed(id);
+        }
+    }
+}
+
+/// Returns the timeline value which must be reached before resources can be reused for the pass
+/// with the specified timeline value.
+fn get_reuse_wait_value(pass_value: u64, passes_in_flight: u64) -> u64 {
+    pass_value.saturating_sub(passes_in_flight)
+}
+
+/// A timeline semaphore which is signaled with the id of each pass once all of its submissions
+/// have completed.
+struct PassTimeline {
+    device: Arc<DeviceContext>,
+    semaphore: vk::Semaphore,
+}
+
+impl PassTimeline {
+    fn new(device: Arc<DeviceContext>) -> Self {
+        let mut timeline = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+
+        let info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut timeline);
+
+        let semaphore = unsafe {
+            device.vk().create_semaphore(&info, device.get_allocation_callbacks())
+        }.unwrap();
+
+        Self {
+            device,
+            semaphore,
+        }
+    }
+
+    fn get_semaphore(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+
+    /// Returns the id of the last pass which has completed on the gpu.
+    fn get_completed_value(&self) -> u64 {
+        unsafe {
+            self.device.timeline_semaphore_khr().get_semaphore_counter_value(self.semaphore)
+        }.unwrap_or_else(|err| {
+            log::error!("vkGetSemaphoreCounterValue returned {:?} in PassTimeline::get_completed_value", err);
+            panic!()
+        })
+    }
+
+    /// Blocks until the timeline has reached the specified value.
+    fn wait(&self, value: u64) {
+        if value == 0 {
+            return;
+        }
+
+        let info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(std::slice::from_ref(&self.semaphore))
+            .values(std::slice::from_ref(&value));
+
+        unsafe {
+            self.device.timeline_semaphore_khr().wait_semaphores(&info, u64::MAX)
+        }.unwrap_or_else(|err| {
+            log::error!("vkWaitSemaphores returned {:?} in PassTimeline::wait", err);
+            panic!()
+        });
+    }
+}
+
+impl Drop for PassTimeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_semaphore(self.semaphore, self.device.get_allocation_callbacks());
+        }
+    }
+}
+
 struct WorkerObjectPool {
     device: Arc<DeviceContext>,
     command_pool: vk::CommandPool,
@@ -226,7 +619,7 @@ impl WorkerObjectPool {
             .queue_family_index(queue_family);
 
         let command_pool = unsafe {
-            device.vk().create_command_pool(&info, None)
+            device.vk().create_command_pool(&info, device.get_allocation_callbacks())
         }.unwrap();
 
         Self {
@@ -267,7 +660,7 @@ impl WorkerObjectPool {
             let info = vk::FenceCreateInfo::builder();
 
             let fence = unsafe {
-                self.device.vk().create_fence(&info, None)
+                self.device.vk().create_fence(&info, self.device.get_allocation_callbacks())
             }.unwrap();
 
             return fence;
@@ -370,14 +763,24 @@ struct PassState {
     outputs: Vec<Box<dyn EmulatorOutput>>,
 
     immediate_buffer: Option<Box<ImmediateBuffer>>,
+    /// The immediate ring used by the pass. Kept alive until the pass has completed.
+    immediate_ring: Option<Arc<ImmediateRing>>,
     global_meshes: Vec<Arc<GlobalMesh>>,
     global_images: Vec<Arc<GlobalImage>>,
     shaders: Vec<ShaderId>,
 
+    /// The resources referenced by pipeline tasks. Only present if resource validation is enabled.
+    referenced_resources: Option<ReferencedResources>,
+
     pre_cmd: vk::CommandBuffer,
     post_cmd: vk::CommandBuffer,
 
-    end_fence: Option<vk::Fence>,
+    /// External semaphores the pass must wait on. The waits are part of the first submission of
+    /// the pass.
+    waits: Vec<(SemaphoreOp, vk::PipelineStageFlags2)>,
+
+    timeline: Rc<PassTimeline>,
+    submitted: bool,
 
     gob: Option<GlobalObjectsRecorder>,
 }
@@ -391,6 +794,7 @@ impl PassState {
         queue: &Queue,
         share: Arc<Share>,
         pool: Rc<RefCell<WorkerObjectPool>>,
+        timeline: Rc<PassTimeline>,
         placeholder_image: Arc<GlobalImage>,
         placeholder_sampler: vk::Sampler
     ) -> Self {
@@ -401,6 +805,12 @@ impl PassState {
 
         pass.init(queue, &mut object_pool, placeholder_image.get_sampler_view(), placeholder_sampler);
 
+        let referenced_resources = if share.is_resource_validation_enabled() {
+            Some(ReferencedResources::new())
+        } else {
+            None
+        };
+
         Self {
             share,
             device,
@@ -413,14 +823,20 @@ impl PassState {
             outputs: Vec::with_capacity(8),
 
             immediate_buffer: None,
+            immediate_ring: None,
             global_meshes: Vec::new(),
             global_images: vec![placeholder_image],
             shaders: Vec::new(),
 
+            referenced_resources,
+
             pre_cmd,
             post_cmd,
 
-            end_fence: None,
+            waits: Vec::new(),
+
+            timeline,
+            submitted: false,
             gob: None
         }
     }
@@ -435,19 +851,63 @@ impl PassState {
         self.immediate_buffer = Some(immediate_buffer);
     }
 
+    fn add_wait(&mut self, op: SemaphoreOp, stage_mask: vk::PipelineStageFlags2) {
+        // A semaphore wait only orders commands of the batch it is part of. This barrier extends
+        // the dependency to all following submissions of the pass.
+        let memory_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(stage_mask)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(stage_mask)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE);
+
+        let info = vk::DependencyInfo::builder()
+            .memory_barriers(std::slice::from_ref(&memory_barrier));
+
+        unsafe {
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(self.pre_cmd, &info);
+        }
+
+        self.waits.push((op, stage_mask));
+    }
+
     fn use_output(&mut self, mut output: Box<dyn EmulatorOutput>) {
         output.init(self.pass.as_ref(), &mut self.object_pool);
         self.outputs.push(output);
     }
 
     fn process_task(&mut self, task: &PipelineTask) {
+        if let Some(referenced) = &mut self.referenced_resources {
+            referenced.record(task);
+        }
+        if let Some(cmd) = self.pass.get_breadcrumb_command_buffer() {
+            self.share.get_breadcrumbs().record(&self.device, cmd, Some(self.pass_id), task.get_name());
+        }
         self.pass.process_task(task, &mut self.object_pool);
     }
 
+    /// Panics if a pipeline task referenced a resource which is not kept alive by this pass.
+    fn validate_resources(&self) {
+        if let Some(referenced) = &self.referenced_resources {
+            let mut tracked_buffers: HashSet<_> = self.global_meshes.iter().map(|mesh| mesh.get_buffer_handle()).collect();
+            if let Some(immediate_buffer) = &self.immediate_buffer {
+                tracked_buffers.extend(immediate_buffer.get_buffer_handles());
+            }
+            if let Some(immediate_ring) = &self.immediate_ring {
+                tracked_buffers.insert(immediate_ring.get_buffer_handle());
+            }
+            let tracked_views: HashSet<_> = self.global_images.iter().map(|image| image.get_sampler_view()).collect();
+
+            if let Some(untracked) = referenced.find_untracked(&tracked_buffers, &tracked_views) {
+                log::error!("Pass {:?} references {:?} which is not kept alive by the pass", self.pass_id, untracked);
+                panic!()
+            }
+        }
+    }
+
     fn submit(&mut self, queue: &Queue, gob: Option<GlobalObjectsRecorder>) {
-        assert!(self.end_fence.is_none());
-        let end_fence = self.object_pool.get_fence();
-        self.end_fence = Some(end_fence);
+        assert!(!self.submitted);
+        self.validate_resources();
+        self.submitted = true;
 
         unsafe {
             self.device.vk().end_command_buffer(self.pre_cmd)
@@ -473,7 +933,7 @@ impl PassState {
         self.record_post_submits(&mut submit_recorder, &submit_alloc);
 
         unsafe {
-            queue.submit_2(submit_recorder.as_slice(), Some(end_fence))
+            queue.submit_2(submit_recorder.as_slice(), None)
         }.unwrap();
 
         for output in &mut self.outputs {
@@ -481,14 +941,12 @@ impl PassState {
         }
     }
 
-    fn is_complete(&self) -> bool {
-        if let Some(fence) = self.end_fence {
-            unsafe {
-                self.device.vk().get_fence_status(fence)
-            }.unwrap()
-        } else {
+    /// Blocks until all submissions of this pass have completed.
+    fn wait_complete(&self) {
+        if !self.submitted {
             panic!("Illegal state");
         }
+        self.timeline.wait(self.pass_id.get_raw());
     }
 
     fn record_pre_submits<'a>(&self, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
@@ -498,13 +956,30 @@ impl PassState {
                 .build()
         ]);
 
+        let wait_infos = alloc.alloc_slice_fill_iter(make_wait_semaphore_infos(&self.waits));
+
         let submit_info = vk::SubmitInfo2::builder()
+            .wait_semaphore_infos(wait_infos)
             .command_buffer_infos(cmd_infos);
 
         recorder.push(submit_info);
     }
 
-    fn record_post_submits<'a>(&self, _: &mut SubmitRecorder<'a>, _: &'a Bump) {
+    fn record_post_submits<'a>(&self, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        // Signal operations include all commands submitted before them so this signals the
+        // completion of the whole pass.
+        let signal_infos = alloc.alloc([
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(self.timeline.get_semaphore())
+                .value(self.pass_id.get_raw())
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+        ]);
+
+        let submit_info = vk::SubmitInfo2::builder()
+            .signal_semaphore_infos(signal_infos);
+
+        recorder.push(submit_info);
     }
 }
 
@@ -519,6 +994,75 @@ impl Drop for PassState {
     }
 }
 
+/// Returns the wait infos for a list of semaphore waits. Binary semaphores use a value of 0.
+fn make_wait_semaphore_infos(waits: &[(SemaphoreOp, vk::PipelineStageFlags2)]) -> Vec<vk::SemaphoreSubmitInfo> {
+    waits.iter().map(|(op, stage_mask)| {
+        vk::SemaphoreSubmitInfo::builder()
+            .semaphore(op.semaphore.get_handle())
+            .value(op.value.unwrap_or(0))
+            .stage_mask(*stage_mask)
+            .build()
+    }).collect()
+}
+
+/// A resource referenced by a pipeline task.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ReferencedResource {
+    Buffer(vk::Buffer),
+    ImageView(vk::ImageView),
+}
+
+/// Collects the resources referenced by the pipeline tasks of a pass.
+struct ReferencedResources {
+    buffers: HashSet<vk::Buffer>,
+    image_views: HashSet<vk::ImageView>,
+}
+
+impl ReferencedResources {
+    fn new() -> Self {
+        Self {
+            buffers: HashSet::new(),
+            image_views: HashSet::new(),
+        }
+    }
+
+    fn record(&mut self, task: &PipelineTask) {
+        match task {
+            PipelineTask::Draw(draw) => {
+                self.buffers.insert(draw.vertex_buffer);
+                self.buffers.insert(draw.index_buffer);
+            }
+            PipelineTask::UpdateTexture(_, _, view, _) => {
+                self.image_views.insert(*view);
+            }
+            // Procedural draws generate their vertices in the shader and reference no buffers.
+            // Bindless textures are bound through the table whose images the pass tracks when
+            // they are selected.
+            PipelineTask::DrawProcedural(_) |
+            PipelineTask::SetBindlessTexture(_) |
+            PipelineTask::UpdateUniform(_, _) |
+            PipelineTask::SetStencilReference(_) |
+            PipelineTask::SetLineWidth(_) |
+            PipelineTask::SetBlendConstants(_) |
+            PipelineTask::SetMvp(_) |
+            PipelineTask::SetAttachmentOps(_, _) |
+            PipelineTask::SetPassViewport(_) |
+            PipelineTask::SetPassScissor(_) => {}
+        }
+    }
+
+    /// Returns a referenced resource which is not contained in the tracked sets if one exists.
+    fn find_untracked(&self, tracked_buffers: &HashSet<vk::Buffer>, tracked_views: &HashSet<vk::ImageView>) -> Option<ReferencedResource> {
+        if let Some(buffer) = self.buffers.iter().find(|buffer| !tracked_buffers.contains(*buffer)) {
+            return Some(ReferencedResource::Buffer(*buffer));
+        }
+        if let Some(view) = self.image_views.iter().find(|view| !tracked_views.contains(*view)) {
+            return Some(ReferencedResource::ImageView(*view));
+        }
+        None
+    }
+}
+
 struct GlobalObjectsRecorder {
     share: Arc<Share>,
     _object_pool: PooledObjectProvider,
@@ -529,6 +1073,17 @@ struct GlobalObjectsRecorder {
 
     staging_barriers: Vec<vk::BufferMemoryBarrier2>,
 
+    /// Readbacks whose staging memory must be copied to the host once the submission completed.
+    pending_reads: Vec<PendingRead>,
+
+    /// Views and framebuffers of fullscreen blits and the sampled source images. Must be kept alive
+    /// until the submission completed.
+    blit_objects: Vec<(BlitObjects, Arc<GlobalImage>)>,
+
+    /// Images exported or reacquired by this recorder. They are not tracked in the used image list
+    /// since they must not be transitioned back to the ready state at the end of the submission.
+    external_images: Vec<Arc<GlobalImage>>,
+
     used_global_meshes: HashMap<Arc<GlobalMesh>, gob::MeshState>,
     used_global_images: HashMap<Arc<GlobalImage>, gob::ImageState>,
 
@@ -559,6 +1114,12 @@ impl GlobalObjectsRecorder {
             staging_allocations: Vec::new(),
             staging_barriers: Vec::new(),
 
+            pending_reads: Vec::new(),
+
+            blit_objects: Vec::new(),
+
+            external_images: Vec::new(),
+
             used_global_meshes: HashMap::new(),
             used_global_images: HashMap::new(),
 
@@ -567,10 +1128,22 @@ impl GlobalObjectsRecorder {
         }
     }
 
+    /// Writes a gpu crash breadcrumb for the next task if breadcrumbs are enabled.
+    fn record_breadcrumb(&self, task: &'static str) {
+        self.share.get_breadcrumbs().record(self.share.get_device(), self.cmd, None, task);
+    }
+
     fn record_global_buffer_write(&mut self, write: GlobalMeshWrite, is_uninit: bool) {
+        self.record_breadcrumb("WriteGlobalMesh");
         let dst_buffer = write.dst_mesh.get_buffer_handle();
 
-        if !write.regions.is_empty() {
+        let buffer_size = write.dst_mesh.get_buffer_size();
+        if let Some(region) = write.regions.iter().find(|region| region.dst_offset + region.size > buffer_size) {
+            self.share.push_error(EmulatorTaskError {
+                task: EmulatorTaskKind::WriteGlobalMesh(write.dst_mesh.get_id()),
+                reason: format!("Copy region {:?} exceeds buffer size {:?}", region, buffer_size)
+            });
+        } else if !write.regions.is_empty() {
             self.transition_mesh(write.dst_mesh, gob::MeshState::TransferWrite, is_uninit);
 
             unsafe {
@@ -587,33 +1160,53 @@ impl GlobalObjectsRecorder {
     }
 
     fn record_global_image_clear(&mut self, clear: GlobalImageClear, is_uninit: bool) {
+        self.record_breadcrumb("ClearGlobalImage");
         let dst_image = clear.dst_image.get_image_handle();
+        let aspect_mask = clear.dst_image.get_aspect_mask();
+        let is_depth = clear.dst_image.is_depth();
 
         self.transition_image(clear.dst_image, gob::ImageState::TransferWrite, is_uninit);
 
+        let range = vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS
+        };
         unsafe {
-            self.share.get_device().vk().cmd_clear_color_image(
-                self.cmd,
-                dst_image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &clear.clear_value,
-                std::slice::from_ref(&vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: vk::REMAINING_MIP_LEVELS,
-                    base_array_layer: 0,
-                    layer_count: vk::REMAINING_ARRAY_LAYERS
-                })
-            )
+            if is_depth {
+                self.share.get_device().vk().cmd_clear_depth_stencil_image(
+                    self.cmd,
+                    dst_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearDepthStencilValue { depth: 0.0, stencil: 0 },
+                    std::slice::from_ref(&range)
+                )
+            } else {
+                self.share.get_device().vk().cmd_clear_color_image(
+                    self.cmd,
+                    dst_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &clear.clear_value,
+                    std::slice::from_ref(&range)
+                )
+            }
         }
     }
 
     fn record_global_image_write(&mut self, write: GlobalImageWrite, is_uninit: bool) {
+        self.record_breadcrumb("WriteGlobalImage");
         let dst_image = write.dst_image.get_image_handle();
 
-        self.transition_image(write.dst_image, gob::ImageState::TransferWrite, is_uninit);
+        if let Err(reason) = validate_image_regions(&write.dst_image, &write.regions, write.staging_range) {
+            self.share.push_error(EmulatorTaskError {
+                task: EmulatorTaskKind::WriteGlobalImage(write.dst_image.get_id()),
+                reason
+            });
+        } else if !write.regions.is_empty() {
+            self.transition_image(write.dst_image, gob::ImageState::TransferWrite, is_uninit);
 
-        if !write.regions.is_empty() {
             unsafe {
                 self.share.get_device().vk().cmd_copy_buffer_to_image(
                     self.cmd,
@@ -628,7 +1221,124 @@ impl GlobalObjectsRecorder {
         self.push_staging(write.staging_allocation, write.staging_buffer, write.staging_range.0, write.staging_range.1);
     }
 
+    fn record_global_image_read(&mut self, read: GlobalImageRead) {
+        self.record_breadcrumb("ReadGlobalImage");
+        let src_image = read.src_image.get_image_handle();
+
+        if let Err(reason) = validate_image_regions(&read.src_image, &read.regions, read.staging_range) {
+            let error = EmulatorTaskError {
+                task: EmulatorTaskKind::ReadGlobalImage(read.src_image.get_id()),
+                reason
+            };
+            self.share.push_error(error.clone());
+
+            // Nothing is copied so the read can be completed immediately
+            read.target.fail(error);
+            self.staging_allocations.push(read.staging_allocation);
+            return;
+        }
+
+        if !read.regions.is_empty() {
+            self.transition_image(read.src_image, gob::ImageState::TransferRead, false);
+
+            unsafe {
+                self.share.get_device().vk().cmd_copy_image_to_buffer(
+                    self.cmd,
+                    src_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    read.staging.buffer,
+                    read.regions.as_ref()
+                );
+            }
+        }
+
+        self.staging_allocations.push(read.staging_allocation);
+        let barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .buffer(read.staging.buffer)
+            .offset(read.staging_range.0)
+            .size(read.staging_range.1);
+
+        let info = vk::DependencyInfo::builder()
+            .buffer_memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.share.get_device().synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info)
+        };
+
+        self.pending_reads.push(PendingRead {
+            staging: read.staging,
+            dst: read.dst,
+            target: read.target,
+        });
+    }
+
+    fn record_global_image_blit(&mut self, blit: GlobalImageBlit) {
+        self.record_breadcrumb("BlitGlobalImage");
+        // Images not used by this recorder are already in the ready state
+        if let Some(state) = self.used_global_images.get(&blit.src_image) {
+            if *state != gob::ImageState::Ready {
+                self.transition_image(blit.src_image.clone(), gob::ImageState::Ready, false);
+            }
+        }
+        self.transition_image(blit.dst_image.clone(), gob::ImageState::ColorAttachmentWrite, false);
+
+        let objects = self.share.get_fullscreen_blit().record(
+            self.cmd,
+            blit.src_image.get_sampler_view(),
+            blit.sampler,
+            blit.dst_image.get_image_handle(),
+            blit.dst_image.get_format().get_format(),
+            blit.dst_image.get_size(),
+            &blit.options
+        );
+        self.blit_objects.push((objects, blit.src_image));
+    }
+
+    fn record_global_image_export(&mut self, export: GlobalImageExport) {
+        self.record_breadcrumb("ExportGlobalImage");
+
+        // The image must be in the ready state before the export barrier. The image is removed from
+        // the used image list so that it is not transitioned back to ready at the end of the
+        // submission.
+        if let Some(state) = self.used_global_images.remove(&export.image) {
+            if state != gob::ImageState::Ready {
+                self.tmp_image_barriers.clear();
+                gob::generate_image_barriers(state, gob::ImageState::Ready, export.image.get_image_handle(), export.image.get_mip_levels(), export.image.get_aspect_mask(), &mut self.tmp_image_barriers);
+                self.push_image_barriers();
+            }
+        }
+
+        self.tmp_image_barriers.clear();
+        self.tmp_image_barriers.push(gob::make_export_barrier(export.image.get_image_handle(), export.image.get_aspect_mask(), &export.state));
+        self.push_image_barriers();
+        self.external_images.push(export.image);
+    }
+
+    fn record_global_image_reacquire(&mut self, export: GlobalImageExport) {
+        self.record_breadcrumb("ReacquireGlobalImage");
+
+        self.tmp_image_barriers.clear();
+        self.tmp_image_barriers.push(gob::make_reacquire_barrier(export.image.get_image_handle(), export.image.get_aspect_mask(), &export.state));
+        self.push_image_barriers();
+        self.external_images.push(export.image);
+    }
+
+    /// Records all barriers in the temporary image barrier list.
+    fn push_image_barriers(&mut self) {
+        let info = vk::DependencyInfo::builder()
+            .image_memory_barriers(self.tmp_image_barriers.as_slice());
+
+        unsafe {
+            self.share.get_device().synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
+        }
+    }
+
     fn record_global_image_generate_mipmaps(&mut self, image: Arc<GlobalImage>) {
+        self.record_breadcrumb("GenerateGlobalImageMipmaps");
         let mip_levels = image.get_mip_levels();
         if mip_levels > 1 {
             let handle = image.get_image_handle();
@@ -769,7 +1479,7 @@ impl GlobalObjectsRecorder {
             let handle = image.get_image_handle();
             let mip_levels = image.get_mip_levels();
 
-            gob::generate_image_barriers(*old_state, gob::ImageState::Ready, handle, mip_levels, &mut barriers);
+            gob::generate_image_barriers(*old_state, gob::ImageState::Ready, handle, mip_levels, image.get_aspect_mask(), &mut barriers);
         }
 
         barriers
@@ -831,7 +1541,10 @@ impl GlobalObjectsRecorder {
     fn transition_image(&mut self, image: Arc<GlobalImage>, new_state: gob::ImageState, maybe_uninit: bool) {
         let handle = image.get_image_handle();
         let mip_levels = image.get_mip_levels();
+        let aspect_mask = image.get_aspect_mask();
 
+        // The first transition of a image always leaves the uninitialized state
+        image.mark_initialized();
         let old_state = self.used_global_images.insert(image, new_state).unwrap_or_else(|| {
             if maybe_uninit {
                 gob::ImageState::Uninitialized
@@ -841,7 +1554,7 @@ impl GlobalObjectsRecorder {
         });
 
         self.tmp_image_barriers.clear();
-        gob::generate_image_barriers(old_state, new_state, handle, mip_levels, &mut self.tmp_image_barriers);
+        gob::generate_image_barriers(old_state, new_state, handle, mip_levels, aspect_mask, &mut self.tmp_image_barriers);
 
         if !self.tmp_image_barriers.is_empty() {
             let info = vk::DependencyInfo::builder()
@@ -856,6 +1569,20 @@ impl GlobalObjectsRecorder {
 
 impl Drop for GlobalObjectsRecorder {
     fn drop(&mut self) {
+        // The recorder is only dropped once its submission has completed so the staging memory
+        // must be read before it is freed.
+        for mut read in std::mem::replace(&mut self.pending_reads, Vec::new()) {
+            unsafe {
+                let mapped = std::slice::from_raw_parts(read.staging.mapped.as_ptr(), read.dst.len());
+                read.dst.copy_from_slice(mapped);
+            }
+            read.target.complete(read.dst);
+        }
+
+        for (objects, _) in std::mem::replace(&mut self.blit_objects, Vec::new()) {
+            self.share.get_fullscreen_blit().destroy_objects(objects);
+        }
+
         let mut guard = self.share.get_staging_pool().lock().unwrap_or_else(|_| {
             log::error!("Poisoned staging memory mutex in GlobalObjectsRecorder::drop");
             panic!();
@@ -867,6 +1594,63 @@ impl Drop for GlobalObjectsRecorder {
     }
 }
 
+/// Validates that all copy regions are inside the mip level of the image they access and inside
+/// the `(offset, size)` range of the staging buffer.
+fn validate_image_regions(image: &GlobalImage, regions: &[vk::BufferImageCopy], buffer_range: (vk::DeviceSize, vk::DeviceSize)) -> Result<(), String> {
+    let texel_size = image.get_format().get_compatibility_class().get_texel_size();
+    for region in regions {
+        validate_buffer_region(region, texel_size, buffer_range)?;
+
+        let mip_level = region.image_subresource.mip_level;
+        if mip_level >= image.get_mip_levels() {
+            return Err(format!("Copy region mip level {:?} exceeds image mip level count {:?}", mip_level, image.get_mip_levels()));
+        }
+
+        let layer = region.image_subresource.base_array_layer;
+        if layer >= image.get_array_layer_count() {
+            return Err(format!("Copy region array layer {:?} exceeds image array layer count {:?}", layer, image.get_array_layer_count()));
+        }
+
+        let size = image.get_mip_size(mip_level);
+        if region.image_offset.x < 0 || region.image_offset.y < 0 ||
+            (region.image_offset.x as u32) + region.image_extent.width > size[0] ||
+            (region.image_offset.y as u32) + region.image_extent.height > size[1] {
+            return Err(format!("Copy region (offset: {:?}, extent: {:?}) exceeds image size {:?}", region.image_offset, region.image_extent, size));
+        }
+    }
+    Ok(())
+}
+
+/// Validates that the buffer memory accessed by a copy region is inside the `(offset, size)` range
+/// of the staging buffer. If the texel size of the format is unknown only the start of the region
+/// is checked.
+fn validate_buffer_region(region: &vk::BufferImageCopy, texel_size: Option<u32>, buffer_range: (vk::DeviceSize, vk::DeviceSize)) -> Result<(), String> {
+    let width = region.image_extent.width as vk::DeviceSize;
+    let height = region.image_extent.height as vk::DeviceSize;
+    let row_length = if region.buffer_row_length == 0 { width } else { region.buffer_row_length as vk::DeviceSize };
+    if row_length < width {
+        return Err(format!("Copy region buffer row length {:?} is smaller than the extent width {:?}", row_length, width));
+    }
+
+    let byte_size = match texel_size {
+        Some(texel_size) if width != 0 && height != 0 => ((height - 1) * row_length + width) * (texel_size as vk::DeviceSize),
+        _ => 0,
+    };
+
+    let start = region.buffer_offset;
+    let end = start + byte_size;
+    if start < buffer_range.0 || end > buffer_range.0 + buffer_range.1 {
+        return Err(format!("Copy region accessing buffer bytes {:?}..{:?} exceeds buffer range {:?}..{:?}", start, end, buffer_range.0, buffer_range.0 + buffer_range.1));
+    }
+    Ok(())
+}
+
+struct PendingRead {
+    staging: StagingAllocation,
+    dst: Box<[u8]>,
+    target: Arc<ReadState>,
+}
+
 mod gob {
     //! Utility functions to create barriers for global objects
 
@@ -960,14 +1744,18 @@ mod gob {
         TransferWrite,
         /// Image had previously generated its mipmaps
         GenerateMipmaps,
+        /// Image was previously read from
+        TransferRead,
+        /// Image was previously rendered to as a color attachment
+        ColorAttachmentWrite,
     }
 
-    pub(super) fn generate_image_barriers(old_state: ImageState, new_state: ImageState, image: vk::Image, mip_levels: u32, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
+    pub(super) fn generate_image_barriers(old_state: ImageState, new_state: ImageState, image: vk::Image, mip_levels: u32, aspect_mask: vk::ImageAspectFlags, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
         match (old_state, new_state) {
             (ImageState::Uninitialized, ImageState::TransferWrite) => {
                 let mut barrier = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_full_subresource_range(aspect_mask));
                 barrier = IMAGE_UNINITIALIZED_INFO.write_src(barrier);
                 barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
 
@@ -976,7 +1764,7 @@ mod gob {
             (ImageState::Ready, ImageState::TransferWrite) => {
                 let mut barrier = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_full_subresource_range(aspect_mask));
                 barrier = IMAGE_READY_INFO.write_src(barrier);
                 barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
 
@@ -985,7 +1773,7 @@ mod gob {
             (ImageState::Ready, ImageState::GenerateMipmaps) => {
                 let mut barrier0 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_first_mip_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_first_mip_subresource_range(aspect_mask));
                 barrier0 = IMAGE_READY_INFO.write_src(barrier0);
                 barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
 
@@ -993,7 +1781,7 @@ mod gob {
 
                 let mut barrier1 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_exclude_first_mips_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_exclude_first_mips_subresource_range(aspect_mask));
                 barrier1 = IMAGE_READY_INFO.write_src(barrier1);
                 barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
 
@@ -1002,7 +1790,7 @@ mod gob {
             (ImageState::TransferWrite, ImageState::Ready) => {
                 let mut barrier = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_full_subresource_range(aspect_mask));
                 barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
                 barrier = IMAGE_READY_INFO.write_dst(barrier);
 
@@ -1011,7 +1799,7 @@ mod gob {
             (ImageState::TransferWrite, ImageState::TransferWrite) => {
                 let mut barrier = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_full_subresource_range(aspect_mask));
                 barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
                 barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
 
@@ -1020,7 +1808,7 @@ mod gob {
             (ImageState::TransferWrite, ImageState::GenerateMipmaps) => {
                 let mut barrier0 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_first_mip_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_first_mip_subresource_range(aspect_mask));
                 barrier0 = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier0);
                 barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
 
@@ -1028,7 +1816,7 @@ mod gob {
 
                 let mut barrier1 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_exclude_first_mips_subresource_range(vk::ImageAspectFlags::COLOR));
+                    .subresource_range(make_exclude_first_mips_subresource_range(aspect_mask));
                 barrier1 = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier1);
                 barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
 
@@ -1037,7 +1825,7 @@ mod gob {
             (ImageState::GenerateMipmaps, ImageState::Ready) => {
                 let mut barrier0 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_exclude_last_mips_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
+                    .subresource_range(make_exclude_last_mips_subresource_range(aspect_mask, mip_levels));
                 barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
                 barrier0 = IMAGE_READY_INFO.write_dst(barrier0);
 
@@ -1045,7 +1833,7 @@ mod gob {
 
                 let mut barrier1 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_last_mip_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
+                    .subresource_range(make_last_mip_subresource_range(aspect_mask, mip_levels));
                 barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
                 barrier1 = IMAGE_READY_INFO.write_dst(barrier1);
 
@@ -1054,7 +1842,7 @@ mod gob {
             (ImageState::GenerateMipmaps, ImageState::TransferWrite) => {
                 let mut barrier0 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_exclude_last_mips_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
+                    .subresource_range(make_exclude_last_mips_subresource_range(aspect_mask, mip_levels));
                 barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
                 barrier0 = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier0);
 
@@ -1062,12 +1850,182 @@ mod gob {
 
                 let mut barrier1 = vk::ImageMemoryBarrier2::builder()
                     .image(image)
-                    .subresource_range(make_last_mip_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
+                    .subresource_range(make_last_mip_subresource_range(aspect_mask, mip_levels));
                 barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
                 barrier1 = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier1);
 
                 barriers.push(barrier1.build());
             }
+            (ImageState::Ready, ImageState::TransferRead) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_READY_INFO.write_src(barrier);
+                barrier = IMAGE_TRANSFER_READ_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::TransferWrite, ImageState::TransferRead) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_TRANSFER_READ_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::GenerateMipmaps, ImageState::TransferRead) => {
+                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_exclude_last_mips_subresource_range(aspect_mask, mip_levels));
+                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
+                barrier0 = IMAGE_TRANSFER_READ_INFO.write_dst(barrier0);
+
+                barriers.push(barrier0.build());
+
+                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_last_mip_subresource_range(aspect_mask, mip_levels));
+                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
+                barrier1 = IMAGE_TRANSFER_READ_INFO.write_dst(barrier1);
+
+                barriers.push(barrier1.build());
+            }
+            (ImageState::TransferRead, ImageState::Ready) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_TRANSFER_READ_INFO.write_src(barrier);
+                barrier = IMAGE_READY_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::TransferRead, ImageState::TransferWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_TRANSFER_READ_INFO.write_src(barrier);
+                barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::TransferRead, ImageState::GenerateMipmaps) => {
+                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_first_mip_subresource_range(aspect_mask));
+                barrier0 = IMAGE_TRANSFER_READ_INFO.write_src(barrier0);
+                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
+
+                barriers.push(barrier0.build());
+
+                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_exclude_first_mips_subresource_range(aspect_mask));
+                barrier1 = IMAGE_TRANSFER_READ_INFO.write_src(barrier1);
+                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
+
+                barriers.push(barrier1.build());
+            }
+            (ImageState::Ready, ImageState::ColorAttachmentWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_READY_INFO.write_src(barrier);
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::TransferWrite, ImageState::ColorAttachmentWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::TransferRead, ImageState::ColorAttachmentWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_TRANSFER_READ_INFO.write_src(barrier);
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::GenerateMipmaps, ImageState::ColorAttachmentWrite) => {
+                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_exclude_last_mips_subresource_range(aspect_mask, mip_levels));
+                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
+                barrier0 = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier0);
+
+                barriers.push(barrier0.build());
+
+                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_last_mip_subresource_range(aspect_mask, mip_levels));
+                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
+                barrier1 = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier1);
+
+                barriers.push(barrier1.build());
+            }
+            (ImageState::ColorAttachmentWrite, ImageState::Ready) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_READY_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::ColorAttachmentWrite, ImageState::TransferWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::ColorAttachmentWrite, ImageState::TransferRead) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_TRANSFER_READ_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::ColorAttachmentWrite, ImageState::ColorAttachmentWrite) => {
+                let mut barrier = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(aspect_mask));
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier);
+                barrier = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_dst(barrier);
+
+                barriers.push(barrier.build());
+            }
+            (ImageState::ColorAttachmentWrite, ImageState::GenerateMipmaps) => {
+                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_first_mip_subresource_range(aspect_mask));
+                barrier0 = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier0);
+                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
+
+                barriers.push(barrier0.build());
+
+                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
+                    .image(image)
+                    .subresource_range(make_exclude_first_mips_subresource_range(aspect_mask));
+                barrier1 = IMAGE_COLOR_ATTACHMENT_WRITE_INFO.write_src(barrier1);
+                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
+
+                barriers.push(barrier1.build());
+            }
+            (ImageState::TransferRead, ImageState::TransferRead) => {
+                // Consecutive reads do not need a barrier
+            }
             (ImageState::Ready, ImageState::Ready) => {
                 log::warn!("Transitioned image from ready to ready. Why?");
             }
@@ -1141,11 +2099,37 @@ mod gob {
         }
     }
 
+    /// Creates a barrier transitioning a image from the ready state into a external state.
+    pub(super) fn make_export_barrier(image: vk::Image, aspect_mask: vk::ImageAspectFlags, state: &super::ExternalImageState) -> vk::ImageMemoryBarrier2 {
+        let external = ImageAccessInfo::new(state.stage_mask, state.access_mask, state.layout);
+
+        let mut barrier = vk::ImageMemoryBarrier2::builder()
+            .image(image)
+            .subresource_range(make_full_subresource_range(aspect_mask));
+        barrier = IMAGE_READY_INFO.write_src(barrier);
+        barrier = external.write_dst(barrier);
+        barrier.build()
+    }
+
+    /// Creates a barrier transitioning a image from a external state back into the ready state.
+    pub(super) fn make_reacquire_barrier(image: vk::Image, aspect_mask: vk::ImageAspectFlags, state: &super::ExternalImageState) -> vk::ImageMemoryBarrier2 {
+        let external = ImageAccessInfo::new(state.stage_mask, state.access_mask, state.layout);
+
+        let mut barrier = vk::ImageMemoryBarrier2::builder()
+            .image(image)
+            .subresource_range(make_full_subresource_range(aspect_mask));
+        barrier = external.write_src(barrier);
+        barrier = IMAGE_READY_INFO.write_dst(barrier);
+        barrier.build()
+    }
+
     const IMAGE_UNINITIALIZED_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE, vk::ImageLayout::UNDEFINED);
     const IMAGE_READY_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
     const IMAGE_TRANSFER_WRITE_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+    const IMAGE_TRANSFER_READ_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
     const IMAGE_GENERATE_MIPMAPS_0_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
     const IMAGE_GENERATE_MIPMAPS_1_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+    const IMAGE_COLOR_ATTACHMENT_WRITE_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
     struct ImageAccessInfo {
         stage_mask: vk::PipelineStageFlags2,
@@ -1179,4 +2163,281 @@ mod gob {
                 .new_layout(self.layout)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+    use super::*;
+
+    #[test]
+    fn flush_retires_completed_reads() {
+        use crate::renderer::emulator::readback::{ChannelOrder, ImageReadToken};
+
+        // Stands in for a global objects recorder which completes its readbacks when dropped
+        struct PendingReads(Vec<Arc<ReadState>>);
+        impl Drop for PendingReads {
+            fn drop(&mut self) {
+                for state in &self.0 {
+                    state.complete(Box::new([1u8; 4]));
+                }
+            }
+        }
+
+        let (tokens, states): (Vec<_>, Vec<_>) = (0..3).map(|_| ImageReadToken::new(ChannelOrder::Rgba)).unzip();
+        let mut flushes = vec![
+            (PassId::from_raw(2), PendingReads(states[..2].to_vec())),
+            (PassId::from_raw(4), PendingReads(states[2..].to_vec())),
+        ];
+
+        assert_eq!(retire_flushes(&mut flushes, 1), None);
+        assert_eq!(retire_flushes(&mut flushes, 3), Some(PassId::from_raw(2)));
+        assert!(tokens[0].is_complete() && tokens[1].is_complete());
+        assert!(!tokens[2].is_complete());
+
+        // Once the value returned by the flush is reached all its reads have completed
+        assert_eq!(retire_flushes(&mut flushes, 4), Some(PassId::from_raw(4)));
+        assert!(flushes.is_empty());
+        for token in tokens {
+            assert_eq!(token.wait().as_ref(), &[1u8; 4]);
+        }
+    }
+
+    fn make_draw(vertex_buffer: vk::Buffer, index_buffer: vk::Buffer) -> PipelineTask {
+        PipelineTask::Draw(crate::renderer::emulator::pipeline::DrawTask {
+            vertex_buffer,
+            index_buffer,
+            vertex_offset: 0,
+            first_index: 0,
+            index_type: vk::IndexType::UINT32,
+            index_count: 3,
+            shader: ShaderId::new(),
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            depth_write_enable: true
+        })
+    }
+
+    #[test]
+    fn deferred_release_waits_for_external_value() {
+        let image = Arc::new(());
+        let semaphore = vk::Semaphore::from_raw(11);
+        let mut releases = vec![DeferredRelease {
+            semaphore,
+            value: 5,
+            objects: Box::new([image.clone()]) as Box<[_]>,
+        }];
+
+        // The compositor has not finished reading yet
+        process_deferred_releases(&mut releases, |_| 4);
+        assert_eq!(releases.len(), 1);
+        assert_eq!(Arc::strong_count(&image), 2);
+
+        process_deferred_releases(&mut releases, |s| {
+            assert_eq!(s, semaphore);
+            5
+        });
+        assert!(releases.is_empty());
+        assert_eq!(Arc::strong_count(&image), 1);
+    }
+
+    #[test]
+    fn shutdown_leaks_unreleased_exports() {
+        let released = Arc::new(());
+        let in_use = Arc::new(());
+        let releases = vec![
+            DeferredRelease {
+                semaphore: vk::Semaphore::from_raw(11),
+                value: 5,
+                objects: Box::new([released.clone()]) as Box<[_]>,
+            },
+            DeferredRelease {
+                semaphore: vk::Semaphore::from_raw(12),
+                value: 3,
+                objects: Box::new([in_use.clone()]) as Box<[_]>,
+            },
+        ];
+
+        let mut waited = Vec::new();
+        let leaked = finish_deferred_releases(releases, |semaphore, value| {
+            waited.push((semaphore, value));
+            semaphore == vk::Semaphore::from_raw(11)
+        });
+
+        assert_eq!(waited, vec![(vk::Semaphore::from_raw(11), 5), (vk::Semaphore::from_raw(12), 3)]);
+        assert_eq!(leaked, 1);
+        assert_eq!(Arc::strong_count(&released), 1);
+        // The externally used image must never be destroyed
+        assert_eq!(Arc::strong_count(&in_use), 2);
+    }
+
+    #[test]
+    fn transfer_semaphore_handoff() {
+        use crate::objects::sync::Semaphore;
+
+        // A upload on a transfer queue signals timeline value 3 which the draws must wait on
+        let transfer_timeline = Semaphore::new(vk::Semaphore::from_raw(7));
+        let acquire = Semaphore::new(vk::Semaphore::from_raw(8));
+        let waits = [
+            (SemaphoreOp::new_timeline(transfer_timeline, 3), vk::PipelineStageFlags2::VERTEX_INPUT),
+            (SemaphoreOp::new_binary(acquire), vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
+        ];
+
+        let infos = make_wait_semaphore_infos(&waits);
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].semaphore, vk::Semaphore::from_raw(7));
+        assert_eq!(infos[0].value, 3);
+        assert_eq!(infos[0].stage_mask, vk::PipelineStageFlags2::VERTEX_INPUT);
+        assert_eq!(infos[1].value, 0);
+        assert!(make_wait_semaphore_infos(&[]).is_empty());
+    }
+
+    #[test]
+    fn untracked_resource_detected() {
+        let mesh_buffer = vk::Buffer::from_raw(1);
+        let immediate_buffer = vk::Buffer::from_raw(2);
+        let freed_buffer = vk::Buffer::from_raw(3);
+        let view = vk::ImageView::from_raw(4);
+
+        let mut referenced = ReferencedResources::new();
+        referenced.record(&make_draw(mesh_buffer, mesh_buffer));
+        referenced.record(&make_draw(immediate_buffer, immediate_buffer));
+        referenced.record(&PipelineTask::UpdateTexture(ShaderId::new(), 0, view, vk::Sampler::null()));
+
+        let tracked_buffers: HashSet<_> = [mesh_buffer, immediate_buffer].into_iter().collect();
+        let tracked_views: HashSet<_> = [view].into_iter().collect();
+        assert_eq!(referenced.find_untracked(&tracked_buffers, &tracked_views), None);
+
+        // The mesh of this draw was never registered with the pass
+        referenced.record(&make_draw(freed_buffer, freed_buffer));
+        assert_eq!(referenced.find_untracked(&tracked_buffers, &tracked_views), Some(ReferencedResource::Buffer(freed_buffer)));
+        assert_eq!(referenced.find_untracked(&tracked_buffers, &HashSet::new()), Some(ReferencedResource::Buffer(freed_buffer)));
+
+        let all_buffers: HashSet<_> = [mesh_buffer, immediate_buffer, freed_buffer].into_iter().collect();
+        assert_eq!(referenced.find_untracked(&all_buffers, &HashSet::new()), Some(ReferencedResource::ImageView(view)));
+
+        // Tasks without resource handles add nothing
+        let mut referenced = ReferencedResources::new();
+        referenced.record(&PipelineTask::DrawProcedural(crate::renderer::emulator::pipeline::ProceduralDrawTask {
+            vertex_count: 3,
+            instance_count: 1,
+            shader: ShaderId::new(),
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            depth_write_enable: true,
+        }));
+        referenced.record(&PipelineTask::SetBindlessTexture(0));
+        assert_eq!(referenced.find_untracked(&HashSet::new(), &HashSet::new()), None);
+    }
+
+    #[test]
+    fn reuse_waits_for_timeline_value() {
+        assert_eq!(get_reuse_wait_value(1, MAX_PASSES_IN_FLIGHT), 0);
+        assert_eq!(get_reuse_wait_value(MAX_PASSES_IN_FLIGHT, MAX_PASSES_IN_FLIGHT), 0);
+
+        // Simulates a gpu which only makes progress when the worker waits on the timeline
+        let mut completed = 0u64;
+        let mut slots = [0u64; MAX_PASSES_IN_FLIGHT as usize];
+        for pass in 1..=20u64 {
+            let wait_value = get_reuse_wait_value(pass, MAX_PASSES_IN_FLIGHT);
+            completed = completed.max(wait_value);
+
+            // The slot was last used by the pass submitted MAX_PASSES_IN_FLIGHT passes earlier
+            let slot = &mut slots[(pass % MAX_PASSES_IN_FLIGHT) as usize];
+            assert!(*slot <= completed);
+            assert_eq!(*slot, wait_value);
+            *slot = pass;
+
+            assert!(pass - completed <= MAX_PASSES_IN_FLIGHT);
+        }
+    }
+
+    #[test]
+    fn export_barrier_reaches_requested_layout() {
+        let image = vk::Image::from_raw(1);
+        let state = ExternalImageState {
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            access_mask: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        };
+
+        let export = gob::make_export_barrier(image, vk::ImageAspectFlags::COLOR, &state);
+        assert_eq!(export.image, image);
+        assert_eq!(export.old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(export.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(export.dst_stage_mask, vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert_eq!(export.dst_access_mask, vk::AccessFlags2::SHADER_SAMPLED_READ);
+
+        let general = ExternalImageState {
+            layout: vk::ImageLayout::GENERAL,
+            ..state
+        };
+        let reacquire = gob::make_reacquire_barrier(image, vk::ImageAspectFlags::COLOR, &general);
+        assert_eq!(reacquire.old_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(reacquire.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(reacquire.src_stage_mask, vk::PipelineStageFlags2::COMPUTE_SHADER);
+    }
+
+    #[test]
+    fn depth_image_barriers() {
+        let image = vk::Image::from_raw(1);
+        let aspect_mask = vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL;
+
+        let mut barriers = Vec::new();
+        gob::generate_image_barriers(gob::ImageState::Ready, gob::ImageState::TransferWrite, image, 1, aspect_mask, &mut barriers);
+        gob::generate_image_barriers(gob::ImageState::TransferWrite, gob::ImageState::Ready, image, 1, aspect_mask, &mut barriers);
+        assert_eq!(barriers.len(), 2);
+
+        // Sampled depth images are kept in the same layout as color images between operations
+        assert!(barriers.iter().all(|barrier| barrier.subresource_range.aspect_mask == aspect_mask));
+        assert_eq!(barriers[0].old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barriers[0].new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(barriers[1].new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn buffer_regions_validated() {
+        let region = vk::BufferImageCopy {
+            buffer_offset: 64,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers::default(),
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D { width: 4, height: 2, depth: 1 },
+        };
+
+        // 2 rows of 4 texels with 4 bytes each
+        assert!(validate_buffer_region(&region, Some(4), (64, 32)).is_ok());
+        assert!(validate_buffer_region(&region, Some(4), (64, 31)).is_err());
+        assert!(validate_buffer_region(&region, Some(4), (65, 32)).is_err());
+
+        // A row length of 8 texels places the second row 32 bytes after the first
+        let strided = vk::BufferImageCopy { buffer_row_length: 8, ..region };
+        assert!(validate_buffer_region(&strided, Some(4), (64, 48)).is_ok());
+        assert!(validate_buffer_region(&strided, Some(4), (64, 47)).is_err());
+
+        let short_rows = vk::BufferImageCopy { buffer_row_length: 2, ..region };
+        assert!(validate_buffer_region(&short_rows, Some(4), (0, 1024)).is_err());
+
+        // Without a texel size only the start is checked
+        assert!(validate_buffer_region(&region, None, (64, 0)).is_ok());
+        assert!(validate_buffer_region(&region, None, (0, 63)).is_err());
+    }
+
+    #[test]
+    fn failed_read_completes_with_error() {
+        use crate::renderer::emulator::{EmulatorRenderer, ImageReadRegion};
+        use crate::util::format::Format;
+
+        let (_, device) = crate::vk::test::make_headless_instance_device();
+        let emulator = EmulatorRenderer::new(device);
+        let image = emulator.create_global_image(Vec2u32::new(4, 4), &Format::R8G8B8A8_UNORM);
+
+        // The region is larger than the image so the worker rejects it
+        let region = ImageReadRegion::new_full(Vec2u32::new(8, 8));
+        let token = image.read_sub_image_zeroed(&[region], 8 * 8 * 4);
+        emulator.flush().unwrap();
+
+        let err = token.try_wait().unwrap_err();
+        assert_eq!(err.task, EmulatorTaskKind::ReadGlobalImage(image.get_id()));
+        assert_eq!(emulator.take_errors(), vec![err]);
+    }
+}
@@ -4,6 +4,8 @@ use std::hash::{Hash, Hasher};
 
 use ash::vk;
 
+use crate::prelude::*;
+
 #[derive(Eq, Copy, Clone, Debug)]
 pub struct CompatibilityClass {
     name: &'static str,
@@ -24,6 +26,117 @@ impl CompatibilityClass {
         self.name
     }
 
+    /// Returns the size of a single texel in bytes. Returns [`None`] for block compressed and
+    /// packed classes.
+    pub fn get_texel_size(&self) -> Option<u32> {
+        match self.name {
+            "BIT8" => Some(1),
+            "BIT16" => Some(2),
+            "BIT24" => Some(3),
+            "BIT32" => Some(4),
+            "BIT48" => Some(6),
+            "BIT64" => Some(8),
+            "BIT96" => Some(12),
+            "BIT128" => Some(16),
+            "BIT192" => Some(24),
+            "BIT256" => Some(32),
+            _ => None,
+        }
+    }
+
+    /// Returns the size in bytes of a single texel block as laid out in buffer memory for copies.
+    /// For depth stencil classes the size depends on the copied aspect. Returns [`None`] for
+    /// multi-planar classes and depth stencil classes if `aspect` does not select a single
+    /// aspect.
+    pub fn get_block_size(&self, aspect: vk::ImageAspectFlags) -> Option<u32> {
+        if let Some(size) = self.get_texel_size() {
+            return Some(size);
+        }
+
+        match self.name {
+            "BIT32_G8B8G8R8" | "BIT32_B8G8R8G8" => Some(4),
+            "BIT64_R10G10B10A10" | "BIT64_G10B10G10R10" | "BIT64_B10G10R10G10" | "BIT64_R12G12B12A12" |
+            "BIT64_G12B12G12R12" | "BIT64_B12G12R12G12" | "BIT64_G16B16G16R16" | "BIT64_B16G16R16G16" => Some(8),
+            "BC1_RGB" | "BC1_RGBA" | "BC4" | "ETC2_RGB" | "ETC2_RGBA" | "EAC_R" => Some(8),
+            "BC2" | "BC3" | "BC5" | "BC6H" | "BC7" | "ETC2_EAC_RGBA" | "EAC_RG" => Some(16),
+            name if name.starts_with("ASTC_") => Some(16),
+            "D16" => Some(2),
+            "D24" | "D32" => Some(4),
+            "S8" => Some(1),
+            "D16S8" | "D24S8" | "D32S8" => {
+                if aspect == vk::ImageAspectFlags::STENCIL {
+                    Some(1)
+                } else if aspect == vk::ImageAspectFlags::DEPTH {
+                    // Packed 24 bit depth is copied as 32 bit values
+                    if self.name == "D16S8" { Some(2) } else { Some(4) }
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the width and height in texels of a single compressed block. Packed 4:2:2 classes
+    /// use 2x1 blocks. All other classes have a block extent of 1x1.
+    pub fn get_block_extent(&self) -> (u32, u32) {
+        match self.name {
+            "BC1_RGB" | "BC1_RGBA" | "BC2" | "BC3" | "BC4" | "BC5" | "BC6H" | "BC7" |
+            "ETC2_RGB" | "ETC2_RGBA" | "ETC2_EAC_RGBA" | "EAC_R" | "EAC_RG" | "ASTC_4X4" => (4, 4),
+            "ASTC_5X4" => (5, 4),
+            "ASTC_5X5" => (5, 5),
+            "ASTC_6X5" => (6, 5),
+            "ASTC_6X6" => (6, 6),
+            "ASTC_8X5" => (8, 5),
+            "ASTC_8X6" => (8, 6),
+            "ASTC_8X8" => (8, 8),
+            "ASTC_10X5" => (10, 5),
+            "ASTC_10X6" => (10, 6),
+            "ASTC_10X8" => (10, 8),
+            "ASTC_10X10" => (10, 10),
+            "ASTC_12X10" => (12, 10),
+            "ASTC_12X12" => (12, 12),
+            "BIT32_G8B8G8R8" | "BIT32_B8G8R8G8" | "BIT64_R10G10B10A10" | "BIT64_G10B10G10R10" |
+            "BIT64_B10G10R10G10" | "BIT64_R12G12B12A12" | "BIT64_G12B12G12R12" | "BIT64_B12G12R12G12" |
+            "BIT64_G16B16G16R16" | "BIT64_B16G16R16G16" => (2, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// Returns the aspects of images of this class. Multi-planar classes return the color aspect
+    /// which selects all planes.
+    pub fn get_aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self.name {
+            "D16" | "D24" | "D32" => vk::ImageAspectFlags::DEPTH,
+            "S8" => vk::ImageAspectFlags::STENCIL,
+            "D16S8" | "D24S8" | "D32S8" => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    /// Returns the number of planes of multi-planar classes. Returns 1 for all other classes.
+    pub fn get_plane_count(&self) -> u32 {
+        if self.name.starts_with("PLANE3") {
+            3
+        } else if self.name.starts_with("PLANE2") {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Returns the horizontal and vertical subsampling divisor of the chroma planes of
+    /// multi-planar classes. Returns (1, 1) for all other classes.
+    pub fn get_chroma_subsampling(&self) -> (u32, u32) {
+        if self.name.ends_with("_420") {
+            (2, 2)
+        } else if self.name.ends_with("_422") {
+            (2, 1)
+        } else {
+            (1, 1)
+        }
+    }
+
     define_compatibility_class!(BIT8);
     define_compatibility_class!(BIT16);
     define_compatibility_class!(BIT24);
@@ -174,10 +287,75 @@ impl Format {
         self.clear_color_type
     }
 
+    pub fn get_aspect_mask(&self) -> vk::ImageAspectFlags {
+        self.compatibility_class.get_aspect_mask()
+    }
+
     pub fn is_compatible_with(&self, other: &Format) -> bool {
         self.compatibility_class == other.compatibility_class
     }
 
+    /// Returns the number of planes of the format. Returns 1 for formats which are not
+    /// multi-planar.
+    ///
+    /// Images using multi-planar formats require the VK_KHR_sampler_ycbcr_conversion extension,
+    /// see [`DeviceContext::supports_sampler_ycbcr_conversion`].
+    pub fn get_plane_count(&self) -> u32 {
+        self.compatibility_class.get_plane_count()
+    }
+
+    /// Returns the format which must be used to access a single plane of a multi-planar format.
+    /// Returns [`None`] if the format is not multi-planar or the plane does not exist.
+    pub fn get_plane_format(&self, plane: u32) -> Option<&'static Format> {
+        let plane_count = self.get_plane_count();
+        if plane_count == 1 || plane >= plane_count {
+            return None;
+        }
+
+        // The first plane always has a single channel. The second plane of 2 plane formats
+        // contains both chroma channels.
+        let dual_channel = plane_count == 2 && plane == 1;
+        let name = self.compatibility_class.get_name();
+        let format = if name.contains("_8BIT_") {
+            if dual_channel { &Self::R8G8_UNORM } else { &Self::R8_UNORM }
+        } else if name.contains("_10BIT_") {
+            if dual_channel { &Self::R10X6G10X6_UNORM_2PACK16 } else { &Self::R10X6_UNORM_PACK16 }
+        } else if name.contains("_12BIT_") {
+            if dual_channel { &Self::R12X4G12X4_UNORM_2PACK16 } else { &Self::R12X4_UNORM_PACK16 }
+        } else {
+            if dual_channel { &Self::R16G16_UNORM } else { &Self::R16_UNORM }
+        };
+        Some(format)
+    }
+
+    /// Returns the extent of a plane of a image with this format and the provided size. Chroma
+    /// planes of subsampled formats are smaller than the image. Returns [`None`] if the plane does
+    /// not exist.
+    pub fn get_plane_extent(&self, plane: u32, size: Vec2u32) -> Option<Vec2u32> {
+        if plane >= self.get_plane_count() {
+            return None;
+        }
+        if plane == 0 {
+            return Some(size);
+        }
+
+        let (x_divisor, y_divisor) = self.compatibility_class.get_chroma_subsampling();
+        Some(Vec2u32::new(size[0] / x_divisor, size[1] / y_divisor))
+    }
+
+    /// Returns the image aspect used to access a plane of a multi-planar format.
+    pub fn get_plane_aspect(plane: u32) -> vk::ImageAspectFlags {
+        match plane {
+            0 => vk::ImageAspectFlags::PLANE_0,
+            1 => vk::ImageAspectFlags::PLANE_1,
+            2 => vk::ImageAspectFlags::PLANE_2,
+            _ => {
+                log::error!("Called Format::get_plane_aspect with invalid plane {:?}", plane);
+                panic!()
+            }
+        }
+    }
+
     define_formats!(
     R4G4_UNORM_PACK8, CompatibilityClass::BIT8, 2, Some(ClearColorType::Float);
     R4G4B4A4_UNORM_PACK16, CompatibilityClass::BIT16, 4, Some(ClearColorType::Float);
@@ -434,4 +612,112 @@ impl Into<vk::Format> for &Format {
     fn into(self) -> vk::Format {
         self.format
     }
-}
\ No newline at end of file
+}
+
+/// Calculates the number of bytes a buffer must contain for all copy regions between it and a image
+/// of the provided format. Rows and layers padded using `buffer_row_length` and
+/// `buffer_image_height` are accounted for. Buffer offsets of the regions are absolute so the
+/// result is the end of the last byte accessed by any region.
+///
+/// Panics if the format has no defined buffer layout for the copied aspect.
+pub fn compute_staging_size(format: &Format, regions: &[vk::BufferImageCopy]) -> vk::DeviceSize {
+    let class = format.get_compatibility_class();
+    let (block_width, block_height) = class.get_block_extent();
+
+    regions.iter().map(|region| {
+        let extent = region.image_extent;
+        if extent.width == 0 || extent.height == 0 || extent.depth == 0 || region.image_subresource.layer_count == 0 {
+            return 0;
+        }
+
+        let block_size = class.get_block_size(region.image_subresource.aspect_mask).unwrap_or_else(|| {
+            log::error!("Called compute_staging_size with format {:?} which has no buffer layout for aspect {:?}", format, region.image_subresource.aspect_mask);
+            panic!()
+        }) as vk::DeviceSize;
+
+        let row_length = if region.buffer_row_length == 0 { extent.width } else { region.buffer_row_length };
+        let image_height = if region.buffer_image_height == 0 { extent.height } else { region.buffer_image_height };
+
+        let row_blocks = ((row_length + block_width - 1) / block_width) as vk::DeviceSize;
+        let image_blocks = ((image_height + block_height - 1) / block_height) as vk::DeviceSize;
+        let width_blocks = ((extent.width + block_width - 1) / block_width) as vk::DeviceSize;
+        let height_blocks = ((extent.height + block_height - 1) / block_height) as vk::DeviceSize;
+        let slices = (extent.depth as vk::DeviceSize) * (region.image_subresource.layer_count as vk::DeviceSize);
+
+        // The last slice and row only need to contain the accessed blocks
+        let last_block = ((slices - 1) * image_blocks + (height_blocks - 1)) * row_blocks + width_blocks;
+        region.buffer_offset + last_block * block_size
+    }).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_region(buffer_offset: vk::DeviceSize, row_length: u32, image_height: u32, width: u32, height: u32) -> vk::BufferImageCopy {
+        vk::BufferImageCopy {
+            buffer_offset,
+            buffer_row_length: row_length,
+            buffer_image_height: image_height,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D { width, height, depth: 1 }
+        }
+    }
+
+    #[test]
+    fn tightly_packed_staging_size() {
+        let format = &Format::R8G8B8A8_UNORM;
+        assert_eq!(compute_staging_size(format, &[make_region(0, 0, 0, 16, 8)]), 16 * 8 * 4);
+        assert_eq!(compute_staging_size(format, &[make_region(0, 0, 0, 16, 8), make_region(512, 0, 0, 4, 4)]), 512 + 4 * 4 * 4);
+        assert_eq!(compute_staging_size(format, &[]), 0);
+
+        // BC1 uses 8 byte blocks of 4x4 texels and partial blocks are rounded up
+        assert_eq!(compute_staging_size(&Format::BC1_RGB_UNORM_BLOCK, &[make_region(0, 0, 0, 10, 6)]), 3 * 2 * 8);
+    }
+
+    #[test]
+    fn row_padded_staging_size() {
+        let format = &Format::R8G8B8A8_UNORM;
+
+        // Rows are 32 texels apart but only the first 16 texels of the last row are accessed
+        assert_eq!(compute_staging_size(format, &[make_region(0, 32, 0, 16, 8)]), (7 * 32 + 16) * 4);
+
+        // The image height padding only affects regions with multiple layers
+        let mut region = make_region(64, 32, 10, 16, 8);
+        assert_eq!(compute_staging_size(format, &[region]), 64 + (7 * 32 + 16) * 4);
+        region.image_subresource.layer_count = 2;
+        assert_eq!(compute_staging_size(format, &[region]), 64 + (10 * 32 + 7 * 32 + 16) * 4);
+    }
+
+    #[test]
+    fn three_plane_420_subsampled_extents() {
+        let format = &Format::G8_B8_R8_3PLANE_420_UNORM;
+        assert_eq!(format.get_plane_count(), 3);
+
+        let size = Vec2u32::new(1920, 1080);
+        assert_eq!(format.get_plane_extent(0, size), Some(size));
+        assert_eq!(format.get_plane_extent(1, size), Some(Vec2u32::new(960, 540)));
+        assert_eq!(format.get_plane_extent(2, size), Some(Vec2u32::new(960, 540)));
+        assert_eq!(format.get_plane_extent(3, size), None);
+
+        for plane in 0..3 {
+            assert_eq!(format.get_plane_format(plane), Some(&Format::R8_UNORM));
+        }
+        assert_eq!(format.get_plane_format(3), None);
+
+        let format = &Format::G10X6_B10X6R10X6_2PLANE_422_UNORM_3PACK16;
+        assert_eq!(format.get_plane_count(), 2);
+        assert_eq!(format.get_plane_format(1), Some(&Format::R10X6G10X6_UNORM_2PACK16));
+        assert_eq!(format.get_plane_extent(1, size), Some(Vec2u32::new(960, 1080)));
+
+        assert_eq!(Format::R8G8B8A8_UNORM.get_plane_count(), 1);
+        assert_eq!(Format::R8G8B8A8_UNORM.get_plane_format(0), None);
+        assert_eq!(Format::BC7_UNORM_BLOCK.get_compatibility_class().get_block_extent(), (4, 4));
+    }
+}
@@ -6,6 +6,7 @@ use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::num::NonZeroU64;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64};
 
 use lazy_static::lazy_static;
 
@@ -52,6 +53,49 @@ impl Debug for UUID {
     }
 }
 
+/// Generates the [`UUID`]s of the objects owned by a single instance.
+///
+/// By default random uuids are generated. In sequential mode uuids are generated by a counter
+/// starting at 1 instead so that the same sequence of object creations always results in the same
+/// ids. This is intended for tests and capture replay. The counter is never reset so that ids stay
+/// unique when switching back and forth.
+pub struct UUIDSource {
+    sequential: AtomicBool,
+    next: AtomicU64,
+}
+
+impl UUIDSource {
+    pub fn new() -> Self {
+        Self {
+            sequential: AtomicBool::new(false),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn set_sequential(&self, sequential: bool) {
+        self.sequential.store(sequential, std::sync::atomic::Ordering::Release);
+    }
+
+    pub fn is_sequential(&self) -> bool {
+        self.sequential.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Returns a new uuid.
+    pub fn next_uuid(&self) -> UUID {
+        if self.is_sequential() {
+            UUID::from_raw(self.next.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+        } else {
+            UUID::new()
+        }
+    }
+}
+
+impl Default for UUIDSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone, Debug)]
 enum NameType {
     Static(&'static str),
@@ -238,4 +282,31 @@ macro_rules! define_uuid_type {
     }
 }
 
-pub use define_uuid_type;
\ No newline at end of file
+pub use define_uuid_type;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_sources_repeat() {
+        let run = || {
+            let source = UUIDSource::new();
+            source.set_sequential(true);
+            (0..16).map(|_| source.next_uuid()).collect::<Vec<_>>()
+        };
+
+        let first = run();
+        assert_eq!(first, run());
+        assert_eq!(first[0], UUID::from_raw(1));
+
+        // Switching modes never reuses a sequential id
+        let source = UUIDSource::new();
+        source.set_sequential(true);
+        let a = source.next_uuid();
+        source.set_sequential(false);
+        source.next_uuid();
+        source.set_sequential(true);
+        assert_eq!(source.next_uuid().get_raw(), a.get_raw() + 1);
+    }
+}
@@ -20,4 +20,104 @@ pub fn make_full_rect(size: Vec2u32) -> vk::Rect2D {
         offset: vk::Offset2D{ x: 0, y: 0 },
         extent: vk::Extent2D{ width: size[0], height: size[1] }
     }
-}
\ No newline at end of file
+}
+
+/// Builds the map entries and data blob of a [`vk::SpecializationInfo`] from typed constant values.
+///
+/// Every constant occupies 4 bytes. Booleans are stored as `VkBool32`. Setting a constant id
+/// which already has a value replaces the previous value.
+#[derive(Clone, Default, Debug)]
+pub struct SpecializationBuilder {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_bool(self, constant_id: u32, value: bool) -> Self {
+        let value = if value { vk::TRUE } else { vk::FALSE };
+        self.set_raw(constant_id, value.to_ne_bytes())
+    }
+
+    pub fn set_u32(self, constant_id: u32, value: u32) -> Self {
+        self.set_raw(constant_id, value.to_ne_bytes())
+    }
+
+    pub fn set_i32(self, constant_id: u32, value: i32) -> Self {
+        self.set_raw(constant_id, value.to_ne_bytes())
+    }
+
+    pub fn set_f32(self, constant_id: u32, value: f32) -> Self {
+        self.set_raw(constant_id, value.to_ne_bytes())
+    }
+
+    fn set_raw(mut self, constant_id: u32, value: [u8; 4]) -> Self {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.constant_id == constant_id) {
+            let offset = entry.offset as usize;
+            self.data[offset..(offset + 4)].copy_from_slice(&value);
+        } else {
+            self.entries.push(vk::SpecializationMapEntry {
+                constant_id,
+                offset: self.data.len() as u32,
+                size: 4
+            });
+            self.data.extend_from_slice(&value);
+        }
+        self
+    }
+
+    pub fn get_entries(&self) -> &[vk::SpecializationMapEntry] {
+        &self.entries
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns a [`vk::SpecializationInfo`] referencing the entries and data of this builder. The
+    /// info can be passed to [`vk::PipelineShaderStageCreateInfoBuilder::specialization_info`].
+    pub fn build(&self) -> vk::SpecializationInfoBuilder {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specialization_layout() {
+        let builder = SpecializationBuilder::new()
+            .set_bool(0, true)
+            .set_i32(3, -2)
+            .set_f32(1, 0.5f32);
+
+        let entries = builder.get_entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[1].constant_id, entries[1].offset, entries[1].size), (3, 4, 4));
+        assert_eq!((entries[2].constant_id, entries[2].offset, entries[2].size), (1, 8, 4));
+
+        let data = builder.get_data();
+        assert_eq!(&data[0..4], &vk::TRUE.to_ne_bytes());
+        assert_eq!(&data[4..8], &(-2i32).to_ne_bytes());
+        assert_eq!(&data[8..12], &0.5f32.to_ne_bytes());
+
+        let info = builder.build();
+        assert_eq!(info.map_entry_count, 3);
+        assert_eq!(info.data_size, 12);
+    }
+
+    #[test]
+    fn specialization_replace() {
+        let first = SpecializationBuilder::new().set_bool(0, false);
+        let second = first.clone().set_bool(0, true);
+
+        assert_eq!(second.get_entries().len(), 1);
+        assert_ne!(first.get_data(), second.get_data());
+    }
+}
@@ -185,6 +185,29 @@ impl ImageSize {
     pub fn fill_extent_3d(&self, extent: &mut ash::vk::Extent3D) {
         *extent = self.as_extent_3d();
     }
+
+    /// Returns the size of a single mip level. Each dimension is halved per level and clamped to
+    /// 1. The array layers are preserved and the returned size has a single mip level.
+    ///
+    /// Panics if `mip_level` is not smaller than the number of mip levels.
+    pub fn get_mip_size(&self, mip_level: u32) -> Self {
+        if mip_level >= self.get_mip_levels() {
+            log::error!("Called ImageSize::get_mip_size with mip level {:?} but the image only has {:?} levels", mip_level, self.get_mip_levels());
+            panic!()
+        }
+
+        let mip = |size: u32| std::cmp::max(size.checked_shr(mip_level).unwrap_or(0), 1);
+        match self {
+            ImageSize::Type1D { width, array_layers, .. } => ImageSize::Type1D { width: mip(*width), mip_levels: 1, array_layers: *array_layers },
+            ImageSize::Type2D { width, height, array_layers, .. } => ImageSize::Type2D { width: mip(*width), height: mip(*height), mip_levels: 1, array_layers: *array_layers },
+            ImageSize::Type3D { width, height, depth, .. } => ImageSize::Type3D { width: mip(*width), height: mip(*height), depth: mip(*depth), mip_levels: 1 },
+        }
+    }
+
+    /// Returns the extent of a single mip level. See [`ImageSize::get_mip_size`].
+    pub fn get_mip_extent_3d(&self, mip_level: u32) -> ash::vk::Extent3D {
+        self.get_mip_size(mip_level).as_extent_3d()
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -366,4 +389,26 @@ impl ImageViewInstanceData {
     pub unsafe fn get_handle(&self) -> vk::ImageView {
         self.handle
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_size_of_2d_array() {
+        let size = ImageSize::make_2d_array_mip(256, 100, 6, 9);
+
+        let mip1 = size.get_mip_size(1);
+        assert_eq!(mip1.get_width(), 128);
+        assert_eq!(mip1.get_height(), 50);
+        assert_eq!(mip1.get_array_layers(), 6);
+        assert_eq!(mip1.get_mip_levels(), 1);
+
+        // The height reaches 1 before the width
+        let mip8 = size.get_mip_extent_3d(8);
+        assert_eq!((mip8.width, mip8.height, mip8.depth), (1, 1, 1));
+        assert_eq!(size.get_mip_extent_3d(7).width, 2);
+        assert_eq!(size.get_mip_size(8).get_array_layers(), 6);
+    }
+}
@@ -45,7 +45,12 @@ impl Debug for SurfaceId {
 
 #[derive(Debug)]
 pub enum SurfaceInitError {
-    /// A vulkan error
+    /// A required window system integration extension is not supported by the vulkan
+    /// implementation.
+    MissingExtension(CString),
+    /// The windowing platform is not supported.
+    UnsupportedPlatform(String),
+    /// A vulkan error returned by the driver
     Vulkan(vk::Result),
     /// A generic error with attached message
     Message(String),
@@ -59,9 +64,43 @@ impl From<vk::Result> for SurfaceInitError {
     }
 }
 
+/// The window system integration platform used by a surface.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum WsiPlatform {
+    Wayland,
+    Xlib,
+    Xcb,
+    Win32,
+    Metal,
+    Android,
+    Unknown,
+}
+
+impl WsiPlatform {
+    /// Detects the platform from the instance extensions required by a surface.
+    pub fn from_extensions(extensions: &[CString]) -> Self {
+        for extension in extensions {
+            match extension.as_bytes() {
+                b"VK_KHR_wayland_surface" => return Self::Wayland,
+                b"VK_KHR_xlib_surface" => return Self::Xlib,
+                b"VK_KHR_xcb_surface" => return Self::Xcb,
+                b"VK_KHR_win32_surface" => return Self::Win32,
+                b"VK_EXT_metal_surface" => return Self::Metal,
+                b"VK_KHR_android_surface" => return Self::Android,
+                _ => {}
+            }
+        }
+        Self::Unknown
+    }
+}
+
 pub trait SurfaceProvider: Send + Sync {
     fn get_required_instance_extensions(&self) -> Vec<CString>;
 
+    fn get_wsi_platform(&self) -> WsiPlatform {
+        WsiPlatform::from_extensions(&self.get_required_instance_extensions())
+    }
+
     fn init(&mut self, entry: &ash::Entry, instance: &ash::Instance) -> Result<vk::SurfaceKHR, SurfaceInitError>;
 
     fn get_handle(&self) -> Option<vk::SurfaceKHR>;
@@ -2,41 +2,120 @@ use std::ffi::{CStr, CString};
 use ash::{Entry, Instance, vk};
 use winit::dpi::LogicalSize;
 use winit::event_loop::EventLoop;
-use winit::window::WindowBuilder;
+use winit::window::{BadIcon, Icon, WindowBuilder};
 use crate::vk::objects::surface::{SurfaceInitError, SurfaceProvider};
 
+/// RGBA8 pixel data of a window icon. The pixels are stored row by row without padding.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WindowIcon {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowIcon {
+    /// Converts the icon into a winit icon. Fails if the size of the pixel data does not match the
+    /// icon size.
+    pub fn to_winit_icon(&self) -> Result<Icon, BadIcon> {
+        Icon::from_rgba(self.rgba.clone(), self.width, self.height)
+    }
+}
+
+/// Describes how a [`WinitWindow`] is created.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowCreateInfo {
+    pub title: String,
+    pub width: f64,
+    pub height: f64,
+    pub visible: bool,
+    pub resizable: bool,
+    pub icon: Option<WindowIcon>,
+}
+
+impl WindowCreateInfo {
+    /// Creates a info for a visible and resizable window without a icon.
+    pub fn new(title: &str, width: f64, height: f64) -> Self {
+        Self {
+            title: String::from(title),
+            width,
+            height,
+            visible: true,
+            resizable: true,
+            icon: None,
+        }
+    }
+}
+
 pub struct WinitWindow {
     handle: winit::window::Window,
+    /// Winit cannot query the visibility of a window so the last requested value is tracked here.
+    visible: bool,
     ash_surface: Option<ash::extensions::khr::Surface>,
     khr_surface: Option<vk::SurfaceKHR>,
 }
 
 impl WinitWindow {
     pub fn new<E>(title: &str, width: f64, height: f64, event_loop: &EventLoop<E>) -> Self {
+        Self::new_with_info(&WindowCreateInfo::new(title, width, height), event_loop)
+    }
+
+    pub fn new_with_info<E>(info: &WindowCreateInfo, event_loop: &EventLoop<E>) -> Self {
+        let icon = info.icon.as_ref().map(|icon| icon.to_winit_icon().unwrap_or_else(|err| {
+            log::error!("Invalid window icon {:?}", err);
+            panic!()
+        }));
+
         let window = WindowBuilder::new()
-            .with_title(title)
-            .with_inner_size(LogicalSize::new(width, height))
+            .with_title(&info.title)
+            .with_inner_size(LogicalSize::new(info.width, info.height))
+            .with_visible(info.visible)
+            .with_resizable(info.resizable)
+            .with_window_icon(icon)
             .build(&event_loop)
             .unwrap();
-        window.set_visible(true);
 
         Self {
             handle: window,
+            visible: info.visible,
             ash_surface: None,
             khr_surface: None,
         }
     }
+
+    /// Shows or hides the window.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.handle.set_visible(visible);
+        self.visible = visible;
+    }
+
+    /// Returns true if the window was created visible or last made visible with
+    /// [`WinitWindow::set_visible`].
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
 }
 
 impl SurfaceProvider for WinitWindow {
     fn get_required_instance_extensions(&self) -> Vec<CString> {
-        ash_window::enumerate_required_extensions(&self.handle).unwrap().into_iter().map(|str| {
-            CString::from(unsafe { CStr::from_ptr(*str) })
-        }).collect()
+        match ash_window::enumerate_required_extensions(&self.handle) {
+            Ok(extensions) => extensions.into_iter().map(|str| {
+                CString::from(unsafe { CStr::from_ptr(*str) })
+            }).collect(),
+            Err(err) => {
+                log::error!("Failed to enumerate required instance extensions for winit window: {:?}", err);
+                Vec::new()
+            }
+        }
     }
 
     fn init(&mut self, entry: &Entry, instance: &Instance) -> Result<vk::SurfaceKHR, SurfaceInitError> {
-        let surface = unsafe { ash_window::create_surface(entry, instance, &self.handle, None)? };
+        let surface = match unsafe { ash_window::create_surface(entry, instance, &self.handle, None) } {
+            Ok(surface) => surface,
+            Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT) => {
+                return Err(SurfaceInitError::UnsupportedPlatform(String::from("Window handle is not supported by ash_window")));
+            }
+            Err(err) => return Err(SurfaceInitError::Vulkan(err)),
+        };
 
         self.khr_surface = Some(surface);
         self.ash_surface = Some(ash::extensions::khr::Surface::new(entry, instance));
@@ -56,4 +135,52 @@ impl Drop for WinitWindow {
             unsafe { khr.destroy_surface(surface, None) };
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_window_with_icon() {
+        let mut info = WindowCreateInfo::new("Hidden", 800.0, 600.0);
+        info.visible = false;
+        info.icon = Some(WindowIcon {
+            rgba: vec![255u8; 2 * 2 * 4],
+            width: 2,
+            height: 2,
+        });
+
+        assert!(!info.visible);
+        assert!(info.icon.as_ref().unwrap().to_winit_icon().is_ok());
+
+        // Creating a window requires a display so only the icon validation is tested here
+        let bad = WindowIcon {
+            rgba: vec![255u8; 3],
+            width: 2,
+            height: 2,
+        };
+        assert!(bad.to_winit_icon().is_err());
+    }
+
+    #[test]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn visibility_change() {
+        // Windows can only be created if a display server is available
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return;
+        }
+        let event_loop: EventLoop<()> = winit::platform::unix::EventLoopExtUnix::new_any_thread();
+
+        let mut info = WindowCreateInfo::new("Hidden", 800.0, 600.0);
+        info.visible = false;
+        let mut window = WinitWindow::new_with_info(&info, &event_loop);
+        assert!(!window.is_visible());
+
+        window.set_visible(true);
+        assert!(window.is_visible());
+
+        window.set_visible(false);
+        assert!(!window.is_visible());
+    }
+}